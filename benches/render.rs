@@ -0,0 +1,112 @@
+// Benchmarks of the hot paths of the renderer: vector math, ray intersections, tracing paths,
+// and rendering a small image of the example scene, all with fixed seeds, so that runs are comparable.
+
+use std::sync::Arc;
+use criterion::{ black_box, criterion_group, criterion_main, Criterion };
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use rpt::vec3::{ Vec3, cross, dot, normalize };
+use rpt::ray::Ray;
+use rpt::scene::{ Hitable, RayType, Scene, Sphere, Material };
+use rpt::camera::{ Camera, PerspectiveCamera };
+use rpt::settings::RenderSettings;
+use rpt::stats::RenderStats;
+use rpt::{ PathState, example_spheres, render_scene, trace_ray };
+
+const WIDTH: u32 = 32;
+const HEIGHT: u32 = 32;
+
+// Camera looking at the example scene from above, as in the default render.
+fn example_camera() -> PerspectiveCamera {
+    PerspectiveCamera::look_at(
+        Vec3::new(5.0, 5.0, 5.0),
+        Vec3::new(0.0, -1.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        60.0,
+        WIDTH as f32 / HEIGHT as f32,
+        8.0,
+        0.0,
+    )
+}
+
+// Rays through the centers of all the pixels of the image.
+fn primary_rays(camera: &PerspectiveCamera) -> Vec<Ray> {
+    let mut rng = StdRng::seed_from_u64(0);
+    (0..WIDTH * HEIGHT)
+        .map(|i| camera.ray_for_pixel_sample(i % WIDTH, i / WIDTH, (0.5, 0.5), WIDTH, HEIGHT, &mut rng))
+        .collect()
+}
+
+fn vec3_ops(c: &mut Criterion) {
+    let a = Vec3::new(0.3, -1.2, 2.5);
+    let b = Vec3::new(-0.7, 0.4, 1.1);
+    c.bench_function("vec3 dot", |bench| bench.iter(|| dot(black_box(&a), black_box(&b))));
+    c.bench_function("vec3 cross", |bench| bench.iter(|| cross(black_box(&a), black_box(&b))));
+    c.bench_function("vec3 normalize", |bench| bench.iter(|| normalize(black_box(&a))));
+}
+
+fn sphere_hit(c: &mut Criterion) {
+    let sphere = Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Material::Normal);
+    let hit = Ray::new(Vec3::new(0.0, 0.0, 0.0), normalize(&Vec3::new(0.1, 0.2, -1.0)));
+    let miss = Ray::new(Vec3::new(0.0, 0.0, 0.0), normalize(&Vec3::new(1.0, 0.2, -1.0)));
+    c.bench_function("sphere hit", |bench| bench.iter(|| sphere.hit(black_box(&hit))));
+    c.bench_function("sphere miss", |bench| bench.iter(|| sphere.hit(black_box(&miss))));
+}
+
+fn scene_hit(c: &mut Criterion) {
+    let scene = Scene::new(example_spheres());
+    let rays = primary_rays(&example_camera());
+    c.bench_function("scene hit, all pixels", |bench| bench.iter(|| {
+        rays.iter().filter(|ray| scene.hit_visible(ray, RayType::Camera).is_some()).count()
+    }));
+}
+
+fn trace_paths(c: &mut Criterion) {
+    let scene = Scene::new(example_spheres());
+    let settings = RenderSettings { max_depth: scene.suggested_max_depth(), ..RenderSettings::default() };
+    let rays = primary_rays(&example_camera());
+    c.bench_function("trace ray, all pixels", |bench| bench.iter(|| {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut stats = RenderStats::new(settings.max_depth);
+        rays.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, ray| {
+            &sum + &trace_ray(&scene, ray, None, &settings, &mut rng, &mut stats, PathState::new(0.0))
+        })
+    }));
+}
+
+fn render_small(c: &mut Criterion) {
+    let scene = Arc::new(Scene::new(example_spheres()));
+    let camera = Arc::new(example_camera());
+    let settings = Arc::new(RenderSettings {
+        width: WIDTH,
+        height: HEIGHT,
+        pixel_samples: 4,
+        max_depth: scene.suggested_max_depth(),
+        num_threads: 1,
+        ..RenderSettings::default()
+    });
+    let mut group = c.benchmark_group("render");
+    group.sample_size(20);
+    group.bench_function("example scene, 32x32, 4 spp", |bench| bench.iter(|| {
+        render_scene(scene.clone(), camera.clone(), settings.clone())
+    }));
+    group.finish();
+}
+
+fn build_grid(c: &mut Criterion) {
+    // Spheres on a jittered lattice, enough to fill the cells of the grid with several threads.
+    let spheres: Vec<Sphere> = (0..100_000).map(|i| {
+        let jitter = (i * 7919 % 101) as f32 / 101.0;
+        Sphere::new(Vec3::new((i % 50) as f32 + jitter, (i / 50 % 40) as f32, (i / 2000) as f32 - jitter), 0.4, Material::Normal)
+    }).collect();
+    let mut scene = Scene::new(spheres);
+    let mut group = c.benchmark_group("grid");
+    group.sample_size(20);
+    group.bench_function("build, 100k spheres, 1 thread", |bench| bench.iter(|| scene.build_grid(1)));
+    group.bench_function("build, 100k spheres, all cores", |bench| bench.iter(|| scene.build_grid(0)));
+    group.finish();
+}
+
+criterion_group!(benches, vec3_ops, sphere_hit, scene_hit, trace_paths, render_small, build_grid);
+criterion_main!(benches);
@@ -8,16 +8,46 @@ pub struct BBox {
     pub max: Vec3,
 }
 
+impl Default for BBox {
+    fn default() -> BBox {
+        BBox::new()
+    }
+}
+
 impl BBox {
     /// Create new, empty bounding box, with max values set to infinity, and min values set to -infinity.
     #[inline(always)]
     pub fn new() -> BBox {
         BBox {
-            min: Vec3::new(std::f32::INFINITY, std::f32::INFINITY, std::f32::INFINITY),
-            max: Vec3::new(std::f32::NEG_INFINITY, std::f32::NEG_INFINITY, std::f32::NEG_INFINITY),
+            min: Vec3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vec3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
         }
     }
 
+    /// Create new infinite bounding box, for objects without finite bounds (such as infinite planes).
+    #[inline(always)]
+    pub fn infinite() -> BBox {
+        BBox {
+            min: Vec3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+            max: Vec3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+        }
+    }
+
+    /// Check whether the bounding box is empty, that is, it does not contain any point.
+    /// Adding an empty bounding box to another one does not change the other one.
+    #[inline(always)]
+    pub fn is_empty(self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Check whether the bounding box is non-empty and has finite bounds.
+    #[inline(always)]
+    pub fn is_finite(self) -> bool {
+        !self.is_empty()
+            && self.min.x.is_finite() && self.min.y.is_finite() && self.min.z.is_finite()
+            && self.max.x.is_finite() && self.max.y.is_finite() && self.max.z.is_finite()
+    }
+
     /// Create new bounding box for single point.
     #[inline(always)]
     pub fn new_from_point(p: Vec3) -> BBox {
@@ -137,10 +167,10 @@ impl ops::AddAssign<&Vec3> for BBox {
     fn add_assign(&mut self, v: &Vec3) {
         self.min.x = self.min.x.min(v.x);
         self.min.y = self.min.y.min(v.y);
-        self.min.z = self.min.y.min(v.z);
+        self.min.z = self.min.z.min(v.z);
         self.max.x = self.max.x.max(v.x);
         self.max.y = self.max.y.max(v.y);
-        self.max.z = self.max.y.max(v.z);
+        self.max.z = self.max.z.max(v.z);
     }
 }
 
@@ -163,10 +193,10 @@ impl ops::AddAssign<&BBox> for BBox {
     fn add_assign(&mut self, bbox: &BBox) {
         self.min.x = self.min.x.min(bbox.min.x);
         self.min.y = self.min.y.min(bbox.min.y);
-        self.min.z = self.min.y.min(bbox.min.z);
+        self.min.z = self.min.z.min(bbox.min.z);
         self.max.x = self.max.x.max(bbox.max.x);
         self.max.y = self.max.y.max(bbox.max.y);
-        self.max.z = self.max.y.max(bbox.max.z);
+        self.max.z = self.max.z.max(bbox.max.z);
     }
 }
 
@@ -179,15 +209,15 @@ mod tests {
         let bbox1 = BBox::new_from_points(&Vec3::new(-1.0, -1.0, -1.0), &Vec3::new(0.75, 0.75, 0.75));
         let bbox2 = BBox::new_from_points(&Vec3::new(1.0, 1.0, 1.0), &Vec3::new(2.0, 2.0, 2.0));
         let bbox3 = BBox::new_from_points(&Vec3::new(0.5, 0.5, 0.5), &Vec3::new(1.5, 1.5, 1.5));
-        assert_eq!(bbox1.overlaps(bbox2), false);
-        assert_eq!(bbox1.overlaps(bbox3), true);
+        assert!(!bbox1.overlaps(bbox2));
+        assert!(bbox1.overlaps(bbox3));
     }
 
     #[test]
     fn contains_point() {
         let bbox = BBox::new_from_points(&Vec3::new(-1.0, -1.0, -1.0), &Vec3::new(0.75, 0.75, 0.75));
-        assert_eq!(bbox.contains(Vec3::new(0.0, 0.0, 0.0)), true);
-        assert_eq!(bbox.contains(Vec3::new(1.0, 0.0, 0.0)), false);
+        assert!(bbox.contains(Vec3::new(0.0, 0.0, 0.0)));
+        assert!(!bbox.contains(Vec3::new(1.0, 0.0, 0.0)));
     }
 
     #[test]
@@ -269,4 +299,54 @@ mod tests {
         assert_eq!(bbox.min.x, -1.0); assert_eq!(bbox.min.y, -1.0); assert_eq!(bbox.min.z, -1.0);
         assert_eq!(bbox.max.x, 2.0); assert_eq!(bbox.max.y, 2.0); assert_eq!(bbox.max.z, 2.0);
     }
+
+    #[test]
+    fn add_assign_keeps_axes_apart() {
+        // Different bounds along every axis, so that taking the Z bounds from the Y components shows.
+        let mut bbox = BBox::new_from_points(&Vec3::new(0.0, -5.0, 10.0), &Vec3::new(1.0, 5.0, 20.0));
+        bbox += &Vec3::new(0.5, 0.0, 30.0);
+        assert_eq!(bbox.min.x, 0.0); assert_eq!(bbox.min.y, -5.0); assert_eq!(bbox.min.z, 10.0);
+        assert_eq!(bbox.max.x, 1.0); assert_eq!(bbox.max.y, 5.0); assert_eq!(bbox.max.z, 30.0);
+        bbox += &BBox::new_from_points(&Vec3::new(-1.0, -2.0, 5.0), &Vec3::new(0.0, 2.0, 15.0));
+        assert_eq!(bbox.min.x, -1.0); assert_eq!(bbox.min.y, -5.0); assert_eq!(bbox.min.z, 5.0);
+        assert_eq!(bbox.max.x, 1.0); assert_eq!(bbox.max.y, 5.0); assert_eq!(bbox.max.z, 30.0);
+    }
+
+    #[test]
+    fn empty_bbox() {
+        let empty = BBox::new();
+        assert!(empty.is_empty());
+        assert!(!empty.is_finite());
+        assert!(!empty.contains(Vec3::new(0.0, 0.0, 0.0)));
+        let bbox = BBox::new_from_points(&Vec3::new(-1.0, -1.0, -1.0), &Vec3::new(0.75, 0.75, 0.75));
+        assert!(!empty.overlaps(bbox) && !bbox.overlaps(empty));
+        // Union with an empty box is the other box.
+        for sum in &[&empty + &bbox, &bbox + &empty] {
+            assert_eq!(sum.min.x, -1.0); assert_eq!(sum.min.y, -1.0); assert_eq!(sum.min.z, -1.0);
+            assert_eq!(sum.max.x, 0.75); assert_eq!(sum.max.y, 0.75); assert_eq!(sum.max.z, 0.75);
+        }
+        assert!((&empty + &empty).is_empty());
+        let mut sum = BBox::new();
+        sum += &bbox;
+        assert!(sum.is_finite());
+        assert!(!BBox::new_from_point(Vec3::new(1.0, 2.0, 3.0)).is_empty());
+    }
+
+    #[test]
+    fn infinite_bbox() {
+        let infinite = BBox::infinite();
+        assert!(!infinite.is_empty());
+        assert!(!infinite.is_finite());
+        let bbox = BBox::new_from_points(&Vec3::new(-1.0, -1.0, -1.0), &Vec3::new(0.75, 0.75, 0.75));
+        assert!(bbox.is_finite());
+        assert!(infinite.overlaps(bbox) && bbox.overlaps(infinite));
+        assert!(infinite.contains(Vec3::new(1e30, -1e30, 0.0)));
+        // Union with an infinite box is infinite.
+        let sum = &bbox + &infinite;
+        assert_eq!(sum.min.x, f32::NEG_INFINITY); assert_eq!(sum.max.z, f32::INFINITY);
+        assert!((&infinite + &BBox::new()).overlaps(bbox));
+        // Infinite in a single direction, such as a half-space.
+        let half = BBox::new_from_points(&Vec3::new(f32::NEG_INFINITY, -1.0, f32::NEG_INFINITY), &Vec3::new(f32::INFINITY, 0.0, f32::INFINITY));
+        assert!(!half.is_empty() && !half.is_finite());
+    }
 }
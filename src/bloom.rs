@@ -0,0 +1,97 @@
+use super::vec3::Vec3;
+
+// Compute normalized weights of a 1D Gaussian kernel covering `radius` pixels on each side.
+fn gaussian_kernel(radius: u32) -> Vec<f32> {
+    let sigma = (radius as f32 / 2.0).max(0.5);
+    let r = radius as i32;
+    let mut kernel: Vec<f32> = (-r..=r).map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp()).collect();
+    let sum: f32 = kernel.iter().sum();
+    for w in kernel.iter_mut() {
+        *w /= sum;
+    }
+    kernel
+}
+
+// Blur the buffer along one axis, clamping lookups to the edges of the image.
+fn blur_pass(input: &[Vec3], width: u32, height: u32, kernel: &[f32], horizontal: bool) -> Vec<Vec3> {
+    let radius = (kernel.len() / 2) as i32;
+    let mut output = Vec::with_capacity(input.len());
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let mut sum = Vec3::new(0.0, 0.0, 0.0);
+            for (k, w) in kernel.iter().enumerate() {
+                let offset = k as i32 - radius;
+                let (sx, sy) = if horizontal {
+                    ((x + offset).max(0).min(width as i32 - 1), y)
+                } else {
+                    (x, (y + offset).max(0).min(height as i32 - 1))
+                };
+                let c = &input[(sy * width as i32 + sx) as usize];
+                sum.x += w * c.x;
+                sum.y += w * c.y;
+                sum.z += w * c.z;
+            }
+            output.push(sum);
+        }
+    }
+    output
+}
+
+/// Add a glow around bright areas of an HDR image.
+///
+/// The part of each color channel above the threshold is blurred using a separable Gaussian filter,
+/// and added back to the image. Must be applied to linear colors, before tone mapping.
+///
+/// # Arguments
+///
+/// * `buffer` - Linear colors, one per pixel.
+/// * `width` - Image width.
+/// * `height` - Image height.
+/// * `threshold` - Color channel value above which pixels start to glow.
+/// * `radius` - Radius of the blur, in pixels.
+pub fn bloom(buffer: &mut [Vec3], width: u32, height: u32, threshold: f32, radius: u32) {
+    let bright: Vec<Vec3> = buffer.iter().map(|c| Vec3::new(
+        (c.x - threshold).max(0.0),
+        (c.y - threshold).max(0.0),
+        (c.z - threshold).max(0.0),
+    )).collect();
+    let kernel = gaussian_kernel(radius);
+    let blurred = blur_pass(&bright, width, height, &kernel, true);
+    let blurred = blur_pass(&blurred, width, height, &kernel, false);
+    for (c, b) in buffer.iter_mut().zip(blurred.iter()) {
+        *c += b;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dim_image_unchanged() {
+        let mut buffer = vec![Vec3::new(0.5, 0.25, 1.0); 8 * 8];
+        bloom(&mut buffer, 8, 8, 1.0, 2);
+        for c in buffer.iter() {
+            assert_eq!(c.x, 0.5); assert_eq!(c.y, 0.25); assert_eq!(c.z, 1.0);
+        }
+    }
+
+    #[test]
+    fn bright_pixel_glows() {
+        let mut buffer = vec![Vec3::new(0.0, 0.0, 0.0); 9 * 9];
+        buffer[4 * 9 + 4] = Vec3::new(5.0, 1.0, 0.0);
+        bloom(&mut buffer, 9, 9, 1.0, 2);
+        // The glow spreads symmetrically to the neighbors...
+        let (left, right, top, bottom) = (buffer[4 * 9 + 3], buffer[4 * 9 + 5], buffer[3 * 9 + 4], buffer[5 * 9 + 4]);
+        assert!(left.x > 0.0);
+        assert!((left.x - right.x).abs() < 1e-6);
+        assert!((left.x - top.x).abs() < 1e-6);
+        assert!((left.x - bottom.x).abs() < 1e-6);
+        assert_eq!(buffer[0].x, 0.0);
+        // ...only for channels above the threshold...
+        assert!(buffer.iter().all(|c| c.y <= 1.0 && c.z == 0.0));
+        // ...and adds as much energy as there was above the threshold.
+        let total: f32 = buffer.iter().map(|c| c.x).sum();
+        assert!((total - 9.0).abs() < 1e-4);
+    }
+}
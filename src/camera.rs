@@ -1,21 +1,81 @@
-use rand::rngs::ThreadRng;
+use rand::rngs::StdRng;
 use rand::{ Rng };
-use super::vec3::{ Vec3, cross, length };
+use super::vec3::{ Vec3, cross, dot, length, normalize };
 use super::ray::Ray;
+use super::frustum::Frustum;
+use super::sampling::concentric_sample_disk;
 
 pub trait Camera {
-    fn generate_ray(self: &Self, u: f32, v: f32, rng: &mut ThreadRng) -> Ray;
+    fn generate_ray(&self, u: f32, v: f32, rng: &mut StdRng) -> Ray;
+
+    /// Generate a ray through given position within a pixel of an image.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Pixel column, from the left.
+    /// * `y` - Pixel row, from the top.
+    /// * `offset` - Position within the pixel, from (0.0, 0.0) at its top left corner to (1.0, 1.0) at the bottom right.
+    /// * `width` - Image width.
+    /// * `height` - Image height.
+    /// * `rng` - Random number generator, for cameras that sample a lens.
+    fn ray_for_pixel_sample(&self, x: u32, y: u32, offset: (f32, f32), width: u32, height: u32, rng: &mut StdRng) -> Ray {
+        let u = (x as f32 + offset.0) / width as f32 - 0.5;
+        let v = 0.5 - (y as f32 + offset.1) / height as f32;
+        self.generate_ray(u, v, rng)
+    }
+
+    /// Generate a ray through a random position within a pixel of an image.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Pixel column, from the left.
+    /// * `y` - Pixel row, from the top.
+    /// * `width` - Image width.
+    /// * `height` - Image height.
+    /// * `rng` - Random number generator.
+    fn ray_for_pixel(&self, x: u32, y: u32, width: u32, height: u32, rng: &mut StdRng) -> Ray {
+        let offset = (rng.gen(), rng.gen());
+        self.ray_for_pixel_sample(x, y, offset, width, height, rng)
+    }
 }
 
+/// Where a point of the scene appears in the image of a camera.
+#[derive(Debug, Copy, Clone)]
+pub struct Projection {
+    /// Horizontal image coordinate, from -0.5 to 0.5, as passed to `Camera::generate_ray`.
+    pub u: f32,
+    /// Vertical image coordinate, from -0.5 to 0.5, as passed to `Camera::generate_ray`.
+    pub v: f32,
+    /// Point on the lens the point is seen from.
+    pub origin: Vec3,
+    /// Density of the image coordinates per solid angle around the direction from the origin towards the point,
+    /// as a fraction of the whole image.
+    pub importance: f32,
+}
+
+/// Method for choosing points on the lens of a camera with depth of field.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LensSampling {
+    /// Random points of the square around the lens, rejecting those outside of it.
+    Rejection,
+    /// Random points of the unit square mapped onto the lens by `sampling::concentric_sample_disk`, which needs
+    /// exactly two random numbers per point. Gives the same distribution of points as `Rejection`.
+    Concentric,
+}
+
+#[derive(Clone)]
 pub struct PerspectiveCamera {
     origin: Vec3,
     target: Vec3,
     u_axis: Vec3,
     v_axis: Vec3,
+    w_axis: Vec3,
     viewport_width: f32,
     viewport_height: f32,
     focal_distance: f32,
     lens_radius: f32,
+    focus_plane: Option<(Vec3, Vec3)>,
+    lens_sampling: LensSampling,
 }
 
 impl PerspectiveCamera {
@@ -34,35 +94,430 @@ impl PerspectiveCamera {
             target,
             u_axis,
             v_axis,
+            w_axis: dir,
             viewport_width,
             viewport_height,
             focal_distance,
             lens_radius,
+            focus_plane: None,
+            lens_sampling: LensSampling::Rejection,
+        }
+    }
+}
+
+impl PerspectiveCamera {
+    /// Focus the camera on a plane instead of at a fixed distance.
+    ///
+    /// The focus of each ray is where its center (pinhole) ray intersects the plane, so a plane that is
+    /// not perpendicular to the view direction results in a tilted focus. Rays that do not intersect
+    /// the plane fall back to the focal distance.
+    ///
+    /// # Arguments
+    ///
+    /// * `point` - Any point on the plane.
+    /// * `normal` - Normal of the plane (does not need to be normalized).
+    pub fn set_focus_plane(&mut self, point: Vec3, normal: Vec3) {
+        self.focus_plane = Some((point, normal));
+    }
+
+    /// Set how points on the lens are chosen.
+    ///
+    /// # Arguments
+    ///
+    /// * `lens_sampling` - Method for choosing points on the lens.
+    pub fn set_lens_sampling(&mut self, lens_sampling: LensSampling) {
+        self.lens_sampling = lens_sampling;
+    }
+
+    /// Compute the approximate angle between rays through neighboring pixels.
+    ///
+    /// # Arguments
+    ///
+    /// * `image_height` - Height of the rendered image, in pixels.
+    pub fn pixel_spread_angle(&self, image_height: u32) -> f32 {
+        self.viewport_height / (length(&(&self.target - &self.origin)) * image_height as f32)
+    }
+
+    /// Check whether the camera can project points to the image, which is not the case when focusing on a plane.
+    pub fn can_project(&self) -> bool {
+        self.focus_plane.is_none() && (self.lens_radius == 0.0 || self.focal_distance > 0.0)
+    }
+
+    /// Find where a point of the scene appears in the image, as seen from a random point on the lens.
+    ///
+    /// This is the inverse of `generate_ray`: the ray it generates for the returned image coordinates, when it
+    /// samples the same point on the lens, passes through the point. Returns `None` for points outside the image,
+    /// and for all points when the camera cannot project them (see `can_project`).
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - Point to project.
+    /// * `rng` - Random number generator to sample the lens with.
+    pub fn project(&self, p: &Vec3, rng: &mut StdRng) -> Option<Projection> {
+        if !self.can_project() {
+            return None;
+        }
+        let (origin, dir, jacobian) = if self.lens_radius == 0.0 {
+            (self.origin, normalize(&(p - &self.origin)), 1.0)
+        } else {
+            // The ray passes through the point where the pinhole ray of the same image coordinates
+            // reaches the plane of focus, at the focal distance along the view direction.
+            let origin = self.sample_lens(rng);
+            let lens_dir = normalize(&(p - &origin));
+            let lens_cos = dot(&lens_dir, &self.w_axis);
+            if lens_cos <= 0.0 {
+                return None;
+            }
+            // The lens is perpendicular to the view direction, so the distance to the plane only depends on the angle.
+            let t = self.focal_distance / lens_cos;
+            let dir = normalize(&(&(&origin - &self.origin) + &(t * &lens_dir)));
+            // Change of the solid angle between the rays from the lens and the pinhole rays,
+            // through the plane of focus points.
+            let cos = dot(&dir, &self.w_axis);
+            (origin, dir, cos * cos * cos * t * t / (self.focal_distance * self.focal_distance * lens_cos))
+        };
+        let forward = &self.target - &self.origin;
+        let dist = length(&forward);
+        let cos_theta = dot(&dir, &forward) / dist;
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        let offset = &(&self.origin + &((dist / cos_theta) * &dir)) - &self.target;
+        let u = dot(&offset, &self.u_axis) / self.viewport_width;
+        let v = dot(&offset, &self.v_axis) / self.viewport_height;
+        if u.abs() > 0.5 || v.abs() > 0.5 {
+            return None;
+        }
+        let image_area = (self.viewport_width * self.viewport_height).abs();
+        let importance = dist * dist / (cos_theta * cos_theta * cos_theta * image_area) * jacobian;
+        Some(Projection { u, v, origin, importance })
+    }
+
+    // Uniformly distributed random point on the lens.
+    fn sample_lens(&self, rng: &mut StdRng) -> Vec3 {
+        let (u, v) = match self.lens_sampling {
+            LensSampling::Rejection => loop {
+                let (u, v) = (2.0 * rng.gen::<f32>() - 1.0, 2.0 * rng.gen::<f32>() - 1.0);
+                if u * u + v * v < 1.0 {
+                    break (u, v);
+                }
+            },
+            LensSampling::Concentric => concentric_sample_disk(rng.gen(), rng.gen()),
+        };
+        let mut origin = self.origin;
+        origin += &(u * self.lens_radius * &self.u_axis);
+        origin += &(v * self.lens_radius * &self.v_axis);
+        origin
+    }
+
+    /// Compute the region of the scene the primary rays of the camera can pass through.
+    ///
+    /// With a lens, the region is widened to include the rays from anywhere on the lens.
+    /// Returns `None` when the rays cannot be bounded this way, that is, when focusing on a plane.
+    pub fn frustum(&self) -> Option<Frustum> {
+        if self.focus_plane.is_some() || (self.lens_radius > 0.0 && self.focal_distance <= 0.0) {
+            return None;
         }
+        let dist = length(&(&self.target - &self.origin));
+        let dir = normalize(&(&self.target - &self.origin));
+        let (half_width, half_height) = (0.5 * self.viewport_width.abs(), 0.5 * self.viewport_height.abs());
+        // Rays through the lens cross the pinhole rays at the focal distance along the view direction,
+        // and spread out by up to the lens radius around the origin.
+        let widening = if self.lens_radius > 0.0 { self.lens_radius / self.focal_distance } else { 0.0 };
+        let mut planes = Vec::with_capacity(4);
+        for (axis, half_size) in &[(self.u_axis, half_width), (self.v_axis, half_height)] {
+            let slope = half_size / dist + widening;
+            for &side in &[1.0, -1.0] {
+                let n = &(slope * &dir) + &(-side * axis);
+                planes.push((n, self.lens_radius - dot(&n, &self.origin)));
+            }
+        }
+        Some(Frustum::new(planes))
     }
 }
 
 impl Camera for PerspectiveCamera {
-    fn generate_ray(self: &Self, u: f32, v: f32, rng: &mut ThreadRng) -> Ray {
+    fn generate_ray(&self, u: f32, v: f32, rng: &mut StdRng) -> Ray {
         let mut target = self.target;
         target += &(u * self.viewport_width * &self.u_axis);
         target += &(v * self.viewport_height * &self.v_axis);
         let mut dir = &target - &self.origin;
         dir.normalize();
         let mut ray = Ray::new(self.origin, dir);
-
-        let focus_point = ray.point_at(self.focal_distance);
-        loop {
-            let (u, v): (f32, f32) = (rng.gen(), rng.gen());
-            if u * u + v * v < 1.0 {
-                ray.o += &(u * self.lens_radius * &self.u_axis);
-                ray.o += &(v * self.lens_radius * &self.v_axis);
-                break;
-            }
+        // Pinhole camera, everything is in focus and there is no need to sample the lens.
+        if self.lens_radius == 0.0 {
+            return ray;
         }
+
+        // The focal distance is measured along the view direction, so that the points in focus lie on a plane
+        // perpendicular to it, the same as with such a focus plane, instead of on a sphere around the origin.
+        let axial_distance = self.focal_distance / dot(&dir, &self.w_axis);
+        let focal_distance = match &self.focus_plane {
+            Some((point, normal)) => ray.intersect_plane(point, normal).unwrap_or(axial_distance),
+            None => axial_distance,
+        };
+        let focus_point = ray.point_at(focal_distance);
+        ray.o = self.sample_lens(rng);
         ray.d = &focus_point - &ray.o;
         ray.d.normalize();
 
         ray
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    fn camera() -> PerspectiveCamera {
+        PerspectiveCamera::look_at(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            1.0,
+            4.0,
+            0.5,
+        )
+    }
+
+    #[test]
+    fn frustum_contains_primary_rays() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for &lens_radius in &[0.0, 0.5] {
+            let camera = PerspectiveCamera::look_at(
+                Vec3::new(1.0, 2.0, 3.0),
+                Vec3::new(1.0, 1.0, 1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                40.0,
+                1.5,
+                4.0,
+                lens_radius,
+            );
+            let frustum = camera.frustum().unwrap();
+            for &(u, v) in &[(-0.5, -0.5), (0.5, -0.5), (-0.5, 0.5), (0.5, 0.5), (0.0, 0.2)] {
+                let ray = camera.generate_ray(u, v, &mut rng);
+                for &t in &[0.0, 1.0, 4.0, 50.0] {
+                    let p = ray.point_at(t);
+                    assert!(frustum.planes.iter().all(|(n, d)| dot(n, &p) + d > -1e-4));
+                }
+            }
+            // Points behind the camera, or far to the side, are outside.
+            for p in &[Vec3::new(1.0, 2.0 + 4.0, 3.0 + 2.0), Vec3::new(1.0 + 20.0, 1.0, 1.0)] {
+                assert!(frustum.planes.iter().any(|(n, d)| dot(n, p) + d < 0.0));
+            }
+        }
+        let mut camera = camera();
+        camera.set_focus_plane(Vec3::new(0.0, 0.0, -4.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(camera.frustum().is_none());
+    }
+
+    #[test]
+    fn lens_samples_cover_disk() {
+        for &lens_sampling in &[LensSampling::Rejection, LensSampling::Concentric] {
+            let mut camera = camera();
+            camera.set_lens_sampling(lens_sampling);
+            let mut rng = StdRng::seed_from_u64(1);
+            let n = 20000;
+            let (mut mean, mut inner, mut quadrants) = (Vec3::new(0.0, 0.0, 0.0), 0, [0u32; 4]);
+            for _ in 0..n {
+                // The lens of radius 0.5 is centered at the origin, in the XY plane.
+                let p = camera.sample_lens(&mut rng);
+                assert!(p.z == 0.0 && p.x * p.x + p.y * p.y <= 0.25 + 1e-6);
+                mean += &p;
+                if p.x * p.x + p.y * p.y < 0.0625 {
+                    inner += 1;
+                }
+                quadrants[(if p.x < 0.0 { 1 } else { 0 }) + (if p.y < 0.0 { 2 } else { 0 })] += 1;
+            }
+            assert!(mean.x.abs() / (n as f32) < 0.01 && mean.y.abs() / (n as f32) < 0.01);
+            // Half the radius, a quarter of the area.
+            assert!((inner as f32 / n as f32 - 0.25).abs() < 0.01);
+            assert!(quadrants.iter().all(|&q| (q as f32 / n as f32 - 0.25).abs() < 0.01));
+        }
+    }
+
+    #[test]
+    fn defocus_is_centered() {
+        // Rays of the default (rejection) lens sampling through the center of the image cross at the focal point,
+        // and beyond it, spread evenly around the axis of the camera, into a disk as large as the lens.
+        let camera = camera();
+        let mut rng = StdRng::seed_from_u64(2);
+        let n = 4000;
+        let (mut mean, mut quadrants) = (Vec3::new(0.0, 0.0, 0.0), [0u32; 4]);
+        for _ in 0..n {
+            let ray = camera.generate_ray(0.0, 0.0, &mut rng);
+            let focus = ray.point_at(-4.0 / ray.d.z);
+            assert!(focus.x.abs() < 1e-5 && focus.y.abs() < 1e-5);
+            let p = ray.point_at(-8.0 / ray.d.z);
+            assert!(p.x * p.x + p.y * p.y <= 0.25 + 1e-5);
+            mean += &p;
+            quadrants[(if p.x < 0.0 { 1 } else { 0 }) + (if p.y < 0.0 { 2 } else { 0 })] += 1;
+        }
+        assert!(mean.x.abs() / (n as f32) < 0.02 && mean.y.abs() / (n as f32) < 0.02);
+        assert!(quadrants.iter().all(|&q| (q as f32 / n as f32 - 0.25).abs() < 0.03));
+    }
+
+    #[test]
+    fn perpendicular_focus_plane() {
+        let mut planar = camera();
+        planar.set_focus_plane(Vec3::new(0.0, 0.0, -4.0), Vec3::new(0.0, 0.0, 1.0));
+        // The center ray, and the rays in the corners, are focused at the same point as with a scalar focal distance.
+        for &(u, v) in &[(0.0, 0.0), (-0.5, 0.5), (0.5, -0.5)] {
+            let r1 = camera().generate_ray(u, v, &mut StdRng::seed_from_u64(1));
+            let r2 = planar.generate_ray(u, v, &mut StdRng::seed_from_u64(1));
+            assert!((r1.o.x - r2.o.x).abs() < 1e-6); assert!((r1.o.y - r2.o.y).abs() < 1e-6); assert!((r1.o.z - r2.o.z).abs() < 1e-6);
+            assert!((r1.d.x - r2.d.x).abs() < 1e-6); assert!((r1.d.y - r2.d.y).abs() < 1e-6); assert!((r1.d.z - r2.d.z).abs() < 1e-6);
+        }
+        // That is, on the plane at the focal distance along the view direction: the corner of the view at z = -4
+        // (tan 30 deg times 4 along both axes of the image) is in focus from anywhere on the lens.
+        let corner = Vec3::new(4.0 / 3f32.sqrt(), 4.0 / 3f32.sqrt(), -4.0);
+        for seed in 0..4 {
+            let ray = camera().generate_ray(-0.5, 0.5, &mut StdRng::seed_from_u64(seed));
+            let p = ray.point_at((-4.0 - ray.o.z) / ray.d.z);
+            assert!(length(&(&p - &corner)) < 1e-4);
+        }
+    }
+
+    #[test]
+    fn tilted_focus_plane() {
+        let mut camera = camera();
+        let (point, normal) = (Vec3::new(0.0, 0.0, -4.0), Vec3::new(0.5, 0.0, 1.0));
+        camera.set_focus_plane(point, normal);
+        for &(u, v) in &[(0.0, 0.0), (-0.4, 0.1), (0.3, -0.2)] {
+            // Rays through different points of the lens converge on the plane.
+            let r1 = camera.generate_ray(u, v, &mut StdRng::seed_from_u64(1));
+            let r2 = camera.generate_ray(u, v, &mut StdRng::seed_from_u64(2));
+            let p1 = r1.point_at(r1.intersect_plane(&point, &normal).unwrap());
+            let p2 = r2.point_at(r2.intersect_plane(&point, &normal).unwrap());
+            assert!((p1.x - p2.x).abs() < 1e-4); assert!((p1.y - p2.y).abs() < 1e-4); assert!((p1.z - p2.z).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn pinhole_skips_lens_sampling() {
+        // Looking down -Z with a field of view of 90 degrees, the image spans 2 units at unit distance,
+        // with U to the left, along -X, and V up, along +Y.
+        let camera = PerspectiveCamera::look_at(
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(1.0, 2.0, 2.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            1.0,
+            4.0,
+            0.0,
+        );
+        let mut rng = StdRng::seed_from_u64(1);
+        let third = 1.0 / 3.0;
+        let corner = 1.0 / 3f32.sqrt();
+        for &((u, v), d) in &[
+            ((0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)),
+            ((-0.5, 0.5), Vec3::new(corner, corner, -corner)),
+            ((0.25, -0.5), Vec3::new(-third, -2.0 * third, -2.0 * third)),
+        ] {
+            // The rays start at the eye, through the point of the image, wherever the focal distance is.
+            let ray = camera.generate_ray(u, v, &mut rng);
+            assert_eq!(ray.o.x, 1.0); assert_eq!(ray.o.y, 2.0); assert_eq!(ray.o.z, 3.0);
+            assert!((ray.d.x - d.x).abs() < 1e-6); assert!((ray.d.y - d.y).abs() < 1e-6); assert!((ray.d.z - d.z).abs() < 1e-6);
+        }
+        // No random numbers were used.
+        let (a, b): (f32, f32) = (rng.gen(), StdRng::seed_from_u64(1).gen());
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn ray_for_center_pixel() {
+        let camera = PerspectiveCamera::look_at(
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(0.0, 1.0, -2.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            0.5,
+            4.0,
+            0.0,
+        );
+        let mut rng = StdRng::seed_from_u64(1);
+        // The center of the middle pixel of an image with odd dimensions looks at the target.
+        let ray = camera.ray_for_pixel_sample(2, 1, (0.5, 0.5), 5, 3, &mut rng);
+        let d = normalize(&Vec3::new(-1.0, -1.0, -5.0));
+        assert_eq!(ray.o.x, 1.0); assert_eq!(ray.o.y, 2.0); assert_eq!(ray.o.z, 3.0);
+        assert!((ray.d.x - d.x).abs() < 1e-6); assert!((ray.d.y - d.y).abs() < 1e-6); assert!((ray.d.z - d.z).abs() < 1e-6);
+        // The top left corner of the image, above and to the left of the target.
+        let corner = camera.ray_for_pixel_sample(0, 0, (0.0, 0.0), 5, 3, &mut rng);
+        let expected = camera.generate_ray(-0.5, 0.5, &mut rng);
+        assert!((corner.d.x - expected.d.x).abs() < 1e-6); assert!((corner.d.y - expected.d.y).abs() < 1e-6); assert!((corner.d.z - expected.d.z).abs() < 1e-6);
+        assert!(corner.d.y > d.y);
+        // Random positions stay within the pixel.
+        for _ in 0..10 {
+            let ray = camera.ray_for_pixel(2, 1, 5, 3, &mut rng);
+            let p = camera.project(&ray.point_at(1.0), &mut rng).unwrap();
+            assert!(p.u >= -0.1 - 1e-6 && p.u <= 0.1 + 1e-6);
+            assert!(p.v >= -1.0 / 6.0 - 1e-6 && p.v <= 1.0 / 6.0 + 1e-6);
+        }
+    }
+
+    #[test]
+    fn project_inverts_generate_ray() {
+        for &lens_radius in &[0.0, 0.5] {
+            let camera = PerspectiveCamera::look_at(
+                Vec3::new(1.0, 2.0, 3.0),
+                Vec3::new(0.0, 1.0, -2.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                60.0,
+                1.5,
+                4.0,
+                lens_radius,
+            );
+            for &(u, v) in &[(0.0, 0.0), (-0.45, 0.3), (0.2, -0.4)] {
+                let ray = camera.generate_ray(u, v, &mut StdRng::seed_from_u64(1));
+                for &t in &[1.0, 4.0, 20.0] {
+                    // The same lens sample sees the point at the same image coordinates.
+                    let projection = camera.project(&ray.point_at(t), &mut StdRng::seed_from_u64(1)).unwrap();
+                    assert!((projection.u - u).abs() < 1e-4); assert!((projection.v - v).abs() < 1e-4);
+                    assert!(length(&(&projection.origin - &ray.o)) < 1e-5);
+                }
+            }
+            // Points behind the camera, or outside the view.
+            let mut rng = StdRng::seed_from_u64(1);
+            assert!(camera.project(&Vec3::new(2.0, 3.0, 8.0), &mut rng).is_none());
+            assert!(camera.project(&Vec3::new(40.0, 1.0, -2.0), &mut rng).is_none());
+        }
+        let mut camera = camera();
+        camera.set_focus_plane(Vec3::new(0.0, 0.0, -4.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(!camera.can_project());
+        assert!(camera.project(&Vec3::new(0.0, 0.0, -4.0), &mut StdRng::seed_from_u64(1)).is_none());
+    }
+
+    #[test]
+    fn projection_importance() {
+        // Importance integrates to 1 over the solid angle of the image: sum it over a fine grid
+        // of points on a plane at unit distance in front of the camera.
+        for &lens_radius in &[0.0, 0.5] {
+            let camera = PerspectiveCamera::look_at(
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, -1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                60.0,
+                1.0,
+                4.0,
+                lens_radius,
+            );
+            let mut rng = StdRng::seed_from_u64(1);
+            let (n, size) = (300, 3.0);
+            let cell = size / n as f32;
+            let mut total = 0.0;
+            for i in 0..n {
+                for j in 0..n {
+                    let p = Vec3::new(-0.5 * size + (i as f32 + 0.5) * cell, -0.5 * size + (j as f32 + 0.5) * cell, -1.0);
+                    if let Some(projection) = camera.project(&p, &mut rng) {
+                        // Solid angle of the cell as seen from the lens.
+                        let d = length(&(&p - &projection.origin));
+                        total += projection.importance * cell * cell / (d * d * d);
+                    }
+                }
+            }
+            assert!((total - 1.0).abs() < 0.02);
+        }
+    }
+}
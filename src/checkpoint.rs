@@ -0,0 +1,395 @@
+use std::fs::{ self, File };
+use std::io::{ self, BufReader, BufWriter, Read, Write };
+use std::path::Path;
+use super::vec3::Vec3;
+
+const MAGIC: &[u8; 4] = b"RPTK";
+// Version of the layout following the magic, increased whenever the layout changes.
+const VERSION: u32 = 2;
+// Magic of the checkpoints from before the layout had a version.
+const OLD_MAGIC: &[u8; 4] = b"RPTC";
+// Size of the header following the magic, and of each pixel, with colors in single and double precision.
+const HEADER_SIZE: u64 = 44;
+const PIXEL_SIZE: u64 = 20;
+const PRECISE_PIXEL_SIZE: u64 = 32;
+
+/// Sums of the colors, coverage, and sample counts of all the passes rendered so far,
+/// which can be saved to disk and loaded again to resume a progressive render.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub width: u32,
+    pub height: u32,
+    /// Base seed of the render, so that a checkpoint of a different render is not resumed by accident.
+    pub seed: u64,
+    /// Samples per pixel of each pass of the render, for the same reason as `seed`.
+    pub pixel_samples: u32,
+    /// Max depth of the paths of the render, for the same reason as `seed`.
+    pub max_depth: u32,
+    /// Fingerprint of the rendered scene (see `Scene::fingerprint`), for the same reason as `seed`.
+    pub scene: u64,
+    /// Number of passes accumulated.
+    pub passes: u32,
+    /// Sum of the linear colors of all passes, one per pixel, or empty when summing in double precision.
+    pub color: Vec<Vec3>,
+    /// Sum of the linear colors of all passes in double precision, one per pixel, or empty when summing in single precision.
+    pub precise_color: Vec<[f64; 3]>,
+    /// Whether the colors are summed in double precision, in `precise_color`.
+    pub double_precision: bool,
+    /// Sum of the coverage of all passes, one per pixel.
+    pub coverage: Vec<f32>,
+    /// Total number of samples taken, one per pixel.
+    pub samples: Vec<u32>,
+}
+
+impl Checkpoint {
+    /// Create new checkpoint with no passes accumulated.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - Image width.
+    /// * `height` - Image height.
+    /// * `seed` - Base seed of the render.
+    pub fn new(width: u32, height: u32, seed: u64) -> Checkpoint {
+        let size = (width * height) as usize;
+        Checkpoint {
+            width,
+            height,
+            seed,
+            pixel_samples: 0,
+            max_depth: 0,
+            scene: 0,
+            passes: 0,
+            color: vec![Vec3::new(0.0, 0.0, 0.0); size],
+            precise_color: Vec::new(),
+            double_precision: false,
+            coverage: vec![0.0; size],
+            samples: vec![0; size],
+        }
+    }
+
+    /// Create new checkpoint with no passes accumulated, summing the colors in double precision.
+    ///
+    /// Takes twice as much memory for the colors, but the average does not drift
+    /// even after summing thousands of passes.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - Image width.
+    /// * `height` - Image height.
+    /// * `seed` - Base seed of the render.
+    pub fn new_double_precision(width: u32, height: u32, seed: u64) -> Checkpoint {
+        let mut checkpoint = Checkpoint::new(width, height, seed);
+        checkpoint.precise_color = vec![[0.0; 3]; checkpoint.color.len()];
+        checkpoint.color = Vec::new();
+        checkpoint.double_precision = true;
+        checkpoint
+    }
+
+    /// Add the result of another pass.
+    ///
+    /// # Arguments
+    ///
+    /// * `color` - Linear colors of the pass, one per pixel.
+    /// * `coverage` - Coverage of the pass, one per pixel.
+    /// * `samples` - Number of samples taken in the pass, one per pixel.
+    pub fn add_pass(&mut self, color: &[Vec3], coverage: &[f32], samples: &[u32]) {
+        for (sum, c) in self.color.iter_mut().zip(color.iter()) {
+            *sum += c;
+        }
+        for (sum, c) in self.precise_color.iter_mut().zip(color.iter()) {
+            sum[0] += c.x as f64;
+            sum[1] += c.y as f64;
+            sum[2] += c.z as f64;
+        }
+        for (sum, c) in self.coverage.iter_mut().zip(coverage.iter()) {
+            *sum += c;
+        }
+        for (sum, s) in self.samples.iter_mut().zip(samples.iter()) {
+            *sum += s;
+        }
+        self.passes += 1;
+    }
+
+    /// Remove all the accumulated passes, keeping the buffers, to start another render of the same size.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - Base seed of the new render.
+    pub fn clear(&mut self, seed: u64) {
+        self.seed = seed;
+        self.passes = 0;
+        self.color.fill(Vec3::new(0.0, 0.0, 0.0));
+        self.precise_color.fill([0.0; 3]);
+        self.coverage.fill(0.0);
+        self.samples.fill(0);
+    }
+
+    /// Compute the average colors and coverage of all the accumulated passes into buffers of the caller.
+    ///
+    /// # Arguments
+    ///
+    /// * `color` - Where to write the colors to, one per pixel.
+    /// * `coverage` - Where to write the coverage to, one per pixel.
+    pub fn average_into(&self, color: &mut [Vec3], coverage: &mut [f32]) {
+        let scale = 1.0 / self.passes.max(1) as f32;
+        if self.double_precision {
+            let scale = 1.0 / self.passes.max(1) as f64;
+            for (average, c) in color.iter_mut().zip(self.precise_color.iter()) {
+                *average = Vec3::new((scale * c[0]) as f32, (scale * c[1]) as f32, (scale * c[2]) as f32);
+            }
+        } else {
+            for (average, c) in color.iter_mut().zip(self.color.iter()) {
+                *average = scale * c;
+            }
+        }
+        for (average, c) in coverage.iter_mut().zip(self.coverage.iter()) {
+            *average = scale * c;
+        }
+    }
+
+    /// Save the checkpoint to a file.
+    ///
+    /// The data is written to a temporary file first and then moved in place,
+    /// so that a crash while saving does not destroy the previous checkpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            writer.write_all(MAGIC)?;
+            writer.write_all(&VERSION.to_le_bytes())?;
+            writer.write_all(&(self.double_precision as u32).to_le_bytes())?;
+            writer.write_all(&self.width.to_le_bytes())?;
+            writer.write_all(&self.height.to_le_bytes())?;
+            writer.write_all(&self.seed.to_le_bytes())?;
+            writer.write_all(&self.pixel_samples.to_le_bytes())?;
+            writer.write_all(&self.max_depth.to_le_bytes())?;
+            writer.write_all(&self.scene.to_le_bytes())?;
+            writer.write_all(&self.passes.to_le_bytes())?;
+            for (i, (a, s)) in self.coverage.iter().zip(self.samples.iter()).enumerate() {
+                if self.double_precision {
+                    for c in &self.precise_color[i] {
+                        writer.write_all(&c.to_le_bytes())?;
+                    }
+                } else {
+                    writer.write_all(&self.color[i].to_le_bytes())?;
+                }
+                writer.write_all(&a.to_le_bytes())?;
+                writer.write_all(&s.to_le_bytes())?;
+            }
+            writer.flush()?;
+        }
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Load a checkpoint previously saved with `save`.
+    ///
+    /// Fails with `InvalidData` for files that are not checkpoints, for checkpoints saved by other versions
+    /// of the renderer, with a different layout, and for checkpoints whose size does not match the file length.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file.
+    pub fn load(path: &Path) -> io::Result<Checkpoint> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic == OLD_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "checkpoint of an older version of the renderer"));
+        }
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a render checkpoint"));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported checkpoint version {}", version)));
+        }
+        let double_precision = read_u32(&mut reader)? != 0;
+        let width = read_u32(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        if width.checked_mul(height).is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("checkpoint size {}x{} too large", width, height)));
+        }
+        // Checked before allocating the buffers, so that a damaged header cannot ask for any amount of memory.
+        let pixel_size = if double_precision { PRECISE_PIXEL_SIZE } else { PIXEL_SIZE };
+        if file_len != MAGIC.len() as u64 + HEADER_SIZE + width as u64 * height as u64 * pixel_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("checkpoint size {}x{} does not match the file length {}", width, height, file_len)));
+        }
+        let seed = read_u64(&mut reader)?;
+        let mut checkpoint = if double_precision {
+            Checkpoint::new_double_precision(width, height, seed)
+        } else {
+            Checkpoint::new(width, height, seed)
+        };
+        checkpoint.pixel_samples = read_u32(&mut reader)?;
+        checkpoint.max_depth = read_u32(&mut reader)?;
+        checkpoint.scene = read_u64(&mut reader)?;
+        checkpoint.passes = read_u32(&mut reader)?;
+        for i in 0..checkpoint.coverage.len() {
+            if checkpoint.double_precision {
+                for c in checkpoint.precise_color[i].iter_mut() {
+                    *c = read_f64(&mut reader)?;
+                }
+            } else {
+                checkpoint.color[i] = read_vec3(&mut reader)?;
+            }
+            checkpoint.coverage[i] = read_f32(&mut reader)?;
+            checkpoint.samples[i] = read_u32(&mut reader)?;
+        }
+        Ok(checkpoint)
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_vec3(reader: &mut impl Read) -> io::Result<Vec3> {
+    let mut bytes = [0u8; 12];
+    reader.read_exact(&mut bytes)?;
+    Ok(Vec3::from_le_bytes(bytes))
+}
+
+fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn average(checkpoint: &Checkpoint) -> (Vec<Vec3>, Vec<f32>) {
+        let mut color = vec![Vec3::new(0.0, 0.0, 0.0); checkpoint.coverage.len()];
+        let mut coverage = vec![0.0; checkpoint.coverage.len()];
+        checkpoint.average_into(&mut color, &mut coverage);
+        (color, coverage)
+    }
+
+    #[test]
+    fn average_passes() {
+        let mut checkpoint = Checkpoint::new(2, 1, 0);
+        checkpoint.add_pass(&[Vec3::new(1.0, 2.0, 3.0), Vec3::new(0.0, 0.0, 0.0)], &[1.0, 0.0], &[4, 4]);
+        checkpoint.add_pass(&[Vec3::new(3.0, 2.0, 1.0), Vec3::new(0.0, 0.0, 4.0)], &[1.0, 1.0], &[4, 8]);
+        let (color, coverage) = average(&checkpoint);
+        assert_eq!(checkpoint.passes, 2);
+        assert_eq!(color[0].x, 2.0); assert_eq!(color[0].y, 2.0); assert_eq!(color[0].z, 2.0);
+        assert_eq!(color[1].z, 2.0);
+        assert_eq!(coverage, vec![1.0, 0.5]);
+        assert_eq!(checkpoint.samples, vec![8, 12]);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut checkpoint = Checkpoint::new(2, 2, 42);
+        (checkpoint.pixel_samples, checkpoint.max_depth, checkpoint.scene) = (4, 8, 1 << 40);
+        checkpoint.add_pass(&[Vec3::new(0.1, 0.2, 0.3), Vec3::new(1.0, -1.0, 10.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.5, 0.5, 0.5)], &[1.0, 0.25, 0.0, 0.5], &[1, 2, 3, 4]);
+        let path = std::env::temp_dir().join(format!("rpt-checkpoint-test-{}.ckpt", std::process::id()));
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.width, 2); assert_eq!(loaded.height, 2);
+        assert_eq!(loaded.seed, 42); assert_eq!(loaded.passes, 1);
+        assert_eq!(loaded.pixel_samples, 4); assert_eq!(loaded.max_depth, 8); assert_eq!(loaded.scene, 1 << 40);
+        assert_eq!(loaded.color[1].x, 1.0); assert_eq!(loaded.color[1].y, -1.0); assert_eq!(loaded.color[1].z, 10.0);
+        assert_eq!(loaded.color[0].x, 0.1);
+        assert_eq!(loaded.coverage, checkpoint.coverage);
+        assert_eq!(loaded.samples, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn load_invalid_file() {
+        let path = std::env::temp_dir().join(format!("rpt-checkpoint-invalid-{}.ckpt", std::process::id()));
+        std::fs::write(&path, b"PF\n2 2\n").unwrap();
+        let result = Checkpoint::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn load_mismatched_size() {
+        let path = std::env::temp_dir().join(format!("rpt-checkpoint-size-{}.ckpt", std::process::id()));
+        Checkpoint::new(2, 2, 0).save(&path).unwrap();
+        // A damaged header asking for a huge image fails without allocating it...
+        let mut large = std::fs::read(&path).unwrap();
+        large[12..20].copy_from_slice(&[0xff, 0xff, 0, 0, 0xff, 0xff, 0, 0]);
+        std::fs::write(&path, &large).unwrap();
+        let error = Checkpoint::load(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("65535x65535"));
+        // ...and so does a truncated file.
+        Checkpoint::new_double_precision(2, 2, 0).save(&path).unwrap();
+        let truncated = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &truncated[..truncated.len() - 1]).unwrap();
+        let error = Checkpoint::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn load_other_versions() {
+        let path = std::env::temp_dir().join(format!("rpt-checkpoint-version-{}.ckpt", std::process::id()));
+        // Checkpoints of 1x1 images from before the layout had a version...
+        let mut old = b"RPTC".to_vec();
+        for value in &[1u32, 1, 0, 0, 1, 8, 0, 0, 1] {
+            old.extend_from_slice(&value.to_le_bytes());
+        }
+        old.extend_from_slice(&[0; 16]);
+        std::fs::write(&path, &old).unwrap();
+        let error = Checkpoint::load(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("older version"));
+        // ...and from a later version are both rejected.
+        Checkpoint::new(1, 1, 0).save(&path).unwrap();
+        let mut newer = std::fs::read(&path).unwrap();
+        newer[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        std::fs::write(&path, &newer).unwrap();
+        let error = Checkpoint::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("version"));
+    }
+
+    #[test]
+    fn double_precision() {
+        // A million passes of a value that cannot be summed exactly in single precision.
+        let mut single = Checkpoint::new(1, 1, 0);
+        let mut double = Checkpoint::new_double_precision(1, 1, 0);
+        for _ in 0..1_000_000 {
+            single.add_pass(&[Vec3::new(0.1, 0.1, 0.1)], &[1.0], &[1]);
+            double.add_pass(&[Vec3::new(0.1, 0.1, 0.1)], &[1.0], &[1]);
+        }
+        assert!((average(&single).0[0].x - 0.1).abs() > 1e-4);
+        assert_eq!(average(&double).0[0].x, 0.1);
+        assert!(double.color.is_empty());
+
+        let path = std::env::temp_dir().join(format!("rpt-checkpoint-double-{}.ckpt", std::process::id()));
+        double.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.double_precision);
+        assert_eq!(loaded.passes, 1_000_000);
+        assert_eq!(loaded.precise_color, double.precise_color);
+        assert_eq!(loaded.samples, vec![1_000_000]);
+    }
+}
@@ -0,0 +1,456 @@
+use super::vec3::Vec3;
+
+/// Convert sRGB encoded value to linear value.
+///
+/// # Arguments
+///
+/// * `v` - sRGB encoded value in the range of 0.0 to 1.0.
+#[inline(always)]
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert linear value to sRGB encoded value.
+///
+/// # Arguments
+///
+/// * `v` - Linear value in the range of 0.0 to 1.0.
+#[inline(always)]
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Compute the luminance of a linear color, using the Rec. 709 (sRGB) primaries.
+///
+/// # Arguments
+///
+/// * `c` - Linear color.
+#[inline(always)]
+pub fn luminance(c: &Vec3) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// Downsample RGBA buffer by averaging blocks of pixels in linear space.
+///
+/// Color channels are decoded to linear values with the transfer function they were encoded with
+/// before averaging, and encoded with it again afterwards. The alpha channel is averaged as is. Pixels that do not fit into a whole block
+/// on the right or bottom edge are dropped. Panics if the factor is 0.
+///
+/// # Arguments
+///
+/// * `buffer` - RGBA values, 4 bytes per pixel.
+/// * `width` - Image width.
+/// * `height` - Image height.
+/// * `factor` - Size of the block of pixels to average into one output pixel.
+/// * `transfer` - Transfer function the color channels are encoded with.
+pub fn downsample(buffer: &[u8], width: u32, height: u32, factor: u32, transfer: Transfer) -> Vec<u8> {
+    assert!(factor > 0, "downsample factor must be positive");
+    let (new_width, new_height) = (width / factor, height / factor);
+    let inv_count = 1.0 / (factor * factor) as f32;
+    let mut output = Vec::with_capacity((new_width * new_height * 4) as usize);
+    for y in 0..new_height {
+        for x in 0..new_width {
+            let mut sum = [0.0f32; 4];
+            for sy in y * factor..(y + 1) * factor {
+                for sx in x * factor..(x + 1) * factor {
+                    let i = ((sy * width + sx) * 4) as usize;
+                    sum[0] += transfer.decode(buffer[i] as f32 / 255.0);
+                    sum[1] += transfer.decode(buffer[i + 1] as f32 / 255.0);
+                    sum[2] += transfer.decode(buffer[i + 2] as f32 / 255.0);
+                    sum[3] += buffer[i + 3] as f32 / 255.0;
+                }
+            }
+            output.push((255.0 * transfer.encode(sum[0] * inv_count) + 0.5) as u8);
+            output.push((255.0 * transfer.encode(sum[1] * inv_count) + 0.5) as u8);
+            output.push((255.0 * transfer.encode(sum[2] * inv_count) + 0.5) as u8);
+            output.push((255.0 * sum[3] * inv_count + 0.5) as u8);
+        }
+    }
+    output
+}
+
+/// Downsample linear colors by averaging blocks of pixels, the same way as `downsample`, but before
+/// the colors are tone mapped and encoded, so that no transfer function has to be undone.
+/// Panics if the factor is 0.
+///
+/// # Arguments
+///
+/// * `buffer` - Linear colors, one per pixel.
+/// * `width` - Image width.
+/// * `height` - Image height.
+/// * `factor` - Size of the block of pixels to average into one output pixel.
+pub fn downsample_linear(buffer: &[Vec3], width: u32, height: u32, factor: u32) -> Vec<Vec3> {
+    assert!(factor > 0, "downsample factor must be positive");
+    let (new_width, new_height) = (width / factor, height / factor);
+    let inv_count = 1.0 / (factor * factor) as f32;
+    let mut output = Vec::with_capacity((new_width * new_height) as usize);
+    for y in 0..new_height {
+        for x in 0..new_width {
+            let mut sum = Vec3::new(0.0, 0.0, 0.0);
+            for sy in y * factor..(y + 1) * factor {
+                for sx in x * factor..(x + 1) * factor {
+                    sum += &buffer[(sy * width + sx) as usize];
+                }
+            }
+            output.push(inv_count * &sum);
+        }
+    }
+    output
+}
+
+/// Operator mapping linear HDR color channels into the displayable range of 0.0 to 1.0.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMapping {
+    /// Clamp values above 1.0.
+    Clamp,
+    /// Reinhard operator `c / (1 + c)`, which only approaches 1.0 for infinitely bright values.
+    Reinhard,
+    /// Extended Reinhard operator `c * (1 + c / white²) / (1 + c)`, which maps the white point to 1.0.
+    /// Brighter values are clamped. A white point that is not positive is treated as infinitely bright,
+    /// which gives the plain Reinhard operator.
+    ReinhardExtended(f32 /* white point */),
+}
+
+impl ToneMapping {
+    /// Map a single linear color channel into the range of 0.0 to 1.0.
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - Linear color channel value.
+    #[inline(always)]
+    pub fn apply(&self, c: f32) -> f32 {
+        match *self {
+            ToneMapping::Clamp => if c > 1.0 { 1.0 } else { c },
+            ToneMapping::Reinhard => c / (1.0 + c),
+            ToneMapping::ReinhardExtended(white) if white > 0.0 => (c * (1.0 + c / (white * white)) / (1.0 + c)).min(1.0),
+            // Zero, negative, and NaN white points would divide by zero or give NaN.
+            ToneMapping::ReinhardExtended(_) => c / (1.0 + c),
+        }
+    }
+}
+
+/// Transfer function encoding tone mapped linear values for storage in an image file.
+///
+/// Rendered colors stay linear until they are written, so that each output format can pick its own curve:
+/// linear for floating point formats such as PFM, and a gamma curve for 8 or 16-bit formats such as PNG.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Transfer {
+    /// Store the values as they are.
+    Linear,
+    /// Square root, a cheap approximation of the sRGB curve, and the inverse of `Image::get_color`.
+    Gamma2,
+    /// The exact sRGB curve, as expected by image viewers.
+    Srgb,
+}
+
+impl Transfer {
+    /// Encode a single value.
+    ///
+    /// # Arguments
+    ///
+    /// * `v` - Linear value in the range of 0.0 to 1.0.
+    #[inline(always)]
+    pub fn encode(&self, v: f32) -> f32 {
+        match *self {
+            Transfer::Linear => v,
+            Transfer::Gamma2 => v.sqrt(),
+            Transfer::Srgb => linear_to_srgb(v),
+        }
+    }
+
+    /// Decode a single value, the inverse of `encode`.
+    ///
+    /// # Arguments
+    ///
+    /// * `v` - Encoded value in the range of 0.0 to 1.0.
+    #[inline(always)]
+    pub fn decode(&self, v: f32) -> f32 {
+        match *self {
+            Transfer::Linear => v,
+            Transfer::Gamma2 => v * v,
+            Transfer::Srgb => srgb_to_linear(v),
+        }
+    }
+}
+
+/// Encode linear colors into an RGBA buffer, with an opaque alpha channel.
+///
+/// # Arguments
+///
+/// * `buffer` - Linear colors, one per pixel.
+/// * `tone_mapping` - Operator mapping the colors into the range of 0.0 to 1.0.
+/// * `transfer` - Transfer function applied after tone mapping.
+pub fn to_rgba8(buffer: &[Vec3], tone_mapping: ToneMapping, transfer: Transfer) -> Vec<u8> {
+    let mut output = vec![0; buffer.len() * 4];
+    encode_rgba8(buffer, &mut output, tone_mapping, transfer);
+    output
+}
+
+/// Encode linear colors into an existing RGBA buffer, with an opaque alpha channel, the same way as `to_rgba8`.
+///
+/// Panics if the output buffer does not have exactly 4 values per color.
+///
+/// # Arguments
+///
+/// * `buffer` - Linear colors, one per pixel.
+/// * `output` - RGBA values, 4 per pixel, to overwrite.
+/// * `tone_mapping` - Operator mapping the colors into the range of 0.0 to 1.0.
+/// * `transfer` - Transfer function applied after tone mapping.
+pub fn encode_rgba8(buffer: &[Vec3], output: &mut [u8], tone_mapping: ToneMapping, transfer: Transfer) {
+    assert_eq!(output.len(), buffer.len() * 4, "output must have 4 values per pixel");
+    for (pixel, c) in output.chunks_mut(4).zip(buffer.iter()) {
+        pixel[0] = (255.99 * transfer.encode(tone_mapping.apply(c.x))) as u8;
+        pixel[1] = (255.99 * transfer.encode(tone_mapping.apply(c.y))) as u8;
+        pixel[2] = (255.99 * transfer.encode(tone_mapping.apply(c.z))) as u8;
+        pixel[3] = 255;
+    }
+}
+
+/// Encode linear colors into a 16-bit RGBA buffer, with an opaque alpha channel.
+///
+/// The same as `to_rgba8`, with 256 times as many levels per channel, which avoids banding in smooth gradients.
+///
+/// # Arguments
+///
+/// * `buffer` - Linear colors, one per pixel.
+/// * `tone_mapping` - Operator mapping the colors into the range of 0.0 to 1.0.
+/// * `transfer` - Transfer function applied after tone mapping.
+pub fn to_rgba16(buffer: &[Vec3], tone_mapping: ToneMapping, transfer: Transfer) -> Vec<u16> {
+    let mut output = Vec::with_capacity(buffer.len() * 4);
+    for c in buffer {
+        output.push((65535.99 * transfer.encode(tone_mapping.apply(c.x))) as u16);
+        output.push((65535.99 * transfer.encode(tone_mapping.apply(c.y))) as u16);
+        output.push((65535.99 * transfer.encode(tone_mapping.apply(c.z))) as u16);
+        output.push(65535);
+    }
+    output
+}
+
+/// Encode values (such as the number of samples per pixel) into a grayscale RGBA buffer,
+/// where the largest value is white and zero is black.
+///
+/// # Arguments
+///
+/// * `values` - Values, one per pixel.
+pub fn heatmap_to_rgba8(values: &[u32]) -> Vec<u8> {
+    let max = values.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let mut output = Vec::with_capacity(values.len() * 4);
+    for v in values {
+        let gray = (255.0 * *v as f32 / max + 0.5) as u8;
+        output.extend_from_slice(&[gray, gray, gray, 255]);
+    }
+    output
+}
+
+/// Encode values (such as the number of intersection tests per pixel) into an RGBA buffer with a color ramp,
+/// going from black for zero through blue, cyan, green, and yellow, to red for the largest value.
+///
+/// # Arguments
+///
+/// * `values` - Values, one per pixel.
+pub fn heatmap_to_color_rgba8(values: &[u32]) -> Vec<u8> {
+    const RAMP: [[f32; 3]; 6] = [
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [0.0, 1.0, 1.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [1.0, 0.0, 0.0],
+    ];
+    let max = values.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let mut output = Vec::with_capacity(values.len() * 4);
+    for v in values {
+        let position = *v as f32 / max * (RAMP.len() - 1) as f32;
+        let i = (position as usize).min(RAMP.len() - 2);
+        let t = position - i as f32;
+        for (c0, c1) in RAMP[i].iter().zip(RAMP[i + 1].iter()) {
+            output.push((255.0 * (c0 + t * (c1 - c0)) + 0.5) as u8);
+        }
+        output.push(255);
+    }
+    output
+}
+
+/// Replace the alpha channel of an RGBA buffer.
+///
+/// # Arguments
+///
+/// * `buffer` - RGBA values, 4 bytes per pixel.
+/// * `alpha` - New alpha values, one per pixel, in the range of 0.0 to 1.0.
+pub fn set_alpha(buffer: &mut [u8], alpha: &[f32]) {
+    for (pixel, a) in buffer.chunks_mut(4).zip(alpha.iter()) {
+        pixel[3] = (255.0 * a + 0.5) as u8;
+    }
+}
+
+/// Replace the alpha channel of a 16-bit RGBA buffer.
+///
+/// # Arguments
+///
+/// * `buffer` - RGBA values, 4 per pixel.
+/// * `alpha` - New alpha values, one per pixel, in the range of 0.0 to 1.0.
+pub fn set_alpha16(buffer: &mut [u16], alpha: &[f32]) {
+    for (pixel, a) in buffer.chunks_mut(4).zip(alpha.iter()) {
+        pixel[3] = (65535.0 * a + 0.5) as u16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trip() {
+        for i in 0..=255 {
+            let v = i as f32 / 255.0;
+            assert!((linear_to_srgb(srgb_to_linear(v)) - v).abs() < 1e-5);
+        }
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert_eq!(srgb_to_linear(1.0), 1.0);
+    }
+
+    #[test]
+    fn downsample_flat_color() {
+        let buffer: Vec<u8> = [100, 150, 200, 255].iter().cycle().take(4 * 4 * 4).cloned().collect();
+        for &transfer in &[Transfer::Linear, Transfer::Gamma2, Transfer::Srgb] {
+            let output = downsample(&buffer, 4, 4, 2, transfer);
+            assert_eq!(output, [100, 150, 200, 255].iter().cycle().take(2 * 2 * 4).cloned().collect::<Vec<u8>>());
+        }
+    }
+
+    #[test]
+    fn downsample_edge_in_linear_space() {
+        // Black/white edge within a single 2x2 block.
+        let buffer = vec![
+            0, 0, 0, 255,   255, 255, 255, 255,
+            0, 0, 0, 255,   255, 255, 255, 255,
+        ];
+        let naive = ((buffer[0] as u32 + buffer[4] as u32) / 2) as u8;
+        let output = downsample(&buffer, 2, 2, 2, Transfer::Srgb);
+        assert_eq!(output.len(), 4);
+        assert_eq!(output[0], 188);
+        assert_ne!(output[0], naive);
+        assert_eq!(output[3], 255);
+        // Rendered images are encoded with gamma 2 by default, which decodes the edge differently.
+        assert_eq!(downsample(&buffer, 2, 2, 2, Transfer::Gamma2)[0], 180);
+        assert_eq!(downsample(&buffer, 2, 2, 2, Transfer::Linear)[0], naive + 1);
+    }
+
+    #[test]
+    fn downsample_edge_before_encoding() {
+        let buffer = vec![
+            Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.5, 0.5, 0.5),
+            Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.5, 0.5, 0.5),
+        ];
+        let output = downsample_linear(&buffer, 3, 2, 2);
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].x, 0.5); assert_eq!(output[0].y, 0.5); assert_eq!(output[0].z, 0.5);
+        // Encoded with gamma 2, the edge is lighter than the 8-bit average of black and white.
+        assert_eq!(to_rgba8(&output, ToneMapping::Clamp, Transfer::Gamma2)[0], 181);
+    }
+
+    #[test]
+    #[should_panic]
+    fn downsample_linear_by_zero() {
+        downsample_linear(&[Vec3::new(0.0, 0.0, 0.0)], 1, 1, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn downsample_by_zero() {
+        downsample(&[0, 0, 0, 255], 1, 1, 0, Transfer::Srgb);
+    }
+
+    #[test]
+    fn encode_rgba8() {
+        let buffer = vec![Vec3::new(0.0, 0.25, 1.0), Vec3::new(4.0, -1.0, f32::NAN)];
+        assert_eq!(to_rgba8(&buffer, ToneMapping::Clamp, Transfer::Gamma2), vec![0, 127, 255, 255, 255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn encode_with_transfer() {
+        let buffer = vec![Vec3::new(0.0, 0.25, 1.0)];
+        assert_eq!(to_rgba8(&buffer, ToneMapping::Clamp, Transfer::Linear), vec![0, 63, 255, 255]);
+        assert_eq!(to_rgba8(&buffer, ToneMapping::Clamp, Transfer::Srgb), vec![0, 137, 255, 255]);
+        for &v in &[0.0, 0.001, 0.2, 0.5, 1.0] {
+            assert_eq!(Transfer::Linear.encode(v), v);
+            assert_eq!(Transfer::Gamma2.encode(v), v.sqrt());
+            assert!((srgb_to_linear(Transfer::Srgb.encode(v)) - v).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn encode_rgba16() {
+        let buffer = vec![Vec3::new(0.0, 0.25, 1.0), Vec3::new(4.0, -1.0, f32::NAN)];
+        assert_eq!(to_rgba16(&buffer, ToneMapping::Clamp, Transfer::Gamma2), vec![0, 32767, 65535, 65535, 65535, 0, 0, 65535]);
+        let mut buffer = to_rgba16(&buffer, ToneMapping::Clamp, Transfer::Gamma2);
+        set_alpha16(&mut buffer, &[0.0, 0.5]);
+        assert_eq!(buffer[3], 0); assert_eq!(buffer[7], 32768);
+        // A dark gradient, quantized into many more distinct levels.
+        let gradient: Vec<Vec3> = (0..1000).map(|i| { let c = i as f32 / 10000.0; Vec3::new(c, c, c) }).collect();
+        let mut levels8: Vec<u8> = to_rgba8(&gradient, ToneMapping::Clamp, Transfer::Gamma2).chunks(4).map(|p| p[0]).collect();
+        let mut levels16: Vec<u16> = to_rgba16(&gradient, ToneMapping::Clamp, Transfer::Gamma2).chunks(4).map(|p| p[0]).collect();
+        levels8.dedup();
+        levels16.dedup();
+        assert!(levels8.len() < 100);
+        assert_eq!(levels16.len(), 1000);
+        // The top 8 bits are (up to rounding) the same as the 8-bit encoding.
+        let buffer8 = to_rgba8(&gradient, ToneMapping::Clamp, Transfer::Gamma2);
+        assert!(to_rgba16(&gradient, ToneMapping::Clamp, Transfer::Gamma2).iter().zip(buffer8.iter()).all(|(a, b)| ((*a >> 8) as i32 - *b as i32).abs() <= 1));
+    }
+
+    #[test]
+    fn reinhard_extended_white_point() {
+        let tone_mapping = ToneMapping::ReinhardExtended(4.0);
+        assert_eq!(tone_mapping.apply(4.0), 1.0);
+        assert_eq!(tone_mapping.apply(0.0), 0.0);
+        assert!(tone_mapping.apply(2.0) < 1.0);
+        for &c in &[4.5, 10.0, 1000.0] {
+            assert!(tone_mapping.apply(c) <= 1.0);
+        }
+        assert!(ToneMapping::Reinhard.apply(1000.0) < 1.0);
+    }
+
+    #[test]
+    fn reinhard_extended_invalid_white_point() {
+        for &white in &[0.0, -4.0, f32::NAN] {
+            let tone_mapping = ToneMapping::ReinhardExtended(white);
+            for &c in &[0.0, 0.5, 4.0, 1000.0] {
+                assert_eq!(tone_mapping.apply(c), ToneMapping::Reinhard.apply(c));
+            }
+        }
+    }
+
+    #[test]
+    fn heatmap_normalized_to_max() {
+        let buffer = heatmap_to_rgba8(&[0, 64, 128]);
+        assert_eq!(buffer, vec![0, 0, 0, 255, 128, 128, 128, 255, 255, 255, 255, 255]);
+        assert_eq!(heatmap_to_rgba8(&[0, 0]), vec![0, 0, 0, 255, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn color_heatmap_ramp() {
+        let buffer = heatmap_to_color_rgba8(&[0, 20, 50, 100, 90]);
+        assert_eq!(&buffer[0..4], &[0, 0, 0, 255]);
+        assert_eq!(&buffer[4..8], &[0, 0, 255, 255]);
+        assert_eq!(&buffer[8..12], &[0, 255, 128, 255]);
+        assert_eq!(&buffer[12..16], &[255, 0, 0, 255]);
+        assert_eq!(&buffer[16..20], &[255, 128, 0, 255]);
+        assert_eq!(heatmap_to_color_rgba8(&[0, 0]), vec![0, 0, 0, 255, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn replace_alpha() {
+        let mut buffer = vec![10, 20, 30, 255, 40, 50, 60, 255];
+        set_alpha(&mut buffer, &[0.0, 0.5]);
+        assert_eq!(buffer, vec![10, 20, 30, 0, 40, 50, 60, 128]);
+    }
+}
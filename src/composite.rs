@@ -0,0 +1,134 @@
+use super::vec3::Vec3;
+use super::math::lerp;
+
+/// How a layer is combined with the layers below it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlendMode {
+    /// Add the colors of the layer, for example, to put separately rendered light back together.
+    Add,
+    /// Multiply by the colors of the layer, for example, to darken the image by a shadow pass.
+    Multiply,
+    /// Put the layer over the layers below, letting them show through where it is (partially) transparent.
+    Over,
+}
+
+/// Linear (HDR) colors of a render, with optional alpha, to combine with other renders of the same size.
+#[derive(Debug, Copy, Clone)]
+pub struct Layer<'a> {
+    pub color: &'a [Vec3],
+    /// Opacity of each pixel, or `None` for a fully opaque layer. The colors must already be multiplied by it,
+    /// as they are in renders with transparent background.
+    pub alpha: Option<&'a [f32]>,
+    pub mode: BlendMode,
+    /// Factor scaling the colors of the layer, to rebalance it against the other layers.
+    pub weight: f32,
+}
+
+impl<'a> Layer<'a> {
+    /// Create new opaque layer with weight 1.0.
+    ///
+    /// # Arguments
+    ///
+    /// * `color` - Linear colors, one per pixel.
+    /// * `mode` - How to combine the layer with the layers below it.
+    pub fn new(color: &'a [Vec3], mode: BlendMode) -> Layer<'a> {
+        Layer { color, alpha: None, mode, weight: 1.0 }
+    }
+
+    /// Return the same layer with given alpha.
+    ///
+    /// # Arguments
+    ///
+    /// * `alpha` - Opacity of each pixel, for example, the coverage of a render.
+    pub fn with_alpha(self, alpha: &'a [f32]) -> Layer<'a> {
+        Layer { alpha: Some(alpha), ..self }
+    }
+
+    /// Return the same layer with given weight.
+    ///
+    /// # Arguments
+    ///
+    /// * `weight` - Factor scaling the colors of the layer.
+    pub fn with_weight(self, weight: f32) -> Layer<'a> {
+        Layer { weight, ..self }
+    }
+}
+
+/// Combine several layers into one buffer, in linear space, before tone mapping.
+///
+/// The first layer is the bottom one, and each layer is blended onto the result of the layers below it,
+/// starting from black. Alpha decides how much of a layer is multiplied in, or how much of the layers
+/// below shows through a layer put over them; it does not affect added layers, whose colors are already
+/// multiplied by it. Panics if the layers have different numbers of pixels.
+///
+/// # Arguments
+///
+/// * `layers` - Layers, from the bottom one to the top one.
+/// * `pixel_count` - Number of pixels of each layer.
+pub fn composite(layers: &[Layer], pixel_count: usize) -> Vec<Vec3> {
+    let mut output = vec![Vec3::new(0.0, 0.0, 0.0); pixel_count];
+    for layer in layers {
+        assert_eq!(layer.color.len(), pixel_count, "layer colors must have one value per pixel");
+        if let Some(alpha) = layer.alpha {
+            assert_eq!(alpha.len(), pixel_count, "layer alpha must have one value per pixel");
+        }
+        for (i, (dst, src)) in output.iter_mut().zip(layer.color.iter()).enumerate() {
+            let a = layer.alpha.map_or(1.0, |alpha| alpha[i]);
+            let src = layer.weight * src;
+            *dst = match layer.mode {
+                BlendMode::Add => &*dst + &src,
+                BlendMode::Multiply => Vec3::new(
+                    lerp(dst.x, dst.x * src.x, a),
+                    lerp(dst.y, dst.y * src.y, a),
+                    lerp(dst.z, dst.z * src.z, a),
+                ),
+                BlendMode::Over => &src + &((1.0 - a) * &*dst),
+            };
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_layers() {
+        let beauty = vec![Vec3::new(0.5, 0.25, 0.0), Vec3::new(1.0, 1.0, 1.0)];
+        let reflection = vec![Vec3::new(0.25, 0.25, 0.25), Vec3::new(2.0, 0.0, 0.0)];
+        let output = composite(&[Layer::new(&beauty, BlendMode::Add), Layer::new(&reflection, BlendMode::Add).with_weight(0.5)], 2);
+        assert_eq!(output[0].x, 0.625); assert_eq!(output[0].y, 0.375); assert_eq!(output[0].z, 0.125);
+        assert_eq!(output[1].x, 2.0); assert_eq!(output[1].y, 1.0); assert_eq!(output[1].z, 1.0);
+    }
+
+    #[test]
+    fn over_with_alpha() {
+        let background = vec![Vec3::new(1.0, 0.0, 0.0); 3];
+        // Premultiplied: the second pixel is half covered by a white object.
+        let foreground = vec![Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.5, 0.5, 0.5), Vec3::new(0.0, 0.0, 0.0)];
+        let alpha = vec![1.0, 0.5, 0.0];
+        let output = composite(&[Layer::new(&background, BlendMode::Over), Layer::new(&foreground, BlendMode::Over).with_alpha(&alpha)], 3);
+        assert_eq!(output[0].x, 0.0); assert_eq!(output[0].y, 0.0); assert_eq!(output[0].z, 1.0);
+        assert_eq!(output[1].x, 1.0); assert_eq!(output[1].y, 0.5); assert_eq!(output[1].z, 0.5);
+        assert_eq!(output[2].x, 1.0); assert_eq!(output[2].y, 0.0); assert_eq!(output[2].z, 0.0);
+    }
+
+    #[test]
+    fn multiply_with_alpha() {
+        let base = vec![Vec3::new(1.0, 0.5, 0.25)];
+        let shadow = vec![Vec3::new(0.5, 0.5, 0.5)];
+        let output = composite(&[Layer::new(&base, BlendMode::Add), Layer::new(&shadow, BlendMode::Multiply)], 1);
+        assert_eq!(output[0].x, 0.5); assert_eq!(output[0].y, 0.25); assert_eq!(output[0].z, 0.125);
+        let output = composite(&[Layer::new(&base, BlendMode::Add), Layer::new(&shadow, BlendMode::Multiply).with_alpha(&[0.5])], 1);
+        assert_eq!(output[0].x, 0.75); assert_eq!(output[0].y, 0.375); assert_eq!(output[0].z, 0.1875);
+    }
+
+    #[test]
+    #[should_panic(expected = "one value per pixel")]
+    fn mismatched_layers() {
+        let a = vec![Vec3::new(0.0, 0.0, 0.0); 4];
+        let b = vec![Vec3::new(0.0, 0.0, 0.0); 3];
+        composite(&[Layer::new(&a, BlendMode::Add), Layer::new(&b, BlendMode::Add)], 4);
+    }
+}
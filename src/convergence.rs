@@ -0,0 +1,154 @@
+use super::vec3::Vec3;
+use super::color::luminance;
+
+// Pixels darker than this are measured relative to this brightness instead,
+// so that the noise of nearly black pixels does not dominate the metric.
+const MIN_BRIGHTNESS: f64 = 1e-2;
+
+/// Running estimate of how much the average of progressive passes still changes from pass to pass.
+///
+/// Tracks the mean and variance of the brightness of each pixel over the passes, which gives
+/// the variance of the average of the passes, that is, of the accumulated image.
+pub struct Convergence {
+    sum: Vec<f64>,
+    sum_squared: Vec<f64>,
+    passes: u32,
+}
+
+impl Convergence {
+    /// Create new, empty estimate.
+    ///
+    /// # Arguments
+    ///
+    /// * `pixel_count` - Number of pixels of the image.
+    pub fn new(pixel_count: usize) -> Convergence {
+        Convergence { sum: vec![0.0; pixel_count], sum_squared: vec![0.0; pixel_count], passes: 0 }
+    }
+
+    /// Remove all the passes, keeping the number of pixels.
+    pub fn clear(&mut self) {
+        self.sum.fill(0.0);
+        self.sum_squared.fill(0.0);
+        self.passes = 0;
+    }
+
+    /// Add a single pass of the image.
+    ///
+    /// # Arguments
+    ///
+    /// * `color` - Linear (HDR) colors of the pass, one per pixel.
+    pub fn add_pass(&mut self, color: &[Vec3]) {
+        for (i, c) in color.iter().enumerate() {
+            let luminance = luminance(c) as f64;
+            self.sum[i] += luminance;
+            self.sum_squared[i] += luminance * luminance;
+        }
+        self.passes += 1;
+    }
+
+    /// Compute the mean relative variance of the accumulated image, that is, the variance of the average
+    /// brightness of each pixel divided by its squared average, averaged over all the pixels.
+    ///
+    /// Halves with every doubling of the number of passes. Returns `None` until at least two passes are added.
+    pub fn relative_variance(&self) -> Option<f32> {
+        if self.passes < 2 || self.sum.is_empty() {
+            return None;
+        }
+        let n = self.passes as f64;
+        let total: f64 = self.sum.iter().zip(self.sum_squared.iter())
+            .map(|(sum, sum_squared)| {
+                let mean = sum / n;
+                let variance = ((sum_squared - n * mean * mean) / (n - 1.0)).max(0.0);
+                let brightness = mean.max(MIN_BRIGHTNESS);
+                variance / n / (brightness * brightness)
+            })
+            .sum();
+        Some((total / self.sum.len() as f64) as f32)
+    }
+}
+
+/// Running mean and variance of a sequence of values, such as the brightness of the samples of a pixel,
+/// updated one value at a time using Welford's algorithm.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RunningStats {
+    count: u32,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    /// Add a value.
+    ///
+    /// # Arguments
+    ///
+    /// * `v` - The value.
+    pub fn add(&mut self, v: f32) {
+        self.count += 1;
+        let delta = v as f64 - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (v as f64 - self.mean);
+    }
+
+    /// Get number of values added so far.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Get mean of the values, or 0.0 if there are none.
+    pub fn mean(&self) -> f32 {
+        self.mean as f32
+    }
+
+    /// Get (sample) variance of the values, or 0.0 if there are fewer than two.
+    pub fn variance(&self) -> f32 {
+        if self.count < 2 { 0.0 } else { (self.m2 / (self.count - 1) as f64) as f32 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running_stats() {
+        let mut stats = RunningStats::default();
+        assert_eq!(stats.mean(), 0.0); assert_eq!(stats.variance(), 0.0);
+        stats.add(2.0);
+        assert_eq!(stats.mean(), 2.0); assert_eq!(stats.variance(), 0.0);
+        for &v in &[4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.add(v);
+        }
+        assert_eq!(stats.count(), 8);
+        assert!((stats.mean() - 5.0).abs() < 1e-6);
+        assert!((stats.variance() - 32.0 / 7.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn needs_two_passes() {
+        let mut convergence = Convergence::new(2);
+        assert!(convergence.relative_variance().is_none());
+        convergence.add_pass(&[Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, 0.0)]);
+        assert!(convergence.relative_variance().is_none());
+        convergence.add_pass(&[Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, 0.0)]);
+        assert!(convergence.relative_variance().unwrap().abs() < 1e-6);
+    }
+
+    #[test]
+    fn relative_variance_of_mean() {
+        let mut convergence = Convergence::new(2);
+        // Alternating between 1 and 3 (mean 2, sample variance 4/3), and a constant pixel.
+        for i in 0..4 {
+            let v = if i % 2 == 0 { 1.0 } else { 3.0 };
+            convergence.add_pass(&[Vec3::new(v, v, v), Vec3::new(0.5, 0.5, 0.5)]);
+        }
+        let expected = 0.5 * ((4.0 / 3.0) / 4.0 / (2.0 * 2.0));
+        assert!((convergence.relative_variance().unwrap() - expected).abs() < 1e-6);
+        // Twice as many passes of the same kind halve the variance, roughly.
+        for i in 0..4 {
+            let v = if i % 2 == 0 { 1.0 } else { 3.0 };
+            convergence.add_pass(&[Vec3::new(v, v, v), Vec3::new(0.5, 0.5, 0.5)]);
+        }
+        let halved = 0.5 * ((8.0 / 7.0) / 8.0 / (2.0 * 2.0));
+        assert!((convergence.relative_variance().unwrap() - halved).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,150 @@
+use super::vec3::Vec3;
+
+/// Image accumulating samples of linear (HDR) colors, with the sum of the colors and the number
+/// of samples of each pixel, which resolves to their average.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    sum: Vec<Vec3>,
+    count: Vec<u32>,
+}
+
+impl Framebuffer {
+    /// Create new framebuffer with no samples.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - Image width.
+    /// * `height` - Image height.
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        let size = (width * height) as usize;
+        Framebuffer { width, height, sum: vec![Vec3::new(0.0, 0.0, 0.0); size], count: vec![0; size] }
+    }
+
+    /// Add a single sample to a pixel.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Horizontal pixel coordinate.
+    /// * `y` - Vertical pixel coordinate.
+    /// * `color` - Linear color of the sample.
+    pub fn add_sample(&mut self, x: u32, y: u32, color: &Vec3) {
+        let i = (y * self.width + x) as usize;
+        self.sum[i] += color;
+        self.count[i] += 1;
+    }
+
+    /// Get number of samples added to a pixel.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Horizontal pixel coordinate.
+    /// * `y` - Vertical pixel coordinate.
+    pub fn count(&self, x: u32, y: u32) -> u32 {
+        self.count[(y * self.width + x) as usize]
+    }
+
+    /// Get number of samples added to each pixel, row by row.
+    pub fn counts(&self) -> &[u32] {
+        &self.count
+    }
+
+    /// Compute the average color of the samples of each pixel, row by row. Pixels without any samples are black.
+    pub fn resolve(&self) -> Vec<Vec3> {
+        let mut image = vec![Vec3::new(0.0, 0.0, 0.0); self.sum.len()];
+        self.resolve_into(&mut image);
+        image
+    }
+
+    /// Compute the average color of the samples of each pixel (see `resolve`) into a buffer of the caller.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - Where to write the colors to, one per pixel.
+    pub fn resolve_into(&self, image: &mut [Vec3]) {
+        for ((c, sum), &count) in image.iter_mut().zip(self.sum.iter()).zip(self.count.iter()) {
+            *c = if count > 0 { (1.0 / count as f32) * sum } else { Vec3::new(0.0, 0.0, 0.0) };
+        }
+    }
+
+    /// Remove all the samples, keeping the size of the framebuffer.
+    pub fn clear(&mut self) {
+        self.sum.fill(Vec3::new(0.0, 0.0, 0.0));
+        self.count.fill(0);
+    }
+
+    /// Add the average color of several samples of a pixel at once, the same as adding each of the samples.
+    ///
+    /// # Arguments
+    ///
+    /// * `x` - Horizontal pixel coordinate.
+    /// * `y` - Vertical pixel coordinate.
+    /// * `color` - Average linear color of the samples.
+    /// * `count` - Number of the samples.
+    pub fn add_samples(&mut self, x: u32, y: u32, color: &Vec3, count: u32) {
+        let i = (y * self.width + x) as usize;
+        self.sum[i] += &(count as f32 * color);
+        self.count[i] += count;
+    }
+
+    /// Add all the samples of another framebuffer covering a region of this one, for example,
+    /// of a tile rendered by another thread, which owns its region of the image while rendering it.
+    ///
+    /// # Arguments
+    ///
+    /// * `min_x` - Column of this framebuffer where the region starts.
+    /// * `min_y` - Row of this framebuffer where the region starts.
+    /// * `region` - Samples of the region, which has to fit in this framebuffer.
+    pub fn add_region(&mut self, min_x: u32, min_y: u32, region: &Framebuffer) {
+        let width = region.width as usize;
+        for y in 0..region.height {
+            let start = ((min_y + y) * self.width + min_x) as usize;
+            let row = (y * region.width) as usize..((y + 1) * region.width) as usize;
+            for (sum, added) in self.sum[start..start + width].iter_mut().zip(&region.sum[row.clone()]) {
+                *sum += added;
+            }
+            for (count, added) in self.count[start..start + width].iter_mut().zip(&region.count[row]) {
+                *count += added;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn average_samples() {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        framebuffer.add_sample(0, 0, &Vec3::new(1.0, 2.0, 3.0));
+        framebuffer.add_sample(0, 0, &Vec3::new(3.0, 0.0, 1.0));
+        framebuffer.add_sample(2, 1, &Vec3::new(0.5, 0.5, 0.5));
+        assert_eq!(framebuffer.count(0, 0), 2); assert_eq!(framebuffer.count(2, 1), 1); assert_eq!(framebuffer.count(1, 0), 0);
+        let image = framebuffer.resolve();
+        assert_eq!(image.len(), 6);
+        assert_eq!(image[0].x, 2.0); assert_eq!(image[0].y, 1.0); assert_eq!(image[0].z, 2.0);
+        assert_eq!(image[5].x, 0.5); assert_eq!(image[5].y, 0.5); assert_eq!(image[5].z, 0.5);
+        // Pixels without samples stay black.
+        assert_eq!(image[1].x, 0.0); assert_eq!(image[1].y, 0.0); assert_eq!(image[1].z, 0.0);
+        assert_eq!(framebuffer.counts(), &[2, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn add_regions() {
+        let mut framebuffer = Framebuffer::new(4, 3);
+        let mut region = Framebuffer::new(2, 2);
+        region.add_samples(0, 0, &Vec3::new(1.0, 2.0, 3.0), 4);
+        region.add_sample(1, 1, &Vec3::new(0.5, 0.5, 0.5));
+        framebuffer.add_region(2, 1, &region);
+        framebuffer.add_region(0, 0, &region);
+        assert_eq!(framebuffer.counts(), &[4, 0, 0, 0, 0, 1, 4, 0, 0, 0, 0, 1]);
+        let image = framebuffer.resolve();
+        assert_eq!(image[6].x, 1.0); assert_eq!(image[6].y, 2.0); assert_eq!(image[6].z, 3.0);
+        assert_eq!(image[11].x, 0.5);
+        // Averages added at once weigh as much as their samples.
+        framebuffer.add_sample(2, 1, &Vec3::new(6.0, 2.0, 3.0));
+        assert_eq!(framebuffer.resolve()[6].x, 2.0);
+    }
+}
@@ -0,0 +1,68 @@
+use super::bbox::BBox;
+use super::vec3::{ Vec3, dot };
+
+/// Convex region bounded by planes, such as the part of the scene a camera can see.
+///
+/// Each plane is given by its normal `n` and offset `d`, with points `p` where `dot(n, p) + d >= 0`
+/// being on the inner side.
+#[derive(Debug, Clone)]
+pub struct Frustum {
+    pub planes: Vec<(Vec3, f32)>,
+}
+
+impl Frustum {
+    /// Create new frustum.
+    ///
+    /// # Arguments
+    ///
+    /// * `planes` - Normals and offsets of the bounding planes, with the normals pointing inwards.
+    pub fn new(planes: Vec<(Vec3, f32)>) -> Frustum {
+        Frustum { planes }
+    }
+
+    /// Check whether a bounding box may be (at least partially) inside the frustum.
+    ///
+    /// Only returns false for boxes entirely on the outer side of one of the planes, so some boxes
+    /// near the corners of the frustum are reported as intersecting even if they are outside.
+    /// Boxes without finite bounds are always reported as intersecting, and empty ones never.
+    ///
+    /// # Arguments
+    ///
+    /// * `bbox` - Bounding box to test.
+    pub fn intersects_bbox(&self, bbox: &BBox) -> bool {
+        if bbox.is_empty() {
+            return false;
+        }
+        if !bbox.is_finite() {
+            return true;
+        }
+        self.planes.iter().all(|(n, d)| {
+            // Corner of the box furthest along the normal.
+            let p = Vec3::new(
+                if n.x >= 0.0 { bbox.max.x } else { bbox.min.x },
+                if n.y >= 0.0 { bbox.max.y } else { bbox.min.y },
+                if n.z >= 0.0 { bbox.max.z } else { bbox.min.z },
+            );
+            dot(n, &p) + d >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bbox_against_half_spaces() {
+        // Slab of points with 0 <= x <= 1.
+        let frustum = Frustum::new(vec![(Vec3::new(1.0, 0.0, 0.0), 0.0), (Vec3::new(-1.0, 0.0, 0.0), 1.0)]);
+        let bbox = |x0: f32, x1: f32| BBox::new_from_points(&Vec3::new(x0, -5.0, -5.0), &Vec3::new(x1, 5.0, 5.0));
+        assert!(frustum.intersects_bbox(&bbox(0.25, 0.75)));
+        assert!(frustum.intersects_bbox(&bbox(-1.0, 0.25)));
+        assert!(frustum.intersects_bbox(&bbox(-1.0, 2.0)));
+        assert!(!frustum.intersects_bbox(&bbox(-2.0, -0.5)));
+        assert!(!frustum.intersects_bbox(&bbox(1.5, 2.0)));
+        assert!(frustum.intersects_bbox(&BBox::infinite()));
+        assert!(!frustum.intersects_bbox(&BBox::new()));
+    }
+}
@@ -0,0 +1,318 @@
+use super::bbox::BBox;
+use super::ray::Ray;
+use super::vec3::{ Vec3, distance };
+use super::thread_count;
+use std::thread;
+
+// Target average number of objects per cell.
+const OBJECTS_PER_CELL: f32 = 3.0;
+// Max number of cells along each axis.
+const MAX_RESOLUTION: u32 = 64;
+// Objects larger than this many times the median size are kept out of the cells.
+const MAX_RELATIVE_SIZE: f32 = 8.0;
+
+/// Uniform grid of cells, each listing the objects whose bounding boxes overlap it.
+///
+/// Works best for objects of similar sizes. Objects much larger than the rest (such as a ground plane
+/// made of a huge sphere) would overlap most of the cells, so they are kept in a separate list instead,
+/// together with objects without finite bounds. Objects with empty bounding boxes are left out completely.
+/// Objects are identified by their index in the list of bounding boxes the grid was built from.
+#[derive(Debug, Clone)]
+pub struct Grid {
+    bbox: BBox,
+    large: Vec<usize>,
+    resolution: [u32; 3],
+    cell_size: [f32; 3],
+    cells: Vec<Vec<usize>>,
+}
+
+impl Grid {
+    /// Create new grid.
+    ///
+    /// # Arguments
+    ///
+    /// * `bboxes` - Bounding boxes of the objects.
+    /// * `num_threads` - Number of threads filling the cells, where 0 means one thread per available core.
+    pub fn new(bboxes: &[BBox], num_threads: u32) -> Grid {
+        let size = |b: &BBox| distance(&b.min, &b.max);
+        let finite: Vec<usize> = (0..bboxes.len()).filter(|i| bboxes[*i].is_finite()).collect();
+        let mut sizes: Vec<f32> = finite.iter().map(|i| size(&bboxes[*i])).collect();
+        sizes.sort_by(f32::total_cmp);
+        let max_size = sizes.get(sizes.len() / 2).map_or(f32::INFINITY, |median| MAX_RELATIVE_SIZE * median);
+        let (mut large, small): (Vec<usize>, Vec<usize>) = finite.into_iter().partition(|i| size(&bboxes[*i]) > max_size);
+        // Objects without finite bounds would overlap all the cells, or could not be placed at all.
+        large.extend((0..bboxes.len()).filter(|i| !bboxes[*i].is_empty() && !bboxes[*i].is_finite()));
+        large.sort_unstable();
+        let bbox = if small.is_empty() {
+            BBox::new_from_point(Vec3::new(0.0, 0.0, 0.0))
+        } else {
+            small.iter().fold(BBox::new(), |acc, i| &acc + &bboxes[*i])
+        };
+        let extent = [bbox.max.x - bbox.min.x, bbox.max.y - bbox.min.y, bbox.max.z - bbox.min.z];
+        // Choose cells as close to cubes as possible, with the target number of objects per cell on average.
+        let volume = extent.iter().map(|e| e.max(1e-3)).product::<f32>();
+        let cells_per_unit = (small.len() as f32 / (OBJECTS_PER_CELL * volume)).cbrt();
+        let mut resolution = [1; 3];
+        let mut cell_size = [0.0; 3];
+        for axis in 0..3 {
+            resolution[axis] = ((extent[axis] * cells_per_unit).round() as u32).clamp(1, MAX_RESOLUTION);
+            cell_size[axis] = extent[axis] / resolution[axis] as f32;
+        }
+        let mut grid = Grid {
+            bbox,
+            large,
+            resolution,
+            cell_size,
+            cells: vec![Vec::new(); (resolution[0] * resolution[1] * resolution[2]) as usize],
+        };
+        grid.fill_cells(bboxes, &small, thread_count(num_threads));
+        grid
+    }
+
+    // Add the objects with given ids to the cells they overlap, in the order of the ids. With several threads,
+    // each fills a slab of the cells along the Z axis, so that the cells end up the same as with one thread.
+    // A single thread fills all the cells itself.
+    fn fill_cells(&mut self, bboxes: &[BBox], ids: &[usize], threads: u32) {
+        let ranges: Vec<(usize, [u32; 3], [u32; 3])> = ids.iter()
+            .map(|&id| (id, self.cell_coords(&bboxes[id].min), self.cell_coords(&bboxes[id].max)))
+            .collect();
+        let [rx, ry, rz] = self.resolution;
+        let slab_depth = rz.div_ceil(threads.max(1));
+        let mut cells = std::mem::take(&mut self.cells);
+        if slab_depth >= rz {
+            self.fill_slab(&ranges, 0, rz - 1, &mut cells);
+        } else {
+            let grid = &*self;
+            thread::scope(|scope| {
+                for (slab, slab_cells) in cells.chunks_mut((slab_depth * rx * ry) as usize).enumerate() {
+                    let ranges = &ranges;
+                    let first_z = slab as u32 * slab_depth;
+                    scope.spawn(move || grid.fill_slab(ranges, first_z, first_z + slab_depth - 1, slab_cells));
+                }
+            });
+        }
+        self.cells = cells;
+    }
+
+    // Add the objects to the cells of a slab from `first_z` to `last_z`, inclusive, given the ranges of cells
+    // each object overlaps. The cells of the slab are the part of all the cells starting at `first_z`.
+    fn fill_slab(&self, ranges: &[(usize, [u32; 3], [u32; 3])], first_z: u32, last_z: u32, slab_cells: &mut [Vec<usize>]) {
+        let offset = self.cell_index([0, 0, first_z]);
+        for &(id, min, max) in ranges.iter().filter(|(_, min, max)| min[2] <= last_z && max[2] >= first_z) {
+            for z in min[2].max(first_z)..=max[2].min(last_z) {
+                for y in min[1]..=max[1] {
+                    for x in min[0]..=max[0] {
+                        slab_cells[self.cell_index([x, y, z]) - offset].push(id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get the objects kept out of the cells because of their size,
+    /// which have to be tested for every ray in addition to the ones in the cells.
+    pub fn large_objects(&self) -> &[usize] {
+        &self.large
+    }
+
+    // Find the cell containing given point, clamped to the grid.
+    fn cell_coords(&self, p: &Vec3) -> [u32; 3] {
+        let p = [p.x - self.bbox.min.x, p.y - self.bbox.min.y, p.z - self.bbox.min.z];
+        let mut coords = [0; 3];
+        for axis in 0..3 {
+            let c = if self.cell_size[axis] > 0.0 { (p[axis] / self.cell_size[axis]).floor() } else { 0.0 };
+            coords[axis] = (c.max(0.0) as u32).min(self.resolution[axis] - 1);
+        }
+        coords
+    }
+
+    fn cell_index(&self, coords: [u32; 3]) -> usize {
+        ((coords[2] * self.resolution[1] + coords[1]) * self.resolution[0] + coords[0]) as usize
+    }
+
+    /// Visit the cells pierced by a ray, in the order along the ray, using a 3D-DDA.
+    ///
+    /// An object overlapping several cells is visited once per cell.
+    ///
+    /// # Arguments
+    ///
+    /// * `ray` - Ray to traverse the grid with.
+    /// * `max_t` - Ray parameter beyond which cells are not visited.
+    /// * `visit` - Called with the objects of each cell and the ray parameter where the ray leaves the cell.
+    ///   Returns true to stop the traversal.
+    pub fn traverse<F: FnMut(&[usize], f32) -> bool>(&self, ray: &Ray, max_t: f32, mut visit: F) {
+        let o = [ray.o.x, ray.o.y, ray.o.z];
+        let d = [ray.d.x, ray.d.y, ray.d.z];
+        let min = [self.bbox.min.x, self.bbox.min.y, self.bbox.min.z];
+        let max = [self.bbox.max.x, self.bbox.max.y, self.bbox.max.z];
+
+        // Clip the ray to the bounds of the grid.
+        let (mut t_enter, mut t_leave) = (0.0f32, max_t);
+        for axis in 0..3 {
+            if d[axis] == 0.0 {
+                if o[axis] < min[axis] || o[axis] > max[axis] {
+                    return;
+                }
+                continue;
+            }
+            let t0 = (min[axis] - o[axis]) / d[axis];
+            let t1 = (max[axis] - o[axis]) / d[axis];
+            t_enter = t_enter.max(t0.min(t1));
+            t_leave = t_leave.min(t0.max(t1));
+        }
+        if t_enter > t_leave {
+            return;
+        }
+
+        let mut cell = self.cell_coords(&ray.point_at(t_enter));
+        let mut step = [0i64; 3];
+        let mut t_next = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        for axis in 0..3 {
+            if d[axis] > 0.0 {
+                step[axis] = 1;
+                t_next[axis] = (min[axis] + (cell[axis] + 1) as f32 * self.cell_size[axis] - o[axis]) / d[axis];
+                t_delta[axis] = self.cell_size[axis] / d[axis];
+            } else if d[axis] < 0.0 {
+                step[axis] = -1;
+                t_next[axis] = (min[axis] + cell[axis] as f32 * self.cell_size[axis] - o[axis]) / d[axis];
+                t_delta[axis] = -self.cell_size[axis] / d[axis];
+            }
+        }
+
+        loop {
+            let axis = if t_next[0] < t_next[1] {
+                if t_next[0] < t_next[2] { 0 } else { 2 }
+            } else if t_next[1] < t_next[2] { 1 } else { 2 };
+            let t_exit = t_next[axis].min(t_leave);
+            if visit(&self.cells[self.cell_index(cell)], t_exit) || t_exit >= t_leave {
+                return;
+            }
+            let next = cell[axis] as i64 + step[axis];
+            if next < 0 || next >= self.resolution[axis] as i64 {
+                return;
+            }
+            cell[axis] = next as u32;
+            t_next[axis] += t_delta[axis];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_boxes() -> Vec<BBox> {
+        // Row of 4 unit boxes along the X axis, with gaps between them.
+        (0..4).map(|i| {
+            let x = 2.0 * i as f32;
+            BBox::new_from_points(&Vec3::new(x, 0.0, 0.0), &Vec3::new(x + 1.0, 1.0, 1.0))
+        }).collect()
+    }
+
+    #[test]
+    fn objects_in_cells() {
+        let grid = Grid::new(&unit_boxes(), 1);
+        let resolution = grid.resolution;
+        assert!(resolution[0] > 1);
+        assert_eq!(resolution[1], 1); assert_eq!(resolution[2], 1);
+        // Every object is in at least one cell, and the first cell only has the first object.
+        for id in 0..4 {
+            assert!(grid.cells.iter().any(|c| c.contains(&id)));
+        }
+        assert_eq!(grid.cells[0], vec![0]);
+    }
+
+    #[test]
+    fn fill_cells_in_parallel() {
+        // Overlapping boxes of varied sizes, filled into the same cells by any number of threads.
+        let bboxes: Vec<BBox> = (0..500).map(|i| {
+            let p = Vec3::new((i * 7 % 23) as f32, (i * 11 % 17) as f32, (i * 13 % 29) as f32);
+            BBox::new_from_points(&p, &(&p + &Vec3::new(1.0 + (i % 3) as f32, 1.0, 1.0 + (i % 5) as f32)))
+        }).collect();
+        let ids: Vec<usize> = (0..bboxes.len()).collect();
+        let serial = Grid::new(&bboxes, 1);
+        assert!(serial.resolution[2] > 4);
+        for threads in [2, 3, 64] {
+            let mut grid = serial.clone();
+            grid.cells.iter_mut().for_each(Vec::clear);
+            grid.fill_cells(&bboxes, &ids, threads);
+            assert_eq!(grid.cells, serial.cells);
+        }
+        assert_eq!(Grid::new(&bboxes, 4).cells, serial.cells);
+    }
+
+    #[test]
+    fn large_objects_outside_cells() {
+        let mut bboxes = unit_boxes();
+        bboxes.push(BBox::new_from_points(&Vec3::new(-100.0, -200.0, -100.0), &Vec3::new(100.0, 0.0, 100.0)));
+        let grid = Grid::new(&bboxes, 1);
+        assert_eq!(grid.large_objects(), &[4]);
+        assert!(grid.cells.iter().all(|c| !c.contains(&4)));
+        // The grid only spans the small objects.
+        assert_eq!(grid.bbox.min.y, 0.0); assert_eq!(grid.bbox.max.x, 7.0);
+    }
+
+    #[test]
+    fn infinite_and_empty_objects() {
+        let mut bboxes = unit_boxes();
+        bboxes.insert(1, BBox::infinite());
+        bboxes.insert(3, BBox::infinite());
+        bboxes.push(BBox::new());
+        let grid = Grid::new(&bboxes, 1);
+        // Infinite boxes do not break the grid, and go to the objects tested for every ray...
+        assert_eq!(grid.large_objects(), &[1, 3]);
+        assert_eq!(grid.bbox.min.x, 0.0); assert_eq!(grid.bbox.max.x, 7.0);
+        // ...while empty ones cannot be hit at all.
+        assert!(grid.cells.iter().all(|c| !c.contains(&1) && !c.contains(&3) && !c.contains(&6)));
+        for id in &[0, 2, 4, 5] {
+            assert!(grid.cells.iter().any(|c| c.contains(id)));
+        }
+        // Only infinite objects.
+        let grid = Grid::new(&[BBox::infinite()], 1);
+        assert_eq!(grid.large_objects(), &[0]);
+    }
+
+    #[test]
+    fn traverse_in_order() {
+        let grid = Grid::new(&unit_boxes(), 1);
+        let mut visited = Vec::new();
+        let mut last_t = 0.0;
+        grid.traverse(&Ray::new(Vec3::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0)), f32::MAX, |objects, t| {
+            assert!(t >= last_t);
+            last_t = t;
+            for id in objects {
+                if !visited.contains(id) {
+                    visited.push(*id);
+                }
+            }
+            false
+        });
+        assert_eq!(visited, vec![0, 1, 2, 3]);
+        // Same in the opposite direction.
+        visited.clear();
+        grid.traverse(&Ray::new(Vec3::new(10.0, 0.5, 0.5), Vec3::new(-1.0, 0.0, 0.0)), f32::MAX, |objects, _| {
+            for id in objects {
+                if !visited.contains(id) {
+                    visited.push(*id);
+                }
+            }
+            false
+        });
+        assert_eq!(visited, vec![3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn stop_traversal() {
+        let grid = Grid::new(&unit_boxes(), 1);
+        let mut count = 0;
+        grid.traverse(&Ray::new(Vec3::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0)), f32::MAX, |_, _| {
+            count += 1;
+            true
+        });
+        assert_eq!(count, 1);
+        // Rays limited to before the grid, or missing it, visit no cells.
+        grid.traverse(&Ray::new(Vec3::new(-1.0, 0.5, 0.5), Vec3::new(1.0, 0.0, 0.0)), 0.5, |_, _| panic!("visited a cell"));
+        grid.traverse(&Ray::new(Vec3::new(-1.0, 2.0, 0.5), Vec3::new(1.0, 0.0, 0.0)), f32::MAX, |_, _| panic!("visited a cell"));
+    }
+}
@@ -0,0 +1,281 @@
+use std::fs::File;
+use std::io::{ BufWriter, Error, ErrorKind, Write };
+use std::path::Path;
+use super::vec3::Vec3;
+
+/// Image with 8-bit RGBA pixels, stored row by row starting at the top-left corner.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Create new image from raw RGBA pixels.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - Image width.
+    /// * `height` - Image height.
+    /// * `pixels` - RGBA values, 4 bytes per pixel.
+    pub fn new(width: u32, height: u32, pixels: Vec<u8>) -> Image {
+        debug_assert_eq!(pixels.len(), (width * height * 4) as usize);
+        Image { width, height, pixels }
+    }
+
+    /// Load image from a PNG file, converting it to 8-bit RGBA.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the PNG file.
+    pub fn load_png(path: &Path) -> Result<Image, png::DecodingError> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut buff = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buff)?;
+        let pixels = match info.color_type {
+            png::ColorType::RGBA => buff,
+            png::ColorType::RGB => buff.chunks(3).flat_map(|c| vec![c[0], c[1], c[2], 255]).collect(),
+            png::ColorType::GrayscaleAlpha => buff.chunks(2).flat_map(|c| vec![c[0], c[0], c[0], c[1]]).collect(),
+            png::ColorType::Grayscale => buff.iter().flat_map(|&c| vec![c, c, c, 255]).collect(),
+            png::ColorType::Indexed => return Err(png::DecodingError::Other("unexpanded indexed image".into())),
+        };
+        Ok(Image::new(info.width, info.height, pixels))
+    }
+
+    /// Save image to a PNG file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the PNG file.
+    pub fn save_png(&self, path: &Path) -> Result<(), png::EncodingError> {
+        self.write_png(BufWriter::new(File::create(path)?))
+    }
+
+    /// Encode image as PNG into a writer, for example, the standard output.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Destination of the PNG data.
+    pub fn write_png(&self, writer: impl Write) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut png_writer = encoder.write_header()?;
+        png_writer.write_image_data(&self.pixels)
+    }
+
+    /// Get RGBA values of specific pixel.
+    #[inline(always)]
+    pub fn get_pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * self.width + x) * 4) as usize;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]]
+    }
+
+    /// Get linear color of specific pixel.
+    ///
+    /// The stored values are decoded with gamma 2.0, the inverse of the encoding used for rendered output.
+    #[inline(always)]
+    pub fn get_color(&self, x: u32, y: u32) -> Vec3 {
+        let i = ((y * self.width + x) * 4) as usize;
+        let r = self.pixels[i] as f32 / 255.0;
+        let g = self.pixels[i + 1] as f32 / 255.0;
+        let b = self.pixels[i + 2] as f32 / 255.0;
+        Vec3::new(r * r, g * g, b * b)
+    }
+
+    /// Sample the image with bilinear filtering.
+    ///
+    /// # Arguments
+    ///
+    /// * `u` - Horizontal coordinate, from 0.0 (left edge) to 1.0 (right edge).
+    /// * `v` - Vertical coordinate, from 0.0 (top edge) to 1.0 (bottom edge).
+    pub fn sample_bilinear(&self, u: f32, v: f32) -> Vec3 {
+        let x = (u * self.width as f32 - 0.5).max(0.0).min((self.width - 1) as f32);
+        let y = (v * self.height as f32 - 0.5).max(0.0).min((self.height - 1) as f32);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+        let c00 = self.get_color(x0, y0);
+        let c10 = self.get_color(x1, y0);
+        let c01 = self.get_color(x0, y1);
+        let c11 = self.get_color(x1, y1);
+        let mut top = (1.0 - tx) * &c00;
+        top += &(tx * &c10);
+        let mut bottom = (1.0 - tx) * &c01;
+        bottom += &(tx * &c11);
+        let mut color = (1.0 - ty) * &top;
+        color += &(ty * &bottom);
+        color
+    }
+}
+
+/// Number of bits per channel of saved images.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+/// Save 16-bit RGBA pixels to a PNG file.
+///
+/// # Arguments
+///
+/// * `path` - Path to the PNG file.
+/// * `pixels` - RGBA values, 4 per pixel, stored row by row starting at the top-left corner.
+/// * `width` - Image width.
+/// * `height` - Image height.
+pub fn save_png16(path: &Path, pixels: &[u16], width: u32, height: u32) -> Result<(), png::EncodingError> {
+    write_png16(BufWriter::new(File::create(path)?), pixels, width, height)
+}
+
+/// Encode 16-bit RGBA pixels as PNG into a writer, the same way as `save_png16`.
+///
+/// # Arguments
+///
+/// * `writer` - Destination of the PNG data.
+/// * `pixels` - RGBA values, 4 per pixel, stored row by row starting at the top-left corner.
+/// * `width` - Image width.
+/// * `height` - Image height.
+pub fn write_png16(writer: impl Write, pixels: &[u16], width: u32, height: u32) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    let mut png_writer = encoder.write_header()?;
+    // PNG stores 16-bit values in big-endian order.
+    let bytes: Vec<u8> = pixels.iter().flat_map(|v| v.to_be_bytes()).collect();
+    png_writer.write_image_data(&bytes)
+}
+
+/// Save linear colors to a PFM (portable float map) file, without any tone mapping or gamma,
+/// so that the render can be processed by external tools.
+///
+/// Fails with `InvalidInput` for an empty image size, and for a buffer not matching the image size.
+///
+/// # Arguments
+///
+/// * `path` - Path to the PFM file.
+/// * `buffer` - Linear colors, one per pixel, stored row by row starting at the top-left corner.
+/// * `width` - Image width.
+/// * `height` - Image height.
+pub fn save_pfm(path: &Path, buffer: &[Vec3], width: u32, height: u32) -> std::io::Result<()> {
+    if width == 0 || height == 0 || buffer.len() != width as usize * height as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("cannot save {} colors as a {}x{} float map", buffer.len(), width, height)));
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    // Negative scale means little-endian values.
+    write!(writer, "PF\n{} {}\n-1.0\n", width, height)?;
+    // PFM stores rows starting at the bottom.
+    for row in buffer.chunks(width as usize).rev() {
+        for c in row {
+            writer.write_all(&c.x.to_le_bytes())?;
+            writer.write_all(&c.y.to_le_bytes())?;
+            writer.write_all(&c.z.to_le_bytes())?;
+        }
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker_image() -> Image {
+        Image::new(2, 2, vec![
+            0, 0, 0, 255,       255, 255, 255, 255,
+            255, 255, 255, 255, 0, 0, 0, 255,
+        ])
+    }
+
+    #[test]
+    fn get_pixel_color() {
+        let image = checker_image();
+        let black = image.get_color(0, 0);
+        let white = image.get_color(1, 0);
+        assert_eq!(black.x, 0.0); assert_eq!(black.y, 0.0); assert_eq!(black.z, 0.0);
+        assert_eq!(white.x, 1.0); assert_eq!(white.y, 1.0); assert_eq!(white.z, 1.0);
+    }
+
+    #[test]
+    fn sample_pixel_centers() {
+        let image = checker_image();
+        let c = image.sample_bilinear(0.75, 0.25);
+        assert_eq!(c.x, 1.0); assert_eq!(c.y, 1.0); assert_eq!(c.z, 1.0);
+        let c = image.sample_bilinear(0.25, 0.25);
+        assert_eq!(c.x, 0.0); assert_eq!(c.y, 0.0); assert_eq!(c.z, 0.0);
+    }
+
+    #[test]
+    fn sample_between_pixels() {
+        let image = checker_image();
+        let c = image.sample_bilinear(0.5, 0.25);
+        assert_eq!(c.x, 0.5); assert_eq!(c.y, 0.5); assert_eq!(c.z, 0.5);
+        let c = image.sample_bilinear(0.5, 0.5);
+        assert_eq!(c.x, 0.5); assert_eq!(c.y, 0.5); assert_eq!(c.z, 0.5);
+    }
+
+    #[test]
+    fn sample_clamps_to_edges() {
+        let image = checker_image();
+        let c = image.sample_bilinear(0.0, 0.0);
+        assert_eq!(c.x, 0.0); assert_eq!(c.y, 0.0); assert_eq!(c.z, 0.0);
+        let c = image.sample_bilinear(1.0, 0.0);
+        assert_eq!(c.x, 1.0); assert_eq!(c.y, 1.0); assert_eq!(c.z, 1.0);
+    }
+
+    #[test]
+    fn save_and_load_png() {
+        let image = Image::new(2, 1, vec![10, 20, 30, 255, 40, 50, 60, 128]);
+        assert_eq!(image.get_pixel(1, 0), [40, 50, 60, 128]);
+        let path = std::env::temp_dir().join(format!("rpt-image-test-{}.png", std::process::id()));
+        image.save_png(&path).unwrap();
+        let loaded = Image::load_png(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((loaded.width, loaded.height), (2, 1));
+        assert_eq!(loaded.pixels, image.pixels);
+    }
+
+    #[test]
+    fn save_16_bit_png() {
+        let pixels = vec![0x0a0b, 0x1415, 0x1e1f, 0xffff, 0x2829, 0x3233, 0x3c3d, 0x8000];
+        let path = std::env::temp_dir().join(format!("rpt-image16-test-{}.png", std::process::id()));
+        save_png16(&path, &pixels, 2, 1).unwrap();
+        let mut decoder = png::Decoder::new(File::open(&path).unwrap());
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let (info, mut reader) = decoder.read_info().unwrap();
+        let mut buff = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buff).unwrap();
+        // Loading as 8-bit keeps the more significant bytes.
+        let loaded = Image::load_png(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(info.bit_depth, png::BitDepth::Sixteen);
+        assert_eq!(buff, vec![0x0a, 0x0b, 0x14, 0x15, 0x1e, 0x1f, 0xff, 0xff, 0x28, 0x29, 0x32, 0x33, 0x3c, 0x3d, 0x80, 0x00]);
+        assert_eq!(loaded.pixels, vec![0x0a, 0x14, 0x1e, 0xff, 0x28, 0x32, 0x3c, 0x80]);
+    }
+
+    #[test]
+    fn save_float_map() {
+        let buffer = vec![Vec3::new(1.0, 2.0, 3.0), Vec3::new(0.5, 0.25, 0.125), Vec3::new(10.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 0.0)];
+        let path = std::env::temp_dir().join(format!("rpt-pfm-test-{}.pfm", std::process::id()));
+        save_pfm(&path, &buffer, 2, 2).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 4 * 3 * 4);
+        // The first stored pixel is the bottom-left one.
+        let value = |i: usize| f32::from_le_bytes([bytes[header.len() + 4 * i], bytes[header.len() + 4 * i + 1], bytes[header.len() + 4 * i + 2], bytes[header.len() + 4 * i + 3]]);
+        assert_eq!(value(0), 10.0); assert_eq!(value(2), -1.0);
+        assert_eq!(value(6), 1.0); assert_eq!(value(11), 0.125);
+    }
+
+    #[test]
+    fn save_empty_float_map() {
+        let path = std::env::temp_dir().join(format!("rpt-empty-pfm-test-{}.pfm", std::process::id()));
+        assert_eq!(save_pfm(&path, &[], 0, 2).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(save_pfm(&path, &[Vec3::new(0.0, 0.0, 0.0)], 2, 2).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,135 @@
+use super::bbox::BBox;
+use super::ray::Ray;
+use super::scene::{ Hit, Hitable, Sphere, Visibility };
+use super::vec3::normalize;
+use super::xform::Transform;
+
+/// Object placed in the scene using a transform, for example,
+/// a unit sphere with a non-uniform scale to get an ellipsoid.
+#[derive(Clone)]
+pub struct Instance<T: Hitable> {
+    object: T,
+    object_to_world: Transform,
+    world_to_object: Transform,
+}
+
+impl<T: Hitable> Instance<T> {
+    /// Create new instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `object` - Object in its own coordinate system.
+    /// * `transform` - Transform from the object's coordinate system to the scene.
+    pub fn new(object: T, transform: Transform) -> Instance<T> {
+        Instance { object, object_to_world: transform, world_to_object: transform.invert() }
+    }
+
+    /// Get the object in its own coordinate system.
+    pub fn object(&self) -> &T {
+        &self.object
+    }
+
+    /// Get the transform from the object's coordinate system to the scene.
+    pub fn transform(&self) -> &Transform {
+        &self.object_to_world
+    }
+}
+
+impl Instance<Sphere> {
+    /// Compute the (exact) bounding box of the instance in the scene.
+    pub fn bbox(&self) -> BBox {
+        self.object_to_world.apply_to_sphere_bbox(&self.object.center(), self.object.radius())
+    }
+
+    /// Set which kinds of rays see the instance, and return the instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `visibility` - Visibility of the instance.
+    pub fn with_visibility(mut self, visibility: Visibility) -> Instance<Sphere> {
+        self.object = self.object.with_visibility(visibility);
+        self
+    }
+
+    /// Get which kinds of rays see the instance, the same as for the sphere it was created from.
+    pub fn visibility(&self) -> Visibility {
+        self.object.visibility()
+    }
+}
+
+impl<T: Hitable> Hitable for Instance<T> {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+        // Affine transforms keep the ray parameter, so the hit is at the same `t` in both coordinate systems.
+        let local_ray = self.world_to_object.apply_to_ray(ray);
+        self.object.hit(&local_ray).map(|mut hit| {
+            hit.p = self.object_to_world.apply_to_point(&hit.p);
+            // Normals need the inverse transpose, and renormalizing, to stay perpendicular to scaled surfaces.
+            hit.n = normalize(&self.object_to_world.apply_to_normal(&hit.n));
+            hit.ng = normalize(&self.object_to_world.apply_to_normal(&hit.ng));
+            hit.dpdu = self.object_to_world.apply_to_vector(&hit.dpdu);
+            hit.dpdv = self.object_to_world.apply_to_vector(&hit.dpdv);
+            hit
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, max_t: f32) -> bool {
+        self.object.occluded(&self.world_to_object.apply_to_ray(ray), max_t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::scene::Material;
+    use super::super::vec3::{ Vec3, dot };
+
+    #[test]
+    fn ellipsoid_normals() {
+        // Unit sphere scaled into an ellipsoid with semi-axes 2, 1, and 1, placed at z = -5.
+        let (a, b, c) = (2.0, 1.0, 1.0);
+        let ellipsoid = Instance::new(
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Normal),
+            &Transform::translate(0.0, 0.0, -5.0) * &Transform::scale(a, b, c),
+        );
+        for &(x, y) in &[(0.0, 0.0), (1.0, 0.0), (1.5, 0.3), (-1.2, -0.5)] {
+            let hit = ellipsoid.hit(&Ray::new(Vec3::new(x, y, 0.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+            // The hit point is on the ellipsoid...
+            let (px, py, pz) = (hit.p.x, hit.p.y, hit.p.z + 5.0);
+            assert!((px * px / (a * a) + py * py / (b * b) + pz * pz / (c * c) - 1.0).abs() < 1e-4);
+            assert!((hit.p.x - x).abs() < 1e-5 && (hit.p.y - y).abs() < 1e-5);
+            // ...and the normal is the normalized gradient of the implicit surface there.
+            let expected = normalize(&Vec3::new(px / (a * a), py / (b * b), pz / (c * c)));
+            assert!(dot(&hit.n, &expected) > 1.0 - 1e-5);
+            assert!((dot(&hit.n, &hit.n) - 1.0).abs() < 1e-5);
+            assert!(dot(&hit.ng, &expected) > 1.0 - 1e-5);
+            // Tangents stay perpendicular to the normal.
+            assert!(dot(&normalize(&hit.dpdu), &hit.n).abs() < 1e-4);
+            assert!(dot(&normalize(&hit.dpdv), &hit.n).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn instance_hit_distance() {
+        let ellipsoid = Instance::new(
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Normal),
+            &Transform::translate(0.0, 0.0, -5.0) * &Transform::scale(1.0, 1.0, 2.0),
+        );
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = ellipsoid.hit(&ray).unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-5);
+        assert!(ellipsoid.occluded(&ray, 4.0));
+        assert!(!ellipsoid.occluded(&ray, 2.5));
+        assert!(ellipsoid.hit(&Ray::new(Vec3::new(1.5, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0))).is_none());
+    }
+
+    #[test]
+    fn instance_bbox() {
+        let ellipsoid = Instance::new(
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Normal),
+            &Transform::translate(1.0, 2.0, 3.0) * &Transform::scale(2.0, 1.0, 0.5),
+        );
+        let bbox = ellipsoid.bbox();
+        assert_eq!(bbox.min.x, -1.0); assert_eq!(bbox.min.y, 1.0); assert_eq!(bbox.min.z, 2.5);
+        assert_eq!(bbox.max.x, 3.0); assert_eq!(bbox.max.y, 3.0); assert_eq!(bbox.max.z, 3.5);
+    }
+}
@@ -0,0 +1,2741 @@
+pub mod math;
+pub mod vec3;
+pub mod ray;
+pub mod scene;
+pub mod camera;
+pub mod bbox;
+pub mod mat4;
+pub mod xform;
+pub mod quat;
+pub mod image;
+mod onb;
+pub mod color;
+pub mod preview;
+pub mod stats;
+pub mod sampling;
+pub mod settings;
+pub mod bloom;
+pub mod composite;
+pub mod tile;
+mod checkpoint;
+mod convergence;
+mod grid;
+pub mod instance;
+pub mod mesh;
+pub mod material_library;
+pub mod scene_file;
+mod frustum;
+mod obj;
+mod spectrum;
+pub mod framebuffer;
+
+extern crate png;
+extern crate rand;
+
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::path::{ Path, PathBuf };
+use std::fs::File;
+use std::io::{ BufWriter, Write };
+use std::thread;
+use rand::{ Rng, SeedableRng };
+use rand::rngs::StdRng;
+use vec3::{ Vec3, normalize, length, length_squared, reflect, dot, refract, reflectance };
+use ray::Ray;
+use image::{ BitDepth, Image };
+use onb::Onb;
+use scene::{ Hit, Hitable, LightSample, RayType, Scene, Sphere, Material, Texture, get_checkered_color, ray_epsilon };
+use camera::{ Camera, PerspectiveCamera, Projection };
+use stats::RenderStats;
+use sampling::{ LightSelection, SamplingMode, blue_noise_sample, concentric_sample_disk, pixel_seed, pass_seed };
+use settings::RenderSettings;
+use tile::{ Tile, TileIterator, TileOrder };
+use color::luminance;
+use checkpoint::Checkpoint;
+use framebuffer::Framebuffer;
+use material_library::MaterialLibrary;
+use convergence::{ Convergence, RunningStats };
+use spectrum::{ dispersed_ior, rgb_to_spectrum, sample_wavelength, wavelength_to_rgb };
+
+/// State of a path carried from one bounce to the next.
+#[derive(Debug, Copy, Clone)]
+pub struct PathState {
+    /// Number of bounces so far.
+    depth: u32,
+    /// Number of bounces off glass so far, when those are counted separately.
+    refraction_depth: u32,
+    /// Distance travelled along the path so far.
+    distance: f32,
+    /// Whether the background was sampled directly at the last diffuse bounce, together with the lights in front of it
+    /// (also through glass, see `PathState::through_glass`), so that hitting either of them again would count their light twice.
+    background_sampled: bool,
+    /// Whether one of the lights chosen by `Scene::pick_light` was sampled directly at the last diffuse bounce
+    /// (also through glass), so that hitting any of them again would count their light twice.
+    lights_sampled: bool,
+    /// Spread angle of the primary ray, used to estimate the footprint of the path on surfaces.
+    spread_angle: f32,
+    /// Number of specular bounces since the diffuse surface seen by the camera, as long as the path
+    /// has only bounced specularly since. Such paths ending at a light are caustics, which are rendered
+    /// by light tracing instead, when enabled.
+    specular_after_diffuse: Option<u32>,
+    /// Wavelength of the light carried by the path, in nanometers, when rendering spectrally.
+    wavelength: Option<f32>,
+}
+
+impl PathState {
+    pub fn new(spread_angle: f32) -> PathState {
+        PathState { depth: 0, refraction_depth: 0, distance: 0.0, background_sampled: false, lights_sampled: false, spread_angle, specular_after_diffuse: None, wavelength: None }
+    }
+
+    // Color of a material, light, or the background as seen by the path: unchanged, or when rendering spectrally,
+    // the value of the color's spectrum at the wavelength of the path, in all channels.
+    fn spectral(&self, c: &Vec3) -> Vec3 {
+        match self.wavelength {
+            Some(lambda) => {
+                let v = rgb_to_spectrum(c, lambda);
+                Vec3::new(v, v, v)
+            },
+            None => *c,
+        }
+    }
+
+    // State of the path after bouncing off a surface at given ray parameter.
+    fn bounce(&self, t: f32) -> PathState {
+        PathState { depth: self.depth + 1, distance: self.distance + t, background_sampled: false, lights_sampled: false, specular_after_diffuse: None, ..*self }
+    }
+
+    // State of the path after bouncing off glass at given ray parameter,
+    // counted against the refraction budget instead of the main one.
+    fn bounce_refraction(&self, t: f32) -> PathState {
+        PathState { refraction_depth: self.refraction_depth + 1, distance: self.distance + t, background_sampled: false, lights_sampled: false, specular_after_diffuse: None, ..*self }
+    }
+
+    // Total number of bounces so far, including the ones off glass.
+    fn bounces(&self) -> u32 {
+        self.depth + self.refraction_depth
+    }
+
+    // Approximate width of the path's footprint on a surface at given ray parameter.
+    // The footprint keeps growing with the total distance, ignoring the focusing or spreading
+    // effect of curved or rough surfaces along the way.
+    fn footprint(&self, t: f32) -> f32 {
+        (self.distance + t) * self.spread_angle
+    }
+
+    // State of the path after a specular bounce, given the state after the bounce itself.
+    fn specular(&self, mut next: PathState) -> PathState {
+        next.specular_after_diffuse = self.specular_after_diffuse.map(|n| n + 1);
+        next
+    }
+
+    // State of the path after refracting through glass, given the state after the bounce itself. Shadow rays pass
+    // through glass the same way (see `Scene::transmittance`), so light sampled directly at the last diffuse bounce
+    // stays sampled until the path reaches something else, or reflects off the glass instead.
+    fn through_glass(&self, mut next: PathState) -> PathState {
+        next.background_sampled = self.background_sampled;
+        next.lights_sampled = self.lights_sampled;
+        next
+    }
+}
+
+fn random_in_unit_sphere(rng: &mut StdRng) -> Vec3 {
+    let mut rand = Vec3::new(0.0, 0.0, 0.0);
+    loop {
+        let (u, v, w): (f32, f32, f32) = (rng.gen(), rng.gen(), rng.gen());
+        rand.x = 2.0 * u - 1.0;
+        rand.y = 2.0 * v - 1.0;
+        rand.z = 2.0 * w - 1.0;
+        if length_squared(&rand) < 1.0 {
+            return rand;
+        }
+    }
+}
+
+// Direction of the reflection off a metal, perturbed towards a random point within an ellipsoid around the tip
+// of the mirror reflection, with the squared radii of the roughness along the tangent and the bitangent of the
+// surface (and their average along the normal). With equal roughness values, the ellipsoid is a ball, sampled
+// without the tangent frame, so that anisotropic metals reproduce isotropic ones exactly.
+fn metal_reflection(ray: &Ray, hit: &Hit, roughness_u: f32, roughness_v: f32, rng: &mut StdRng) -> Vec3 {
+    let mut target = reflect(&ray.d, &hit.n);
+    if roughness_u == roughness_v {
+        if roughness_u > 0.0 {
+            let mut rand = Vec3::new(0.0, 0.0, 0.0);
+            loop {
+                let (u, v, w): (f32, f32, f32) = (rng.gen(), rng.gen(), rng.gen());
+                rand.x = 2.0 * u - 1.0;
+                rand.y = 2.0 * v - 1.0;
+                rand.z = 2.0 * w - 1.0;
+                if length_squared(&rand) < roughness_u {
+                    break;
+                }
+            }
+            target += &rand;
+        }
+    } else {
+        let frame = Onb::new_from_w_and_u(&hit.n, &hit.dpdu);
+        let rand = random_in_unit_sphere(rng);
+        target += &frame.local_to_world(&Vec3::new(
+            roughness_u.sqrt() * rand.x,
+            roughness_v.sqrt() * rand.y,
+            (0.5 * (roughness_u + roughness_v)).sqrt() * rand.z,
+        ));
+    }
+    target.normalize();
+    target
+}
+
+// Direction of a diffuse bounce off a surface with given normal: towards a random point in the unit sphere
+// around the tip of the normal. This is not the cosine distribution of a Lambertian surface, but favors
+// directions closer to the normal, with the density 2 cos^3 / PI (see `diffuse_bounce_density`). A point right
+// opposite the normal, next to the surface, leaves no reliable direction, so the bounce goes along the normal instead.
+fn diffuse_direction(n: &Vec3, rand: &Vec3) -> Vec3 {
+    let target = n + rand;
+    if length_squared(&target) < 1e-6 {
+        return *n;
+    }
+    normalize(&target)
+}
+
+// Random direction in the hemisphere around +Z, with probability proportional to the cosine with +Z:
+// a uniformly distributed point on the unit disk, projected up onto the hemisphere (Malley's method).
+fn random_cosine_direction(rng: &mut StdRng) -> Vec3 {
+    let (x, y) = concentric_sample_disk(rng.gen(), rng.gen());
+    Vec3::new(x, y, (1.0 - x * x - y * y).max(0.0).sqrt())
+}
+
+// Albedo of a diffuse surface at given hit, with the texture applied.
+fn diffuse_albedo(albedo: &Vec3, texture: &Texture, hit: &Hit, path: &PathState, settings: &RenderSettings) -> Vec3 {
+    match *texture {
+        Texture::Checkered(color1, color2, scale_u, scale_v) => {
+            let footprint = if settings.filter_textures {
+                let width = path.footprint(hit.t);
+                (width / length(&hit.dpdu), width / length(&hit.dpdv))
+            } else {
+                (0.0, 0.0)
+            };
+            get_checkered_color(&color1, &color2, (scale_u, scale_v), hit.uv, footprint)
+        },
+        Texture::None => *albedo,
+    }
+}
+
+// Fraction of the short rays, cosine-distributed around the normal of given frame, blocked by geometry
+// within `settings.ao_radius` from the point.
+fn ambient_occlusion(scene: &Scene, p: &Vec3, frame: &Onb, epsilon: f32, settings: &RenderSettings, rng: &mut StdRng) -> f32 {
+    // Offset along the normal, so that rays at grazing angles do not hit the surface they start from.
+    let o = p + &(epsilon * &frame.w);
+    let mut blocked = 0;
+    for _ in 0..settings.ao_samples {
+        let d = frame.local_to_world(&random_cosine_direction(rng));
+        let ray = Ray::new(o, d);
+        if scene.occluded(&ray, settings.ao_radius) {
+            blocked += 1;
+        }
+    }
+    blocked as f32 / settings.ao_samples as f32
+}
+
+// Ratio of the Oren-Nayar diffuse reflection to the Lambertian one, for given normal,
+// direction towards the viewer, direction towards the light, and roughness
+// (standard deviation of the microfacet angle, in radians). Exactly 1.0 for zero roughness.
+fn oren_nayar(n: &Vec3, wo: &Vec3, wi: &Vec3, roughness: f32) -> f32 {
+    if roughness <= 0.0 {
+        return 1.0;
+    }
+    let sigma2 = roughness * roughness;
+    let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+    let b = 0.45 * sigma2 / (sigma2 + 0.09);
+    let cos_o = dot(n, wo).abs().clamp(1e-4, 1.0);
+    let cos_i = dot(n, wi).abs().clamp(1e-4, 1.0);
+    let (sin_o, sin_i) = ((1.0 - cos_o * cos_o).sqrt(), (1.0 - cos_i * cos_i).sqrt());
+    // Cosine of the azimuthal angle between the two directions.
+    let mut cos_phi = 0.0;
+    if sin_o > 1e-4 && sin_i > 1e-4 {
+        let to = (1.0 / sin_o) * &(wo - &(cos_o * n));
+        let ti = (1.0 / sin_i) * &(wi - &(cos_i * n));
+        cos_phi = dot(&to, &ti).max(0.0);
+    }
+    // sin(alpha) * tan(beta), where alpha is the larger and beta the smaller of the two polar angles.
+    let sin_alpha_tan_beta = if cos_i < cos_o { sin_i * sin_o / cos_o } else { sin_o * sin_i / cos_i };
+    a + b * cos_phi * sin_alpha_tan_beta
+}
+
+// Ray reflected or refracted by glass with given index of refraction at given hit,
+// choosing between the two randomly by the Fresnel reflectance, and whether it was refracted.
+fn scatter_glass(ray: &Ray, hit: &Hit, ior: f32, epsilon: f32, rng: &mut StdRng, stats: &mut RenderStats) -> (Ray, bool) {
+    let refraction_ratio = if hit.front_face { 1.0 / ior } else { ior };
+    let normal = hit.n;
+
+    let schlick = {
+        let mut v = -&ray.d;
+        v.normalize();
+        let cos_theta = (v.x * normal.x + v.y * normal.y + v.z * normal.z).min(1.0);
+        reflectance(cos_theta, refraction_ratio)
+    };
+    let rand: f32 = rng.gen();
+
+    let (mut new_ray, is_refracted) = if let Some(mut refracted) = refract(&ray.d, &normal, refraction_ratio) {
+        if schlick > rand {
+            let mut reflected = reflect(&ray.d, &normal);
+            reflected.normalize();
+            (Ray::new(hit.p, reflected), false)
+        } else {
+            refracted.normalize();
+            (Ray::new(hit.p, refracted), true)
+        }
+    } else {
+        // Total internal reflection, when leaving a denser medium at a grazing angle.
+        // With `ior` below 1 (for example, an air bubble in water), this happens when entering.
+        stats.total_internal_reflections += 1;
+        let mut reflected = reflect(&ray.d, &normal);
+        reflected.normalize();
+        (Ray::new(hit.p, reflected), false)
+    };
+    new_ray.o.x += epsilon * new_ray.d.x;
+    new_ray.o.y += epsilon * new_ray.d.y;
+    new_ray.o.z += epsilon * new_ray.d.z;
+    (new_ray, is_refracted)
+}
+
+// Index of refraction of the clearcoat of metals.
+const CLEARCOAT_IOR: f32 = 1.5;
+
+// Tint and roughness of the layer of a (possibly clearcoated) metal that given ray reflects off: the clearcoat,
+// picked randomly by its weight times its Fresnel reflectance, or the metal below it.
+fn metal_layer(ray: &Ray, hit: &Hit, albedo: Vec3, roughness: f32, clearcoat: f32, clearcoat_roughness: f32, rng: &mut StdRng) -> (Vec3, f32) {
+    if clearcoat > 0.0 {
+        let cos_theta = dot(&normalize(&ray.d), &hit.n).abs().min(1.0);
+        if rng.gen::<f32>() < clearcoat * reflectance(cos_theta, 1.0 / CLEARCOAT_IOR) {
+            return (Vec3::new(1.0, 1.0, 1.0), clearcoat_roughness);
+        }
+    }
+    (albedo, roughness)
+}
+
+// Density of the diffuse bounces in `trace_ray` relative to the Lambertian (cosine-weighted) one, in a direction
+// at given cosine with the normal. Unless sampling the background, the bounces are not cosine-weighted, but have
+// the density 2 cos^3 / PI, so light arriving closer to the normal is reflected more.
+fn diffuse_bounce_density(cos: f32, settings: &RenderSettings) -> f32 {
+    if settings.sample_background { 1.0 } else { 2.0 * cos * cos }
+}
+
+// Light arriving at a diffuse surface directly from one of the lights, chosen at random (see `Scene::pick_light`),
+// along a direction towards it, and tinted by any glass in the way. Weighted the same as the light of the next bounce
+// of the surface (by its density and the Oren-Nayar factor), and divided by the probability of choosing the light
+// and the direction.
+fn direct_light(scene: &Scene, ray: &Ray, hit: &Hit, roughness: f32, settings: &RenderSettings, rng: &mut StdRng) -> Vec3 {
+    let black = Vec3::new(0.0, 0.0, 0.0);
+    let (light, probability) = match scene.pick_light(rng.gen(), settings.light_selection) {
+        Some(light) => light,
+        None => return black,
+    };
+    let LightSample { d, t, inverse_density } = match light.sample(&hit.p, rng.gen(), rng.gen()) {
+        Some(sample) => sample,
+        None => return black,
+    };
+    let cos = dot(&hit.n, &d);
+    if cos <= 0.0 || !hit.leaves_surface(&ray.d, &d) {
+        return black;
+    }
+    let epsilon = scene.hit_epsilon(hit);
+    let shadow_ray = Ray::new(&hit.p + &(epsilon * &d), d);
+    // Stopping short of the light, from the start of the shadow ray, which is itself offset from the hit.
+    let transmittance = scene.transmittance(&shadow_ray, t - 2.0 * epsilon);
+    let emission = light.emission();
+    // Cosine-weighted over PI, like the diffuse bounces, divided by the density of the direction (for spheres,
+    // one over the solid angle).
+    let lambert = cos / std::f32::consts::PI * diffuse_bounce_density(cos, settings);
+    let weight = lambert * inverse_density / probability * oren_nayar(&hit.n, &(-&ray.d), &d, roughness);
+    weight * &Vec3::new(transmittance.x * emission.x, transmittance.y * emission.y, transmittance.z * emission.z)
+}
+
+// Decide whether a path hitting a surface of given albedo continues, by Russian roulette (see
+// `RenderSettings::russian_roulette`), and return the weight of the light it brings back, making up
+// for the paths ended, or `None` if it ends here.
+fn roulette_weight(albedo: &Vec3, path: &PathState, settings: &RenderSettings, rng: &mut StdRng) -> Option<f32> {
+    match settings.russian_roulette {
+        Some(min_bounces) if path.bounces() >= min_bounces => {
+            let survival = albedo.x.max(albedo.y).max(albedo.z).min(1.0);
+            if survival > 0.0 && rng.gen::<f32>() < survival { Some(1.0 / survival) } else { None }
+        },
+        _ => Some(1.0),
+    }
+}
+
+// Limit the brightness of the light scattered towards the camera by a surface the path has reached after bouncing
+// at least once, which only carries light bounced two or more times, to `settings.indirect_clamp` (if set).
+// The color is scaled down as a whole, keeping its hue.
+fn clamp_indirect(c: Vec3, path: &PathState, settings: &RenderSettings) -> Vec3 {
+    match settings.indirect_clamp {
+        Some(max) if path.bounces() > 0 => {
+            let brightest = c.x.max(c.y).max(c.z);
+            if brightest > max { (max / brightest) * &c } else { c }
+        },
+        _ => c,
+    }
+}
+
+/// Trace a path starting with given ray. When `objects` are given, the ray is only tested against those
+/// objects; this does not apply to the rest of the path.
+pub fn trace_ray(scene: &Scene, ray: &Ray, objects: Option<&[usize]>, settings: &RenderSettings, rng: &mut StdRng, stats: &mut RenderStats, path: PathState) -> Vec3 {
+    trace_ray_hit(scene, ray, objects, settings, rng, stats, path).0
+}
+
+// Trace a path the same as `trace_ray`, and also tell whether its first ray hit any object,
+// for example, to measure the coverage of a pixel without intersecting its primary rays again.
+fn trace_ray_hit(scene: &Scene, ray: &Ray, objects: Option<&[usize]>, settings: &RenderSettings, rng: &mut StdRng, stats: &mut RenderStats, path: PathState) -> (Vec3, bool) {
+    // Path distances (and with them texture footprints) are measured in ray parameters.
+    debug_assert!((length_squared(&ray.d) - 1.0).abs() < 1e-3, "traced rays must be normalized");
+    let max_refraction_depth = settings.max_refraction_depth.unwrap_or(u32::MAX);
+    if path.depth >= settings.max_depth || path.refraction_depth >= max_refraction_depth {
+        stats.record_path_depth(path.depth);
+        return (Vec3::new(0.0, 0.0, 0.0), false);
+    }
+
+    // Refractions count as bounces even when they are limited separately, so that objects hidden from the camera
+    // still show through glass.
+    let ray_type = if path.bounces() == 0 { RayType::Camera } else { RayType::Reflection };
+    let hit = match objects {
+        Some(ids) => scene.hit_objects(ray, ids, ray_type),
+        None => scene.hit_visible(ray, ray_type),
+    };
+    let covered = hit.is_some();
+    (shade(scene, ray, hit, settings, rng, stats, path), covered)
+}
+
+// Material of the surface at a hit: one of mixed materials, picked at random, or the surface of a wireframe
+// material away from the edges. Edges keep the wireframe material, which shades them in their flat color.
+fn surface_material<'a>(hit: &Hit<'a>, rng: &mut StdRng) -> &'a Material {
+    let mut material = hit.m;
+    loop {
+        material = match material {
+            Material::Mix(first, second, factor) => if rng.gen::<f32>() < *factor { second } else { first },
+            Material::Wireframe(surface, _, edge_width) if hit.edge_distance >= 0.5 * edge_width => surface,
+            _ => return material,
+        };
+    }
+}
+
+// Compute the light of a path arriving along a ray from its first intersection, or from the background
+// if there is none, tracing the rest of the path from there.
+fn shade(scene: &Scene, ray: &Ray, hit: Option<Hit>, settings: &RenderSettings, rng: &mut StdRng, stats: &mut RenderStats, path: PathState) -> Vec3 {
+    if let Some(mut hit) = hit {
+        hit.clamp_shading_normal(&ray.d);
+        let material = surface_material(&hit, rng);
+        match *material {
+            Material::Diffuse(albedo, texture, _) if settings.ao_samples > 0 => {
+                // Stylized shading instead of global illumination, as if lit by a uniform white sky
+                // that is only blocked by geometry close to the surface.
+                stats.record_path_depth(path.depth);
+                let open = 1.0 - ambient_occlusion(scene, &hit.p, &Onb::new_from_w(&hit.n), scene.hit_epsilon(&hit), settings, rng);
+                open * &path.spectral(&diffuse_albedo(&albedo, &texture, &hit, &path, settings))
+            },
+            Material::Diffuse(albedo, texture, roughness) => {
+                let albedo = path.spectral(&diffuse_albedo(&albedo, &texture, &hit, &path, settings));
+                let Some(weight) = roulette_weight(&albedo, &path, settings, rng) else {
+                    stats.record_path_depth(path.depth);
+                    return Vec3::new(0.0, 0.0, 0.0);
+                };
+                let wo = -&normalize(&ray.d);
+                let frame = Onb::new_from_w(&hit.n);
+                let target = if settings.sample_background {
+                    // Sample the same (Lambertian) distribution as the direct background sampling below.
+                    frame.local_to_world(&random_cosine_direction(rng))
+                } else {
+                    diffuse_direction(&hit.n, &random_in_unit_sphere(rng))
+                };
+                let mut new_ray = Ray::new(hit.p, target);
+                let epsilon = scene.hit_epsilon(&hit);
+                new_ray.o.x += epsilon * new_ray.d.x;
+                new_ray.o.y += epsilon * new_ray.d.y;
+                new_ray.o.z += epsilon * new_ray.d.z;
+                let mut next = path.bounce(hit.t);
+                if settings.light_samples > 0 && path.bounces() == 0 {
+                    next.specular_after_diffuse = Some(0);
+                }
+                // Non-physical fill light, arriving from everywhere without any shadowing.
+                let mut c = if settings.lighting.includes(path.bounces() + 1) { path.spectral(&settings.ambient) } else { Vec3::new(0.0, 0.0, 0.0) };
+                if settings.sample_background {
+                    // Treat the background as a light, and sample it directly with a shadow ray. Cosine-weighted
+                    // sampling cancels out the cosine term of the diffuse reflection, leaving just the background color.
+                    let light_dir = frame.local_to_world(&random_cosine_direction(rng));
+                    let shadow_ray = Ray::new(&hit.p + &(scene.hit_epsilon(&hit) * &light_dir), light_dir);
+                    if settings.lighting.includes(path.bounces() + 1) {
+                        // Glass in the way tints the light instead of blocking it, and lights in the way
+                        // contribute their own light instead (and are not counted by the next bounce).
+                        let mut direct = path.spectral(&scene.background_light(&shadow_ray, settings.sample_lights));
+                        direct *= oren_nayar(&hit.n, &wo, &light_dir, roughness);
+                        c += &direct;
+                    }
+                    next.background_sampled = true;
+                }
+                if settings.sample_lights {
+                    // Sample one of the lights directly; the next bounce must not count any of them again.
+                    let direct = direct_light(scene, ray, &hit, roughness, settings, rng);
+                    if settings.lighting.includes(path.bounces() + 1) {
+                        c += &path.spectral(&direct);
+                    }
+                    next.lights_sampled = true;
+                }
+                let mut indirect = trace_ray(scene, &new_ray, None, settings, rng, stats, next);
+                indirect *= oren_nayar(&hit.n, &wo, &target, roughness);
+                c += &indirect;
+                clamp_indirect(weight * &Vec3::new(
+                    albedo.x * c.x,
+                    albedo.y * c.y,
+                    albedo.z * c.z,
+                ), &path, settings)
+            },
+            Material::Metal(albedo, roughness, clearcoat, clearcoat_roughness) => {
+                let (albedo, roughness) = metal_layer(ray, &hit, albedo, roughness, clearcoat, clearcoat_roughness, rng);
+                let albedo = path.spectral(&albedo);
+                let Some(weight) = roulette_weight(&albedo, &path, settings, rng) else {
+                    stats.record_path_depth(path.depth);
+                    return Vec3::new(0.0, 0.0, 0.0);
+                };
+                let target = metal_reflection(ray, &hit, roughness, roughness, rng);
+                // Reflections (or their rough perturbations) going into the surface would leak light through it.
+                if !hit.leaves_surface(&ray.d, &target) {
+                    stats.record_path_depth(path.depth);
+                    return Vec3::new(0.0, 0.0, 0.0);
+                }
+                let mut new_ray = Ray::new(hit.p, target);
+                let epsilon = scene.hit_epsilon(&hit);
+                new_ray.o.x += epsilon * new_ray.d.x;
+                new_ray.o.y += epsilon * new_ray.d.y;
+                new_ray.o.z += epsilon * new_ray.d.z;
+                let next = if roughness <= 0.0 { path.specular(path.bounce(hit.t)) } else { path.bounce(hit.t) };
+                let c = trace_ray(scene, &new_ray, None, settings, rng, stats, next);
+                clamp_indirect(weight * &Vec3::new(
+                    albedo.x * c.x,
+                    albedo.y * c.y,
+                    albedo.z * c.z,
+                ), &path, settings)
+            },
+            Material::AnisotropicMetal(albedo, roughness_u, roughness_v) => {
+                let albedo = path.spectral(&albedo);
+                let Some(weight) = roulette_weight(&albedo, &path, settings, rng) else {
+                    stats.record_path_depth(path.depth);
+                    return Vec3::new(0.0, 0.0, 0.0);
+                };
+                let target = metal_reflection(ray, &hit, roughness_u, roughness_v, rng);
+                if !hit.leaves_surface(&ray.d, &target) {
+                    stats.record_path_depth(path.depth);
+                    return Vec3::new(0.0, 0.0, 0.0);
+                }
+                let mut new_ray = Ray::new(hit.p, target);
+                let epsilon = scene.hit_epsilon(&hit);
+                new_ray.o.x += epsilon * new_ray.d.x;
+                new_ray.o.y += epsilon * new_ray.d.y;
+                new_ray.o.z += epsilon * new_ray.d.z;
+                let next = if roughness_u <= 0.0 && roughness_v <= 0.0 { path.specular(path.bounce(hit.t)) } else { path.bounce(hit.t) };
+                let c = trace_ray(scene, &new_ray, None, settings, rng, stats, next);
+                clamp_indirect(weight * &Vec3::new(
+                    albedo.x * c.x,
+                    albedo.y * c.y,
+                    albedo.z * c.z,
+                ), &path, settings)
+            },
+            Material::Glass(attenuation, ior) => {
+                // Each wavelength bends differently, splitting white light into colors.
+                let ior = path.wavelength.map_or(ior, |lambda| dispersed_ior(ior, lambda));
+                let attenuation = path.spectral(&attenuation);
+                let Some(weight) = roulette_weight(&attenuation, &path, settings, rng) else {
+                    stats.record_path_depth(path.depth);
+                    return Vec3::new(0.0, 0.0, 0.0);
+                };
+                let (new_ray, refracted) = scatter_glass(ray, &hit, ior, scene.hit_epsilon(&hit), rng, stats);
+                let mut next = path.specular(if settings.max_refraction_depth.is_some() {
+                    path.bounce_refraction(hit.t)
+                } else {
+                    path.bounce(hit.t)
+                });
+                if refracted {
+                    next = path.through_glass(next);
+                }
+                let c = trace_ray(scene, &new_ray, None, settings, rng, stats, next);
+                clamp_indirect(weight * &Vec3::new(
+                    attenuation.x * c.x,
+                    attenuation.y * c.y,
+                    attenuation.z * c.z,
+                ), &path, settings)
+            },
+            Material::Light(color) => {
+                stats.record_path_depth(path.depth);
+                let caustic = path.specular_after_diffuse.is_some_and(|n| n > 0);
+                // Lights casting shadows have already been found by the shadow ray sampling the background,
+                // and the lights that can be sampled directly by `direct_light`.
+                let sampled = path.background_sampled && scene.object_visibility(hit.object_id).casts_shadows
+                    || path.lights_sampled && scene.is_sampled_light(hit.object_id);
+                if caustic || sampled || !settings.lighting.includes(path.bounces()) {
+                    return Vec3::new(0.0, 0.0, 0.0);
+                }
+                path.spectral(&color)
+            },
+            Material::Normal => {
+                stats.record_path_depth(path.depth);
+                path.spectral(&Vec3::new(
+                    0.5 * (hit.n.x + 1.0),
+                    0.5 * (hit.n.y + 1.0),
+                    0.5 * (hit.n.z + 1.0),
+                ))
+            },
+            Material::Uv => {
+                stats.record_path_depth(path.depth);
+                path.spectral(&Vec3::new(hit.uv.0, hit.uv.1, 0.0))
+            },
+            Material::Wireframe(_, edge_color, _) => {
+                stats.record_path_depth(path.depth);
+                path.spectral(&edge_color)
+            },
+            Material::Mix(..) => unreachable!("mixed materials are resolved above"),
+        }
+    } else {
+        // Render background, unless it has already been sampled directly at the previous bounce
+        stats.record_path_depth(path.depth);
+        if path.background_sampled || !settings.lighting.includes(path.bounces()) {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        path.spectral(&scene.background_color(&ray.d))
+    }
+}
+
+// Irradiance probes trace their rays like the bounces of paths, so they live next to `trace_ray`.
+impl Scene {
+    /// Measure the light arriving at a point from the hemisphere around a normal, as a probe for baking lighting.
+    ///
+    /// Traces `samples` paths in cosine-distributed directions, stratified so that they cover the hemisphere
+    /// evenly, with the max depth fitting the materials of the scene (see `Scene::suggested_max_depth`).
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - Point to measure the light at.
+    /// * `n` - Normal of the hemisphere (does not need to be normalized).
+    /// * `samples` - Number of rays to trace.
+    /// * `rng` - Random number generator.
+    ///
+    /// # Returns
+    ///
+    /// Average of the incoming light weighted by the cosine with the normal, that is, the irradiance divided by PI.
+    /// This is the light that a white diffuse surface at the point would reflect.
+    pub fn irradiance_at(&self, p: &Vec3, n: &Vec3, samples: u32, rng: &mut StdRng) -> Vec3 {
+        if samples == 0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let settings = RenderSettings { max_depth: self.suggested_max_depth(), ..RenderSettings::default() };
+        let mut stats = RenderStats::new(settings.max_depth);
+        let frame = Onb::new_from_w(&normalize(n));
+        // The disk samples are jittered on the largest square grid that fits, and the rest are placed at random.
+        let k = (samples as f32).sqrt() as u32;
+        let epsilon = self.point_epsilon(p);
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..samples {
+            let (u, v) = if i < k * k {
+                (((i % k) as f32 + rng.gen::<f32>()) / k as f32, ((i / k) as f32 + rng.gen::<f32>()) / k as f32)
+            } else {
+                (rng.gen(), rng.gen())
+            };
+            let (x, y) = concentric_sample_disk(u.min(1.0), v.min(1.0));
+            let d = frame.local_to_world(&Vec3::new(x, y, (1.0 - x * x - y * y).max(0.0).sqrt()));
+            let ray = Ray::new(p + &(epsilon * &d), d);
+            // Traced as if bouncing off a surface at the point.
+            sum += &trace_ray(self, &ray, None, &settings, rng, &mut stats, PathState::new(0.0).bounce(0.0));
+        }
+        (1.0 / samples as f32) * &sum
+    }
+}
+
+// Trace a path from a random point on one of the lights, and if it reaches a diffuse surface after bouncing
+// off glass or mirrors, add its light to the pixel of `image` where the camera sees the surface.
+// Paths reaching a diffuse surface directly, or through rough surfaces, are left to the paths from the camera.
+fn trace_light_path(scene: &Scene, camera: &PerspectiveCamera, settings: &RenderSettings, rng: &mut StdRng, stats: &mut RenderStats, image: &mut [Vec3]) {
+    // Lights are chosen by their power, the same as the lights sampled directly by the paths from the camera.
+    let (light, probability) = match scene.pick_light(rng.gen(), LightSelection::Power) {
+        Some(light) => light,
+        None => return,
+    };
+    // Uniformly distributed point on the light, emitting in a cosine-weighted direction.
+    let (p, n) = light.surface_point(rng.gen(), rng.gen());
+    let d = Onb::new_from_w(&n).local_to_world(&random_cosine_direction(rng));
+    let mut ray = Ray::new(&p + &(ray_epsilon(&p, &light.bbox()) * &d), d);
+    let mut power = (light.area() * std::f32::consts::PI / probability) * &light.emission();
+    // Bounces are counted the same way as for the camera path taking the same way in the opposite direction.
+    let mut path = PathState::new(0.0);
+    let max_refraction_depth = settings.max_refraction_depth.unwrap_or(u32::MAX);
+    while path.depth < settings.max_depth && path.refraction_depth < max_refraction_depth {
+        let mut hit = match scene.hit_visible(&ray, RayType::Reflection) {
+            Some(hit) => hit,
+            None => return,
+        };
+        hit.clamp_shading_normal(&ray.d);
+        let material = surface_material(&hit, rng);
+        match *material {
+            Material::Glass(attenuation, ior) => {
+                ray = scatter_glass(&ray, &hit, ior, scene.hit_epsilon(&hit), rng, stats).0;
+                power = Vec3::new(attenuation.x * power.x, attenuation.y * power.y, attenuation.z * power.z);
+                path = if settings.max_refraction_depth.is_some() { path.bounce_refraction(hit.t) } else { path.bounce(hit.t) };
+            },
+            Material::Metal(..) | Material::AnisotropicMetal(..) => {
+                let (albedo, roughness) = match *material {
+                    Material::Metal(albedo, roughness, clearcoat, clearcoat_roughness) =>
+                        metal_layer(&ray, &hit, albedo, roughness, clearcoat, clearcoat_roughness, rng),
+                    Material::AnisotropicMetal(albedo, roughness_u, roughness_v) => (albedo, roughness_u.max(roughness_v)),
+                    _ => unreachable!(),
+                };
+                if roughness > 0.0 {
+                    return;
+                }
+                let target = normalize(&reflect(&ray.d, &hit.n));
+                if !hit.leaves_surface(&ray.d, &target) {
+                    return;
+                }
+                ray = Ray::new(&hit.p + &(scene.hit_epsilon(&hit) * &target), target);
+                power = Vec3::new(albedo.x * power.x, albedo.y * power.y, albedo.z * power.z);
+                path = path.bounce(hit.t);
+            },
+            Material::Diffuse(albedo, texture, roughness) if path.bounces() > 0 && settings.ao_samples == 0 => {
+                // The camera path would reach the light after one more bounce, off this surface.
+                if path.depth + 1 >= settings.max_depth || !settings.lighting.includes(path.bounces() + 1) {
+                    return;
+                }
+                if let Some(projection) = camera.project(&hit.p, rng) {
+                    let albedo = diffuse_albedo(&albedo, &texture, &hit, &PathState::new(0.0), settings);
+                    let reflected = Vec3::new(albedo.x * power.x, albedo.y * power.y, albedo.z * power.z);
+                    splat(scene, &hit, &(-&ray.d), roughness, &reflected, &projection, settings, image);
+                }
+                return;
+            },
+            _ => return,
+        }
+    }
+}
+
+// Add the light arriving at a diffuse surface to the pixel where the camera sees the surface, if it does.
+// The `reflected` light is the power of the light path times the albedo of the surface.
+#[allow(clippy::too_many_arguments)]
+fn splat(scene: &Scene, hit: &Hit, wi: &Vec3, roughness: f32, reflected: &Vec3, projection: &Projection, settings: &RenderSettings, image: &mut [Vec3]) {
+    let to_camera = &projection.origin - &hit.p;
+    let dist = length(&to_camera);
+    let wo = (1.0 / dist) * &to_camera;
+    // The light and the camera must be on the same side of the surface, and nothing can be in between.
+    if dot(&hit.n, &wo) * dot(&hit.n, wi) <= 0.0 {
+        return;
+    }
+    match scene.hit_visible(&Ray::new(projection.origin, -&wo), RayType::Camera) {
+        Some(camera_hit) if camera_hit.t > dist - scene.hit_epsilon(hit) => {},
+        _ => return,
+    }
+    // Reflect the light the same way as the bounces of the camera paths off diffuse surfaces (see `trace_ray`).
+    let lambert = diffuse_bounce_density(dot(&hit.n, wi), settings);
+    let brdf = lambert * oren_nayar(&hit.n, &wo, wi, roughness) / std::f32::consts::PI;
+    let weight = brdf * dot(&hit.n, &wo).abs() / (dist * dist) * projection.importance / settings.light_samples as f32;
+    if !weight.is_finite() {
+        return;
+    }
+    let mut pixel_u = projection.u + 0.5;
+    let mut pixel_v = projection.v + 0.5;
+    if settings.flip_u {
+        pixel_u = 1.0 - pixel_u;
+    }
+    if settings.flip_v {
+        pixel_v = 1.0 - pixel_v;
+    }
+    let x = ((pixel_u * settings.width as f32) as u32).min(settings.width - 1);
+    let y = (((1.0 - pixel_v) * settings.height as f32) as u32).min(settings.height - 1);
+    image[(y * settings.width + x) as usize] += &(weight * reflected);
+}
+
+// Number of independently seeded parts that the light paths of a pass are split into, and the most threads tracing them.
+const LIGHT_PATH_CHUNKS: u32 = 16;
+
+// Trace `settings.light_samples` light paths per pixel for a single progressive pass, into one image
+// per chunk of the paths, with the light they add to each pixel (see `trace_light_path`).
+// The images are allocated on first use, and reused by the following passes.
+fn trace_light_paths(images: &mut Vec<Vec<Vec3>>, scene: &Scene, camera: &PerspectiveCamera, settings: &RenderSettings, pass: u32) -> RenderStats {
+    let pixel_count = (settings.width * settings.height) as usize;
+    let num_threads = thread_count(settings.num_threads).min(LIGHT_PATH_CHUNKS);
+    let total = settings.light_samples as u64 * pixel_count as u64;
+    images.resize(LIGHT_PATH_CHUNKS as usize, Vec::new());
+    // The paths are split into a fixed number of chunks, each with its own seed and image, which are added up
+    // in the same order no matter how many threads trace them, so that the thread count does not change the image.
+    let mut thread_chunks: Vec<Vec<(u32, &mut Vec<Vec3>)>> = (0..num_threads).map(|_| Vec::new()).collect();
+    for (chunk, image) in images.iter_mut().enumerate() {
+        thread_chunks[chunk % num_threads as usize].push((chunk as u32, image));
+    }
+    thread::scope(|scope| {
+        let handles: Vec<_> = thread_chunks.into_iter().map(|chunks| {
+            scope.spawn(move || {
+                let mut stats = RenderStats::new(settings.max_depth);
+                for (chunk, image) in chunks {
+                    let count = total / LIGHT_PATH_CHUNKS as u64 + if (chunk as u64) < total % LIGHT_PATH_CHUNKS as u64 { 1 } else { 0 };
+                    // Seeded apart from the pixels, none of which is in the last column.
+                    let mut rng = StdRng::seed_from_u64(pixel_seed(pass_seed(settings.seed, pass), u32::MAX, chunk));
+                    image.clear();
+                    image.resize(pixel_count, Vec3::new(0.0, 0.0, 0.0));
+                    for _ in 0..count {
+                        trace_light_path(scene, camera, settings, &mut rng, &mut stats, image);
+                    }
+                }
+                stats
+            })
+        }).collect();
+        let mut stats = RenderStats::new(settings.max_depth);
+        for handle in handles {
+            stats.merge(&handle.join().unwrap());
+        }
+        stats
+    })
+}
+
+// Find the pixel of the camera rendered into given pixel of the image, according to the flip settings.
+fn mirror_pixel(settings: &RenderSettings, (mut x, mut y): (u32, u32)) -> (u32, u32) {
+    if settings.flip_u {
+        x = settings.width - 1 - x;
+    }
+    if settings.flip_v {
+        y = settings.height - 1 - y;
+    }
+    (x, y)
+}
+
+// Number of samples of a pixel always kept, to estimate the distribution of their brightness
+// before rejecting any outliers (see `RenderSettings::outlier_sigma`).
+const MIN_SAMPLES_BEFORE_REJECTION: u32 = 8;
+
+// Samples of a pixel kept in its average so far, in all the passes, to recognize outliers among the following ones.
+#[derive(Clone)]
+struct KeptSamples {
+    // Brightness of the samples.
+    brightness: RunningStats,
+    // Sum of the colors of the samples.
+    color: Vec3,
+}
+
+impl Default for KeptSamples {
+    fn default() -> KeptSamples {
+        KeptSamples { brightness: RunningStats::default(), color: Vec3::new(0.0, 0.0, 0.0) }
+    }
+}
+
+// Compute the average color of all samples of a single pixel of the camera (see `mirror_pixel`), the fraction of primary rays
+// that hit geometry (or 1.0 if coverage is not being computed), and the number of samples taken.
+// Samples rejected as outliers are not included in the average, but are counted as taken. They are recognized
+// by the samples of the pixel kept so far, in `kept`, which carries over from pass to pass. When all the samples
+// of a pass are rejected, the pass takes the average color of the samples kept in the previous passes instead.
+// Samples are numbered from `first_sample`, so that progressive passes do not repeat the positions
+// of the blue noise samples. When `visible_objects` are given, primary rays are only tested against those.
+// Without any samples, the pixel is black and not covered.
+#[allow(clippy::too_many_arguments)]
+fn sample_pixel(scene: &Scene, camera: &PerspectiveCamera, visible_objects: Option<&[usize]>, settings: &RenderSettings, (x, y): (u32, u32), first_sample: u32, kept: &mut KeptSamples, rng: &mut StdRng, stats: &mut RenderStats) -> (Vec3, f32, u32) {
+    let spread_angle = camera.pixel_spread_angle(settings.height);
+    let mut color = Vec3::new(0.0, 0.0, 0.0);
+    let mut precise_color = [0.0f64; 3];
+    let mut hits = 0;
+    let mut kept_in_pass = 0;
+    for sample in 0..settings.pixel_samples {
+        let (pixel_sample_u, pixel_sample_v) = match settings.sampling {
+            _ if settings.center_single_sample && settings.pixel_samples == 1 => (0.5, 0.5),
+            SamplingMode::Random => (rng.gen(), rng.gen()),
+            SamplingMode::BlueNoise => blue_noise_sample(x, y, first_sample + sample),
+        };
+        let ray = camera.ray_for_pixel_sample(x, y, (pixel_sample_u, pixel_sample_v), settings.width, settings.height, rng);
+        let (c, covered) = if settings.spectral {
+            // The path carries a single wavelength, and returns the same value in all channels.
+            let lambda = sample_wavelength(rng.gen());
+            let path = PathState { wavelength: Some(lambda), ..PathState::new(spread_angle) };
+            let (c, covered) = trace_ray_hit(scene, &ray, visible_objects, settings, rng, stats, path);
+            (c.y * &wavelength_to_rgb(lambda), covered)
+        } else {
+            trace_ray_hit(scene, &ray, visible_objects, settings, rng, stats, PathState::new(spread_angle))
+        };
+        if !settings.alpha_coverage || covered {
+            hits += 1;
+        }
+        if let Some(sigma) = settings.outlier_sigma {
+            let brightness = luminance(&c);
+            let accepted = &kept.brightness;
+            if accepted.count() >= MIN_SAMPLES_BEFORE_REJECTION && brightness > accepted.mean() + sigma * accepted.variance().sqrt() {
+                stats.rejected_samples += 1;
+                continue;
+            }
+            kept.brightness.add(brightness);
+            kept.color += &c;
+        }
+        kept_in_pass += 1;
+        if settings.double_precision {
+            precise_color[0] += c.x as f64;
+            precise_color[1] += c.y as f64;
+            precise_color[2] += c.z as f64;
+        } else {
+            color += &c;
+        }
+    }
+    if kept_in_pass == 0 {
+        // Without any samples of this pass to average, black unless earlier passes kept some.
+        if kept.brightness.count() > 0 {
+            color = (1.0 / kept.brightness.count() as f32) * &kept.color;
+        }
+    } else if settings.double_precision {
+        let scale = 1.0 / kept_in_pass as f64;
+        color = Vec3::new((scale * precise_color[0]) as f32, (scale * precise_color[1]) as f32, (scale * precise_color[2]) as f32);
+    } else {
+        color *= 1.0 / kept_in_pass as f32;
+    }
+    (color, hits as f32 / settings.pixel_samples.max(1) as f32, settings.pixel_samples)
+}
+
+/// Render a single pixel, and return its linear (HDR) color.
+///
+/// Runs the same sampling as the full render, which makes it
+/// convenient for stepping through the tracing of a specific pixel in a debugger.
+/// Caustics rendered by light tracing (see `RenderSettings::light_samples`) are not included.
+///
+/// # Arguments
+///
+/// * `scene` - Scene to render.
+/// * `camera` - Camera to render the scene from.
+/// * `settings` - Render settings.
+/// * `x` - Pixel column.
+/// * `y` - Pixel row.
+pub fn render_pixel(scene: &Scene, camera: &PerspectiveCamera, settings: &RenderSettings, x: u32, y: u32) -> Vec3 {
+    // Seeded the same way as in a full render, which gives the same color as the first pass there.
+    let (x, y) = mirror_pixel(settings, (x, y));
+    let mut rng = StdRng::seed_from_u64(pixel_seed(settings.seed, x, y));
+    let mut stats = RenderStats::new(settings.max_depth);
+    sample_pixel(scene, camera, None, settings, (x, y), 0, &mut KeptSamples::default(), &mut rng, &mut stats).0
+}
+
+/// Count the intersection tests of the primary ray through the center of each pixel, for profiling
+/// the acceleration structure. With a lens, the rays go through random points of the lens.
+pub fn render_intersection_tests(scene: &Scene, camera: &PerspectiveCamera, settings: &RenderSettings) -> Vec<u32> {
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    let mut tests = Vec::with_capacity((settings.width * settings.height) as usize);
+    for y in 0..settings.height {
+        for x in 0..settings.width {
+            let (x, y) = mirror_pixel(settings, (x, y));
+            let ray = camera.ray_for_pixel_sample(x, y, (0.5, 0.5), settings.width, settings.height, &mut rng);
+            tests.push(scene.hit_counting_tests(&ray, RayType::Camera).1);
+        }
+    }
+    tests
+}
+
+/// Render the texture coordinates of the surface seen through the center of each pixel, for baking lighting into
+/// texture space with other tools: U and V in the red and green channels, and the id of the object plus one
+/// in the blue one (see `Hit::object_id`). Pixels seeing the background are all zero.
+pub fn render_uv_pass(scene: &Scene, camera: &PerspectiveCamera, settings: &RenderSettings) -> Vec<Vec3> {
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    let mut uvs = Vec::with_capacity((settings.width * settings.height) as usize);
+    for y in 0..settings.height {
+        for x in 0..settings.width {
+            let (x, y) = mirror_pixel(settings, (x, y));
+            let ray = camera.ray_for_pixel_sample(x, y, (0.5, 0.5), settings.width, settings.height, &mut rng);
+            uvs.push(match scene.hit_visible(&ray, RayType::Camera) {
+                Some(hit) => Vec3::new(hit.uv.0, hit.uv.1, (hit.object_id + 1) as f32),
+                None => Vec3::new(0.0, 0.0, 0.0),
+            });
+        }
+    }
+    uvs
+}
+
+// Replace NaN or infinite channels of a color with the corresponding channels of another color.
+// Returns true if any of the channels had to be replaced.
+fn sanitize_color(color: &mut Vec3, replacement: &Vec3) -> bool {
+    let mut invalid = false;
+    if !color.x.is_finite() {
+        color.x = replacement.x;
+        invalid = true;
+    }
+    if !color.y.is_finite() {
+        color.y = replacement.y;
+        invalid = true;
+    }
+    if !color.z.is_finite() {
+        color.z = replacement.z;
+        invalid = true;
+    }
+    invalid
+}
+
+/// Rendered pixels, one per pixel in each of the buffers: linear (HDR) colors, geometry coverage,
+/// and the number of samples taken, followed by the statistics collected along the way.
+pub type RenderOutput = (Vec<Vec3>, Vec<f32>, Vec<u32>, RenderStats);
+
+// Buffers of a single tile, which the render context keeps for the same tile in every pass.
+struct TileBuffers {
+    // Samples of the pixels of the tile, with the tile in the top left corner.
+    framebuffer: Framebuffer,
+    // Coverage of the pixels, row by row.
+    coverage: Vec<f32>,
+    // Samples of each pixel kept so far, in all the passes, to recognize outliers.
+    kept: Vec<KeptSamples>,
+    stats: RenderStats,
+}
+
+impl TileBuffers {
+    fn new(tile: &Tile, max_depth: u32) -> TileBuffers {
+        TileBuffers {
+            framebuffer: Framebuffer::new(tile.max_x - tile.min_x, tile.max_y - tile.min_y),
+            coverage: vec![0.0; tile.pixel_count() as usize],
+            kept: vec![KeptSamples::default(); tile.pixel_count() as usize],
+            stats: RenderStats::new(max_depth),
+        }
+    }
+}
+
+/// Buffers of a render: the passes accumulated so far, and everything a single pass needs on the way.
+///
+/// A context can be reused by renders of the same size one after another, for example, by the frames
+/// of an animation (see `render_scene_into`), which then do not allocate any buffers of the size of the image.
+pub struct RenderContext {
+    // Size of the square tiles, the tiles of the image, in the order the threads take them,
+    // and the buffers of each one, locked by the thread rendering the tile.
+    tile_size: u32,
+    tiles: Vec<Tile>,
+    tile_order: TileOrder,
+    tile_buffers: Vec<Mutex<TileBuffers>>,
+    // Samples, colors, and coverage of a single pass of the whole image.
+    framebuffer: Framebuffer,
+    color: Vec<Vec3>,
+    coverage: Vec<f32>,
+    // Light added by each chunk of the light paths of a pass (see `trace_light_paths`).
+    light_images: Vec<Vec<Vec3>>,
+    // All the passes rendered so far, and their average.
+    checkpoint: Checkpoint,
+    convergence: Convergence,
+    average_color: Vec<Vec3>,
+    average_coverage: Vec<f32>,
+}
+
+impl Default for RenderContext {
+    fn default() -> RenderContext {
+        RenderContext::new()
+    }
+}
+
+impl RenderContext {
+    /// Create new context, without any buffers; the first render using it allocates them.
+    pub fn new() -> RenderContext {
+        RenderContext::with_tile_size(TILE_SIZE)
+    }
+
+    // Create new context splitting the image into tiles of the given size instead of `TILE_SIZE`.
+    // The size of the tiles does not change the image (see `render_tile`), only how the threads share the work.
+    fn with_tile_size(tile_size: u32) -> RenderContext {
+        RenderContext {
+            tile_size,
+            tiles: Vec::new(),
+            tile_order: TileOrder::Scanline,
+            tile_buffers: Vec::new(),
+            framebuffer: Framebuffer::new(0, 0),
+            color: Vec::new(),
+            coverage: Vec::new(),
+            light_images: Vec::new(),
+            checkpoint: Checkpoint::new(0, 0, 0),
+            convergence: Convergence::new(0),
+            average_color: Vec::new(),
+            average_coverage: Vec::new(),
+        }
+    }
+
+    // Get ready for a new render, with no passes accumulated. The buffers are only allocated again
+    // when the size of the image, the order of the tiles, or the precision of the sums changes.
+    fn start(&mut self, settings: &RenderSettings) {
+        let (width, height) = (settings.width, settings.height);
+        let pixel_count = (width * height) as usize;
+        if self.framebuffer.width != width || self.framebuffer.height != height || self.tile_order != settings.tile_order {
+            self.tiles = TileIterator::with_order(width, height, self.tile_size, self.tile_size, settings.tile_order).collect();
+            self.tile_order = settings.tile_order;
+            self.tile_buffers = self.tiles.iter().map(|tile| Mutex::new(TileBuffers::new(tile, settings.max_depth))).collect();
+            self.framebuffer = Framebuffer::new(width, height);
+            self.color = vec![Vec3::new(0.0, 0.0, 0.0); pixel_count];
+            self.coverage = vec![0.0; pixel_count];
+            self.light_images.clear();
+            self.convergence = Convergence::new(pixel_count);
+            self.average_color = vec![Vec3::new(0.0, 0.0, 0.0); pixel_count];
+            self.average_coverage = vec![0.0; pixel_count];
+        } else {
+            for buffers in self.tile_buffers.iter_mut() {
+                buffers.get_mut().unwrap().kept.fill(KeptSamples::default());
+            }
+            self.convergence.clear();
+        }
+        if self.checkpoint.width != width || self.checkpoint.height != height || self.checkpoint.double_precision != settings.double_precision {
+            self.checkpoint = if settings.double_precision {
+                Checkpoint::new_double_precision(width, height, settings.seed)
+            } else {
+                Checkpoint::new(width, height, settings.seed)
+            };
+        } else {
+            self.checkpoint.clear(settings.seed);
+        }
+    }
+
+    // Copy the average of the accumulated passes out of the context.
+    fn output(&self, stats: RenderStats) -> RenderOutput {
+        (self.average_color.clone(), self.average_coverage.clone(), self.checkpoint.samples.clone(), stats)
+    }
+}
+
+// Render a single pass of the pixels of a tile into its buffers, replacing the previous pass.
+fn render_tile(scene: &Scene, camera: &PerspectiveCamera, visible_objects: Option<&[usize]>, settings: &RenderSettings, tile: &Tile, pass: u32, buffers: &mut TileBuffers) {
+    let seed = pass_seed(settings.seed, pass);
+    let first_sample = pass * settings.pixel_samples;
+    let mut stats = RenderStats::new(settings.max_depth);
+    buffers.framebuffer.clear();
+    for y in tile.min_y..tile.max_y {
+        for x in tile.min_x..tile.max_x {
+            // Seeded per pixel of the camera, so that neither the size of the tiles nor flipping the image
+            // changes the colors of the pixels.
+            let source = mirror_pixel(settings, (x, y));
+            let mut rng = StdRng::seed_from_u64(pixel_seed(seed, source.0, source.1));
+            let (tile_x, tile_y) = (x - tile.min_x, y - tile.min_y);
+            let i = (tile_y * buffers.framebuffer.width + tile_x) as usize;
+            let (mut color, hit_fraction, sample_count) = sample_pixel(scene, camera, visible_objects, settings, source, first_sample, &mut buffers.kept[i], &mut rng, &mut stats);
+            if sanitize_color(&mut color, &settings.invalid_color) {
+                stats.invalid_pixels += 1;
+            }
+            buffers.framebuffer.add_samples(tile_x, tile_y, &color, sample_count);
+            buffers.coverage[i] = hit_fraction;
+        }
+    }
+    buffers.stats = stats;
+}
+
+// Number of threads to render with, where 0 means one thread per available core.
+fn thread_count(num_threads: u32) -> u32 {
+    if num_threads > 0 {
+        return num_threads;
+    }
+    thread::available_parallelism().map(|n| n.get() as u32).unwrap_or(1).max(1)
+}
+
+// Size of the square tiles that the threads take one after another, in the order of the settings.
+const TILE_SIZE: u32 = 32;
+
+// Call `render` with the index of each of the tiles and the tile on `num_threads` threads, which take
+// the next tile from a shared queue whenever they finish one, so that the tiles start in the given order,
+// and threads done with cheap tiles take more of them.
+fn render_tiles(tiles: &[Tile], num_threads: u32, render: impl Fn(usize, &Tile) + Sync) {
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..num_threads.min(tiles.len() as u32) {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    match tiles.get(i) {
+                        Some(tile) => render(i, tile),
+                        None => break,
+                    }
+                }
+            });
+        }
+    });
+}
+
+// Render a single progressive pass of the whole image into the colors, coverage, and sample counts
+// of the pass in the context, and return the statistics of the pass.
+fn render_pass(context: &mut RenderContext, scene: &Scene, camera: &PerspectiveCamera, settings: &RenderSettings, pass: u32) -> RenderStats {
+    // Objects outside the view of the camera cannot be hit by the primary rays.
+    let visible_objects = if settings.frustum_culling {
+        camera.frustum().map(|frustum| scene.objects_in_frustum(&frustum))
+    } else {
+        None
+    };
+    let tile_buffers = &context.tile_buffers;
+    render_tiles(&context.tiles, thread_count(settings.num_threads), |i, tile| {
+        render_tile(scene, camera, visible_objects.as_deref(), settings, tile, pass, &mut tile_buffers[i].lock().unwrap());
+    });
+    context.framebuffer.clear();
+    let mut stats = RenderStats::new(settings.max_depth);
+    for (tile, buffers) in context.tiles.iter().zip(context.tile_buffers.iter()) {
+        let buffers = buffers.lock().unwrap();
+        context.framebuffer.add_region(tile.min_x, tile.min_y, &buffers.framebuffer);
+        // Copy the coverage row by row to where it belongs in the image.
+        let tile_width = (tile.max_x - tile.min_x) as usize;
+        for (i, y) in (tile.min_y..tile.max_y).enumerate() {
+            let start = (y * settings.width + tile.min_x) as usize;
+            context.coverage[start..start + tile_width].copy_from_slice(&buffers.coverage[i * tile_width..(i + 1) * tile_width]);
+        }
+        stats.merge(&buffers.stats);
+    }
+    context.framebuffer.resolve_into(&mut context.color);
+    if settings.light_samples > 0 {
+        let light_stats = trace_light_paths(&mut context.light_images, scene, camera, settings, pass);
+        for image in &context.light_images {
+            for (c, splatted) in context.color.iter_mut().zip(image.iter()) {
+                *c += splatted;
+            }
+        }
+        stats.merge(&light_stats);
+    }
+    stats
+}
+
+/// Render the whole image, and return its linear (HDR) colors, geometry coverage, and sample counts.
+/// The colors are not tone mapped nor gamma encoded yet; that is up to the output format (see `color::Transfer`).
+pub fn render_scene(scene: Arc<Scene>, camera: Arc<PerspectiveCamera>, settings: Arc<RenderSettings>) -> RenderOutput {
+    let mut context = RenderContext::new();
+    let stats = render_progressive(&mut context, scene, camera, settings, None, None);
+    context.output(stats)
+}
+
+/// Render the whole image into a buffer owned by the caller, with all the other buffers of the render in a context,
+/// so that animations and interactive loops can reuse a single buffer and context for all their frames.
+/// The colors are tone mapped and encoded with 4 bytes (RGBA) per pixel, the same as in 8-bit PNG images,
+/// with the coverage in the alpha channel when `settings.alpha_coverage` is set.
+/// Panics if the buffer does not have exactly `width * height * 4` bytes.
+pub fn render_scene_into(context: &mut RenderContext, scene: Arc<Scene>, camera: Arc<PerspectiveCamera>, settings: Arc<RenderSettings>, buffer: &mut [u8]) -> RenderStats {
+    assert_eq!(buffer.len(), (settings.width * settings.height * 4) as usize, "buffer must have 4 bytes per pixel");
+    let stats = render_progressive(context, scene, camera, settings.clone(), None, None);
+    color::encode_rgba8(&context.average_color, buffer, settings.tone_mapping, settings.png_transfer);
+    if settings.alpha_coverage {
+        color::set_alpha(buffer, &context.average_coverage);
+    }
+    stats
+}
+
+/// Render the whole image into a newly allocated RGBA buffer (see `render_scene_into`).
+pub fn render_scene_rgba8(scene: Arc<Scene>, camera: Arc<PerspectiveCamera>, settings: Arc<RenderSettings>) -> (Vec<u8>, RenderStats) {
+    let mut buffer = vec![0; (settings.width * settings.height * 4) as usize];
+    let stats = render_scene_into(&mut RenderContext::new(), scene, camera, settings, &mut buffer);
+    (buffer, stats)
+}
+
+// Index of the pass used for the preview, chosen so that its random numbers are not shared with any real pass.
+const PREVIEW_PASS: u32 = u32::MAX;
+
+/// Callback reporting the progress of a render, with the number of passes accumulated so far (0 for the preview)
+/// and the average colors and coverage of those passes.
+pub type Progress<'a> = &'a mut dyn FnMut(u32, &[Vec3], &[f32]);
+
+/// Callback reporting the progress of rendering several views, with the index of the view first.
+pub type ViewProgress<'a> = &'a mut dyn FnMut(usize, u32, &[Vec3], &[f32]);
+
+// Render all the progressive passes of the image into the context, which ends up with the average
+// of their colors and coverage, and the total number of samples of each pixel.
+// When a checkpoint file is given, the accumulated passes are saved to it every `checkpoint_interval` passes,
+// and a render started with an existing checkpoint file continues from the passes stored in it.
+// When `settings.preview` is set, a render without any passes accumulated yet starts with a quick preview
+// of the whole image, taking a single sample per pixel. The preview is only reported to `progress`,
+// which is also called after every pass, and does not change the final image.
+// With `settings.convergence_threshold`, the render stops as soon as the relative variance of the average
+// drops below the threshold, even before all the passes are done. The variance is only estimated from
+// the passes rendered by this call, so it takes two passes after resuming to estimate it again,
+// and the same goes for the samples of each pixel used to reject outliers.
+// The statistics only cover the passes rendered by this call.
+fn render_progressive(context: &mut RenderContext, scene: Arc<Scene>, camera: Arc<PerspectiveCamera>, settings: Arc<RenderSettings>, checkpoint_path: Option<&Path>, mut progress: Option<Progress>) -> RenderStats {
+    let scene = match &settings.solo {
+        Some(ids) => Arc::new(scene.isolate(ids)),
+        None => scene,
+    };
+    let settings = if settings.light_samples > 0 && !camera.can_project() {
+        eprintln!("Light tracing is not supported with a focus plane, rendering without it");
+        Arc::new(RenderSettings { light_samples: 0, ..(*settings).clone() })
+    } else {
+        settings
+    };
+    context.start(&settings);
+    context.checkpoint.pixel_samples = settings.pixel_samples;
+    context.checkpoint.max_depth = settings.max_depth;
+    context.checkpoint.scene = scene.fingerprint();
+    if let Some(path) = checkpoint_path.filter(|p| p.exists()) {
+        match Checkpoint::load(path) {
+            Ok(c) if c.width == settings.width && c.height == settings.height && c.seed == settings.seed && c.double_precision == settings.double_precision
+                && c.pixel_samples == settings.pixel_samples && c.max_depth == settings.max_depth && c.scene == context.checkpoint.scene => {
+                if settings.verbose {
+                    eprintln!("Resuming from pass {} of {}", c.passes, settings.passes);
+                }
+                context.checkpoint = c;
+            }
+            Ok(_) if settings.verbose => eprintln!("Ignoring checkpoint {} of a different render", path.display()),
+            Err(e) => eprintln!("Ignoring checkpoint {}: {}", path.display(), e),
+            _ => {},
+        }
+    }
+    let mut stats = RenderStats::new(settings.max_depth);
+    if let Some(report) = progress.as_mut() {
+        if settings.preview && context.checkpoint.passes == 0 && settings.passes > 0 {
+            let preview_settings = RenderSettings { pixel_samples: 1, ..(*settings).clone() };
+            render_pass(context, &scene, &camera, &preview_settings, PREVIEW_PASS);
+            report(0, &context.color, &context.coverage);
+        }
+    }
+    while context.checkpoint.passes < settings.passes {
+        let pass_stats = render_pass(context, &scene, &camera, &settings, context.checkpoint.passes);
+        context.checkpoint.add_pass(&context.color, &context.coverage, context.framebuffer.counts());
+        context.convergence.add_pass(&context.color);
+        stats.merge(&pass_stats);
+        stats.relative_variance = context.convergence.relative_variance();
+        if let Some(path) = checkpoint_path {
+            if settings.checkpoint_interval > 0 && context.checkpoint.passes.is_multiple_of(settings.checkpoint_interval) {
+                if let Err(e) = context.checkpoint.save(path) {
+                    eprintln!("Failed to save checkpoint {}: {}", path.display(), e);
+                }
+            }
+        }
+        if let Some(report) = progress.as_mut() {
+            context.checkpoint.average_into(&mut context.average_color, &mut context.average_coverage);
+            report(context.checkpoint.passes, &context.average_color, &context.average_coverage);
+        }
+        if let Some(relative_variance) = stats.relative_variance {
+            if settings.verbose {
+                eprintln!("Pass {} of {}: relative variance {:.3e}", context.checkpoint.passes, settings.passes, relative_variance);
+            }
+            if settings.convergence_threshold.is_some_and(|threshold| relative_variance < threshold) {
+                if settings.verbose {
+                    eprintln!("Converged after {} passes", context.checkpoint.passes);
+                }
+                break;
+            }
+        }
+    }
+    context.checkpoint.average_into(&mut context.average_color, &mut context.average_coverage);
+    stats
+}
+
+/// Render the scene from each of the cameras, one after another, sharing the scene and the buffers of the render
+/// between all of them. Each view can have its own checkpoint file. The progress is reported together with the index of the view.
+pub fn render_views(scene: Arc<Scene>, cameras: &[PerspectiveCamera], settings: Arc<RenderSettings>, checkpoints: Option<&[PathBuf]>, mut progress: Option<ViewProgress>) -> Vec<RenderOutput> {
+    let mut context = RenderContext::new();
+    cameras.iter().enumerate()
+        .map(|(i, camera)| {
+            let mut view_progress = progress.as_mut().map(|p| move |passes: u32, color: &[Vec3], coverage: &[f32]| p(i, passes, color, coverage));
+            let stats = render_progressive(&mut context, scene.clone(), Arc::new(camera.clone()), settings.clone(), checkpoints.map(|c| c[i].as_path()), view_progress.as_mut().map(|p| p as Progress));
+            context.output(stats)
+        })
+        .collect()
+}
+
+/// Tone map and encode rendered colors, and save them to a PNG file with the bit depth of the settings.
+/// The alpha channel is the coverage when `settings.alpha_coverage` is set, and opaque otherwise.
+pub fn save_png(path: &Path, hdr: &[Vec3], coverage: &[f32], settings: &RenderSettings) -> Result<(), png::EncodingError> {
+    write_png(BufWriter::new(File::create(path)?), hdr, coverage, settings)
+}
+
+/// Tone map and encode rendered colors into PNG data written to a writer, for example, the standard output,
+/// the same way as `save_png`.
+pub fn write_png(writer: impl Write, hdr: &[Vec3], coverage: &[f32], settings: &RenderSettings) -> Result<(), png::EncodingError> {
+    match settings.bit_depth {
+        BitDepth::Eight => {
+            let mut buff = color::to_rgba8(hdr, settings.tone_mapping, settings.png_transfer);
+            if settings.alpha_coverage {
+                color::set_alpha(&mut buff, coverage);
+            }
+            Image::new(settings.width, settings.height, buff).write_png(writer)
+        },
+        BitDepth::Sixteen => {
+            let mut buff = color::to_rgba16(hdr, settings.tone_mapping, settings.png_transfer);
+            if settings.alpha_coverage {
+                color::set_alpha16(&mut buff, coverage);
+            }
+            image::write_png16(writer, &buff, settings.width, settings.height)
+        },
+    }
+}
+
+/// Materials of the example scene, by name.
+pub fn example_materials() -> MaterialLibrary {
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    let black = Vec3::new(0.0, 0.0, 0.0);
+    MaterialLibrary::new()
+        .with("floor", Material::Diffuse(white, Texture::checkered(white, black, 200.0), 0.0))
+        .with("mirror", Material::Metal(white, 0.0, 0.0, 0.0))
+        .with("gold", Material::Metal(Vec3::new(0.9, 0.6, 0.3), 0.1, 0.0, 0.0))
+        .with("brushed", Material::Metal(white, 0.2, 0.0, 0.0))
+        .with("normal", Material::Normal)
+        .with("white", Material::Diffuse(white, Texture::None, 0.0))
+        .with("yellow_light", Material::Light(Vec3::new(1.0, 1.0, 0.0)))
+        .with("diamond", Material::Glass(white, 2.0))
+        .with("blue_glass", Material::Glass(Vec3::new(0.3, 0.6, 0.9), 1.75))
+        .with("glass", Material::Glass(white, 1.5))
+}
+
+/// Spheres of the example scene: a checkered floor with a row of each of the metal, glass, and other materials
+/// (see `example_materials`).
+pub fn example_spheres() -> Vec<Sphere> {
+    let materials = example_materials();
+    vec!(
+        Sphere::new(Vec3::new(0.0, -100.0, 0.0), 99.0, materials.material("floor")),
+
+        Sphere::new(Vec3::new(-2.5, 0.0, -2.5), 1.0, materials.material("mirror")),
+        Sphere::new(Vec3::new(-2.5, 0.0, 0.0),  1.0, materials.material("gold")),
+        Sphere::new(Vec3::new(-2.5, 0.0, 2.5),  1.0, materials.material("brushed")),
+
+        Sphere::new(Vec3::new(0.0, 0.0, -2.5),  1.0, materials.material("normal")),
+        Sphere::new(Vec3::new(0.0, 0.0, 0.0),   1.0, materials.material("white")),
+        Sphere::new(Vec3::new(0.0, 0.0, 2.5),   1.0, materials.material("yellow_light")),
+
+        Sphere::new(Vec3::new(2.5, 0.0, -2.5),  1.0, materials.material("diamond")),
+        Sphere::new(Vec3::new(2.5, 0.0, 0.0),   1.0, materials.material("blue_glass")),
+        Sphere::new(Vec3::new(2.5, 0.0, 2.5),   1.0, materials.material("glass")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scene::{ Background, Visibility };
+    use sampling::LightSelection;
+    use settings::LightingComponent;
+    use color::{ ToneMapping, Transfer };
+    use instance::Instance;
+    use xform::Transform;
+    use mesh::{ Mesh, Triangle };
+
+    fn sky_camera(settings: &RenderSettings) -> Arc<PerspectiveCamera> {
+        Arc::new(PerspectiveCamera::look_at(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            settings.width as f32 / settings.height as f32,
+            1.0,
+            0.0,
+        ))
+    }
+
+    // Render all the progressive passes with a new context, for tests of checkpoints and progress.
+    fn progressive(scene: Arc<Scene>, camera: Arc<PerspectiveCamera>, settings: Arc<RenderSettings>, checkpoint_path: Option<&Path>, progress: Option<Progress>) -> RenderOutput {
+        let mut context = RenderContext::new();
+        let stats = render_progressive(&mut context, scene, camera, settings, checkpoint_path, progress);
+        context.output(stats)
+    }
+
+    fn small_settings() -> RenderSettings {
+        RenderSettings {
+            width: 16,
+            height: 16,
+            pixel_samples: 4,
+            num_threads: 2,
+            ..RenderSettings::default()
+        }
+    }
+
+    #[test]
+    fn tiles_start_in_order() {
+        let tiles: Vec<Tile> = TileIterator::with_order(100, 70, 16, 16, TileOrder::Spiral).collect();
+        // A single thread takes the tiles one by one, in the order of the queue.
+        let started = Mutex::new(Vec::new());
+        render_tiles(&tiles, 1, |i, tile| started.lock().unwrap().push((i, *tile)));
+        assert_eq!(*started.lock().unwrap(), tiles.iter().copied().enumerate().collect::<Vec<_>>());
+        // Any number of threads renders each tile exactly once.
+        for &num_threads in &[3, 100] {
+            let started = Mutex::new(Vec::new());
+            render_tiles(&tiles, num_threads, |i, tile| started.lock().unwrap().push((i, *tile)));
+            let mut started = started.into_inner().unwrap();
+            started.sort_by_key(|(i, _)| *i);
+            assert_eq!(started, tiles.iter().copied().enumerate().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn intersection_tests_heatmap() {
+        let mut spheres: Vec<Sphere> = (0..10).map(|i| Sphere::new(Vec3::new(i as f32 - 4.5, 0.0, -5.0 - 3.0 * i as f32), 0.5, Material::Normal)).collect();
+        spheres.push(Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Normal));
+        let mut scene = Scene::new(spheres);
+        let settings = small_settings();
+        let camera = sky_camera(&settings);
+        let tests = render_intersection_tests(&scene, &camera, &settings);
+        assert_eq!(tests.len(), 16 * 16);
+        assert!(tests.iter().all(|t| *t == 11));
+        // The grid saves tests, more so for the rays going over the spheres into the sky.
+        scene.build_grid(1);
+        let tests = render_intersection_tests(&scene, &camera, &settings);
+        assert!(tests.iter().all(|t| *t < 11 + 10));
+        assert!(tests.iter().any(|t| *t < 11));
+    }
+
+    #[test]
+    fn uv_pass() {
+        let scene = Scene::new(vec![
+            Sphere::new(Vec3::new(-1.0, 0.0, -3.0), 0.8, Material::Normal),
+            Sphere::new(Vec3::new(1.0, 0.0, -3.0), 0.8, Material::Normal),
+        ]);
+        let settings = small_settings();
+        let camera = sky_camera(&settings);
+        let uvs = render_uv_pass(&scene, &camera, &settings);
+        assert_eq!(uvs.len(), 16 * 16);
+        let pixel = |x: usize, y: usize| uvs[y * 16 + x];
+        let (left, right) = (pixel(4, 8), pixel(11, 8));
+        assert!(left.z == 1.0 || left.z == 2.0); assert_eq!(left.z + right.z, 3.0);
+        // U goes around each sphere, changing steadily across its visible side.
+        assert!(pixel(3, 8).x > pixel(4, 8).x && pixel(4, 8).x > pixel(5, 8).x || pixel(3, 8).x < pixel(4, 8).x && pixel(4, 8).x < pixel(5, 8).x);
+        assert!(uvs.iter().all(|c| (0.0..=1.0).contains(&c.x) && (0.0..=1.0).contains(&c.y)));
+        let miss = pixel(0, 0);
+        assert_eq!(miss.x, 0.0); assert_eq!(miss.y, 0.0); assert_eq!(miss.z, 0.0);
+    }
+
+    #[test]
+    fn single_sample_at_pixel_center() {
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Material::Normal),
+        ]));
+        let settings = RenderSettings { pixel_samples: 1, center_single_sample: true, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let render = |settings: RenderSettings| render_scene(scene.clone(), camera.clone(), Arc::new(settings)).0;
+        // Same image regardless of the seed...
+        let (a, b) = (render(settings.clone()), render(RenderSettings { seed: 5, ..settings.clone() }));
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert_eq!(a.x, b.x); assert_eq!(a.y, b.y); assert_eq!(a.z, b.z);
+        }
+        // ...with the ray through the center of the pixel.
+        let (x, y) = (11, 6);
+        let ray = camera.generate_ray((x as f32 + 0.5) / 16.0 - 0.5, 0.5 - (y as f32 + 0.5) / 16.0, &mut StdRng::seed_from_u64(0));
+        let n = scene.hit(&ray).unwrap().n;
+        let c = a[y * 16 + x];
+        assert!((c.x - 0.5 * (n.x + 1.0)).abs() < 1e-6); assert!((c.y - 0.5 * (n.y + 1.0)).abs() < 1e-6); assert!((c.z - 0.5 * (n.z + 1.0)).abs() < 1e-6);
+        // Jittered by default, and with more samples.
+        let jittered = |settings: RenderSettings| render(settings.clone()).iter().zip(render(RenderSettings { seed: 5, ..settings }).iter()).any(|(a, b)| a.x != b.x);
+        assert!(jittered(RenderSettings { center_single_sample: false, ..settings.clone() }));
+        assert!(jittered(RenderSettings { pixel_samples: 2, ..settings }));
+    }
+
+    #[test]
+    fn solo_hides_other_objects() {
+        let floor = Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0));
+        let settings = RenderSettings { sample_background: true, ..small_settings() };
+        let camera = Arc::new(PerspectiveCamera::look_at(
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::new(0.0, -1.0, -4.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            1.0,
+            1.0,
+            0.0,
+        ));
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -4.0), 1.0, Material::Metal(Vec3::new(0.9, 0.9, 0.9), 0.0, 0.0, 0.0)),
+            floor.clone(),
+        ]));
+        let floor_only = render_scene(Arc::new(Scene::new(vec![floor])), camera.clone(), Arc::new(settings.clone())).0;
+        let full = render_scene(scene.clone(), camera.clone(), Arc::new(settings.clone())).0;
+        let solo = render_scene(scene, camera, Arc::new(RenderSettings { solo: Some(vec![1]), ..settings })).0;
+        // Without the sphere and its shadow, the paths are exactly the same as without the sphere in the scene.
+        for (a, b) in floor_only.iter().zip(solo.iter()) {
+            assert_eq!(a.x, b.x); assert_eq!(a.y, b.y); assert_eq!(a.z, b.z);
+        }
+        assert!(full.iter().zip(solo.iter()).any(|(a, b)| a.x != b.x));
+    }
+
+    #[test]
+    fn isotropic_anisotropic_metal() {
+        let settings = RenderSettings { light_samples: 2, ..small_settings() };
+        let camera = Arc::new(PerspectiveCamera::look_at(
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::new(0.0, -1.0, -4.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            1.0,
+            1.0,
+            0.0,
+        ));
+        let render = |metal: Material| {
+            let scene = Arc::new(Scene::new(vec![
+                Sphere::new(Vec3::new(0.0, 0.0, -4.0), 1.0, metal),
+                Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+                Sphere::new(Vec3::new(2.0, 3.0, -4.0), 0.5, Material::Light(Vec3::new(4.0, 4.0, 4.0))),
+            ]));
+            render_scene(scene, camera.clone(), Arc::new(settings.clone())).0
+        };
+        // Equal roughness values take the same paths as the isotropic metal, mirrors included.
+        let albedo = Vec3::new(0.9, 0.6, 0.3);
+        for &roughness in &[0.0, 0.2] {
+            let isotropic = render(Material::Metal(albedo, roughness, 0.0, 0.0));
+            let anisotropic = render(Material::AnisotropicMetal(albedo, roughness, roughness));
+            for (a, b) in isotropic.iter().zip(anisotropic.iter()) {
+                assert_eq!(a.x, b.x); assert_eq!(a.y, b.y); assert_eq!(a.z, b.z);
+            }
+        }
+        assert!(render(Material::AnisotropicMetal(albedo, 0.0, 0.2)).iter().zip(render(Material::Metal(albedo, 0.0, 0.0, 0.0)).iter()).any(|(a, b)| a.x != b.x));
+    }
+
+    #[test]
+    fn anisotropic_metal_highlight() {
+        let settings = RenderSettings { width: 33, height: 33, pixel_samples: 32, ..small_settings() };
+        let camera = Arc::new(PerspectiveCamera::look_at(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            1.0,
+            1.0,
+            0.0,
+        ));
+        // A wall facing the camera, with the tangent along X and the bitangent along Y, reflecting
+        // a light behind the camera.
+        let render = |roughness_u: f32, roughness_v: f32| {
+            let mut scene = Scene::new(vec![
+                Sphere::new(Vec3::new(0.0, 0.0, -1004.0), 1000.0, Material::AnisotropicMetal(Vec3::new(1.0, 1.0, 1.0), roughness_u, roughness_v)),
+                Sphere::new(Vec3::new(0.0, 0.0, 1.0), 1.0, Material::Light(Vec3::new(4.0, 4.0, 4.0))),
+            ]);
+            scene.background = Background::Gradient(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+            render_scene(Arc::new(scene), camera.clone(), Arc::new(settings.clone())).0
+        };
+        // Light reflected away from the center along the middle row and column of the image.
+        let spread = |image: &[Vec3]| {
+            let (mut row, mut column) = (0.0, 0.0);
+            for i in (0..33).filter(|i| (*i as i32 - 16).abs() > 4) {
+                row += image[16 * 33 + i].x;
+                column += image[i * 33 + 16].x;
+            }
+            (row, column)
+        };
+        // The highlight stretches along the rougher direction.
+        let (row, column) = spread(&render(0.3, 0.01));
+        assert!(row > 2.0 * column);
+        let (row, column) = spread(&render(0.01, 0.3));
+        assert!(column > 2.0 * row);
+    }
+
+    #[test]
+    fn irradiance_probe() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        // A uniform sky arrives the same from all directions.
+        let mut scene = Scene::new(vec![]);
+        scene.background = Background::Gradient(Vec3::new(0.2, 0.4, 0.8), Vec3::new(0.2, 0.4, 0.8));
+        let e = scene.irradiance_at(&Vec3::new(0.0, 0.0, 0.0), &up, 16, &mut rng);
+        assert!((e.x - 0.2).abs() < 1e-6); assert!((e.y - 0.4).abs() < 1e-6); assert!((e.z - 0.8).abs() < 1e-6);
+        assert_eq!(scene.irradiance_at(&Vec3::new(0.0, 0.0, 0.0), &up, 0, &mut rng).x, 0.0);
+        // A black sphere above the probe, at twice its radius, blocks the cosine-weighted fraction sin^2 (30 deg) of the sky.
+        let mut scene = Scene::new(vec![Sphere::new(Vec3::new(0.0, 2.0, 0.0), 1.0, Material::Diffuse(Vec3::new(0.0, 0.0, 0.0), Texture::None, 0.0))]);
+        scene.background = Background::Gradient(Vec3::new(1.0, 1.0, 1.0), Vec3::new(1.0, 1.0, 1.0));
+        let e = scene.irradiance_at(&Vec3::new(0.0, 0.0, 0.0), &Vec3::new(0.0, 3.0, 0.0), 1024, &mut rng);
+        assert!((e.x - 0.75).abs() < 0.01);
+        // Facing away from it, nothing is blocked.
+        let e = scene.irradiance_at(&Vec3::new(0.0, 0.0, 0.0), &Vec3::new(0.0, -1.0, 0.0), 64, &mut rng);
+        assert!((e.x - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn degenerate_diffuse_direction() {
+        let n = Vec3::new(0.0, 1.0, 0.0);
+        let d = diffuse_direction(&n, &Vec3::new(0.6, -0.5, 0.0));
+        assert!((length(&d) - 1.0).abs() < 1e-6); assert!(dot(&d, &n) > 0.0);
+        // Points right opposite the normal go along it, instead of producing NaNs or a zero direction.
+        for rand in &[Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, -0.99999994, 0.0), Vec3::new(1e-5, -0.9999, -1e-5)] {
+            let d = diffuse_direction(&n, rand);
+            assert_eq!(d.x, 0.0); assert_eq!(d.y, 1.0); assert_eq!(d.z, 0.0);
+        }
+    }
+
+    #[test]
+    fn cosine_directions() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let n = 100000;
+        let mut cos_sum = 0.0;
+        let mut cos_squared_sum = 0.0;
+        for _ in 0..n {
+            let d = random_cosine_direction(&mut rng);
+            assert!((length_squared(&d) - 1.0).abs() < 1e-5 && d.z >= 0.0);
+            cos_sum += d.z;
+            cos_squared_sum += d.z * d.z;
+        }
+        // With density cos / PI, the cosine averages to 2/3, and its square to 1/2.
+        assert!((cos_sum / n as f32 - 2.0 / 3.0).abs() < 0.01);
+        assert!((cos_squared_sum / n as f32 - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn show_uv() {
+        let scene = Scene::new(vec![Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Material::Uv)]);
+        let settings = small_settings();
+        let mut stats = RenderStats::new(settings.max_depth);
+        let mut trace = |o: Vec3, d: Vec3| trace_ray(&scene, &Ray::new(o, d), None, &settings, &mut StdRng::seed_from_u64(0), &mut stats, PathState::new(0.0));
+        // Facing the camera, a quarter of the way around the equator.
+        let c = trace(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!((c.x - 0.25).abs() < 1e-6); assert!((c.y - 0.5).abs() < 1e-6); assert_eq!(c.z, 0.0);
+        // V goes from 0.0 at the bottom pole to 1.0 at the top one.
+        let c = trace(Vec3::new(0.0, 5.0, -3.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!((c.y - 1.0).abs() < 1e-6);
+        let c = trace(Vec3::new(0.0, -5.0, -3.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(c.y.abs() < 1e-6);
+    }
+
+    #[test]
+    fn wireframe_edges() {
+        // A square facing the camera, split into two triangles along its diagonal from (-1, -1) to (1, 1).
+        let red = Vec3::new(1.0, 0.0, 0.0);
+        let wireframe = Material::Wireframe(Box::new(Material::Normal), red, 0.1);
+        let (a, b, c, d) = (Vec3::new(-1.0, -1.0, -5.0), Vec3::new(1.0, -1.0, -5.0), Vec3::new(1.0, 1.0, -5.0), Vec3::new(-1.0, 1.0, -5.0));
+        let triangles = vec![Triangle::new(a, b, c, wireframe.clone()), Triangle::new(a, c, d, wireframe.clone())];
+        let settings = small_settings();
+        let mut stats = RenderStats::new(settings.max_depth);
+        let mut trace = |scene: &Scene, x: f32, y: f32| trace_ray(scene, &Ray::new(Vec3::new(x, y, 0.0), Vec3::new(0.0, 0.0, -1.0)), None, &settings, &mut StdRng::seed_from_u64(0), &mut stats, PathState::new(0.0));
+        let mut scene = Scene::new(vec![]);
+        scene.meshes.push(Mesh::new(triangles.clone()));
+        // Within half the width of an edge, the flat edge color, and the surface everywhere else.
+        let c = trace(&scene, 0.97, 0.0);
+        assert_eq!(c.x, 1.0); assert_eq!(c.y, 0.0); assert_eq!(c.z, 0.0);
+        let c = trace(&scene, 0.02, 0.0);
+        assert_eq!(c.x, 1.0); assert_eq!(c.y, 0.0);
+        let c = trace(&scene, 0.5, -0.3);
+        assert_eq!(c.x, 0.5); assert_eq!(c.y, 0.5); assert_eq!(c.z, 1.0);
+        // Merged into a quad, the square has no diagonal.
+        let mut scene = Scene::new(vec![]);
+        scene.meshes.push(Mesh::new(triangles).with_quads());
+        assert_eq!(trace(&scene, 0.02, 0.0).x, 0.5);
+        assert_eq!(trace(&scene, 0.97, 0.0).x, 1.0);
+        // Spheres have no edges.
+        let scene = Scene::new(vec![Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, wireframe)]);
+        assert_eq!(trace(&scene, 0.0, 0.0).x, 0.5);
+    }
+
+    #[test]
+    fn clearcoat_layer_selection() {
+        let gold = Vec3::new(0.9, 0.6, 0.3);
+        let scene = Scene::new(vec![Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Normal)]);
+        let mut rng = StdRng::seed_from_u64(0);
+        // Without a clearcoat, always the metal.
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = scene.hit(&ray).unwrap();
+        for _ in 0..100 {
+            let (albedo, roughness) = metal_layer(&ray, &hit, gold, 0.2, 0.0, 0.0, &mut rng);
+            assert_eq!(albedo.x, gold.x); assert_eq!(roughness, 0.2);
+        }
+        // With one, picked by its weight times the Fresnel reflectance, which rises at grazing angles.
+        let mut coat_fraction = |ray: &Ray| {
+            let hit = scene.hit(ray).unwrap();
+            let n = 100_000;
+            let coats = (0..n).filter(|_| metal_layer(ray, &hit, gold, 0.2, 0.5, 0.0, &mut rng).1 == 0.0).count();
+            let cos_theta = dot(&normalize(&ray.d), &hit.n).abs();
+            (coats as f32 / n as f32, 0.5 * reflectance(cos_theta, 1.0 / CLEARCOAT_IOR))
+        };
+        let (normal, expected) = coat_fraction(&ray);
+        assert!((normal - 0.02).abs() < 0.002 && (normal - expected).abs() < 0.002);
+        let (grazing, expected) = coat_fraction(&Ray::new(Vec3::new(0.98, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)));
+        assert!((grazing - expected).abs() < 0.01 && grazing > 5.0 * normal);
+    }
+
+    #[test]
+    fn same_image_at_any_scale() {
+        let settings = RenderSettings { sample_background: true, ..small_settings() };
+        let render = |scale: f32| {
+            let scene = Scene::new(vec![
+                Sphere::new(scale * &Vec3::new(0.0, -1001.0, 0.0), scale * 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+                Sphere::new(scale * &Vec3::new(0.5, 0.0, -3.0), scale, Material::Diffuse(Vec3::new(0.8, 0.6, 0.2), Texture::None, 0.0)),
+                Sphere::new(scale * &Vec3::new(-1.5, 0.0, -4.0), scale, Material::Glass(Vec3::new(1.0, 1.0, 1.0), 1.5)),
+            ]);
+            let camera = Arc::new(PerspectiveCamera::look_at(
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(0.0, -0.2, -1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                60.0,
+                1.0,
+                1.0,
+                0.0,
+            ));
+            let hdr = render_scene(Arc::new(scene), camera, Arc::new(settings.clone())).0;
+            hdr.iter().map(|c| c.x + c.y + c.z).sum::<f32>() / hdr.len() as f32
+        };
+        // With a fixed offset of spawned rays, large scenes are darkened by surfaces shadowing themselves
+        // (shadow acne), and in small ones, rays skip through thin objects.
+        let reference = render(1.0);
+        for &scale in &[1e-3, 1e4] {
+            assert!((render(scale) - reference).abs() < 0.01 * reference);
+        }
+    }
+
+    #[test]
+    fn reject_fireflies() {
+        // Floor lit by the sky, and by a small, very bright light that paths bouncing off the floor only rarely hit.
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.0, 1.0, -3.0), 0.2, Material::Light(Vec3::new(300.0, 300.0, 300.0))),
+        ]));
+        let settings = RenderSettings { pixel_samples: 32, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let render = |settings: RenderSettings| render_scene(scene.clone(), camera.clone(), Arc::new(settings));
+        let firefly_pixels = |hdr: &[Vec3]| hdr[8 * 16..].iter().filter(|c| luminance(c) > 2.0).count();
+        let (noisy, _, _, stats) = render(settings.clone());
+        assert_eq!(stats.rejected_samples, 0);
+        let (clean, _, samples, stats) = render(RenderSettings { outlier_sigma: Some(3.0), ..settings.clone() });
+        assert!(stats.rejected_samples > 0);
+        assert!(samples.iter().all(|&n| n == 32));
+        // Fireflies among the first samples of a pixel, before the rejection starts, are kept.
+        assert!(firefly_pixels(&noisy) >= 10);
+        assert!(firefly_pixels(&clean) * 2 < firefly_pixels(&noisy));
+        // The brightness of the samples carries over from pass to pass, so that even passes of a single sample reject outliers.
+        let (single, _, _, stats) = render(RenderSettings { outlier_sigma: Some(3.0), pixel_samples: 1, passes: 32, ..settings.clone() });
+        assert!(stats.rejected_samples > 0);
+        assert!(firefly_pixels(&single) * 2 < firefly_pixels(&noisy));
+        // Without any outliers, such as in the smooth sky, the few samples left out barely change the image.
+        let sky = |settings: RenderSettings| render_scene(Arc::new(Scene::new(vec![])), camera.clone(), Arc::new(settings));
+        let (a, _, _, _) = sky(RenderSettings { outlier_sigma: Some(3.0), ..settings.clone() });
+        for (a, b) in a.iter().zip(sky(settings.clone()).0.iter()) {
+            assert!((luminance(a) - luminance(b)).abs() < 0.01 * luminance(b));
+        }
+        // Without any samples, there is nothing to reject, nor to divide by.
+        let (empty, coverage, _, stats) = render(RenderSettings { outlier_sigma: Some(3.0), pixel_samples: 0, ..settings });
+        assert_eq!(stats.invalid_pixels, 0);
+        assert!(empty.iter().all(|c| c.x == 0.0 && c.y == 0.0 && c.z == 0.0));
+        assert!(coverage.iter().all(|&c| c == 0.0));
+    }
+
+    #[test]
+    fn direct_light_from_sphere() {
+        // A light right above a point on the floor, at twice its radius: a cosine-weighted average of sin^2 (30 deg).
+        let scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.0, 2.0, 0.0), 1.0, Material::Light(Vec3::new(1.0, 2.0, 4.0))),
+        ]);
+        let ray = Ray::new(Vec3::new(0.0, 0.5, 0.5), normalize(&Vec3::new(0.0, -1.0, -1.0)));
+        let hit = scene.spheres[0].hit(&ray).unwrap();
+        let average = |settings: &RenderSettings| {
+            let mut rng = StdRng::seed_from_u64(0);
+            let n = 10000;
+            let mut sum = Vec3::new(0.0, 0.0, 0.0);
+            for _ in 0..n {
+                sum += &direct_light(&scene, &ray, &hit, 0.0, settings, &mut rng);
+            }
+            (1.0 / n as f32) * &sum
+        };
+        let lambert = average(&RenderSettings { sample_background: true, ..RenderSettings::default() });
+        assert!((lambert.x - 0.25).abs() < 0.0025); assert!((lambert.y - 0.5).abs() < 0.005); assert!((lambert.z - 1.0).abs() < 0.01);
+        // With the bounces at density 2 cos^3 / PI instead, the light averages to 1 - cos^4 (30 deg).
+        let bounces = average(&RenderSettings::default());
+        assert!((bounces.x - 0.4375).abs() < 0.005); assert!((bounces.z - 1.75).abs() < 0.02);
+    }
+
+    #[test]
+    fn direct_light_matches_bounces() {
+        // Gray ground under a black sky and a light covering 30 degrees around the normal (see `direct_light_from_sphere`),
+        // found either by the bounces off the ground or by sampling the light, with and without cosine-weighted bounces.
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.0, 2.0, 0.0), 1.5, Material::Light(Vec3::new(1.0, 1.0, 1.0))),
+        ]);
+        scene.background = Background::Gradient(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let camera = PerspectiveCamera::look_at(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            10.0,
+            1.0,
+            1.0,
+            0.0,
+        );
+        for &(sample_background, expected) in &[(false, 0.5 * 0.4375), (true, 0.5 * 0.25)] {
+            let settings = RenderSettings { pixel_samples: 4096, max_depth: 2, sample_background, ..small_settings() };
+            let bounces = render_pixel(&scene, &camera, &settings, 8, 8);
+            let sampled = render_pixel(&scene, &camera, &RenderSettings { sample_lights: true, ..settings }, 8, 8);
+            assert!((bounces.x - expected).abs() < 0.01);
+            assert!((sampled.x - expected).abs() < 0.005);
+        }
+    }
+
+    #[test]
+    fn sample_mesh_lights() {
+        // Gray ground under a black sky and a square light above it, as a quad, found either by the bounces
+        // off the ground or by sampling the light.
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+        ]);
+        let light = Material::Light(Vec3::new(1.0, 1.0, 1.0));
+        let (a, b, c, d) = (Vec3::new(-1.0, 1.0, -1.0), Vec3::new(1.0, 1.0, -1.0), Vec3::new(1.0, 1.0, 1.0), Vec3::new(-1.0, 1.0, 1.0));
+        scene.meshes.push(Mesh::new(vec![Triangle::new(a, b, c, light.clone()), Triangle::new(a, c, d, light)]).with_quads());
+        scene.update_lights();
+        scene.background = Background::Gradient(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let camera = PerspectiveCamera::look_at(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            10.0,
+            1.0,
+            1.0,
+            0.0,
+        );
+        for &sample_background in &[false, true] {
+            let settings = RenderSettings { pixel_samples: 4096, max_depth: 2, sample_background, ..small_settings() };
+            let bounces = render_pixel(&scene, &camera, &settings, 8, 8);
+            let sampled = render_pixel(&scene, &camera, &RenderSettings { sample_lights: true, ..settings }, 8, 8);
+            assert!(bounces.x > 0.05);
+            assert!((sampled.x - bounces.x).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn sample_lights_through_glass() {
+        // The light and ground of `direct_light_matches_bounces`, with the light in a shell of glass that does
+        // not bend the light. The light sampled through the glass is not found again by the bounces through it.
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.0, 2.0, 0.0), 1.5, Material::Light(Vec3::new(1.0, 1.0, 1.0))),
+        ]);
+        scene.background = Background::Gradient(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let camera = PerspectiveCamera::look_at(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            10.0,
+            1.0,
+            1.0,
+            0.0,
+        );
+        let settings = RenderSettings { pixel_samples: 4096, sample_lights: true, ..small_settings() };
+        let open = render_pixel(&scene, &camera, &settings, 8, 8);
+        scene.spheres.push(Sphere::new(Vec3::new(0.0, 2.0, 0.0), 1.7, Material::Glass(Vec3::new(1.0, 1.0, 1.0), 1.0)));
+        let shell = render_pixel(&scene, &camera, &settings, 8, 8);
+        assert!((open.x - 0.5 * 0.4375).abs() < 0.005);
+        assert!((shell.x - open.x).abs() < 0.01);
+    }
+
+    #[test]
+    fn sample_many_lights() {
+        // Floor lit by a row of lights of very different power, without any sky.
+        let mut spheres = vec![Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0))];
+        spheres.extend((0..12).map(|i| {
+            let e = if i % 4 == 0 { 40.0 } else { 0.5 };
+            Sphere::new(Vec3::new(i as f32 - 5.5, 0.5 * (i % 3) as f32, -6.0), 0.4, Material::Light(Vec3::new(e, e, 0.5 * e)))
+        }));
+        let mut scene = Scene::new(spheres);
+        scene.background = Background::Gradient(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let scene = Arc::new(scene);
+        let settings = RenderSettings { pixel_samples: 64, max_depth: 2, sample_lights: true, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let render = |settings: RenderSettings| render_scene(scene.clone(), camera.clone(), Arc::new(settings)).0;
+        let uniform_settings = RenderSettings { light_selection: LightSelection::Uniform, ..settings.clone() };
+        let power = render(settings.clone());
+        let uniform = render(uniform_settings.clone());
+        // Choosing the lights by power gives the same floor on average as choosing them uniformly.
+        let floor = |hdr: &[Vec3]| hdr[12 * 16..].iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, c| &sum + c);
+        let (a, b) = (floor(&power), floor(&uniform));
+        assert!((a.x - b.x).abs() < 0.02 * b.x); assert!((a.z - b.z).abs() < 0.02 * b.z);
+        // But with less noise, as the few bright lights are sampled more often.
+        let reference = render(RenderSettings { pixel_samples: 1024, seed: 1, ..uniform_settings });
+        let error = |hdr: &[Vec3]| hdr[12 * 16..].iter().zip(reference[12 * 16..].iter()).map(|(a, b)| (a.x - b.x) * (a.x - b.x)).sum::<f32>();
+        assert!(error(&power) < 0.75 * error(&uniform));
+    }
+
+    #[test]
+    fn clamp_indirect_light_only() {
+        // Floor lit by the sky, and by a small, very bright light, both directly and through a diffuse sphere.
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.5, -0.5, -3.0), 0.5, Material::Diffuse(Vec3::new(0.9, 0.9, 0.9), Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.0, 0.5, -3.0), 0.2, Material::Light(Vec3::new(300.0, 300.0, 300.0))),
+        ]));
+        let settings = RenderSettings { pixel_samples: 16, max_depth: 4, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let render = |settings: RenderSettings| render_scene(scene.clone(), camera.clone(), Arc::new(settings)).0;
+        let clamped = RenderSettings { indirect_clamp: Some(1.0), ..settings.clone() };
+        // Lights seen directly, and the light they shine directly onto surfaces, stay as they are.
+        let direct = render(RenderSettings { lighting: LightingComponent::Direct, ..settings.clone() });
+        let direct_clamped = render(RenderSettings { lighting: LightingComponent::Direct, ..clamped.clone() });
+        assert!(direct.iter().zip(direct_clamped.iter()).all(|(a, b)| a.x == b.x && a.y == b.y && a.z == b.z));
+        assert!(direct.iter().any(|c| c.x > 100.0));
+        // Light bounced more than once reaches the camera off a surface with an albedo of at most 0.9.
+        let indirect = render(RenderSettings { lighting: LightingComponent::Indirect, ..settings.clone() });
+        let indirect_clamped = render(RenderSettings { lighting: LightingComponent::Indirect, ..clamped });
+        assert!(indirect.iter().any(|c| c.x > 0.9));
+        assert!(indirect_clamped.iter().all(|c| c.x <= 0.9 + 1e-5 && c.y <= 0.9 + 1e-5 && c.z <= 0.9 + 1e-5));
+        assert!(indirect_clamped.iter().any(|c| c.x > 0.0));
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        // Dark and bright spheres on a floor, lit by the sky.
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.8, 0.8, 0.8), Texture::None, 0.0)),
+            Sphere::new(Vec3::new(-1.0, 0.0, -3.0), 1.0, Material::Diffuse(Vec3::new(0.1, 0.1, 0.1), Texture::None, 0.0)),
+            Sphere::new(Vec3::new(1.0, 0.0, -3.0), 1.0, Material::Metal(Vec3::new(0.9, 0.6, 0.3), 0.1, 0.0, 0.0)),
+        ]));
+        let settings = RenderSettings { pixel_samples: 64, max_depth: 32, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let render = |settings: RenderSettings| render_scene(scene.clone(), camera.clone(), Arc::new(settings));
+        let (fixed, _, _, fixed_stats) = render(settings.clone());
+        let (roulette, _, _, roulette_stats) = render(RenderSettings { russian_roulette: Some(1), ..settings.clone() });
+        // The same light on average, from much shorter paths.
+        let total = |hdr: &[Vec3]| hdr.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, c| &sum + c);
+        let (fixed, roulette) = (total(&fixed), total(&roulette));
+        assert!((roulette.x - fixed.x).abs() < 0.02 * fixed.x);
+        assert!((roulette.y - fixed.y).abs() < 0.02 * fixed.y);
+        assert!((roulette.z - fixed.z).abs() < 0.02 * fixed.z);
+        let mean_depth = |stats: &RenderStats| stats.path_depths.iter().enumerate().map(|(d, &n)| d as u64 * n).sum::<u64>() as f32 / stats.total_paths() as f32;
+        assert!(mean_depth(&roulette_stats) < 0.8 * mean_depth(&fixed_stats));
+    }
+
+    #[test]
+    fn thread_count_does_not_change_image() {
+        // Glass focusing a light onto the ground, with caustics rendered by light tracing.
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.0, 0.0, -3.0), 0.7, Material::Glass(Vec3::new(1.0, 1.0, 1.0), 1.5)),
+            Sphere::new(Vec3::new(0.0, 3.0, -3.0), 0.5, Material::Light(Vec3::new(4.0, 4.0, 4.0))),
+        ]));
+        // Several tiles, which the threads take in a different order each time.
+        let settings = RenderSettings { width: 80, height: 40, light_samples: 1, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let render = |num_threads| render_scene(scene.clone(), camera.clone(), Arc::new(RenderSettings { num_threads, ..settings.clone() })).0;
+        let single = render(1);
+        let bytes = color::to_rgba8(&single, settings.tone_mapping, settings.png_transfer);
+        for &num_threads in &[3, 7] {
+            let image = render(num_threads);
+            for (a, b) in single.iter().zip(image.iter()) {
+                assert_eq!(a.x, b.x); assert_eq!(a.y, b.y); assert_eq!(a.z, b.z);
+            }
+            assert_eq!(bytes, color::to_rgba8(&image, settings.tone_mapping, settings.png_transfer));
+        }
+        // A single pixel rendered on its own is the same too, without the light traced caustics.
+        let settings = RenderSettings { light_samples: 0, ..settings };
+        let image = render_scene(scene.clone(), camera.clone(), Arc::new(settings.clone())).0;
+        let pixel = render_pixel(&scene, &camera, &settings, 5, 11);
+        let expected = &image[(11 * settings.width + 5) as usize];
+        assert_eq!(pixel.x, expected.x); assert_eq!(pixel.y, expected.y); assert_eq!(pixel.z, expected.z);
+    }
+
+    #[test]
+    fn tile_size_does_not_change_image() {
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.5, 0.0, -3.0), 1.0, Material::Metal(Vec3::new(0.8, 0.6, 0.2), 0.1, 0.0, 0.0)),
+        ]));
+        // Larger than a single tile of the default size, cropped at the right and bottom edges.
+        let settings = Arc::new(RenderSettings { width: 80, height: 40, num_threads: 3, ..small_settings() });
+        let camera = sky_camera(&settings);
+        let render = |tile_size| {
+            let mut context = RenderContext::with_tile_size(tile_size);
+            let stats = render_progressive(&mut context, scene.clone(), camera.clone(), settings.clone(), None, None);
+            context.output(stats)
+        };
+        let (image, coverage, _, _) = render(TILE_SIZE);
+        let bytes = color::to_rgba8(&image, settings.tone_mapping, settings.png_transfer);
+        for &tile_size in &[1, 7, 64] {
+            let (tiled, tiled_coverage, _, _) = render(tile_size);
+            assert_eq!(bytes, color::to_rgba8(&tiled, settings.tone_mapping, settings.png_transfer));
+            for (a, b) in image.iter().zip(tiled.iter()) {
+                assert_eq!(a.x, b.x); assert_eq!(a.y, b.y); assert_eq!(a.z, b.z);
+            }
+            assert_eq!(coverage, tiled_coverage);
+        }
+    }
+
+    #[test]
+    fn render_into_reused_buffer() {
+        let scene = Arc::new(Scene::new(vec![Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Material::Diffuse(Vec3::new(0.8, 0.3, 0.3), Texture::None, 0.0))]));
+        let settings = RenderSettings { alpha_coverage: true, ..small_settings() };
+        let camera = sky_camera(&settings);
+        // Left over from a previous frame, from another seed.
+        let mut buffer = vec![7u8; 16 * 16 * 4];
+        let mut context = RenderContext::new();
+        let mut first_frame_color = None;
+        for seed in 0..2 {
+            let settings = Arc::new(RenderSettings { seed, ..settings.clone() });
+            let stats = render_scene_into(&mut context, scene.clone(), camera.clone(), settings.clone(), &mut buffer);
+            // The following frames keep the buffers of the first one.
+            assert_eq!(context.average_color.as_ptr(), *first_frame_color.get_or_insert(context.average_color.as_ptr()));
+            let (expected, expected_stats) = render_scene_rgba8(scene.clone(), camera.clone(), settings.clone());
+            assert_eq!(buffer, expected);
+            assert_eq!(stats.path_depths, expected_stats.path_depths);
+            // The same as the PNG encoding of the linear colors.
+            let (hdr, coverage, _, _) = render_scene(scene.clone(), camera.clone(), settings.clone());
+            let mut png = color::to_rgba8(&hdr, settings.tone_mapping, settings.png_transfer);
+            color::set_alpha(&mut png, &coverage);
+            assert_eq!(buffer, png);
+        }
+        // The sphere covers the center, but not the corner.
+        assert_eq!(buffer[(8 * 16 + 8) * 4 + 3], 255); assert_eq!(buffer[3], 0);
+        // Frames of another size take new buffers.
+        let settings = Arc::new(RenderSettings { width: 40, height: 8, ..settings });
+        let mut buffer = vec![0u8; 40 * 8 * 4];
+        render_scene_into(&mut context, scene.clone(), sky_camera(&settings), settings.clone(), &mut buffer);
+        assert_eq!(buffer, render_scene_rgba8(scene, sky_camera(&settings), settings).0);
+    }
+
+    #[test]
+    #[should_panic(expected = "4 bytes per pixel")]
+    fn render_into_buffer_of_wrong_size() {
+        let settings = small_settings();
+        let camera = sky_camera(&settings);
+        render_scene_into(&mut RenderContext::new(), Arc::new(Scene::new(vec![])), camera, Arc::new(settings), &mut vec![0u8; 16 * 16 * 3]);
+    }
+
+    #[test]
+    fn tile_order_does_not_change_image() {
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.5, 0.0, -3.0), 1.0, Material::Metal(Vec3::new(0.8, 0.6, 0.2), 0.1, 0.0, 0.0)),
+        ]));
+        // Several tiles, cropped at the right and bottom edges.
+        let settings = RenderSettings { width: 80, height: 40, num_threads: 5, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let render = |tile_order| render_scene(scene.clone(), camera.clone(), Arc::new(RenderSettings { tile_order, ..settings.clone() }));
+        let (scanline, scanline_coverage, _, _) = render(TileOrder::Scanline);
+        for &order in &[TileOrder::Spiral, TileOrder::Hilbert] {
+            let (image, coverage, _, _) = render(order);
+            for (a, b) in scanline.iter().zip(image.iter()) {
+                assert_eq!(a.x, b.x); assert_eq!(a.y, b.y); assert_eq!(a.z, b.z);
+            }
+            assert_eq!(coverage, scanline_coverage);
+        }
+    }
+
+    #[test]
+    fn flip_v_mirrors_image() {
+        let scene = Arc::new(Scene::new(vec![]));
+        let settings = small_settings();
+        let camera = sky_camera(&settings);
+        let flipped_settings = RenderSettings { flip_v: true, ..settings.clone() };
+        let image = render_scene(scene.clone(), camera.clone(), Arc::new(settings.clone())).0;
+        let flipped = render_scene(scene, camera, Arc::new(flipped_settings)).0;
+        // Pixels are seeded by the pixel of the camera they show, so the images are exact mirrors.
+        let (width, height) = (settings.width as usize, settings.height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let (a, b) = (&image[y * width + x], &flipped[(height - 1 - y) * width + x]);
+                assert_eq!(a.x, b.x); assert_eq!(a.y, b.y); assert_eq!(a.z, b.z);
+            }
+        }
+        // The sky gradient is not symmetric, so flipping must change the image.
+        assert_ne!(image[0].x, flipped[0].x);
+    }
+
+    #[test]
+    fn sanitize_invalid_colors() {
+        let magenta = Vec3::new(1.0, 0.0, 1.0);
+        let mut color = Vec3::new(0.5, 0.25, 0.125);
+        assert!(!sanitize_color(&mut color, &magenta));
+        assert_eq!(color.x, 0.5); assert_eq!(color.y, 0.25); assert_eq!(color.z, 0.125);
+        let mut color = Vec3::new(f32::NAN, 0.25, f32::INFINITY);
+        assert!(sanitize_color(&mut color, &magenta));
+        assert_eq!(color.x, 1.0); assert_eq!(color.y, 0.25); assert_eq!(color.z, 1.0);
+        let mut color = Vec3::new(0.5, f32::NEG_INFINITY, 0.125);
+        assert!(sanitize_color(&mut color, &magenta));
+        assert_eq!(color.x, 0.5); assert_eq!(color.y, 0.0); assert_eq!(color.z, 0.125);
+    }
+
+    #[test]
+    fn render_single_pixel() {
+        let scene = Scene::new(vec![]);
+        let settings = RenderSettings { pixel_samples: 64, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let buff = color::to_rgba8(&render_scene(Arc::new(Scene::new(vec![])), camera.clone(), Arc::new(settings.clone())).0, ToneMapping::Clamp, Transfer::Gamma2);
+        for &(x, y) in &[(0, 0), (7, 3), (15, 15)] {
+            let color = render_pixel(&scene, &camera, &settings, x, y);
+            let i = ((y * settings.width + x) * 4) as usize;
+            assert!(((255.99 * color.x.sqrt()) as i32 - buff[i] as i32).abs() <= 2);
+            assert!(((255.99 * color.y.sqrt()) as i32 - buff[i + 1] as i32).abs() <= 2);
+            assert!(((255.99 * color.z.sqrt()) as i32 - buff[i + 2] as i32).abs() <= 2);
+        }
+    }
+
+    #[test]
+    fn adjacent_tiles_have_uncorrelated_noise() {
+        // Camera looking straight down at a large diffuse sphere, so that all pixels see
+        // (nearly) the same flat surface and only differ in the noise of their samples.
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+        ]));
+        let settings = Arc::new(RenderSettings { pixel_samples: 1, seed: 42, ..small_settings() });
+        let camera = Arc::new(PerspectiveCamera::look_at(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            10.0,
+            1.0,
+            1.0,
+            0.0,
+        ));
+        let render = |tile: &Tile| {
+            let mut buffers = TileBuffers::new(tile, settings.max_depth);
+            render_tile(&scene, &camera, None, &settings, tile, 0, &mut buffers);
+            color::to_rgba8(&buffers.framebuffer.resolve(), ToneMapping::Clamp, Transfer::Gamma2)
+        };
+        let left = render(&Tile::new(0, 0, 8, 8));
+        let right = render(&Tile::new(8, 0, 16, 8));
+        // Same tile and seed must give the same noise...
+        assert_eq!(left, render(&Tile::new(0, 0, 8, 8)));
+        // ...but neighboring tiles must not repeat it.
+        let differing = left.iter().zip(right.iter()).filter(|(a, b)| a != b).count();
+        assert!(differing > left.len() / 2);
+    }
+
+    #[test]
+    fn separate_refraction_budget() {
+        // Three concentric glass spheres with no refraction, so that the central ray
+        // has to pass 6 glass surfaces to get to the sky.
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0, Material::Glass(white, 1.0)),
+            Sphere::new(Vec3::new(0.0, 0.0, -10.0), 2.0, Material::Glass(white, 1.0)),
+            Sphere::new(Vec3::new(0.0, 0.0, -10.0), 3.0, Material::Glass(white, 1.0)),
+        ]);
+        let settings = RenderSettings { max_depth: 4, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let color = render_pixel(&scene, &camera, &settings, 8, 8);
+        assert!(color.x < 0.01);
+        let settings = RenderSettings { max_refraction_depth: Some(16), ..settings };
+        let color = render_pixel(&scene, &camera, &settings, 8, 8);
+        assert!(color.x > 0.5);
+    }
+
+    #[test]
+    fn background_intensity_scales_sky() {
+        let mut scene = Scene::new(vec![]);
+        let settings = small_settings();
+        let camera = sky_camera(&settings);
+        let color = render_pixel(&scene, &camera, &settings, 8, 8);
+        scene.background_intensity = 0.5;
+        let dimmed = render_pixel(&scene, &camera, &settings, 8, 8);
+        assert!((dimmed.x - 0.5 * color.x).abs() < 1e-3);
+        assert!((dimmed.y - 0.5 * color.y).abs() < 1e-3);
+        assert!((dimmed.z - 0.5 * color.z).abs() < 1e-3);
+    }
+
+    #[test]
+    fn render_with_any_thread_count() {
+        let scene = Arc::new(Scene::new(vec![]));
+        for &num_threads in &[0, 1, 3, 100] {
+            let settings = RenderSettings { num_threads, pixel_samples: 1, ..small_settings() };
+            let camera = sky_camera(&settings);
+            let (buff, _, _, _) = render_scene(scene.clone(), camera, Arc::new(settings.clone()));
+            assert_eq!(buff.len(), (settings.width * settings.height) as usize);
+        }
+        assert!(thread_count(0) >= 1);
+        assert_eq!(thread_count(3), 3);
+    }
+
+    #[test]
+    fn alpha_from_coverage() {
+        let settings = RenderSettings { alpha_coverage: true, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let (hdr, coverage, _, _) = render_scene(Arc::new(Scene::new(vec![])), camera.clone(), Arc::new(settings.clone()));
+        let mut buff = color::to_rgba8(&hdr, ToneMapping::Clamp, Transfer::Gamma2);
+        color::set_alpha(&mut buff, &coverage);
+        assert!(buff.chunks(4).all(|p| p[3] == 0));
+        // A sphere covering the center of the view, but not the corners.
+        let scene = Scene::new(vec![Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Normal)]);
+        let (_, coverage, _, _) = render_scene(Arc::new(scene), camera, Arc::new(settings.clone()));
+        assert_eq!(coverage[(8 * settings.width + 8) as usize], 1.0);
+        assert_eq!(coverage[0], 0.0);
+        assert!(coverage.iter().any(|c| *c > 0.0 && *c < 1.0));
+    }
+
+    #[test]
+    fn mix_materials() {
+        let (red, green) = (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let settings = small_settings();
+        let camera = sky_camera(&settings);
+        let render = |factor: f32| {
+            let m = Material::Mix(Box::new(Material::Light(red)), Box::new(Material::Light(green)), factor);
+            let scene = Scene::new(vec![Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, m)]);
+            render_pixel(&scene, &camera, &RenderSettings { pixel_samples: 256, ..settings.clone() }, 8, 8)
+        };
+        let color = render(0.0);
+        assert_eq!(color.x, 1.0); assert_eq!(color.y, 0.0);
+        let color = render(1.0);
+        assert_eq!(color.x, 0.0); assert_eq!(color.y, 1.0);
+        let color = render(0.25);
+        assert!((color.x - 0.75).abs() < 0.1); assert!((color.y - 0.25).abs() < 0.1);
+    }
+
+    #[test]
+    fn sample_background_directly() {
+        // Gray ground under a sky going from black at the horizon to white at the zenith.
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+        ]);
+        scene.background = Background::Gradient(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+        let camera = PerspectiveCamera::look_at(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            10.0,
+            1.0,
+            1.0,
+            0.0,
+        );
+        // The sky radiance is cos(theta), so a Lambertian ground reflects 0.5 * E[cos(theta)] = 0.5 * 2/3.
+        let settings = RenderSettings { pixel_samples: 4096, sample_background: true, ..small_settings() };
+        let color = render_pixel(&scene, &camera, &settings, 8, 8);
+        assert!((color.x - 1.0 / 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn sample_background_through_glass() {
+        // Gray ground under a white sky, seen through the center of the image, with the sky sampled directly.
+        let camera = PerspectiveCamera::look_at(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            10.0,
+            1.0,
+            1.0,
+            0.0,
+        );
+        let settings = RenderSettings { pixel_samples: 1024, sample_background: true, ..small_settings() };
+        let traced_settings = RenderSettings { pixel_samples: 16384, sample_background: false, ..settings.clone() };
+        let ground = || Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0));
+        let scene = |mut spheres: Vec<Sphere>| {
+            spheres.insert(0, ground());
+            let mut scene = Scene::new(spheres);
+            scene.background = Background::Gradient(Vec3::new(1.0, 1.0, 1.0), Vec3::new(1.0, 1.0, 1.0));
+            scene
+        };
+        let open = render_pixel(&scene(vec![]), &camera, &settings, 8, 8).x;
+        assert!((open - 0.5).abs() < 0.01);
+        // Under a pane of glass (the bottom of a large sphere), the sky found by the shadow rays through the glass
+        // is not found again by the bounces through it. The result matches, up to noise, the light reflected by the
+        // ground found by following the paths only, without sampling the sky, and is never brighter than without the pane.
+        let clear = Vec3::new(1.0, 1.0, 1.0);
+        for &(attenuation, ior) in &[(clear, 1.0), (0.5 * &clear, 1.0), (clear, 1.5)] {
+            let scene = scene(vec![Sphere::new(Vec3::new(0.0, 100.0, 0.0), 99.0, Material::Glass(attenuation, ior))]);
+            let covered = render_pixel(&scene, &camera, &settings, 8, 8).x;
+            let traced = render_pixel(&scene, &camera, &traced_settings, 8, 8).x;
+            assert!((covered - traced).abs() < 0.025 && covered < open);
+        }
+    }
+
+    #[test]
+    fn sample_background_with_lights() {
+        // Gray ground under a black sky and a large light, covering a quarter of the ground's hemisphere (in the cosine
+        // weighted sense): at distance 3 from the ground, with radius 1.5. The ground reflects 0.5 * 0.25 of its light.
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.0, 2.0, 0.0), 1.5, Material::Light(Vec3::new(1.0, 1.0, 1.0))),
+        ]);
+        scene.background = Background::Gradient(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let camera = PerspectiveCamera::look_at(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            10.0,
+            1.0,
+            1.0,
+            0.0,
+        );
+        // The shadow rays sampling the background find the light instead, and the bounces do not count it again.
+        let settings = RenderSettings { pixel_samples: 4096, sample_background: true, ..small_settings() };
+        let color = render_pixel(&scene, &camera, &settings, 8, 8);
+        assert!((color.x - 0.125).abs() < 0.01);
+        // Neither when they find it through a shell of glass that does not bend the light.
+        scene.spheres.push(Sphere::new(Vec3::new(0.0, 2.0, 0.0), 1.7, Material::Glass(Vec3::new(1.0, 1.0, 1.0), 1.0)));
+        let color = render_pixel(&scene, &camera, &settings, 8, 8);
+        assert!((color.x - 0.125).abs() < 0.01);
+    }
+
+    #[test]
+    fn spectral_matches_rgb() {
+        // Orange ground under the default, blueish sky, seen from above.
+        let scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.8, 0.5, 0.2), Texture::None, 0.0)),
+        ]);
+        let camera = PerspectiveCamera::look_at(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            10.0,
+            1.0,
+            1.0,
+            0.0,
+        );
+        let settings = RenderSettings { pixel_samples: 16384, sample_background: true, ..small_settings() };
+        let rgb = render_pixel(&scene, &camera, &settings, 8, 8);
+        let spectral = render_pixel(&scene, &camera, &RenderSettings { spectral: true, ..settings }, 8, 8);
+        assert!((spectral.x - rgb.x).abs() < 0.02); assert!((spectral.y - rgb.y).abs() < 0.02); assert!((spectral.z - rgb.z).abs() < 0.02);
+    }
+
+    #[test]
+    fn render_multiple_views() {
+        let scene = Arc::new(Scene::new(vec![Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Normal)]));
+        let settings = RenderSettings { pixel_samples: 1, ..small_settings() };
+        let front = (*sky_camera(&settings)).clone();
+        let back = PerspectiveCamera::look_at(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            1.0,
+            1.0,
+            0.0,
+        );
+        let views = render_views(scene.clone(), &[front.clone(), back], Arc::new(settings.clone()), None, None);
+        assert_eq!(views.len(), 2);
+        let (single, _, _, _) = render_scene(scene, Arc::new(front), Arc::new(settings.clone()));
+        let center = (8 * settings.width + 8) as usize;
+        assert_eq!(views[0].0[center].x, single[center].x);
+        // Only the first view looks at the sphere, with a normal color of about 0.5 in red;
+        // the sky is brighter than that.
+        assert!(views[0].0[center].x < 0.6);
+        assert!(views[1].0[center].x > 0.7);
+    }
+
+    #[test]
+    fn oren_nayar_factor() {
+        let n = Vec3::new(0.0, 1.0, 0.0);
+        let wo = normalize(&Vec3::new(1.0, 0.2, 0.0));
+        let wi = normalize(&Vec3::new(1.0, 0.3, 0.0));
+        assert_eq!(oren_nayar(&n, &wo, &wi, 0.0), 1.0);
+        // Rough surfaces are darker when viewed and lit from above...
+        assert!(oren_nayar(&n, &n, &n, 0.5) < 1.0);
+        // ...but reflect back towards grazing light (retroreflection).
+        assert!(oren_nayar(&n, &wo, &wi, 0.5) > 1.0);
+        let wi_opposite = normalize(&Vec3::new(-1.0, 0.3, 0.0));
+        assert!(oren_nayar(&n, &wo, &wi_opposite, 0.5) < oren_nayar(&n, &wo, &wi, 0.5));
+    }
+
+    #[test]
+    fn direct_and_indirect_lighting_add_up() {
+        let white = Vec3::new(0.8, 0.8, 0.8);
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(white, Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Diffuse(white, Texture::None, 0.0)),
+            Sphere::new(Vec3::new(2.0, 0.0, -5.0), 0.5, Material::Light(Vec3::new(4.0, 4.0, 4.0))),
+        ]));
+        for &sample_background in &[false, true] {
+            let settings = RenderSettings { sample_background, ..small_settings() };
+            let camera = sky_camera(&settings);
+            let render = |lighting| render_scene(scene.clone(), camera.clone(), Arc::new(RenderSettings { lighting, ..settings.clone() })).0;
+            let (all, direct, indirect) = (render(LightingComponent::All), render(LightingComponent::Direct), render(LightingComponent::Indirect));
+            for i in 0..all.len() {
+                // Each pass follows exactly the same paths, so the sum is the same up to rounding.
+                assert!((direct[i].x + indirect[i].x - all[i].x).abs() < 1e-4);
+            }
+            assert!(direct.iter().zip(all.iter()).any(|(d, a)| d.x < a.x));
+            assert!(indirect.iter().any(|c| c.x > 0.0));
+        }
+    }
+
+    #[test]
+    fn stop_when_converged() {
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+        ]));
+        let settings = RenderSettings { pixel_samples: 2, passes: 20, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let (_, _, samples, stats) = render_scene(scene.clone(), camera.clone(), Arc::new(settings.clone()));
+        assert_eq!(samples[0], 40);
+        let converged = stats.relative_variance.unwrap();
+        // A threshold reached halfway through stops the render early, with the variance below it.
+        let threshold = 2.0 * converged;
+        let (_, _, samples, stats) = render_scene(scene, camera, Arc::new(RenderSettings { convergence_threshold: Some(threshold), ..settings }));
+        assert!(samples[0] < 40 && samples[0] >= 4);
+        assert!(stats.relative_variance.unwrap() < threshold);
+    }
+
+    #[test]
+    fn resume_progressive_render() {
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+        ]));
+        let settings = RenderSettings { pixel_samples: 2, passes: 3, seed: 7, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let (uninterrupted, _, uninterrupted_samples, uninterrupted_stats) = render_scene(scene.clone(), camera.clone(), Arc::new(settings.clone()));
+
+        let path = std::env::temp_dir().join(format!("rpt-resume-test-{}.ckpt", std::process::id()));
+        let interrupted = RenderSettings { passes: 2, checkpoint_interval: 1, ..settings.clone() };
+        let (partial, _, _, _) = progressive(scene.clone(), camera.clone(), Arc::new(interrupted), Some(&path), None);
+        let resumed_settings = RenderSettings { checkpoint_interval: 1, ..settings.clone() };
+        let (resumed, _, resumed_samples, resumed_stats) = progressive(scene, camera, Arc::new(resumed_settings), Some(&path), None);
+        std::fs::remove_file(&path).unwrap();
+
+        // Only the last pass had to be rendered after resuming...
+        assert_eq!(3 * resumed_stats.total_paths(), uninterrupted_stats.total_paths());
+        // ...and the result is exactly the same as without the interruption.
+        for (a, b) in uninterrupted.iter().zip(resumed.iter()) {
+            assert_eq!(a.x, b.x); assert_eq!(a.y, b.y); assert_eq!(a.z, b.z);
+        }
+        assert!(partial.iter().zip(resumed.iter()).any(|(a, b)| a.x != b.x));
+        assert_eq!(resumed_samples, uninterrupted_samples);
+        assert!(resumed_samples.iter().all(|s| *s == 3 * settings.pixel_samples));
+    }
+
+    #[test]
+    fn checkpoint_of_other_render() {
+        let sphere = |radius: f32| Sphere::new(Vec3::new(0.0, 0.0, -3.0), radius, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0));
+        let scene = Arc::new(Scene::new(vec![sphere(1.0)]));
+        let settings = RenderSettings { passes: 2, checkpoint_interval: 1, seed: 8, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let path = std::env::temp_dir().join(format!("rpt-other-render-test-{}.ckpt", std::process::id()));
+        // Renders with other samples per pass, max depth, or scene start over instead of resuming the checkpoint.
+        let other_settings = RenderSettings { pixel_samples: 2 * settings.pixel_samples, ..settings.clone() };
+        let deeper_settings = RenderSettings { max_depth: settings.max_depth + 1, ..settings.clone() };
+        let other_scene = Arc::new(Scene::new(vec![sphere(1.5)]));
+        for (resumed_scene, resumed_settings) in [(&scene, &other_settings), (&scene, &deeper_settings), (&other_scene, &settings)] {
+            progressive(scene.clone(), camera.clone(), Arc::new(settings.clone()), Some(&path), None);
+            let resumed_settings = RenderSettings { passes: 3, ..resumed_settings.clone() };
+            let (_, _, _, stats) = progressive(resumed_scene.clone(), camera.clone(), Arc::new(resumed_settings.clone()), Some(&path), None);
+            assert_eq!(stats.total_paths() as u32, 3 * settings.width * settings.height * resumed_settings.pixel_samples);
+        }
+        // The same render resumes it.
+        progressive(scene.clone(), camera.clone(), Arc::new(settings.clone()), Some(&path), None);
+        let (_, _, _, stats) = progressive(scene, camera, Arc::new(RenderSettings { passes: 3, ..settings.clone() }), Some(&path), None);
+        assert_eq!(stats.total_paths() as u32, settings.width * settings.height * settings.pixel_samples);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn render_ellipsoid() {
+        // Unit sphere stretched 3 times along X, filling the width of the view.
+        let mut scene = Scene::new(vec![]);
+        scene.instances.push(Instance::new(
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Normal),
+            &Transform::translate(0.0, 0.0, -5.0) * &Transform::scale(3.0, 1.0, 1.0),
+        ));
+        let settings = RenderSettings { pixel_samples: 1, ..small_settings() };
+        let (hdr, _, _, _) = render_scene(Arc::new(scene), sky_camera(&settings), Arc::new(settings.clone()));
+        let normal = |x: u32, y: u32| {
+            let c = &hdr[(y * settings.width + x) as usize];
+            Vec3::new(2.0 * c.x - 1.0, 2.0 * c.y - 1.0, 2.0 * c.z - 1.0)
+        };
+        // Normals are unit length, and stretching flattens them along X but not along Y.
+        let row: Vec<Vec3> = (0..settings.width).map(|x| normal(x, 8)).collect();
+        let column: Vec<Vec3> = (5..11).map(|y| normal(8, y)).collect();
+        for n in row.iter().chain(column.iter()) {
+            assert!((length(n) - 1.0).abs() < 1e-3);
+        }
+        assert!(row.iter().all(|n| n.x.abs() < 0.6));
+        // The normals along the column turn up and down away from the center, towards the silhouette.
+        assert!(column[0].y > 0.6 && column[5].y < -0.6);
+    }
+
+    #[test]
+    fn render_ellipsoid_silhouette() {
+        // The same ellipsoid, seen through a sample close to the top of the row of pixels it ends in.
+        let mut scene = Scene::new(vec![]);
+        scene.instances.push(Instance::new(
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Normal),
+            &Transform::translate(0.0, 0.0, -5.0) * &Transform::scale(3.0, 1.0, 1.0),
+        ));
+        let settings = RenderSettings { pixel_samples: 1, ..small_settings() };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut stats = RenderStats::new(settings.max_depth);
+        let ray = sky_camera(&settings).ray_for_pixel_sample(8, 5, (0.5, 0.3), settings.width, settings.height, &mut rng);
+        let c = trace_ray(&scene, &ray, None, &settings, &mut rng, &mut stats, PathState::new(0.0));
+        // Close to the silhouette, the normal turns up, the same as for the sphere before stretching.
+        let n = Vec3::new(2.0 * c.x - 1.0, 2.0 * c.y - 1.0, 2.0 * c.z - 1.0);
+        assert!((length(&n) - 1.0).abs() < 1e-3);
+        assert!(n.y > 0.8);
+    }
+
+    #[test]
+    fn render_mesh() {
+        // A diffuse box on the ground, lit by the sky, first as triangles, and then merged into quads.
+        let cuboid = Mesh::cuboid(Vec3::new(-1.0, -1.0, -6.0), Vec3::new(1.0, 0.5, -4.0), Material::Diffuse(Vec3::new(0.8, 0.3, 0.3), Texture::None, 0.0));
+        let render = |mesh: Mesh| {
+            let mut scene = Scene::new(vec![Sphere::new(Vec3::new(0.0, -101.0, 0.0), 100.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0))]);
+            scene.meshes.push(mesh);
+            scene.build_grid(1);
+            let settings = RenderSettings { seed: 5, max_depth: 4, ..small_settings() };
+            let camera = sky_camera(&settings);
+            render_scene(Arc::new(scene), camera, Arc::new(settings)).0
+        };
+        let triangles = render(cuboid.clone());
+        let quads = render(cuboid.with_quads());
+        // The box is red in the middle of the view.
+        let center = &triangles[8 * 16 + 8];
+        assert!(center.x > 2.0 * center.y);
+        let difference: f32 = triangles.iter().zip(quads.iter()).map(|(a, b)| length(&(a - b))).sum();
+        assert!(difference < 1e-3 * triangles.len() as f32);
+    }
+
+    #[test]
+    fn frustum_culling_keeps_image() {
+        // A sphere in view, and a row of spheres behind the camera that only show up in reflections.
+        let mut spheres = vec![Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Metal(Vec3::new(0.9, 0.9, 0.9), 0.0, 0.0, 0.0))];
+        for i in 0..10 {
+            spheres.push(Sphere::new(Vec3::new(3.0 * i as f32 - 13.5, 0.0, 5.0), 1.5, Material::Light(Vec3::new(1.0, 0.5, 0.0))));
+        }
+        let scene = Arc::new(Scene::new(spheres));
+        let settings = RenderSettings { seed: 3, ..small_settings() };
+        let camera = sky_camera(&settings);
+        assert_eq!(scene.objects_in_frustum(&camera.frustum().unwrap()), vec![0]);
+        let (all, _, _, _) = render_scene(scene.clone(), camera.clone(), Arc::new(settings.clone()));
+        let (culled, _, _, _) = render_scene(scene, camera, Arc::new(RenderSettings { frustum_culling: true, ..settings }));
+        for (a, b) in all.iter().zip(culled.iter()) {
+            assert_eq!(a.x, b.x); assert_eq!(a.y, b.y); assert_eq!(a.z, b.z);
+        }
+        // The lights behind the camera are still visible in the mirror.
+        assert!(all.iter().any(|c| c.z < c.x));
+    }
+
+    #[test]
+    fn ambient_light() {
+        // Diffuse sphere filling the whole view, with nothing else to light it.
+        let mut scene = Scene::new(vec![Sphere::new(Vec3::new(0.0, 0.0, 0.0), -10.0, Material::Diffuse(Vec3::new(0.5, 0.25, 1.0), Texture::None, 0.0))]);
+        scene.background_intensity = 0.0;
+        let scene = Arc::new(scene);
+        let settings = RenderSettings { max_depth: 1, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let (dark, _, _, _) = render_scene(scene.clone(), camera.clone(), Arc::new(settings.clone()));
+        assert!(dark.iter().all(|c| c.x == 0.0 && c.y == 0.0 && c.z == 0.0));
+        let ambient = Vec3::new(0.4, 0.4, 0.2);
+        let (lit, _, _, _) = render_scene(scene, camera, Arc::new(RenderSettings { ambient, ..settings }));
+        for c in lit.iter() {
+            assert!((c.x - 0.2).abs() < 1e-6); assert!((c.y - 0.1).abs() < 1e-6); assert!((c.z - 0.2).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn preview_then_refine() {
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+        ]));
+        let settings = RenderSettings { pixel_samples: 2, passes: 3, seed: 5, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let (direct, _, _, _) = render_scene(scene.clone(), camera.clone(), Arc::new(settings.clone()));
+
+        let mut reports: Vec<(u32, Vec<Vec3>)> = Vec::new();
+        let mut progress = |passes: u32, color: &[Vec3], coverage: &[f32]| {
+            assert_eq!(coverage.len(), color.len());
+            reports.push((passes, color.to_vec()));
+        };
+        let preview_settings = RenderSettings { preview: true, ..settings.clone() };
+        let (refined, _, samples, stats) = progressive(scene, camera, Arc::new(preview_settings), None, Some(&mut progress));
+
+        // The preview of the whole image comes first, followed by every pass...
+        assert_eq!(reports.iter().map(|r| r.0).collect::<Vec<u32>>(), vec![0, 1, 2, 3]);
+        assert_eq!(reports[0].1.len(), (settings.width * settings.height) as usize);
+        assert!(reports[0].1.iter().zip(direct.iter()).any(|(a, b)| a.x != b.x));
+        // ...and the final image is exactly the same as without the preview, which is not counted.
+        for ((a, b), c) in direct.iter().zip(refined.iter()).zip(reports[3].1.iter()) {
+            assert_eq!(a.x, b.x); assert_eq!(a.y, b.y); assert_eq!(a.z, b.z);
+            assert_eq!(b.x, c.x);
+        }
+        assert!(samples.iter().all(|s| *s == 3 * settings.pixel_samples));
+        assert_eq!(stats.total_paths() as u32, settings.width * settings.height * 3 * settings.pixel_samples);
+    }
+
+    #[test]
+    fn hidden_from_camera() {
+        let settings = RenderSettings { alpha_coverage: true, seed: 2, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let (empty, _, _, _) = render_scene(Arc::new(Scene::new(vec![])), camera.clone(), Arc::new(settings.clone()));
+        let hidden = Visibility { visible_to_camera: false, ..Visibility::default() };
+        let scene = Arc::new(Scene::new(vec![Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Material::Normal).with_visibility(hidden)]));
+        let (hdr, coverage, _, _) = render_scene(scene, camera, Arc::new(settings));
+        for (a, b) in empty.iter().zip(hdr.iter()) {
+            assert_eq!(a.x, b.x); assert_eq!(a.y, b.y); assert_eq!(a.z, b.z);
+        }
+        assert!(coverage.iter().all(|c| *c == 0.0));
+    }
+
+    #[test]
+    fn hidden_from_camera_behind_glass() {
+        // A light hidden from the camera, inside a shell of glass that does not bend the light, under a black sky.
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let hidden = Visibility { visible_to_camera: false, ..Visibility::default() };
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Material::Light(white)).with_visibility(hidden),
+            Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.5, Material::Glass(white, 1.0)),
+        ]);
+        scene.background = Background::Gradient(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0));
+        let settings = small_settings();
+        // Seen through the glass, whether the refractions are limited separately or not.
+        for max_refraction_depth in [None, Some(16)] {
+            let settings = RenderSettings { max_refraction_depth, ..settings.clone() };
+            let c = render_pixel(&scene, &sky_camera(&settings), &settings, 8, 8);
+            assert!(c.x > 0.5);
+        }
+        // But not without it.
+        scene.spheres.pop();
+        assert_eq!(render_pixel(&scene, &sky_camera(&settings), &settings, 8, 8).x, 0.0);
+    }
+
+    #[test]
+    fn air_bubble_in_water() {
+        // Camera inside a large body of water, looking at an air bubble.
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 100.0, Material::Glass(white, 1.33)),
+            Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Material::Glass(white, 1.0 / 1.33)),
+        ]);
+        scene.background = Background::Gradient(white, white);
+        let settings = RenderSettings { pixel_samples: 16, max_depth: 32, seed: 4, ..small_settings() };
+        let (hdr, _, _, stats) = render_scene(Arc::new(scene), sky_camera(&settings), Arc::new(settings));
+        // Near the rim of the bubble, rays are reflected on entering it...
+        assert!(stats.total_internal_reflections > 0);
+        // ...and clear glass in a uniformly white world neither adds nor (apart from paths cut short) loses light.
+        assert!(hdr.iter().all(|c| c.x.is_finite() && c.x <= 1.0 + 1e-5));
+        let average = hdr.iter().map(|c| c.x).sum::<f32>() / hdr.len() as f32;
+        assert!(average > 0.95);
+    }
+
+    #[test]
+    fn double_precision_accumulation() {
+        let scene = Arc::new(Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)),
+        ]));
+        let settings = RenderSettings { passes: 2, seed: 6, ..small_settings() };
+        let camera = sky_camera(&settings);
+        let (single, _, single_samples, _) = render_scene(scene.clone(), camera.clone(), Arc::new(settings.clone()));
+        let double_settings = RenderSettings { double_precision: true, ..settings };
+        let (double, _, double_samples, _) = render_scene(scene.clone(), camera.clone(), Arc::new(double_settings.clone()));
+        // The same samples, only summed more precisely.
+        assert_eq!(single_samples, double_samples);
+        for (a, b) in single.iter().zip(double.iter()) {
+            assert!((a.x - b.x).abs() < 1e-5); assert!((a.y - b.y).abs() < 1e-5); assert!((a.z - b.z).abs() < 1e-5);
+        }
+
+        // Checkpoints of the other precision are not resumed.
+        let path = std::env::temp_dir().join(format!("rpt-double-test-{}.ckpt", std::process::id()));
+        let single_checkpoint = RenderSettings { double_precision: false, checkpoint_interval: 1, ..double_settings.clone() };
+        progressive(scene.clone(), camera.clone(), Arc::new(single_checkpoint), Some(&path), None);
+        let resumed_settings = RenderSettings { checkpoint_interval: 1, ..double_settings };
+        let (_, _, _, stats) = progressive(scene, camera, Arc::new(resumed_settings), Some(&path), None);
+        assert!(Checkpoint::load(&path).unwrap().double_precision);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(stats.total_paths() as u32, 2 * settings.width * settings.height * settings.pixel_samples);
+    }
+
+    #[test]
+    fn ambient_occlusion_contact_shadows() {
+        // Looking down at a ball resting on the floor.
+        let gray = Vec3::new(0.5, 0.5, 0.5);
+        let floor = || Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, Material::Diffuse(gray, Texture::None, 0.0));
+        let settings = RenderSettings { ao_samples: 16, ao_radius: 2.0, seed: 1, ..small_settings() };
+        let camera = Arc::new(PerspectiveCamera::look_at(
+            Vec3::new(0.0, 3.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            60.0,
+            1.0,
+            1.0,
+            0.0,
+        ));
+        let (empty, _, _, _) = render_scene(Arc::new(Scene::new(vec![floor()])), camera.clone(), Arc::new(settings.clone()));
+        assert!(empty.iter().all(|c| c.x == 0.5 && c.y == 0.5 && c.z == 0.5));
+        let ball = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Diffuse(gray, Texture::None, 0.0));
+        let (hdr, _, _, stats) = render_scene(Arc::new(Scene::new(vec![floor(), ball])), camera, Arc::new(settings.clone()));
+        // The floor darkens around the ball, but not further away from it, and neither does the top of the ball.
+        assert!(hdr.iter().any(|c| c.x < 0.4));
+        assert_eq!(hdr[0].x, 0.5);
+        assert_eq!(hdr[(8 * settings.width + 8) as usize].x, 0.5);
+        // No paths go beyond the first hit.
+        assert_eq!(stats.total_paths(), stats.path_depths[0]);
+    }
+
+    #[test]
+    fn light_tracing_caustics() {
+        let white = Vec3::new(0.8, 0.8, 0.8);
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Material::Diffuse(white, Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.0, 1.5, -5.0), 1.0, Material::Glass(Vec3::new(1.0, 1.0, 1.0), 1.5)),
+            Sphere::new(Vec3::new(0.0, 6.0, -5.0), 1.0, Material::Light(Vec3::new(2.0, 2.0, 2.0))),
+        ]);
+        scene.background_intensity = 0.0;
+        let scene = Arc::new(scene);
+        let settings = RenderSettings { pixel_samples: 1024, ..small_settings() };
+        let camera = Arc::new(PerspectiveCamera::look_at(
+            Vec3::new(0.0, 4.0, 3.0),
+            Vec3::new(0.0, 0.0, -5.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.0,
+            1.0,
+            0.0,
+        ));
+        let traced = Arc::new(RenderSettings { light_samples: 64, ..settings.clone() });
+        let mut images = Vec::new();
+        trace_light_paths(&mut images, &scene, &camera, &traced, 0);
+        let caustics: f32 = images.iter().flatten().map(|c| c.x).sum();
+        let without: f32 = render_scene(scene.clone(), camera.clone(), Arc::new(settings)).0.iter().map(|c| c.x).sum();
+        let with: f32 = render_scene(scene, camera, traced).0.iter().map(|c| c.x).sum();
+        // Camera paths follow the same ways either way, only the caustics are replaced by the light traced ones,
+        // which add up to the same light (up to the noise of both).
+        assert!(caustics > 0.05 * with);
+        assert!((with - without).abs() < 0.2 * caustics);
+    }
+}
@@ -1,259 +1,246 @@
-mod math;
-mod vec3;
-mod ray;
-mod scene;
-mod camera;
-mod bbox;
-mod mat4;
-mod xform;
-
-extern crate png;
-extern crate rand;
+extern crate rpt;
 
+use std::io::{ self, Write };
+use std::process;
 use std::sync::Arc;
-use std::path::Path;
-use std::fs::File;
-use std::io::BufWriter;
-use std::thread;
-use rand::{ Rng };
-use rand::rngs::ThreadRng;
-use vec3::{ Vec3, normalize, length_squared, reflect, dot, refract };
-use ray::Ray;
-use scene::{ Hitable, Scene, Sphere, Material, Texture };
-use camera::{ Camera, PerspectiveCamera };
+use std::path::{ Path, PathBuf };
+use rpt::vec3::Vec3;
+use rpt::image::{ self, BitDepth, Image };
+use rpt::scene::Scene;
+use rpt::camera::{ LensSampling, PerspectiveCamera };
+use rpt::sampling::SamplingMode;
+use rpt::settings::{ RenderSettings, LightingComponent };
+use rpt::tile::TileOrder;
+use rpt::color::{ self, ToneMapping, Transfer };
+use rpt::{ bloom, preview, scene_file };
+use rpt::{ ViewProgress, example_spheres, render_intersection_tests, render_uv_pass, render_views, save_png, write_png };
 
-const IMAGE_WIDTH: u32 = 512;
-const IMAGE_HEIGHT: u32 = 512;
-const PIXEL_SAMPLES: u32 = 128;
-const MAX_DEPTH: u32 = 8;
 const LENS_RADIUS: f32 = 0.1;
 const FOCAL_DISTANCE: f32 = 8.0;
-const NUM_THREADS: u32 = 16;
-
-struct Tile {
-    min_x: u32,
-    min_y: u32,
-    max_x: u32,
-    max_y: u32,
-}
+const BLOOM_THRESHOLD: f32 = 1.0;
+const BLOOM_RADIUS: u32 = 8;
+// Number of divisions of spheres from pole to pole in scenes exported with `--export-obj`.
+const OBJ_SEGMENTS: u32 = 32;
 
-impl Tile {
-    fn new(min_x: u32, min_y: u32, max_x: u32, max_y: u32) -> Tile {
-        Tile { min_x, min_y, max_x, max_y }
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    // Scenes piped in by other programs replace the example scene.
+    let spheres = if args.iter().any(|a| a == "--stdin") {
+        scene_file::read_spheres(io::stdin().lock()).unwrap_or_else(|e| {
+            eprintln!("Invalid scene on standard input: {}", e);
+            process::exit(1);
+        })
+    } else {
+        example_spheres()
+    };
+    let mut settings = RenderSettings::default();
+    if args.iter().any(|a| a == "--alpha") {
+        settings.alpha_coverage = true;
     }
-}
-
-fn trace_ray(scene: &Scene, ray: &Ray, rng: &mut ThreadRng, depth: u32) -> Vec3 {
-    if depth >= MAX_DEPTH {
-        return Vec3::new(0.0, 0.0, 0.0);
+    if args.iter().any(|a| a == "--direct-only") {
+        settings.lighting = LightingComponent::Direct;
     }
-
-    if let Some(hit) = scene.hit(ray) {
-        match hit.m {
-            Material::Diffuse(mut albedo, texture) => {
-                let mut rand = Vec3::new(0.0, 0.0, 0.0);
-                loop {
-                    let (u, v, w): (f32, f32, f32) = (rng.gen(), rng.gen(), rng.gen());
-                    rand.x = 2.0 * u - 1.0;
-                    rand.y = 2.0 * v - 1.0;
-                    rand.z = 2.0 * w - 1.0;
-                    if length_squared(&rand) < 1.0 {
-                        break;
-                    }
-                }
-                let mut target = &hit.n + &rand;
-                target.normalize();
-                let mut new_ray = Ray::new(hit.p, target);
-                new_ray.o.x += 0.001 * new_ray.d.x;
-                new_ray.o.y += 0.001 * new_ray.d.y;
-                new_ray.o.z += 0.001 * new_ray.d.z;
-                let c = trace_ray(scene, &new_ray, rng, depth + 1);
-                if let Texture::Checkered(color1, color2, scale) = texture {
-                    let (u, v) = hit.uv;
-                    albedo = if (scale * u).sin() * (10.0 * scale * v).sin() > 0.0 {
-                        color1
-                    } else {
-                        color2
-                    };
-                }
-                Vec3::new(
-                    albedo.x * c.x,
-                    albedo.y * c.y,
-                    albedo.z * c.z,
-                )
-            },
-            Material::Metal(albedo, roughness) => {
-                let mut target = reflect(&ray.d, &hit.n);
-                if roughness > 0.0 {
-                    let mut rand = Vec3::new(0.0, 0.0, 0.0);
-                    loop {
-                        let (u, v, w): (f32, f32, f32) = (rng.gen(), rng.gen(), rng.gen());
-                        rand.x = 2.0 * u - 1.0;
-                        rand.y = 2.0 * v - 1.0;
-                        rand.z = 2.0 * w - 1.0;
-                        if length_squared(&rand) < roughness {
-                            break;
-                        }
-                    }
-                    target += &rand;
-                }
-                target.normalize();
-                let mut new_ray = Ray::new(hit.p, target);
-                new_ray.o.x += 0.001 * new_ray.d.x;
-                new_ray.o.y += 0.001 * new_ray.d.y;
-                new_ray.o.z += 0.001 * new_ray.d.z;
-                let c = trace_ray(scene, &new_ray, rng, depth + 1);
-                Vec3::new(
-                    albedo.x * c.x,
-                    albedo.y * c.y,
-                    albedo.z * c.z,
-                )
-            },
-            Material::Glass(attenuation, ior) => {
-                let mut refraction_ratio = ior;
-                let mut normal = hit.n;
-                if dot(&ray.d, &hit.n) < 0.0 {
-                    refraction_ratio = 1.0 / ior;
-                } else {
-                    normal = -&hit.n;
-                }
-
-                let schlick = {
-                    let mut v = -&ray.d;
-                    v.normalize();
-                    let cos_theta = (v.x * normal.x + v.y * normal.y + v.z * normal.z).min(1.0);
-                    let mut r0 = (1.0 - refraction_ratio) / (1.0 + refraction_ratio);
-                    r0 = r0 * r0;
-                    r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
-                };
-                let rand: f32 = rng.gen();
-
-                let mut new_ray = if let Some(mut refracted) = refract(&ray.d, &normal, refraction_ratio) {
-                    if schlick > rand {
-                        let mut reflected = reflect(&ray.d, &normal);
-                        reflected.normalize();
-                        Ray::new(hit.p, reflected)
-                    } else {
-                        refracted.normalize();
-                        Ray::new(hit.p, refracted)
-                    }
-                } else {
-                    let mut reflected = reflect(&ray.d, &normal);
-                    reflected.normalize();
-                    Ray::new(hit.p, reflected)                
-                };
-                new_ray.o.x += 0.001 * new_ray.d.x;
-                new_ray.o.y += 0.001 * new_ray.d.y;
-                new_ray.o.z += 0.001 * new_ray.d.z;
-                let c = trace_ray(scene, &new_ray, rng, depth + 1);
-                Vec3::new(
-                    attenuation.x * c.x,
-                    attenuation.y * c.y,
-                    attenuation.z * c.z,
-                )
-            },
-            Material::Light(color) => {
-                color
-            },
-            Material::Normal => {
-                Vec3::new(
-                    0.5 * (hit.n.x + 1.0),
-                    0.5 * (hit.n.y + 1.0),
-                    0.5 * (hit.n.z + 1.0),
-                )
-            },
+    if args.iter().any(|a| a == "--indirect-only") {
+        settings.lighting = LightingComponent::Indirect;
+    }
+    if args.iter().any(|a| a == "--sample-sky") {
+        settings.sample_background = true;
+    }
+    if args.iter().any(|a| a == "--sample-lights") {
+        settings.sample_lights = true;
+    }
+    if args.iter().any(|a| a == "--blue-noise") {
+        settings.sampling = SamplingMode::BlueNoise;
+    }
+    if args.iter().any(|a| a == "--filter-textures") {
+        settings.filter_textures = true;
+    }
+    if let Some(white) = args.iter().position(|a| a == "--white-point").and_then(|i| args.get(i + 1)) {
+        let white = white.parse().ok().filter(|w: &f32| *w > 0.0).expect("Invalid white point, expected a positive number");
+        settings.tone_mapping = ToneMapping::ReinhardExtended(white);
+    }
+    if args.iter().any(|a| a == "--srgb") {
+        settings.png_transfer = Transfer::Srgb;
+    }
+    if let Some(seed) = args.iter().position(|a| a == "--seed").and_then(|i| args.get(i + 1)) {
+        settings.seed = seed.parse().expect("Invalid seed");
+    }
+    if let Some(ambient) = args.iter().position(|a| a == "--ambient").and_then(|i| args.get(i + 1)) {
+        let ambient: f32 = ambient.parse().expect("Invalid ambient light");
+        settings.ambient = Vec3::new(ambient, ambient, ambient);
+    }
+    if args.iter().any(|a| a == "--cull") {
+        settings.frustum_culling = true;
+    }
+    if let Some(passes) = args.iter().position(|a| a == "--passes").and_then(|i| args.get(i + 1)) {
+        settings.passes = passes.parse().expect("Invalid number of passes");
+    }
+    if let Some(samples) = args.iter().position(|a| a == "--ao").and_then(|i| args.get(i + 1)) {
+        settings.ao_samples = samples.parse().expect("Invalid number of ambient occlusion samples");
+    }
+    if let Some(radius) = args.iter().position(|a| a == "--ao-radius").and_then(|i| args.get(i + 1)) {
+        settings.ao_radius = radius.parse().expect("Invalid ambient occlusion radius");
+    }
+    if let Some(samples) = args.iter().position(|a| a == "--caustics").and_then(|i| args.get(i + 1)) {
+        settings.light_samples = samples.parse().expect("Invalid number of light samples");
+    }
+    if let Some(ids) = args.iter().position(|a| a == "--solo").and_then(|i| args.get(i + 1)) {
+        settings.solo = Some(ids.split(',').map(|id| id.trim().parse().expect("Invalid object id")).collect());
+    }
+    if let Some(order) = args.iter().position(|a| a == "--tile-order").and_then(|i| args.get(i + 1)) {
+        settings.tile_order = match order.as_str() {
+            "scanline" => TileOrder::Scanline,
+            "spiral" => TileOrder::Spiral,
+            "hilbert" => TileOrder::Hilbert,
+            _ => panic!("Invalid tile order, expected scanline, spiral, or hilbert"),
+        };
+    }
+    if args.iter().any(|a| a == "--16bit") {
+        settings.bit_depth = BitDepth::Sixteen;
+    }
+    if args.iter().any(|a| a == "--double") {
+        settings.double_precision = true;
+    }
+    if args.iter().any(|a| a == "--preview") {
+        settings.preview = true;
+    }
+    if args.iter().any(|a| a == "--spectral") {
+        settings.spectral = true;
+    }
+    if let Some(sigma) = args.iter().position(|a| a == "--reject-outliers").and_then(|i| args.get(i + 1)) {
+        settings.outlier_sigma = Some(sigma.parse().expect("Invalid number of standard deviations"));
+    }
+    if let Some(max) = args.iter().position(|a| a == "--clamp-indirect").and_then(|i| args.get(i + 1)) {
+        settings.indirect_clamp = Some(max.parse().expect("Invalid max brightness"));
+    }
+    if let Some(bounces) = args.iter().position(|a| a == "--roulette").and_then(|i| args.get(i + 1)) {
+        settings.russian_roulette = Some(bounces.parse().expect("Invalid number of bounces"));
+    }
+    if let Some(threshold) = args.iter().position(|a| a == "--converge").and_then(|i| args.get(i + 1)) {
+        settings.convergence_threshold = Some(threshold.parse().expect("Invalid convergence threshold"));
+    }
+    if args.iter().any(|a| a == "--verbose") {
+        settings.verbose = true;
+    }
+    if let Some(interval) = args.iter().position(|a| a == "--checkpoint").and_then(|i| args.get(i + 1)) {
+        settings.checkpoint_interval = interval.parse().expect("Invalid checkpoint interval");
+    }
+    // Supersampled renders are averaged down to the requested size while the colors are still linear.
+    let downsample: u32 = args.iter().position(|a| a == "--downsample").and_then(|i| args.get(i + 1))
+        .map(|f| f.parse().ok().filter(|f| *f > 0).expect("Invalid downsample factor, expected a positive integer"))
+        .unwrap_or(1);
+    let num_views: u32 = args.iter().position(|a| a == "--views").and_then(|i| args.get(i + 1))
+        .map(|n| n.parse().expect("Invalid number of views")).unwrap_or(1);
+    let mut scene = Scene::new(spheres);
+    // Unless given, the max depth fits the materials in the scene.
+    settings.max_depth = match args.iter().position(|a| a == "--max-depth").and_then(|i| args.get(i + 1)) {
+        Some(depth) => depth.parse().expect("Invalid max depth"),
+        None => scene.suggested_max_depth(),
+    };
+    let output_settings = settings.clone();
+    settings.width *= downsample;
+    settings.height *= downsample;
+    let settings = Arc::new(settings);
+    let resolve = |hdr: &[Vec3], coverage: &[f32]| -> (Vec<Vec3>, Vec<f32>) {
+        if downsample == 1 {
+            return (hdr.to_vec(), coverage.to_vec());
+        }
+        let coverage: Vec<Vec3> = coverage.iter().map(|&a| Vec3::new(a, a, a)).collect();
+        let coverage = color::downsample_linear(&coverage, settings.width, settings.height, downsample);
+        (color::downsample_linear(hdr, settings.width, settings.height, downsample), coverage.iter().map(|a| a.x).collect())
+    };
+    // Materials reflecting more light than they receive make the image diverge with more bounces,
+    // which is easy to miss, so debug builds point them out.
+    if cfg!(debug_assertions) {
+        for (id, problem) in scene.validate_materials() {
+            eprintln!("Warning: object {}: {}", id, problem);
         }
-    } else {
-        // Render background
-        let normalized = normalize(&ray.d);
-        let t = 0.5 * (normalized.y + 1.0);
-        Vec3::new(
-            (1.0 - t) * 1.0 + t * 0.5,
-            (1.0 - t) * 1.0 + t * 0.7,
-            (1.0 - t) * 1.0 + t * 0.9,
-        )
     }
-}
-
-fn render_tile(scene: Arc<Scene>, camera: Arc<PerspectiveCamera>, tile: &Tile) -> Vec<u8> {
-    let mut rng = rand::thread_rng();
-    let size = (tile.max_y - tile.min_y) * (tile.max_x - tile.min_x) * 4;
-    let mut output: Vec<u8> = vec![0; size as usize];
-    let mut i = 0;
-    for y in tile.min_y..tile.max_y {
-        for x in tile.min_x..tile.max_x {
-            let mut color = Vec3::new(0.0, 0.0, 0.0);
-            for _sample in 0..PIXEL_SAMPLES {
-                let pixel_sample_u: f32 = rng.gen();
-                let pixel_sample_v: f32 = rng.gen();
-                let pixel_u: f32 = (x as f32 + pixel_sample_u) / IMAGE_WIDTH as f32;
-                let pixel_v: f32 = 1.0 - (y as f32 + pixel_sample_v) / IMAGE_HEIGHT as f32;
-                let ray = camera.generate_ray(pixel_u - 0.5, pixel_v - 0.5, &mut rng);
-                let c = trace_ray(&scene, &ray, &mut rng, 0);
-                color += &c;
-            }
-            color *= 1.0 / PIXEL_SAMPLES as f32;
-            output[i + 0] = (255.99 * color.x.sqrt()) as u8;
-            output[i + 1] = (255.99 * color.y.sqrt()) as u8;
-            output[i + 2] = (255.99 * color.z.sqrt()) as u8;
-            output[i + 3] = 255;
-            i = i + 4;
+    if args.iter().any(|a| a == "--grid") {
+        scene.build_grid(settings.num_threads);
+    }
+    if args.iter().any(|a| a == "--export-obj") {
+        scene.export_obj(Path::new("output.obj"), OBJ_SEGMENTS).unwrap();
+    }
+    let scene = Arc::new(scene);
+    let lens_sampling = if args.iter().any(|a| a == "--concentric-lens") { LensSampling::Concentric } else { LensSampling::Rejection };
+    // Views orbit around the vertical axis, starting from the default viewpoint.
+    let cameras: Vec<PerspectiveCamera> = (0..num_views.max(1)).map(|i| {
+        let angle = 0.25 * std::f32::consts::PI + 2.0 * std::f32::consts::PI * i as f32 / num_views.max(1) as f32;
+        let distance = 50.0f32.sqrt();
+        let mut camera = PerspectiveCamera::look_at(
+            Vec3::new(distance * angle.cos(), 5.0, distance * angle.sin()),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            output_settings.width as f32 / output_settings.height as f32,
+            FOCAL_DISTANCE,
+            LENS_RADIUS,
+        );
+        camera.set_lens_sampling(lens_sampling);
+        camera
+    }).collect();
+
+    // Other outputs of a single view are named after the image, for example, `out_samples.png` for `out.png`.
+    // With `-`, the image is written to the standard output instead, and the other outputs keep their default names.
+    let output = args.iter().position(|a| a == "--output").and_then(|i| args.get(i + 1));
+    let png_to_stdout = cameras.len() == 1 && output.is_some_and(|path| path == "-");
+    let output = output.filter(|_| !png_to_stdout)
+        .map(|path| String::from(path.strip_suffix(".png").unwrap_or(path)))
+        .unwrap_or_else(|| String::from("output"));
+    // Messages must not mix with the image on the standard output.
+    let log = || -> Box<dyn Write> { if png_to_stdout { Box::new(io::stderr()) } else { Box::new(io::stdout()) } };
+    let basenames: Vec<String> = (0..cameras.len())
+        .map(|i| if cameras.len() == 1 { output.clone() } else { format!("view_{}", i) })
+        .collect();
+    let checkpoints: Vec<PathBuf> = basenames.iter().map(|b| PathBuf::from(format!("{}.ckpt", b))).collect();
+    let checkpoints = if settings.checkpoint_interval > 0 { Some(checkpoints.as_slice()) } else { None };
+
+    // With a preview, the image is saved after every pass, so it can be watched as it refines.
+    // The standard output only gets the final image.
+    let mut save_progress = |view: usize, passes: u32, hdr: &[Vec3], coverage: &[f32]| {
+        writeln!(log(), "{}: pass {} of {}", basenames[view], passes, settings.passes).unwrap();
+        if !png_to_stdout {
+            let (hdr, coverage) = resolve(hdr, coverage);
+            save_png(Path::new(&format!("{}.png", basenames[view])), &hdr, &coverage, &output_settings).unwrap();
+        }
+    };
+    let progress = if settings.preview { Some(&mut save_progress as ViewProgress) } else { None };
+
+    let outputs = render_views(scene.clone(), &cameras, settings.clone(), checkpoints, progress);
+    for ((basename, camera), (hdr, coverage, samples, stats)) in basenames.iter().zip(cameras.iter()).zip(outputs) {
+        write!(log(), "{}", stats).unwrap();
+        let (mut hdr, coverage) = resolve(&hdr, &coverage);
+        if args.iter().any(|a| a == "--pfm") {
+            image::save_pfm(Path::new(&format!("{}.pfm", basename)), &hdr, output_settings.width, output_settings.height).unwrap();
+        }
+        if args.iter().any(|a| a == "--bloom") {
+            bloom::bloom(&mut hdr, output_settings.width, output_settings.height, BLOOM_THRESHOLD, BLOOM_RADIUS);
+        }
+        if args.iter().any(|a| a == "--sample-heatmap") {
+            let heatmap = Image::new(settings.width, settings.height, color::heatmap_to_rgba8(&samples));
+            heatmap.save_png(Path::new(&format!("{}_samples.png", basename))).unwrap();
+        }
+        if args.iter().any(|a| a == "--intersection-heatmap") {
+            let tests = render_intersection_tests(&scene, camera, &settings);
+            writeln!(log(), "Max intersection tests per primary ray: {}", tests.iter().max().unwrap_or(&0)).unwrap();
+            let heatmap = Image::new(settings.width, settings.height, color::heatmap_to_color_rgba8(&tests));
+            heatmap.save_png(Path::new(&format!("{}_intersections.png", basename))).unwrap();
+        }
+        if args.iter().any(|a| a == "--uv-pass") {
+            let uvs = render_uv_pass(&scene, camera, &settings);
+            image::save_pfm(Path::new(&format!("{}_uv.pfm", basename)), &uvs, settings.width, settings.height).unwrap();
+        }
+        if args.iter().any(|a| a == "--ascii" || a == "--ansi") {
+            let columns = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).unwrap_or(80);
+            let ansi_colors = args.iter().any(|a| a == "--ansi");
+            let buff = color::to_rgba8(&hdr, settings.tone_mapping, settings.png_transfer);
+            write!(log(), "{}", preview::to_ascii(&buff, output_settings.width, output_settings.height, columns, ansi_colors)).unwrap();
+        }
+        if png_to_stdout {
+            write_png(io::stdout().lock(), &hdr, &coverage, &output_settings).unwrap();
+        } else {
+            save_png(Path::new(&format!("{}.png", basename)), &hdr, &coverage, &output_settings).unwrap();
         }
     }
-    output
-}
-
-fn render_scene(scene: Arc<Scene>, camera: Arc<PerspectiveCamera>, num_threads: u32) -> Vec<u8> {
-    let mut handles: Vec<std::thread::JoinHandle<Vec<u8>>> = Vec::new();
-    let tile_height = IMAGE_HEIGHT / num_threads;
-    for i in 0..num_threads {
-        let _scene = scene.clone();
-        let _camera = camera.clone();
-        handles.push(thread::spawn(move || { render_tile(_scene, _camera, &Tile::new(0, i * tile_height, IMAGE_WIDTH, (i + 1) * tile_height)) }));
-    }
-    let mut result: Vec<u8> = Vec::new();
-    for handle in handles {
-        let mut tile = handle.join().unwrap();
-        result.append(&mut tile);
-    }
-    result
-}
-
-fn main() {
-    let white = Vec3::new(1.0, 1.0, 1.0);
-    let black = Vec3::new(0.0, 0.0, 0.0);
-    let spheres: Vec<Sphere> = vec!(
-        Sphere::new(Vec3::new(0.0, -100.0, 0.0), 99.0, Material::Diffuse(white, Texture::Checkered(white, black, 200.0))),
-
-        Sphere::new(Vec3::new(-2.5, 0.0, -2.5), 1.0, Material::Metal(white, 0.0)),
-        Sphere::new(Vec3::new(-2.5, 0.0, 0.0),  1.0, Material::Metal(Vec3::new(0.9, 0.6, 0.3), 0.1)),
-        Sphere::new(Vec3::new(-2.5, 0.0, 2.5),  1.0, Material::Metal(white, 0.2)),
-
-        Sphere::new(Vec3::new(0.0, 0.0, -2.5),  1.0, Material::Normal),
-        Sphere::new(Vec3::new(0.0, 0.0, 0.0),   1.0, Material::Diffuse(white, Texture::None)),
-        Sphere::new(Vec3::new(0.0, 0.0, 2.5),   1.0, Material::Light(Vec3::new(1.0, 1.0, 0.0))),
-
-        Sphere::new(Vec3::new(2.5, 0.0, -2.5),  1.0, Material::Glass(white, 2.0)),
-        Sphere::new(Vec3::new(2.5, 0.0, 0.0),   1.0, Material::Glass(Vec3::new(0.3, 0.6, 0.9), 1.75)),
-        Sphere::new(Vec3::new(2.5, 0.0, 2.5),   1.0, Material::Glass(white, 1.5)),
-    );
-    let scene = Arc::new(Scene::new(spheres));
-    let camera = Arc::new(PerspectiveCamera::look_at(
-        Vec3::new(5.0, 5.0, 5.0),
-        Vec3::new(0.0, -1.0, 0.0),
-        Vec3::new(0.0, 1.0, 0.0),
-        60.0,
-        IMAGE_WIDTH as f32 / IMAGE_HEIGHT as f32,
-        FOCAL_DISTANCE,
-        LENS_RADIUS,
-    ));
-
-    let buff = render_scene(scene, camera, NUM_THREADS);
-    let file = File::create(Path::new(&String::from("output.png"))).unwrap();
-    let ref mut buf_writer = BufWriter::new(file);
-    let mut encoder = png::Encoder::new(buf_writer, IMAGE_WIDTH, IMAGE_HEIGHT);
-    encoder.set_color(png::ColorType::RGBA);
-    encoder.set_depth(png::BitDepth::Eight);
-    let mut png_writer = encoder.write_header().unwrap();
-    png_writer.write_image_data(&buff).unwrap();
 }
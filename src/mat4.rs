@@ -12,6 +12,7 @@ pub struct Mat4 {
 impl Mat4 {
     /// Create new matrix with all 16 values specified.
     #[inline(always)]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         m00: f32, m01: f32, m02: f32, m03: f32,
         m10: f32, m11: f32, m12: f32, m13: f32,
@@ -37,6 +38,40 @@ impl Mat4 {
         )
     }
 
+    /// Encode the matrix as its 16 values, row by row, each in the little-endian byte order.
+    pub fn to_le_bytes(self) -> [u8; 64] {
+        let mut bytes = [0u8; 64];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(self.values().iter()) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decode a matrix encoded by `to_le_bytes`.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - 16 values of the matrix, row by row, each in the little-endian byte order.
+    pub fn from_le_bytes(bytes: [u8; 64]) -> Mat4 {
+        let v: Vec<f32> = bytes.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect();
+        Mat4::new(
+            v[0], v[1], v[2], v[3],
+            v[4], v[5], v[6], v[7],
+            v[8], v[9], v[10], v[11],
+            v[12], v[13], v[14], v[15],
+        )
+    }
+
+    // All 16 values, row by row.
+    fn values(&self) -> [f32; 16] {
+        [
+            self.m00, self.m01, self.m02, self.m03,
+            self.m10, self.m11, self.m12, self.m13,
+            self.m20, self.m21, self.m22, self.m23,
+            self.m30, self.m31, self.m32, self.m33,
+        ]
+    }
+
     /// Check if matrix has any NaN values.
     #[inline(always)]
     pub fn has_nans(&self) -> bool {
@@ -318,4 +353,24 @@ mod tests {
         );
         assert_mat4_eq(&m, &expected);
     }
+
+    #[test]
+    fn le_bytes_round_trip() {
+        let m = Mat4::new(
+            1.0, -2.0, 3.5, 0.0,
+            -0.0, f32::INFINITY, f32::NEG_INFINITY, 1e-40,
+            f32::MAX, f32::MIN, 0.25, -8.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+        let bytes = m.to_le_bytes();
+        // Row by row, starting with the first row.
+        assert_eq!(&bytes[0..4], &1.0f32.to_le_bytes());
+        assert_eq!(&bytes[4..8], &(-2.0f32).to_le_bytes());
+        assert_eq!(&bytes[60..64], &1.0f32.to_le_bytes());
+        let decoded = Mat4::from_le_bytes(bytes);
+        assert_mat4_eq(&decoded, &m);
+        assert_eq!(decoded.m10.to_bits(), (-0.0f32).to_bits());
+        let nan = Mat4::from_le_bytes(Mat4 { m21: f32::NAN, ..Mat4::identity() }.to_le_bytes());
+        assert!(nan.has_nans() && nan.m21.is_nan());
+    }
 }
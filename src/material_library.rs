@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use super::scene::Material;
+
+/// Materials identified by name, so that a material can be defined once and used by many objects.
+#[derive(Debug, Clone, Default)]
+pub struct MaterialLibrary {
+    materials: HashMap<String, Material>,
+}
+
+impl MaterialLibrary {
+    /// Create new, empty library.
+    pub fn new() -> MaterialLibrary {
+        MaterialLibrary { materials: HashMap::new() }
+    }
+
+    /// Add a material to the library, replacing any material of the same name, and return the library.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the material.
+    /// * `material` - The material.
+    pub fn with(mut self, name: &str, material: Material) -> MaterialLibrary {
+        self.insert(name, material);
+        self
+    }
+
+    /// Add a material to the library, replacing any material of the same name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the material.
+    /// * `material` - The material.
+    pub fn insert(&mut self, name: &str, material: Material) {
+        self.materials.insert(String::from(name), material);
+    }
+
+    /// Get a copy of the material with given name, for example, to pass it to a new object.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the material.
+    pub fn get(&self, name: &str) -> Option<Material> {
+        self.materials.get(name).cloned()
+    }
+
+    /// Get a copy of the material with given name.
+    ///
+    /// Panics if there is no such material, which is convenient in hand-built scenes.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the material.
+    pub fn material(&self, name: &str) -> Material {
+        self.get(name).unwrap_or_else(|| panic!("Unknown material: {}", name))
+    }
+
+    /// Get number of materials in the library.
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    /// Check whether the library has no materials.
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::vec3::Vec3;
+
+    #[test]
+    fn build_library() {
+        let gold = Vec3::new(0.9, 0.6, 0.3);
+        let library = MaterialLibrary::new()
+            .with("gold", Material::Metal(gold, 0.1, 0.0, 0.0))
+            .with("mirror", Material::Metal(Vec3::new(1.0, 1.0, 1.0), 0.0, 0.0, 0.0));
+        assert_eq!(library.len(), 2);
+        match library.material("gold") {
+            Material::Metal(albedo, roughness, ..) => { assert_eq!(albedo.x, 0.9); assert_eq!(roughness, 0.1); }
+            m => panic!("unexpected material {:?}", m),
+        }
+        assert!(library.get("silver").is_none());
+    }
+
+    #[test]
+    fn replace_material() {
+        let mut library = MaterialLibrary::new().with("ground", Material::Normal);
+        library.insert("ground", Material::Light(Vec3::new(1.0, 1.0, 1.0)));
+        assert_eq!(library.len(), 1);
+        assert!(matches!(library.material("ground"), Material::Light(_)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown material: gold")]
+    fn missing_material() {
+        MaterialLibrary::new().material("gold");
+    }
+}
@@ -6,12 +6,12 @@ pub type Radians = f32;
 
 #[inline(always)]
 pub fn almost_zero(v: f32) -> bool {
-    v.abs() <= std::f32::EPSILON
+    v.abs() <= f32::EPSILON
 }
 
 #[inline(always)]
 pub fn almost_one(v: f32) -> bool {
-    (v - 1.0).abs() <= std::f32::EPSILON
+    (v - 1.0).abs() <= f32::EPSILON
 }
 
 #[inline(always)]
@@ -24,6 +24,21 @@ pub fn deg_to_rad(angle: Degrees) -> Radians {
     angle / 180.0 * PI
 }
 
+// Reflectance at normal incidence of a dielectric with given index of refraction
+// (or ratio of the indices on both sides of the surface).
+#[inline(always)]
+pub fn f0_from_ior(ior: f32) -> f32 {
+    let r0 = (1.0 - ior) / (1.0 + ior);
+    r0 * r0
+}
+
+// Index of refraction (greater than 1.0) of a dielectric with given reflectance at normal incidence.
+#[inline(always)]
+pub fn ior_from_f0(f0: f32) -> f32 {
+    let r = f0.sqrt();
+    (1.0 + r) / (1.0 - r)
+}
+
 #[inline(always)]
 pub fn rad_to_deg(angle: Radians) -> Degrees {
     angle * INV_PI * 180.0
@@ -48,3 +63,18 @@ pub fn quadratic(a: f32, b: f32, c: f32) -> Roots {
         Roots::None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reflectance_at_normal_incidence() {
+        assert!((f0_from_ior(1.5) - 0.04).abs() < 1e-6);
+        assert!((f0_from_ior(1.0 / 1.5) - 0.04).abs() < 1e-6);
+        assert_eq!(f0_from_ior(1.0), 0.0);
+        assert!((f0_from_ior(1.33) - 0.02006).abs() < 1e-5);
+        assert!((ior_from_f0(0.04) - 1.5).abs() < 1e-5);
+        assert!((ior_from_f0(f0_from_ior(2.4)) - 2.4).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,455 @@
+use std::collections::HashMap;
+use super::vec3::{ Vec3, dot, cross, normalize, length, length_squared };
+use super::ray::Ray;
+use super::bbox::BBox;
+use super::xform::Transform;
+use super::scene::{ Hit, Hitable, Material, Visibility };
+
+// Largest distance of the fourth vertex of a pair of triangles from the plane of the first one,
+// relative to the size of the triangles, for the pair to be merged into a quad.
+const COPLANAR_TOLERANCE: f32 = 1e-5;
+
+/// Single triangle of a mesh, with vertices in counter-clockwise order around its normal.
+///
+/// Meshes do not have texture coordinates, so all hits have UVs of (0, 0).
+#[derive(Debug, Clone)]
+pub struct Triangle {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub c: Vec3,
+    pub m: Material,
+}
+
+impl Triangle {
+    /// Create new triangle.
+    ///
+    /// # Arguments
+    ///
+    /// * `a` - First vertex.
+    /// * `b` - Second vertex.
+    /// * `c` - Third vertex.
+    /// * `m` - Material of the triangle.
+    pub fn new(a: Vec3, b: Vec3, c: Vec3, m: Material) -> Triangle {
+        Triangle { a, b, c, m }
+    }
+
+    /// Compute the bounding box of the triangle.
+    pub fn bbox(&self) -> BBox {
+        let mut bbox = BBox::new_from_points(&self.a, &self.b);
+        bbox += &self.c;
+        bbox
+    }
+
+    // Unit normal, following the order of the vertices.
+    fn normal(&self) -> Vec3 {
+        normalize(&cross(&(&self.b - &self.a), &(&self.c - &self.a)))
+    }
+
+    // Vertices, starting with the ones of given edge (0 for `ab`, 1 for `bc`, and 2 for `ca`).
+    fn rotated(&self, edge: usize) -> [Vec3; 3] {
+        match edge {
+            0 => [self.a, self.b, self.c],
+            1 => [self.b, self.c, self.a],
+            _ => [self.c, self.a, self.b],
+        }
+    }
+}
+
+impl Hitable for Triangle {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+        // Möller–Trumbore intersection.
+        let (e1, e2) = (&self.b - &self.a, &self.c - &self.a);
+        let p = cross(&ray.d, &e2);
+        let det = dot(&e1, &p);
+        if det == 0.0 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = &ray.o - &self.a;
+        let u = dot(&s, &p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = cross(&s, &e1);
+        let v = dot(&ray.d, &q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = dot(&e2, &q) * inv_det;
+        if t <= 0.0 {
+            return None;
+        }
+        let n = self.normal();
+        let mut hit = Hit::new(ray.point_at(t), n, n, t, (0.0, 0.0), e1, e2, &self.m);
+        hit.face_ray(&ray.d);
+        // Each barycentric coordinate, times the height of the triangle over the opposite edge.
+        let double_area = length(&cross(&e1, &e2));
+        let edge_distance = ((1.0 - u - v) / length(&(&self.c - &self.b))).min(u / length(&e2)).min(v / length(&e1));
+        hit.edge_distance = double_area * edge_distance;
+        Some(hit)
+    }
+}
+
+/// Planar, convex quadrilateral, with vertices in counter-clockwise order around its normal.
+///
+/// Tested with a single plane intersection instead of as two triangles. See `merge_into_quads`.
+#[derive(Debug, Clone)]
+pub struct Quad {
+    vertices: [Vec3; 4],
+    n: Vec3,
+    m: Material,
+}
+
+impl Quad {
+    /// Get the vertices of the quad.
+    pub fn vertices(&self) -> &[Vec3; 4] {
+        &self.vertices
+    }
+
+    /// Get the material of the quad.
+    pub fn material(&self) -> &Material {
+        &self.m
+    }
+
+    /// Compute the bounding box of the quad.
+    pub fn bbox(&self) -> BBox {
+        let mut bbox = BBox::new_from_points(&self.vertices[0], &self.vertices[1]);
+        bbox += &self.vertices[2];
+        bbox += &self.vertices[3];
+        bbox
+    }
+}
+
+impl Hitable for Quad {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let denom = dot(&self.n, &ray.d);
+        if denom == 0.0 {
+            return None;
+        }
+        let t = dot(&self.n, &(&self.vertices[0] - &ray.o)) / denom;
+        if t <= 0.0 {
+            return None;
+        }
+        let p = ray.point_at(t);
+        // Inside when on the inner side of every edge.
+        let mut edge_distance = f32::INFINITY;
+        for i in 0..4 {
+            let (v0, v1) = (&self.vertices[i], &self.vertices[(i + 1) % 4]);
+            let edge = v1 - v0;
+            let side = dot(&cross(&edge, &(&p - v0)), &self.n);
+            if side < 0.0 {
+                return None;
+            }
+            edge_distance = edge_distance.min(side / length(&edge));
+        }
+        let (e1, e2) = (&self.vertices[1] - &self.vertices[0], &self.vertices[3] - &self.vertices[0]);
+        let mut hit = Hit::new(p, self.n, self.n, t, (0.0, 0.0), e1, e2, &self.m);
+        hit.face_ray(&ray.d);
+        hit.edge_distance = edge_distance;
+        Some(hit)
+    }
+}
+
+// Bits of the coordinates of a vertex, to find the triangles sharing it.
+type VertexKey = (u32, u32, u32);
+
+fn vertex_key(v: &Vec3) -> VertexKey {
+    (v.x.to_bits(), v.y.to_bits(), v.z.to_bits())
+}
+
+// Quad made of triangle `pqr` and the other triangle `qps` across its edge `pq`, if the two are
+// coplanar, face the same way, and form a convex quad.
+fn merge_pair(first: &[Vec3; 3], s: &Vec3, m: &Material) -> Option<Quad> {
+    let [p, q, r] = first;
+    let n = cross(&(q - p), &(r - p));
+    let size_squared = length_squared(&(q - p)).max(length_squared(&(r - p))).max(length_squared(&(s - p)));
+    if length_squared(&n) == 0.0 {
+        return None;
+    }
+    let n = normalize(&n);
+    let distance = dot(&n, &(s - p));
+    if distance * distance > COPLANAR_TOLERANCE * COPLANAR_TOLERANCE * size_squared {
+        return None;
+    }
+    // Replacing the shared edge with its path through `s` keeps the vertices in the same order.
+    let vertices = [*p, *s, *q, *r];
+    // Convex when every corner turns the same way, around the normal.
+    for i in 0..4 {
+        let (v0, v1, v2) = (&vertices[i], &vertices[(i + 1) % 4], &vertices[(i + 2) % 4]);
+        if dot(&cross(&(v1 - v0), &(v2 - v1)), &n) <= 0.0 {
+            return None;
+        }
+    }
+    Some(Quad { vertices, n, m: m.clone() })
+}
+
+/// Merge pairs of triangles sharing an edge into quads, which are faster to intersect,
+/// for example, for the faces of boxes.
+///
+/// Only merges pairs with the same material that lie in the same plane, face the same way (share the edge
+/// in opposite directions), and form a convex quad. Vertices are shared when their coordinates are exactly
+/// the same. Each triangle is merged at most once, so the result depends on the order of the triangles.
+///
+/// # Arguments
+///
+/// * `triangles` - Triangles of the mesh.
+///
+/// # Returns
+///
+/// (quads, triangles that could not be merged).
+pub fn merge_into_quads(triangles: Vec<Triangle>) -> (Vec<Quad>, Vec<Triangle>) {
+    // Triangles by their directed edges.
+    let mut edges: HashMap<(VertexKey, VertexKey), usize> = HashMap::new();
+    for (i, triangle) in triangles.iter().enumerate() {
+        for edge in 0..3 {
+            let [p, q, _] = triangle.rotated(edge);
+            edges.insert((vertex_key(&p), vertex_key(&q)), i);
+        }
+    }
+    let mut merged = vec![false; triangles.len()];
+    let mut quads = Vec::new();
+    for (i, triangle) in triangles.iter().enumerate() {
+        if merged[i] {
+            continue;
+        }
+        for edge in 0..3 {
+            let first = triangle.rotated(edge);
+            let (p, q) = (vertex_key(&first[0]), vertex_key(&first[1]));
+            let j = match edges.get(&(q, p)) {
+                Some(&j) if j != i && !merged[j] && triangles[j].m == triangle.m => j,
+                _ => continue,
+            };
+            // The vertex of the other triangle opposite the shared edge.
+            let other = &triangles[j];
+            let s = [other.a, other.b, other.c].iter().copied().find(|v| vertex_key(v) != p && vertex_key(v) != q);
+            if let Some(quad) = s.and_then(|s| merge_pair(&first, &s, &triangle.m)) {
+                merged[i] = true;
+                merged[j] = true;
+                quads.push(quad);
+                break;
+            }
+        }
+    }
+    let rest = triangles.into_iter().zip(merged).filter(|(_, merged)| !merged).map(|(t, _)| t).collect();
+    (quads, rest)
+}
+
+/// Object made of triangles and quads, with any number of materials, which shows up in a scene
+/// as a whole, under a single object id. Every face is tested for every ray that reaches the mesh.
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    triangles: Vec<Triangle>,
+    quads: Vec<Quad>,
+    bbox: BBox,
+    visibility: Visibility,
+}
+
+impl Mesh {
+    /// Create new mesh.
+    ///
+    /// # Arguments
+    ///
+    /// * `triangles` - Triangles of the mesh.
+    pub fn new(triangles: Vec<Triangle>) -> Mesh {
+        let mut bbox = BBox::new();
+        for triangle in &triangles {
+            bbox += &triangle.bbox();
+        }
+        Mesh { triangles, quads: Vec::new(), bbox, visibility: Visibility::default() }
+    }
+
+    /// Create new mesh of an axis-aligned box, with twelve triangles facing outwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `min` - Corner of the box with the smallest coordinates.
+    /// * `max` - Corner of the box with the largest coordinates.
+    /// * `m` - Material of the box.
+    pub fn cuboid(min: Vec3, max: Vec3, m: Material) -> Mesh {
+        let corner = |i: usize| Vec3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        );
+        let faces = [[0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4], [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5]];
+        Mesh::new(faces.iter().flat_map(|f| [
+            Triangle::new(corner(f[0]), corner(f[1]), corner(f[2]), m.clone()),
+            Triangle::new(corner(f[0]), corner(f[2]), corner(f[3]), m.clone()),
+        ]).collect())
+    }
+
+    /// Merge pairs of the triangles of the mesh into quads (see `merge_into_quads`), without changing its shape.
+    pub fn with_quads(mut self) -> Mesh {
+        let (quads, triangles) = merge_into_quads(self.triangles);
+        self.quads.extend(quads);
+        self.triangles = triangles;
+        self
+    }
+
+    /// Transform the vertices of all the faces, and return the mesh. Faces keep facing outwards
+    /// even under transforms that swap handedness (see `Transform::swaps_handedness`).
+    ///
+    /// # Arguments
+    ///
+    /// * `transform` - Transform to apply, for example, from the coordinate system of a model to the scene.
+    pub fn transformed(mut self, transform: &Transform) -> Mesh {
+        let swap = transform.swaps_handedness();
+        for triangle in &mut self.triangles {
+            let (a, b, c) = (transform.apply_to_point(&triangle.a), transform.apply_to_point(&triangle.b), transform.apply_to_point(&triangle.c));
+            (triangle.a, triangle.b, triangle.c) = if swap { (a, c, b) } else { (a, b, c) };
+        }
+        for quad in &mut self.quads {
+            for v in quad.vertices.iter_mut() {
+                *v = transform.apply_to_point(v);
+            }
+            if swap {
+                quad.vertices.reverse();
+            }
+            quad.n = normalize(&cross(&(&quad.vertices[1] - &quad.vertices[0]), &(&quad.vertices[2] - &quad.vertices[0])));
+        }
+        self.bbox = self.triangles.iter().map(Triangle::bbox).chain(self.quads.iter().map(Quad::bbox)).fold(BBox::new(), |acc, b| &acc + &b);
+        self
+    }
+
+    /// Set the kinds of rays that see the mesh.
+    pub fn with_visibility(mut self, visibility: Visibility) -> Mesh {
+        self.visibility = visibility;
+        self
+    }
+
+    /// Get the kinds of rays that see the mesh.
+    pub fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    /// Get the triangles of the mesh that are not merged into quads.
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+
+    /// Get the quads of the mesh.
+    pub fn quads(&self) -> &[Quad] {
+        &self.quads
+    }
+
+    /// Get the materials of all the faces, quads first.
+    pub fn materials(&self) -> impl Iterator<Item = &Material> {
+        self.quads.iter().map(|q| &q.m).chain(self.triangles.iter().map(|t| &t.m))
+    }
+
+    /// Get the bounding box of the mesh.
+    pub fn bbox(&self) -> BBox {
+        self.bbox
+    }
+}
+
+impl Hitable for Mesh {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+        self.quads.iter().filter_map(|q| q.hit(ray))
+            .chain(self.triangles.iter().filter_map(|t| t.hit(ray)))
+            .min_by(|a, b| a.t.total_cmp(&b.t))
+    }
+
+    fn occluded(&self, ray: &Ray, max_t: f32) -> bool {
+        self.quads.iter().any(|q| q.occluded(ray, max_t)) || self.triangles.iter().any(|t| t.occluded(ray, max_t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Closest hit among given objects.
+    fn closest<'a, T: Hitable>(objects: &'a [T], ray: &Ray) -> Option<Hit<'a>> {
+        objects.iter().filter_map(|o| o.hit(ray)).min_by(|a, b| a.t.partial_cmp(&b.t).unwrap())
+    }
+
+    #[test]
+    fn triangle_hit() {
+        let triangle = Triangle::new(Vec3::new(-1.0, -1.0, -5.0), Vec3::new(1.0, -1.0, -5.0), Vec3::new(0.0, 1.0, -5.0), Material::Normal);
+        let hit = triangle.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+        assert!((hit.t - 5.0).abs() < 1e-6);
+        assert_eq!(hit.n.x, 0.0); assert_eq!(hit.n.y, 0.0); assert_eq!(hit.n.z, 1.0);
+        assert!(hit.front_face);
+        assert!(triangle.hit(&Ray::new(Vec3::new(0.9, 0.9, 0.0), Vec3::new(0.0, 0.0, -1.0))).is_none());
+        assert!(triangle.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0))).is_none());
+        let bbox = triangle.bbox();
+        assert_eq!(bbox.min.x, -1.0); assert_eq!(bbox.max.y, 1.0); assert_eq!(bbox.max.z, -5.0);
+    }
+
+    #[test]
+    fn edge_distance() {
+        let triangle = Triangle::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(2.0, 0.0, -5.0), Vec3::new(0.0, 2.0, -5.0), Material::Normal);
+        let distance = |o: Vec3| triangle.hit(&Ray::new(o, Vec3::new(0.0, 0.0, -1.0))).unwrap().edge_distance;
+        assert!((distance(Vec3::new(0.5, 0.25, 0.0)) - 0.25).abs() < 1e-6);
+        assert!((distance(Vec3::new(0.2, 0.5, 0.0)) - 0.2).abs() < 1e-6);
+        assert!((distance(Vec3::new(1.2, 0.6, 0.0)) - 0.2 / 2.0f32.sqrt()).abs() < 1e-6);
+        // Quads measure the distance to their own edges, not to the diagonal of the triangles they were merged from.
+        let (quads, _) = merge_into_quads(Mesh::cuboid(Vec3::new(-1.0, -1.0, -6.0), Vec3::new(1.0, 1.0, -4.0), Material::Normal).triangles().to_vec());
+        let hit = closest(&quads, &Ray::new(Vec3::new(0.5, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+        assert!((hit.edge_distance - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn merge_box_faces() {
+        let triangles = Mesh::cuboid(Vec3::new(-1.0, -1.5, -6.0), Vec3::new(1.0, 0.5, -4.0), Material::Normal).triangles().to_vec();
+        let (quads, rest) = merge_into_quads(triangles.clone());
+        assert_eq!(quads.len(), 6);
+        assert!(rest.is_empty());
+        // Rays from several viewpoints see exactly the same surfaces.
+        for &o in &[Vec3::new(0.0, 0.0, 0.0), Vec3::new(3.0, 2.0, -1.0), Vec3::new(0.2, -0.3, -5.1)] {
+            for i in 0..32 {
+                for j in 0..32 {
+                    // Off the edges of the box, where rounding can let rays slip between the triangles.
+                    let target = Vec3::new(2.5 * ((i as f32 + 0.37) / 32.0 - 0.5), 2.5 * ((j as f32 + 0.61) / 32.0 - 0.5) - 0.5, -5.0);
+                    let ray = Ray::new(o, normalize(&(&target - &o)));
+                    match (closest(&triangles, &ray), closest(&quads, &ray)) {
+                        (Some(a), Some(b)) => {
+                            assert!((a.t - b.t).abs() < 1e-4);
+                            assert_eq!(a.n.x, b.n.x); assert_eq!(a.n.y, b.n.y); assert_eq!(a.n.z, b.n.z);
+                            assert_eq!(a.front_face, b.front_face);
+                        },
+                        (None, None) => {},
+                        _ => panic!("quads and triangles disagree for ray {:?}", ray.d),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keep_pairs_that_are_not_quads() {
+        let (a, b, c, d) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let red = Material::Diffuse(Vec3::new(1.0, 0.0, 0.0), super::super::scene::Texture::None, 0.0);
+        let pair = |s: Vec3, m: Material| vec![Triangle::new(a, b, c, Material::Normal), Triangle::new(a, c, s, m)];
+        assert_eq!(merge_into_quads(pair(d, Material::Normal)).0.len(), 1);
+        // Different materials
+        assert_eq!(merge_into_quads(pair(d, red)).1.len(), 2);
+        // Folded along the shared edge
+        assert_eq!(merge_into_quads(pair(Vec3::new(0.0, 1.0, 0.5), Material::Normal)).1.len(), 2);
+        // Concave
+        assert_eq!(merge_into_quads(pair(Vec3::new(2.0, 3.0, 0.0), Material::Normal)).1.len(), 2);
+        // Facing the other way
+        let flipped = vec![Triangle::new(a, b, c, Material::Normal), Triangle::new(a, d, c, Material::Normal)];
+        assert_eq!(merge_into_quads(flipped).1.len(), 2);
+    }
+
+    #[test]
+    fn transform_keeps_faces_outwards() {
+        // A unit cube, as triangles and as quads, mirrored and moved along the X axis.
+        let cube = || Mesh::cuboid(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0), Material::Normal);
+        let mirror = Transform::compose(&[Transform::translate(5.0, 0.0, 0.0), Transform::scale(-1.0, 1.0, 1.0)]);
+        let center = Vec3::new(4.5, 0.5, 0.5);
+        let triangles = cube().transformed(&mirror);
+        assert_eq!(triangles.bbox().min.x, 4.0); assert_eq!(triangles.bbox().max.x, 5.0);
+        for t in triangles.triangles() {
+            assert!(dot(&t.normal(), &(&t.a - &center)) > 0.0);
+        }
+        let quads = cube().with_quads().transformed(&mirror);
+        assert_eq!(quads.quads().len(), 6);
+        for q in quads.quads() {
+            assert!(dot(&q.n, &(&q.vertices[0] - &center)) > 0.0);
+            assert!((length(&q.n) - 1.0).abs() < 1e-6);
+        }
+    }
+}
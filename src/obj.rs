@@ -0,0 +1,231 @@
+use std::f32::consts::PI;
+use std::io::{ self, Write };
+use super::vec3::{ Vec3, length_squared, normalize };
+use super::onb::Onb;
+use super::xform::Transform;
+use super::scene::{ Sphere, Capsule };
+use super::mesh::Mesh;
+
+/// Triangle mesh with shared vertices, split into named objects, for exporting the geometry
+/// of a scene to the Wavefront OBJ format and inspecting it in other applications.
+#[derive(Debug, Clone, Default)]
+pub struct TriangleMesh {
+    pub vertices: Vec<Vec3>,
+    /// Indices of the vertices of each triangle, counterclockwise when seen from the side the normals point to.
+    pub faces: Vec<[usize; 3]>,
+    /// Name of each object, and the index of its first face.
+    pub objects: Vec<(String, usize)>,
+}
+
+impl TriangleMesh {
+    /// Create new, empty mesh.
+    pub fn new() -> TriangleMesh {
+        TriangleMesh::default()
+    }
+
+    /// Start a new object; the faces added after this belong to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the object.
+    pub fn begin_object(&mut self, name: &str) {
+        self.objects.push((String::from(name), self.faces.len()));
+    }
+
+    /// Add a sphere, tessellated along lines of latitude and longitude.
+    ///
+    /// # Arguments
+    ///
+    /// * `sphere` - The sphere. Spheres with negative radius get triangles facing inwards.
+    /// * `segments` - Number of divisions from pole to pole; twice as many go around the sphere.
+    pub fn add_sphere(&mut self, sphere: &Sphere, segments: u32) {
+        let segments = segments.max(2);
+        let c = sphere.center();
+        let rings: Vec<(f32, Vec3)> = (0..=segments).map(|i| (PI * i as f32 / segments as f32, c)).collect();
+        let frame = Onb::new_from_w(&Vec3::new(0.0, 1.0, 0.0));
+        self.add_lat_long(&frame, &rings, sphere.radius().abs(), 2 * segments, sphere.radius() < 0.0);
+    }
+
+    /// Add a capsule: a tessellated sphere split at the equator, with its halves moved to the two caps.
+    ///
+    /// # Arguments
+    ///
+    /// * `capsule` - The capsule.
+    /// * `segments` - Number of divisions of a sphere from pole to pole (rounded up to an even number);
+    ///   twice as many go around the capsule.
+    pub fn add_capsule(&mut self, capsule: &Capsule, segments: u32) {
+        let (a, b) = capsule.endpoints();
+        let half = segments.max(2).div_ceil(2);
+        let axis = &b - &a;
+        let axis = if length_squared(&axis) > 0.0 { normalize(&axis) } else { Vec3::new(0.0, 1.0, 0.0) };
+        // Rings from the pole above `b` to the pole below `a`, with the equator at both caps, bounding the body.
+        let rings: Vec<(f32, Vec3)> = (0..=half).map(|i| (0.5 * PI * i as f32 / half as f32, b))
+            .chain((half..=2 * half).map(|i| (0.5 * PI * i as f32 / half as f32, a)))
+            .collect();
+        self.add_lat_long(&Onb::new_from_w(&axis), &rings, capsule.radius(), 4 * half, false);
+    }
+
+    /// Add the faces of a mesh of the scene, with each quad split into two triangles.
+    ///
+    /// # Arguments
+    ///
+    /// * `mesh` - The mesh.
+    pub fn add_mesh(&mut self, mesh: &Mesh) {
+        for quad in mesh.quads() {
+            let offset = self.vertices.len();
+            self.vertices.extend(quad.vertices());
+            self.faces.push([offset, offset + 1, offset + 2]);
+            self.faces.push([offset, offset + 2, offset + 3]);
+        }
+        for triangle in mesh.triangles() {
+            let offset = self.vertices.len();
+            self.vertices.extend([triangle.a, triangle.b, triangle.c]);
+            self.faces.push([offset, offset + 1, offset + 2]);
+        }
+    }
+
+    /// Add another mesh, with all its vertices transformed, as a part of the current object.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Mesh to add. Its object names are ignored.
+    /// * `transform` - Transform applied to the vertices.
+    pub fn add_transformed(&mut self, other: &TriangleMesh, transform: &Transform) {
+        let offset = self.vertices.len();
+        self.vertices.extend(other.vertices.iter().map(|v| transform.apply_to_point(v)));
+        // Mirroring transforms would turn the triangles inside out.
+        let flip = transform.swaps_handedness();
+        self.faces.extend(other.faces.iter().map(|&[a, b, c]| {
+            if flip { [offset + a, offset + c, offset + b] } else { [offset + a, offset + b, offset + c] }
+        }));
+    }
+
+    /// Write the mesh in the Wavefront OBJ format.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where to write the mesh to.
+    pub fn write_obj(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "# {} vertices, {} triangles", self.vertices.len(), self.faces.len())?;
+        for v in &self.vertices {
+            writeln!(writer, "v {} {} {}", v.x, v.y, v.z)?;
+        }
+        let mut objects = self.objects.iter().peekable();
+        for (i, [a, b, c]) in self.faces.iter().enumerate() {
+            while let Some((name, _)) = objects.next_if(|(_, first)| *first <= i) {
+                writeln!(writer, "o {}", name)?;
+            }
+            // OBJ indices start at 1.
+            writeln!(writer, "f {} {} {}", a + 1, b + 1, c + 1)?;
+        }
+        Ok(())
+    }
+
+    // Add a surface of revolution around the W axis of `frame`, made of rings of vertices at given polar angles
+    // (starting at 0.0, the pole in the direction of W, and ending at PI) around given centers, with single
+    // vertices at the poles.
+    fn add_lat_long(&mut self, frame: &Onb, rings: &[(f32, Vec3)], radius: f32, slices: u32, inwards: bool) {
+        let slices = slices as usize;
+        let point = |theta: f32, phi: f32, c: &Vec3| {
+            let d = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+            c + &(radius * &frame.local_to_world(&d))
+        };
+        let first = self.vertices.len();
+        let (theta, c) = rings[0];
+        self.vertices.push(point(theta, 0.0, &c));
+        for (theta, c) in &rings[1..rings.len() - 1] {
+            self.vertices.extend((0..slices).map(|j| point(*theta, 2.0 * PI * j as f32 / slices as f32, c)));
+        }
+        let (theta, c) = rings[rings.len() - 1];
+        self.vertices.push(point(theta, 0.0, &c));
+        let last = self.vertices.len() - 1;
+
+        let ring = |i: usize, j: usize| first + 1 + (i - 1) * slices + j % slices;
+        let mut add_face = |a: usize, b: usize, c: usize| {
+            self.faces.push(if inwards { [a, c, b] } else { [a, b, c] });
+        };
+        let inner_rings = rings.len() - 2;
+        for j in 0..slices {
+            add_face(first, ring(1, j), ring(1, j + 1));
+            for i in 1..inner_rings {
+                add_face(ring(i, j), ring(i + 1, j), ring(i + 1, j + 1));
+                add_face(ring(i, j), ring(i + 1, j + 1), ring(i, j + 1));
+            }
+            add_face(ring(inner_rings, j), last, ring(inner_rings, j + 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::scene::{ Material, Texture };
+    use super::super::vec3::{ cross, dot, length };
+
+    fn diffuse() -> Material {
+        Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0)
+    }
+
+    // Check that every triangle faces away from given point.
+    fn faces_away_from(mesh: &TriangleMesh, p: &Vec3) -> bool {
+        mesh.faces.iter().all(|&[a, b, c]| {
+            let (a, b, c) = (&mesh.vertices[a], &mesh.vertices[b], &mesh.vertices[c]);
+            let n = cross(&(b - a), &(c - a));
+            dot(&n, &(a - p)) > 0.0
+        })
+    }
+
+    #[test]
+    fn sphere_mesh() {
+        let sphere = Sphere::new(Vec3::new(1.0, 2.0, 3.0), 2.0, diffuse());
+        let mut mesh = TriangleMesh::new();
+        mesh.add_sphere(&sphere, 8);
+        assert_eq!(mesh.vertices.len(), 2 + 7 * 16);
+        assert_eq!(mesh.faces.len(), 2 * 16 + 2 * 16 * 6);
+        assert!(mesh.vertices.iter().all(|v| (length(&(v - &sphere.center())) - 2.0).abs() < 1e-5));
+        assert!(faces_away_from(&mesh, &sphere.center()));
+
+        let mut inside_out = TriangleMesh::new();
+        inside_out.add_sphere(&Sphere::new(Vec3::new(1.0, 2.0, 3.0), -2.0, diffuse()), 8);
+        assert_eq!(inside_out.vertices.len(), mesh.vertices.len());
+        assert!(!faces_away_from(&inside_out, &sphere.center()));
+    }
+
+    #[test]
+    fn capsule_mesh() {
+        let capsule = Capsule::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(3.0, 0.0, 0.0), 0.5, diffuse());
+        let mut mesh = TriangleMesh::new();
+        mesh.add_capsule(&capsule, 5);
+        // Rounded up to 6 divisions, with the equator at both caps: 6 rings between the poles, 12 vertices each.
+        assert_eq!(mesh.vertices.len(), 2 + 6 * 12);
+        assert_eq!(mesh.faces.len(), 2 * 12 + 2 * 12 * 5);
+        let bbox_max = mesh.vertices.iter().fold(-1.0f32, |m, v| m.max(v.x));
+        let bbox_min = mesh.vertices.iter().fold(1.0f32, |m, v| m.min(v.x));
+        assert!((bbox_max - 3.5).abs() < 1e-5); assert!((bbox_min + 0.5).abs() < 1e-5);
+        assert!(faces_away_from(&mesh, &Vec3::new(1.5, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn mirrored_mesh_keeps_facing_out() {
+        let mut sphere = TriangleMesh::new();
+        sphere.add_sphere(&Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, diffuse()), 4);
+        let mut mesh = TriangleMesh::new();
+        mesh.add_transformed(&sphere, &Transform::scale(-2.0, 1.0, 1.0));
+        assert_eq!(mesh.faces.len(), sphere.faces.len());
+        assert!(faces_away_from(&mesh, &Vec3::new(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn write_obj() {
+        let mut mesh = TriangleMesh::new();
+        mesh.begin_object("first");
+        mesh.vertices = vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.5), Vec3::new(1.0, 1.0, 0.0)];
+        mesh.faces.push([0, 1, 2]);
+        mesh.begin_object("second");
+        mesh.faces.push([1, 3, 2]);
+        let mut obj = Vec::new();
+        mesh.write_obj(&mut obj).unwrap();
+        let expected = "# 4 vertices, 2 triangles\nv 0 0 0\nv 1 0 0\nv 0 1 0.5\nv 1 1 0\no first\nf 1 2 3\no second\nf 2 4 3\n";
+        assert_eq!(String::from_utf8(obj).unwrap(), expected);
+    }
+}
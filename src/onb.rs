@@ -0,0 +1,99 @@
+use super::vec3::{ Vec3, cross, dot, length_squared, normalize };
+
+/// Orthonormal basis, typically a tangent frame around a surface normal.
+#[derive(Debug, Copy, Clone)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// Create new basis around a normal, with an arbitrary orientation of the other two axes.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - Normalized vector that will become the W axis.
+    #[inline(always)]
+    pub fn new_from_w(n: &Vec3) -> Onb {
+        let a = if n.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let v = normalize(&cross(n, &a));
+        let u = cross(&v, n);
+        Onb { u, v, w: *n }
+    }
+
+    /// Create new tangent frame around a normal, with the U axis as close as possible to given tangent.
+    /// Falls back to an arbitrary orientation when the tangent is (nearly) parallel to the normal.
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - Normalized vector that will become the W axis.
+    /// * `t` - Tangent hint for the U axis.
+    #[inline(always)]
+    pub fn new_from_w_and_u(n: &Vec3, t: &Vec3) -> Onb {
+        let d = dot(n, t);
+        let u = Vec3::new(t.x - d * n.x, t.y - d * n.y, t.z - d * n.z);
+        if length_squared(&u) < 1e-8 {
+            return Onb::new_from_w(n);
+        }
+        let u = normalize(&u);
+        let v = cross(n, &u);
+        Onb { u, v, w: *n }
+    }
+
+    /// Transform vector from the local coordinates of this basis to world coordinates.
+    ///
+    /// # Arguments
+    ///
+    /// * `a` - Vector in local coordinates.
+    #[inline(always)]
+    pub fn local_to_world(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(
+            a.x * self.u.x + a.y * self.v.x + a.z * self.w.x,
+            a.x * self.u.y + a.y * self.v.y + a.z * self.w.y,
+            a.x * self.u.z + a.y * self.v.z + a.z * self.w.z,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_orthonormal(onb: &Onb) {
+        assert!((length_squared(&onb.u) - 1.0).abs() < 1e-6);
+        assert!((length_squared(&onb.v) - 1.0).abs() < 1e-6);
+        assert!((length_squared(&onb.w) - 1.0).abs() < 1e-6);
+        assert!(dot(&onb.u, &onb.v).abs() < 1e-6);
+        assert!(dot(&onb.v, &onb.w).abs() < 1e-6);
+        assert!(dot(&onb.u, &onb.w).abs() < 1e-6);
+    }
+
+    #[test]
+    fn basis_from_normal() {
+        assert_orthonormal(&Onb::new_from_w(&Vec3::new(0.0, 1.0, 0.0)));
+        assert_orthonormal(&Onb::new_from_w(&Vec3::new(1.0, 0.0, 0.0)));
+        assert_orthonormal(&Onb::new_from_w(&normalize(&Vec3::new(1.0, -2.0, 3.0))));
+    }
+
+    #[test]
+    fn basis_keeps_tangent() {
+        let onb = Onb::new_from_w_and_u(&Vec3::new(0.0, 1.0, 0.0), &Vec3::new(1.0, 0.5, 0.0));
+        assert_orthonormal(&onb);
+        assert_eq!(onb.u.x, 1.0); assert_eq!(onb.u.y, 0.0); assert_eq!(onb.u.z, 0.0);
+        assert_eq!(onb.v.x, 0.0); assert_eq!(onb.v.y, 0.0); assert_eq!(onb.v.z, -1.0);
+    }
+
+    #[test]
+    fn basis_with_degenerate_tangent() {
+        let onb = Onb::new_from_w_and_u(&Vec3::new(0.0, 1.0, 0.0), &Vec3::new(0.0, 2.0, 0.0));
+        assert_orthonormal(&onb);
+    }
+
+    #[test]
+    fn local_to_world_coords() {
+        let onb = Onb::new_from_w_and_u(&Vec3::new(0.0, 1.0, 0.0), &Vec3::new(1.0, 0.0, 0.0));
+        let a = onb.local_to_world(&Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(a.x, 1.0); assert_eq!(a.y, 3.0); assert_eq!(a.z, -2.0);
+    }
+}
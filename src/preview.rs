@@ -0,0 +1,88 @@
+/// Characters used for the preview, ordered from darkest to brightest.
+const RAMP: &[u8] = b" .:-=+*#%@";
+
+/// Downsample RGBA buffer to a block of text that can be printed to a terminal.
+///
+/// Each character covers a block of pixels, twice as tall as it is wide to account
+/// for the shape of terminal cells, and is chosen by the average luminance of the block.
+///
+/// # Arguments
+///
+/// * `buffer` - RGBA values, 4 bytes per pixel.
+/// * `width` - Image width.
+/// * `height` - Image height.
+/// * `columns` - Number of characters per line.
+/// * `ansi_colors` - Whether to also color each character using 24-bit ANSI escape codes.
+pub fn to_ascii(buffer: &[u8], width: u32, height: u32, columns: u32, ansi_colors: bool) -> String {
+    let columns = columns.min(width).max(1);
+    let rows = ((columns as f32 * height as f32 / width as f32 * 0.5).round() as u32).min(height).max(1);
+    let mut output = String::new();
+    for row in 0..rows {
+        let (min_y, max_y) = (row * height / rows, (row + 1) * height / rows);
+        for col in 0..columns {
+            let (min_x, max_x) = (col * width / columns, (col + 1) * width / columns);
+            let mut sum = [0u32; 3];
+            for y in min_y..max_y {
+                for x in min_x..max_x {
+                    let i = ((y * width + x) * 4) as usize;
+                    sum[0] += buffer[i] as u32;
+                    sum[1] += buffer[i + 1] as u32;
+                    sum[2] += buffer[i + 2] as u32;
+                }
+            }
+            let count = (max_y - min_y) * (max_x - min_x);
+            let (r, g, b) = (sum[0] / count, sum[1] / count, sum[2] / count);
+            let luminance = (0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32) / 255.0;
+            let c = RAMP[((luminance * (RAMP.len() - 1) as f32).round() as usize).min(RAMP.len() - 1)] as char;
+            if ansi_colors {
+                output.push_str(&format!("\x1b[38;2;{};{};{}m{}", r, g, b, c));
+            } else {
+                output.push(c);
+            }
+        }
+        if ansi_colors {
+            output.push_str("\x1b[0m");
+        }
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split_image(width: u32, height: u32) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for _y in 0..height {
+            for x in 0..width {
+                let v = if x < width / 2 { 0 } else { 255 };
+                buffer.extend_from_slice(&[v, v, v, 255]);
+            }
+        }
+        buffer
+    }
+
+    #[test]
+    fn preview_dimensions() {
+        let buffer = split_image(64, 64);
+        let text = to_ascii(&buffer, 64, 64, 16, false);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines.iter().all(|l| l.len() == 16));
+    }
+
+    #[test]
+    fn preview_luminance() {
+        let buffer = split_image(4, 2);
+        let text = to_ascii(&buffer, 4, 2, 2, false);
+        assert_eq!(text, " @\n");
+    }
+
+    #[test]
+    fn preview_colors() {
+        let buffer = split_image(4, 2);
+        let text = to_ascii(&buffer, 4, 2, 2, true);
+        assert_eq!(text, "\x1b[38;2;0;0;0m \x1b[38;2;255;255;255m@\x1b[0m\n");
+    }
+}
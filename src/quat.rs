@@ -0,0 +1,163 @@
+use super::math::{ Degrees, deg_to_rad };
+use super::vec3::{ Vec3, normalize };
+use super::mat4::Mat4;
+
+/// Quaternion representing a rotation.
+#[derive(Debug, Copy, Clone)]
+pub struct Quat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Quat {
+    /// Create new quaternion with all components specified.
+    #[inline(always)]
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Quat {
+        Quat { x, y, z, w }
+    }
+
+    /// Create new quaternion representing no rotation.
+    #[inline(always)]
+    pub fn identity() -> Quat {
+        Quat::new(0.0, 0.0, 0.0, 1.0)
+    }
+
+    /// Create new quaternion rotating around arbitrary axis.
+    ///
+    /// # Arguments
+    ///
+    /// * `angle` - Rotation angle in degrees.
+    /// * `axis` - Vector representing the rotation axis.
+    #[inline(always)]
+    pub fn from_axis_angle(angle: Degrees, axis: &Vec3) -> Quat {
+        let a = normalize(axis);
+        let half = 0.5 * deg_to_rad(angle);
+        let s = half.sin();
+        Quat::new(s * a.x, s * a.y, s * a.z, half.cos())
+    }
+
+    /// Compute the length of the quaternion, which is 1.0 for quaternions representing rotations.
+    #[inline(always)]
+    pub fn length(self) -> f32 {
+        dot(&self, &self).sqrt()
+    }
+
+    /// Create new quaternion of unit length, pointing the same way as this one.
+    #[inline(always)]
+    pub fn normalized(self) -> Quat {
+        let inv_len = 1.0 / self.length();
+        Quat::new(inv_len * self.x, inv_len * self.y, inv_len * self.z, inv_len * self.w)
+    }
+
+    /// Compute rotation matrix of the quaternion.
+    ///
+    /// The quaternion is expected to be normalized.
+    pub fn to_mat4(self) -> Mat4 {
+        let (x, y, z, w) = (self.x, self.y, self.z, self.w);
+        Mat4::new(
+            1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - z * w), 2.0 * (x * z + y * w), 0.0,
+            2.0 * (x * y + z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - x * w), 0.0,
+            2.0 * (x * z - y * w), 2.0 * (y * z + x * w), 1.0 - 2.0 * (x * x + y * y), 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        )
+    }
+}
+
+/// Compute the dot product of two quaternions.
+#[inline(always)]
+pub fn dot(q1: &Quat, q2: &Quat) -> f32 {
+    q1.x * q2.x + q1.y * q2.y + q1.z * q2.z + q1.w * q2.w
+}
+
+/// Interpolate between two rotations along the shortest arc, at a constant angular speed.
+///
+/// # Arguments
+///
+/// * `q1` - Rotation at `t` equal to 0.0, as a normalized quaternion.
+/// * `q2` - Rotation at `t` equal to 1.0, as a normalized quaternion.
+/// * `t` - Interpolation parameter.
+pub fn slerp(q1: &Quat, q2: &Quat, t: f32) -> Quat {
+    // Quaternions `q` and `-q` represent the same rotation; pick the one closer to `q1` to take the shortest arc.
+    let mut cos_theta = dot(q1, q2);
+    let q2 = if cos_theta < 0.0 {
+        cos_theta = -cos_theta;
+        Quat::new(-q2.x, -q2.y, -q2.z, -q2.w)
+    } else {
+        *q2
+    };
+    let (s1, s2) = if cos_theta > 0.9995 {
+        // Nearly the same rotations, where the sine below would be unstable, are interpolated linearly.
+        (1.0 - t, t)
+    } else {
+        let theta = cos_theta.acos();
+        let sin_theta = theta.sin();
+        (((1.0 - t) * theta).sin() / sin_theta, (t * theta).sin() / sin_theta)
+    };
+    Quat::new(
+        s1 * q1.x + s2 * q2.x,
+        s1 * q1.y + s2 * q2.y,
+        s1 * q1.z + s2 * q2.z,
+        s1 * q1.w + s2 * q2.w,
+    ).normalized()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity_matrix() {
+        let m = Quat::identity().to_mat4();
+        assert_eq!(m.m00, 1.0); assert_eq!(m.m11, 1.0); assert_eq!(m.m22, 1.0); assert_eq!(m.m33, 1.0);
+        assert_eq!(m.m01, 0.0); assert_eq!(m.m12, 0.0); assert_eq!(m.m20, 0.0);
+    }
+
+    #[test]
+    fn rotate_around_y() {
+        // Rotating +X by 90 degrees around +Y results in -Z.
+        let m = Quat::from_axis_angle(90.0, &Vec3::new(0.0, 2.0, 0.0)).to_mat4();
+        assert!(m.m00.abs() < 1e-6); assert!(m.m10.abs() < 1e-6); assert!((m.m20 + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn slerp_endpoints() {
+        let a = Quat::from_axis_angle(30.0, &Vec3::new(1.0, 2.0, 3.0));
+        let b = Quat::from_axis_angle(-120.0, &Vec3::new(0.0, 1.0, 0.0));
+        let q = slerp(&a, &b, 0.0);
+        assert!((q.x - a.x).abs() < 1e-6); assert!((q.y - a.y).abs() < 1e-6); assert!((q.z - a.z).abs() < 1e-6); assert!((q.w - a.w).abs() < 1e-6);
+        // The end is the same rotation, possibly with the opposite sign.
+        let q = slerp(&a, &b, 1.0);
+        assert!((dot(&q, &b).abs() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn slerp_keeps_unit_length() {
+        let a = Quat::from_axis_angle(10.0, &Vec3::new(0.0, 0.0, 1.0));
+        // Far apart, nearly the same, and on the opposite hemisphere (the same rotation as `a`).
+        for b in &[
+            Quat::from_axis_angle(170.0, &Vec3::new(1.0, -1.0, 0.5)),
+            Quat::from_axis_angle(10.01, &Vec3::new(0.0, 0.0, 1.0)),
+            Quat::from_axis_angle(-350.0, &Vec3::new(0.0, 0.0, 1.0)),
+        ] {
+            for i in 0..=10 {
+                let q = slerp(&a, b, i as f32 / 10.0);
+                assert!((q.length() - 1.0).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn slerp_constant_speed_shortest_arc() {
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        let a = Quat::from_axis_angle(0.0, &z);
+        // Rotating by -270 degrees is the same as by 90 degrees, so a quarter of the way is 22.5 degrees.
+        let b = Quat::from_axis_angle(-270.0, &z);
+        let expected = Quat::from_axis_angle(22.5, &z);
+        let q = slerp(&a, &b, 0.25);
+        assert!((dot(&q, &expected).abs() - 1.0).abs() < 1e-6);
+        let m = q.to_mat4();
+        assert!((m.m00 - 22.5f32.to_radians().cos()).abs() < 1e-5); assert!((m.m10 - 22.5f32.to_radians().sin()).abs() < 1e-5);
+    }
+}
@@ -1,5 +1,11 @@
-use super::vec3::Vec3;
+use super::vec3::{ Vec3, dot };
 
+/// Ray with an origin and a direction.
+///
+/// The direction does not need to be normalized (for example, after transforming the ray into
+/// the local space of an object), and all intersection routines handle that. Ray parameters
+/// are then measured in multiples of the direction's length, not in world units.
+/// Rays traced by the renderer itself are always normalized.
 #[derive(Debug, Copy, Clone)]
 pub struct Ray {
     pub o: Vec3,
@@ -8,6 +14,8 @@ pub struct Ray {
 
 impl Ray {
     pub fn new(o: Vec3, d: Vec3) -> Ray {
+        debug_assert!(d.x.is_finite() && d.y.is_finite() && d.z.is_finite(), "ray direction must be finite");
+        debug_assert!(d.x != 0.0 || d.y != 0.0 || d.z != 0.0, "ray direction must not be zero");
         Ray { o, d }
     }
 
@@ -18,4 +26,56 @@ impl Ray {
             self.o.z + t * self.d.z,
         )
     }
+
+    /// Intersect the ray with a plane.
+    ///
+    /// # Arguments
+    ///
+    /// * `point` - Any point on the plane.
+    /// * `normal` - Normal of the plane (does not need to be normalized).
+    ///
+    /// # Returns
+    ///
+    /// Ray parameter of the intersection, or `None` if the ray is parallel to the plane or points away from it.
+    pub fn intersect_plane(&self, point: &Vec3, normal: &Vec3) -> Option<f32> {
+        let denom = dot(&self.d, normal);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = dot(&(point - &self.o), normal) / denom;
+        if t > 0.0 {
+            Some(t)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intersect_plane_hit() {
+        let ray = Ray::new(Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let t = ray.intersect_plane(&Vec3::new(5.0, 0.0, 5.0), &Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(t, Some(2.0));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0));
+        let t = ray.intersect_plane(&Vec3::new(2.0, 0.0, 0.0), &Vec3::new(-1.0, 0.0, 0.0));
+        assert_eq!(t, Some(2.0));
+    }
+
+    #[test]
+    fn intersect_plane_parallel() {
+        let ray = Ray::new(Vec3::new(0.0, 2.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let t = ray.intersect_plane(&Vec3::new(0.0, 0.0, 0.0), &Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(t, None);
+    }
+
+    #[test]
+    fn intersect_plane_behind() {
+        let ray = Ray::new(Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let t = ray.intersect_plane(&Vec3::new(0.0, 0.0, 0.0), &Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(t, None);
+    }
 }
@@ -0,0 +1,335 @@
+/// Strategy for choosing the positions of samples within a pixel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplingMode {
+    /// Uniformly distributed random positions.
+    Random,
+    /// Low-discrepancy sequence per pixel, randomized by a blue noise mask
+    /// so that the error of neighboring pixels is decorrelated.
+    BlueNoise,
+}
+
+/// Strategy for choosing which light to sample directly at a surface, out of all the lights of the scene.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightSelection {
+    /// Every light with the same probability.
+    Uniform,
+    /// Lights with probability proportional to their power, so that bright lights are sampled more often
+    /// than dim ones, which reduces noise in scenes with many lights of different brightness.
+    Power,
+}
+
+const BLUE_NOISE_SIZE: u32 = 32;
+
+/// Tileable 32x32 blue noise mask (generated using the void-and-cluster method).
+const BLUE_NOISE: [u8; (BLUE_NOISE_SIZE * BLUE_NOISE_SIZE) as usize] = [
+     52,  95, 202, 145,  45, 154, 122, 231, 201,  29, 177, 250,  53,  80, 191,  96,  58,  20, 217, 248, 161, 226, 107, 244,  85,  42,  72, 159, 117,  86, 241, 190,
+    134, 252,  29, 108, 194, 239,   7,  66,  87, 220, 156, 131,  23, 164, 118,  12, 237, 126, 146, 175,  95,  64,  32, 183, 141, 230, 193,  14, 208,  67, 228,  21,
+     78, 164,  65, 129,  81, 213,  38, 137, 190,  46,   9, 105, 211,  41, 245,  68, 207, 106,  83,   1,  43, 197, 125, 217,  22,  99, 176, 246,  47, 143, 171, 111,
+    185, 209, 229,  12, 178, 246, 101, 172, 113, 237,  61, 184, 228,  89, 149, 173,  32,  49, 184, 213, 140, 241, 166,  77,   6, 151,  60, 124,  91, 200,   1,  42,
+    147,  26,  92,  48, 158,  17,  73, 150,  21, 205, 125,  76, 141,   4, 196, 130, 226, 158, 235,  21,  70, 110,  52, 203, 251, 113,  36, 163,  27, 239, 103, 219,
+     58, 248, 115, 141, 207, 124, 225,  54, 253,  93, 157,  31, 240,  54, 100,  17,  77, 112, 134,  90, 191, 155,  26, 135,  85, 179, 216, 229, 136, 182,  72, 125,
+     10, 171, 193, 236,  63,  88, 197,  34, 180,  10, 218, 170, 201, 116, 182, 247,  61, 204,   8, 254,  39, 226,  98, 237,  46, 194,  68,   7,  83,  51, 198, 158,
+     99,  41,  79,  31,   3, 167, 105, 146, 133,  83, 109,  43,  70,  24, 154, 214,  35, 177, 165,  56, 124, 181,   5, 168, 122,  20, 101, 155, 112, 255,  20, 224,
+    241, 135, 214, 111, 186, 229,  45, 211, 234,  59, 189, 249, 135, 225,  88, 122, 143,  82, 101, 218, 148,  76, 210,  60, 223, 142, 244, 210, 174,  36, 143,  88,
+    120, 180,  68, 149, 252, 129,  24,  76,   5, 118, 151,  15,  97, 168,   0,  49, 232,  26, 193,  13, 242, 114,  29,  90, 160,  39,  77,  15, 127, 188,  65, 203,
+     50,  25, 163,  14,  54,  93, 157, 195, 245, 165,  36, 206, 236,  62, 184, 200, 249, 111, 159,  44,  67, 138, 197, 252, 183, 107, 199,  57,  94, 230, 161,   4,
+    218, 238, 192,  84, 219, 117, 178,  64, 100, 221,  52, 126,  77, 142, 104,  18,  71, 132, 221,  93, 207, 173,  18,  50, 129,   0, 236, 148, 214,  28,  79, 109,
+    130,  38, 102, 139, 243,  35, 206,  19, 136,  85, 175,  27, 190, 217,  40, 152, 172,  55, 184,  33, 236, 103,  79, 153, 226,  67, 169, 114,  44, 134, 248, 175,
+     91, 152, 202,  71,   1, 168,  49, 110, 238,   8, 147, 255, 112,  12, 240, 123, 227,  81,   5, 147, 126,  12, 216, 118, 193,  31,  86, 243,  11, 156, 194,  61,
+    233,   9,  56, 181, 121, 231, 150, 217, 186,  69, 199,  94,  59, 162,  89, 208,  22, 100, 250, 200,  64, 166, 245,  43,  96, 144, 203, 180,  55,  98, 209,  21,
+    116, 167, 251, 212,  28,  92,  78, 127,  41, 159,  30, 227, 133,  46, 179,  65, 194, 117, 160,  37,  87, 186,  26,  58, 162, 234,  22,  75, 222, 122,  35,  73,
+    225, 132,  42, 108, 143, 195,  59,  17, 250, 105, 121, 211,   2, 244, 148,  31, 139, 237,  48, 215, 133, 230, 106, 208, 128,   8, 111, 137, 164, 253, 145, 187,
+     16,  97,  80, 158,  11, 242, 166, 204, 179,  84,  52, 170, 189,  81, 104, 219,   9,  75, 175, 109,   2,  71, 144, 180,  82, 247,  65, 189,  44,   2,  89, 174,
+     50, 245, 192,  63, 222,  37, 134,  98,   6, 234, 140,  72,  19, 116, 232, 167,  56,  95, 187,  23, 154, 255,  51,  15, 221, 171,  33, 215, 104, 231,  61, 204,
+    138,  29, 215, 176, 114,  74, 230,  47, 152, 216,  27, 248, 156, 205,  40, 130, 199, 249, 120, 227, 205,  92, 195,  38, 119, 146,  94, 198, 131,  25, 155, 110,
+    165, 123,   3, 147,  93,  19, 187, 120,  66, 109, 196, 127,  60,  97,  13,  69, 153,  16, 142,  34,  62, 127, 160, 102, 234,  57,   6,  78, 178, 240,  72, 220,
+     39,  84, 239,  53, 208, 252, 157, 201,  34, 172,  86,  44, 185, 220, 241, 173,  87, 218,  47,  82, 169, 243,  25,  73, 206, 166, 249, 152,  48, 121,  13, 188,
+     59, 199, 102, 170,  30, 130,  82,   7, 238, 144, 225,   4, 162, 138,  30, 118, 191, 108, 235, 183, 113,   7, 219, 187, 132,  17, 110, 224,  32, 210,  92, 254,
+    128,  15, 233, 139,  69, 183, 106,  57, 213,  96,  23, 253, 107,  80,  51, 210,   0,  63,  22, 151, 203,  53, 140,  90,  41,  64, 181,  84, 139, 168, 107, 149,
+     74, 185, 155,  45, 222,  16, 246, 163, 132,  47, 192,  68, 125, 233, 179, 145, 251, 165, 131,  94, 224,  70, 172, 253, 117, 209, 231,  20, 196,  54,   0, 228,
+    212,  28, 115,  85, 202, 123,  40, 188,  73, 115, 174, 151, 201,  10,  37,  99,  75, 198,  42, 244,  11, 105,  33, 149,   3, 161,  99, 126,  70, 242, 177,  37,
+    163,  97, 250,   5, 173,  95, 150, 229,  10, 242,  33, 215,  58,  90, 161, 223, 116,  27, 177,  80, 121, 191, 233, 200,  79,  51, 246,  34, 154, 206,  88, 136,
+    223,  48, 192,  67, 235,  55,  28, 205,  87, 141, 103,  18, 247, 136, 188,  16, 239,  56, 140, 214, 159,  49, 131,  18, 223, 176, 142, 190,  11, 108, 119,  62,
+     13, 146, 129, 209, 160, 137, 112, 176,  62, 221, 162,  81, 119,  46, 211,  69, 153, 202, 102, 235,  14,  66, 182,  98, 114,  63,  89, 216,  45, 232,  24, 247,
+     83, 182, 103,  19,  36,  78, 255,   1, 128, 196,  40, 185, 232, 169, 106, 128,  86,   6, 171,  35,  91, 251, 145,  38, 238,  25, 124, 164,  76, 137, 198, 169,
+     39,  71, 243, 119, 227, 186, 212, 101,  50, 240,  14, 148,  66,   3,  30, 254,  43, 228, 113, 135, 189, 207,  75, 170, 212, 197,   2, 254, 181,  57, 100, 213,
+    156, 220,   4, 174,  60,  91,  23, 167, 144,  74, 115,  96, 204, 224, 138, 178, 157, 195,  74,  53,  24, 120,   8, 153,  55, 133, 104, 222,  32, 150,   9, 123,
+];
+
+// Generalized golden ratio constants of the R2 sequence.
+const R2_ALPHA_U: f32 = 0.754_877_7;
+const R2_ALPHA_V: f32 = 0.569_840_3;
+
+/// Compute the position of a specific sample within a pixel using the blue noise mask.
+///
+/// # Arguments
+///
+/// * `x` - Pixel column.
+/// * `y` - Pixel row.
+/// * `sample` - Index of the sample within the pixel.
+///
+/// # Returns
+///
+/// (horizontal offset, vertical offset), both in the range of 0.0 to 1.0.
+pub fn blue_noise_sample(x: u32, y: u32, sample: u32) -> (f32, f32) {
+    let lookup = |x: u32, y: u32| {
+        let i = (y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + (x % BLUE_NOISE_SIZE);
+        (BLUE_NOISE[i as usize] as f32 + 0.5) / 256.0
+    };
+    // The second dimension reads the mask at an offset of half its size.
+    let half = BLUE_NOISE_SIZE / 2;
+    let u = lookup(x, y) + sample as f32 * R2_ALPHA_U;
+    let v = lookup(x + half, y + half) + sample as f32 * R2_ALPHA_V;
+    (u.fract(), v.fract())
+}
+
+/// Map a point of the unit square onto the unit disk, using the concentric mapping of Shirley and Chiu.
+///
+/// Uniformly distributed points map to uniformly distributed points, without rejecting any of them,
+/// and points close to each other in the square stay close to each other on the disk, which keeps
+/// stratified samples stratified. Used for points on the camera lens, and for cosine-weighted directions
+/// (by projecting the points on the disk up onto the hemisphere above it).
+///
+/// # Arguments
+///
+/// * `u` - Horizontal coordinate, in the range of 0.0 to 1.0.
+/// * `v` - Vertical coordinate, in the range of 0.0 to 1.0.
+///
+/// # Returns
+///
+/// (x, y) coordinates of the point on the disk of radius 1.0 centered at the origin.
+pub fn concentric_sample_disk(u: f32, v: f32) -> (f32, f32) {
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    // The center of the square, where the wedge of the point is undefined, is the center of the disk.
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    // Squares of "radius" max(|a|, |b|) map onto circles of the same radius, split into four wedges.
+    let (r, phi) = if a.abs() > b.abs() {
+        (a, std::f32::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (b, std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * (a / b))
+    };
+    (r * phi.cos(), r * phi.sin())
+}
+
+/// Derive the seed of the random number generator for a pixel from the base seed and the pixel's coordinates,
+/// so that different pixels get uncorrelated sequences, and every pixel gets the same sequence no matter
+/// how the image is split into tiles and between threads.
+///
+/// # Arguments
+///
+/// * `seed` - Base seed of the render.
+/// * `x` - Column of the pixel.
+/// * `y` - Row of the pixel.
+pub fn pixel_seed(seed: u64, x: u32, y: u32) -> u64 {
+    // Mix the inputs using the SplitMix64 finalizer.
+    let mut z = seed ^ ((x as u64) << 32 | y as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Derive the base seed of a progressive render pass, so that every pass samples the image differently.
+///
+/// The first pass uses the base seed itself, which makes a single-pass render
+/// identical to a non-progressive one.
+///
+/// # Arguments
+///
+/// * `seed` - Base seed of the render.
+/// * `pass` - Index of the pass.
+pub fn pass_seed(seed: u64, pass: u32) -> u64 {
+    if pass == 0 {
+        return seed;
+    }
+    pixel_seed(seed.wrapping_add(pass as u64), u32::MAX, u32::MAX)
+}
+
+/// Discrete probability distribution over a list of items, with each item chosen with probability proportional
+/// to its weight, by inverting the cumulative distribution function.
+#[derive(Debug, Clone, Default)]
+pub struct Distribution {
+    cdf: Vec<f32>,
+}
+
+impl Distribution {
+    /// Create new distribution.
+    ///
+    /// # Arguments
+    ///
+    /// * `weights` - Weight of each item. Negative (or NaN) weights count as 0.0, and if all the weights
+    ///   are 0.0, no item can be chosen.
+    pub fn new(weights: &[f32]) -> Distribution {
+        let mut sum = 0.0;
+        let mut cdf: Vec<f32> = weights.iter().map(|&w| { sum += w.max(0.0); sum }).collect();
+        if sum <= 0.0 {
+            return Distribution::default();
+        }
+        for c in cdf.iter_mut() {
+            *c /= sum;
+        }
+        // The last item with any weight has to end at exactly 1.0, so that rounding errors cannot make
+        // the random numbers just below 1.0 fall beyond it.
+        let last = cdf.iter().position(|&c| c >= cdf[cdf.len() - 1]).unwrap_or(0);
+        for c in cdf[last..].iter_mut() {
+            *c = 1.0;
+        }
+        Distribution { cdf }
+    }
+
+    /// Check whether no item can be chosen.
+    pub fn is_empty(&self) -> bool {
+        self.cdf.is_empty()
+    }
+
+    /// Get probability of choosing given item.
+    ///
+    /// # Arguments
+    ///
+    /// * `i` - Index of the item.
+    pub fn probability(&self, i: usize) -> f32 {
+        match i {
+            _ if i >= self.cdf.len() => 0.0,
+            0 => self.cdf[0],
+            _ => self.cdf[i] - self.cdf[i - 1],
+        }
+    }
+
+    /// Choose an item.
+    ///
+    /// # Arguments
+    ///
+    /// * `u` - Random number, in the range of 0.0 to 1.0 (exclusive).
+    ///
+    /// # Returns
+    ///
+    /// Index of the chosen item, and the probability of choosing it, or `None` if no item can be chosen.
+    pub fn sample(&self, u: f32) -> Option<(usize, f32)> {
+        if self.cdf.is_empty() {
+            return None;
+        }
+        let i = self.cdf.partition_point(|&c| c <= u).min(self.cdf.len() - 1);
+        Some((i, self.probability(i)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discrete_distribution() {
+        let distribution = Distribution::new(&[1.0, 0.0, 3.0, -1.0]);
+        assert_eq!(distribution.probability(0), 0.25); assert_eq!(distribution.probability(1), 0.0);
+        assert_eq!(distribution.probability(2), 0.75); assert_eq!(distribution.probability(3), 0.0);
+        assert_eq!(distribution.sample(0.0), Some((0, 0.25)));
+        assert_eq!(distribution.sample(0.2499), Some((0, 0.25)));
+        // Items without weight are never chosen, not even at the boundaries.
+        assert_eq!(distribution.sample(0.25), Some((2, 0.75)));
+        assert_eq!(distribution.sample(0.99999994), Some((2, 0.75)));
+        // Counts of regularly spaced random numbers follow the weights.
+        let distribution = Distribution::new(&[0.1, 0.2, 0.3, 0.4]);
+        let mut counts = [0; 4];
+        for i in 0..1000 {
+            counts[distribution.sample((i as f32 + 0.5) / 1000.0).unwrap().0] += 1;
+        }
+        assert_eq!(counts, [100, 200, 300, 400]);
+        assert!(Distribution::new(&[0.0, 0.0]).sample(0.5).is_none());
+        assert!(Distribution::new(&[]).is_empty());
+    }
+
+    #[test]
+    fn concentric_disk_is_uniform() {
+        assert_eq!(concentric_sample_disk(0.5, 0.5), (0.0, 0.0));
+        let (x, y) = concentric_sample_disk(1.0, 0.5);
+        assert!((x - 1.0).abs() < 1e-6 && y.abs() < 1e-6);
+        let (x, y) = concentric_sample_disk(0.5, 0.0);
+        assert!(x.abs() < 1e-6 && (y + 1.0).abs() < 1e-6);
+        // A regular grid over the square covers the disk evenly: each ring gets its share of the area.
+        let n = 200;
+        let mut rings = [0u32; 4];
+        let mut quadrants = [0u32; 4];
+        for i in 0..n {
+            for j in 0..n {
+                let (x, y) = concentric_sample_disk((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                let r2 = x * x + y * y;
+                assert!(r2 <= 1.0 + 1e-6);
+                rings[((r2 * 4.0) as usize).min(3)] += 1;
+                quadrants[(if x < 0.0 { 1 } else { 0 }) + (if y < 0.0 { 2 } else { 0 })] += 1;
+            }
+        }
+        for &count in rings.iter().chain(quadrants.iter()) {
+            assert!((count as f32 / (n * n) as f32 - 0.25).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn blue_noise_mask_covers_range() {
+        let mut histogram = [0u32; 4];
+        for v in BLUE_NOISE.iter() {
+            histogram[(*v / 64) as usize] += 1;
+        }
+        assert_eq!(histogram, [256, 256, 256, 256]);
+    }
+
+    #[test]
+    fn blue_noise_samples_in_pixel() {
+        for y in 0..40 {
+            for x in 0..40 {
+                for s in 0..16 {
+                    let (u, v) = blue_noise_sample(x, y, s);
+                    assert!((0.0..1.0).contains(&u));
+                    assert!((0.0..1.0).contains(&v));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blue_noise_mask_tiles() {
+        assert_eq!(blue_noise_sample(3, 5, 7), blue_noise_sample(3 + 32, 5 + 64, 7));
+    }
+
+    #[test]
+    fn blue_noise_lacks_low_frequencies() {
+        // Energy of the mask at the lowest frequencies of its discrete Fourier transform, with the mean removed.
+        // White noise of the same variance has the same expected energy at every frequency (n² × variance),
+        // while blue noise has hardly any at low frequencies.
+        let n = BLUE_NOISE_SIZE as i32;
+        let mean = BLUE_NOISE.iter().map(|&v| v as f32).sum::<f32>() / (n * n) as f32;
+        let variance = BLUE_NOISE.iter().map(|&v| (v as f32 - mean).powi(2)).sum::<f32>() / (n * n) as f32;
+        let (mut energy, mut frequencies) = (0.0, 0);
+        for ky in -4..=4 {
+            for kx in -4..=4 {
+                if (kx, ky) == (0, 0) || kx * kx + ky * ky > 16 {
+                    continue;
+                }
+                let (mut re, mut im) = (0.0f32, 0.0f32);
+                for (i, &v) in BLUE_NOISE.iter().enumerate() {
+                    let (x, y) = (i as i32 % n, i as i32 / n);
+                    let angle = -2.0 * std::f32::consts::PI * (kx * x + ky * y) as f32 / n as f32;
+                    re += (v as f32 - mean) * angle.cos();
+                    im += (v as f32 - mean) * angle.sin();
+                }
+                energy += re * re + im * im;
+                frequencies += 1;
+            }
+        }
+        let white_noise_energy = frequencies as f32 * (n * n) as f32 * variance;
+        assert!(energy < 0.01 * white_noise_energy);
+    }
+
+    #[test]
+    fn pixel_seeds_differ() {
+        assert_eq!(pixel_seed(7, 16, 32), pixel_seed(7, 16, 32));
+        assert_ne!(pixel_seed(7, 0, 0), pixel_seed(7, 16, 0));
+        assert_ne!(pixel_seed(7, 16, 0), pixel_seed(7, 0, 16));
+        assert_ne!(pixel_seed(7, 0, 0), pixel_seed(8, 0, 0));
+        // No collisions between the pixels of a few neighboring seeds.
+        let mut seeds: Vec<u64> = (0..4).flat_map(|s| (0..64 * 64).map(move |i| pixel_seed(s, i % 64, i / 64))).collect();
+        seeds.sort_unstable();
+        seeds.dedup();
+        assert_eq!(seeds.len(), 4 * 64 * 64);
+    }
+}
@@ -1,59 +1,925 @@
-use super::vec3::{ Vec3, dot, length_squared };
+use super::vec3::{ Vec3, dot, cross, length, length_squared, normalize, reflectance };
 use super::ray::Ray;
-use super::math::{ quadratic, Roots };
+use super::image::Image;
+use super::math::{ almost_zero, lerp };
+use super::onb::Onb;
+use super::bbox::BBox;
+use super::grid::Grid;
+use super::instance::Instance;
+use super::mesh::Mesh;
+use super::frustum::Frustum;
+use super::xform::UpAxis;
+use super::obj::TriangleMesh;
+use super::sampling::{ Distribution, LightSelection };
+use std::collections::hash_map::DefaultHasher;
 use std::f32::consts::PI;
+use std::hash::{ Hash, Hasher };
+use std::fs::File;
+use std::io::{ self, BufWriter, Write };
+use std::path::Path;
 
-#[derive(Debug, Copy, Clone)]
+// Not `Copy` because of the boxed materials in `Mix`.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Material {
-    Diffuse(Vec3, Texture),
-    Metal(Vec3, f32 /* roughness */),
+    Diffuse(Vec3, Texture, f32 /* roughness, as in the Oren-Nayar model (0.0 for Lambertian) */),
+    /// Metal, optionally under a clearcoat: a thin, colorless dielectric layer (such as the lacquer of car paint)
+    /// adding a Fresnel-weighted highlight on top of the metal's reflection. A clearcoat weight of 0.0 means no coat.
+    Metal(Vec3, f32 /* roughness */, f32 /* clearcoat weight */, f32 /* clearcoat roughness */),
+    AnisotropicMetal(Vec3, f32 /* roughness along tangent */, f32 /* roughness along bitangent */),
     Light(Vec3),
     Glass(Vec3 /* attenuation */, f32 /* ior */),
     Normal,
+    /// Shows the UV coordinates of the surface as red and green, (u, v, 0.0), to debug how textures
+    /// are mapped onto it, for example, where the mapping pinches or has a seam.
+    Uv,
+    /// Randomly chooses between the first and the second material for each path,
+    /// picking the second one with given probability.
+    Mix(Box<Material>, Box<Material>, f32 /* factor */),
+    /// Draws the edges of the faces of meshes in a flat color, lines of given width (in scene units) centered
+    /// on the edges, over the surface material, for the hidden-line look of technical illustrations.
+    /// Other objects have no edges, and only show the surface material.
+    Wireframe(Box<Material> /* surface */, Vec3 /* edge color */, f32 /* edge width */),
 }
 
-#[derive(Debug, Copy, Clone)]
+impl Material {
+    /// Get the fraction of light, for each color channel, that a shadow ray passing through a surface
+    /// of this material lets through. Glass lets through the light of its color, less the part it reflects
+    /// (its Fresnel reflectance), and everything else blocks it. Mixed materials let through the average
+    /// of both materials, weighted the same way they are picked.
+    ///
+    /// # Arguments
+    ///
+    /// * `cos_theta` - Cosine of the angle between the shadow ray and the surface normal.
+    pub fn shadow_transmittance(&self, cos_theta: f32) -> Vec3 {
+        match self {
+            Material::Glass(attenuation, ior) => {
+                // Shadow rays are not bent, so they leave the glass at the same angle as they entered it, and the
+                // reflectance is the same on both sides: the one of entering the denser medium at that angle.
+                let refraction_ratio = if *ior > 1.0 { 1.0 / ior } else { *ior };
+                (1.0 - reflectance(cos_theta.abs().min(1.0), refraction_ratio)) * attenuation
+            },
+            Material::Mix(first, second, factor) => {
+                let (a, b) = (first.shadow_transmittance(cos_theta), second.shadow_transmittance(cos_theta));
+                Vec3::new(lerp(a.x, b.x, *factor), lerp(a.y, b.y, *factor), lerp(a.z, b.z, *factor))
+            },
+            Material::Wireframe(surface, ..) => surface.shadow_transmittance(cos_theta),
+            _ => Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Check that the material does not create energy: that its reflectance (including texture colors)
+    /// and the attenuation of glass are between 0.0 and 1.0 in every channel, that glass has a positive
+    /// index of refraction, and that mixing factors are between 0.0 and 1.0. Lights are not checked.
+    ///
+    /// # Returns
+    ///
+    /// Description of each problem found, empty if there are none.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut check_color = |name: &str, c: &Vec3| {
+            if [c.x, c.y, c.z].iter().any(|v| !(0.0..=1.0).contains(v)) {
+                problems.push(format!("{} ({}, {}, {}) is outside [0, 1]", name, c.x, c.y, c.z));
+            }
+        };
+        match self {
+            Material::Diffuse(albedo, texture, _) => {
+                check_color("diffuse albedo", albedo);
+                if let Texture::Checkered(color1, color2, _, _) = texture {
+                    check_color("texture color", color1);
+                    check_color("texture color", color2);
+                }
+            },
+            Material::Metal(albedo, ..) | Material::AnisotropicMetal(albedo, ..) => check_color("metal albedo", albedo),
+            Material::Glass(attenuation, ior) => {
+                check_color("glass attenuation", attenuation);
+                if *ior <= 0.0 || ior.is_nan() {
+                    problems.push(format!("glass index of refraction {} is not positive", ior));
+                }
+            },
+            Material::Mix(first, second, factor) => {
+                if !(0.0..=1.0).contains(factor) {
+                    problems.push(format!("mixing factor {} is outside [0, 1]", factor));
+                }
+                problems.extend(first.validate());
+                problems.extend(second.validate());
+            },
+            // Edges are drawn in their flat color, like lights.
+            Material::Wireframe(surface, ..) => problems.extend(surface.validate()),
+            Material::Light(_) | Material::Normal | Material::Uv => {},
+        }
+        problems
+    }
+
+    // Max depth of paths that this material needs to look right (see `Scene::suggested_max_depth`).
+    fn suggested_max_depth(&self) -> u32 {
+        match self {
+            Material::Glass(..) => GLASS_MAX_DEPTH,
+            Material::Metal(..) | Material::AnisotropicMetal(..) => METAL_MAX_DEPTH,
+            Material::Mix(first, second, _) => first.suggested_max_depth().max(second.suggested_max_depth()),
+            Material::Wireframe(surface, ..) => surface.suggested_max_depth(),
+            Material::Diffuse(..) | Material::Light(_) | Material::Normal | Material::Uv => DIFFUSE_MAX_DEPTH,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Texture {
     None,
-    Checkered(Vec3 /* first color */, Vec3 /* second color */, f32 /* scale */),
+    /// Checks are square in UV space when both scales are equal. On spheres, where U spans
+    /// twice the arc length of V, use half the U scale for V to get square checks on the surface.
+    Checkered(Vec3 /* first color */, Vec3 /* second color */, f32 /* scale along U */, f32 /* scale along V */),
+}
+
+impl Texture {
+    /// Create new checkered texture whose checks are 10 times denser along V than along U.
+    ///
+    /// # Arguments
+    ///
+    /// * `color1` - First color.
+    /// * `color2` - Second color.
+    /// * `scale` - Frequency of the pattern along U.
+    pub fn checkered(color1: Vec3, color2: Vec3, scale: f32) -> Texture {
+        Texture::Checkered(color1, color2, scale, 10.0 * scale)
+    }
+}
+
+/// Kind of ray, deciding which objects it sees according to their visibility.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RayType {
+    /// Primary ray from the camera.
+    Camera,
+    /// Ray scattered off a surface, whether reflected, refracted, or bounced off a diffuse surface.
+    Reflection,
+    /// Ray testing whether a point is lit.
+    Shadow,
+}
+
+/// Kinds of rays that see an object, for compositing tricks such as objects that only cast shadows.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Visibility {
+    pub visible_to_camera: bool,
+    pub casts_shadows: bool,
+    /// Whether the object is seen by rays scattered off other surfaces, including refractions and diffuse bounces.
+    pub visible_in_reflections: bool,
+}
+
+impl Default for Visibility {
+    fn default() -> Visibility {
+        Visibility { visible_to_camera: true, casts_shadows: true, visible_in_reflections: true }
+    }
 }
 
-pub struct Hit {
+impl Visibility {
+    /// Check whether the object is seen by given kind of rays.
+    #[inline(always)]
+    pub fn includes(&self, ray_type: RayType) -> bool {
+        match ray_type {
+            RayType::Camera => self.visible_to_camera,
+            RayType::Reflection => self.visible_in_reflections,
+            RayType::Shadow => self.casts_shadows,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Background {
+    Gradient(Vec3 /* bottom color */, Vec3 /* top color */),
+    Cubemap([Image; 6] /* +X, -X, +Y, -Y, +Z, -Z faces */),
+}
+
+/// Evaluate the checkered texture pattern at given UV coordinates.
+///
+/// When the footprint of the sample covers a significant part of a single check,
+/// the result fades towards the average of both colors to avoid aliasing.
+///
+/// # Arguments
+///
+/// * `color1` - First color.
+/// * `color2` - Second color.
+/// * `scale` - Frequency of the pattern along U and V.
+/// * `uv` - UV coordinates of the sample.
+/// * `footprint` - Size of the sample's footprint in the U and V directions, or zeros to disable filtering.
+pub fn get_checkered_color(color1: &Vec3, color2: &Vec3, scale: (f32, f32), uv: (f32, f32), footprint: (f32, f32)) -> Vec3 {
+    let (u, v) = uv;
+    let (scale_u, scale_v) = scale;
+    let color = if (scale_u * u).sin() * (scale_v * v).sin() > 0.0 {
+        *color1
+    } else {
+        *color2
+    };
+    // Size of the footprint relative to a single check (which spans PI radians in both directions).
+    let (du, dv) = footprint;
+    let checks = (scale_u * du).max(scale_v * dv) / PI;
+    if checks > 0.5 {
+        let fade = ((checks - 0.5) / 0.5).min(1.0);
+        Vec3::new(
+            lerp(color.x, 0.5 * (color1.x + color2.x), fade),
+            lerp(color.y, 0.5 * (color1.y + color2.y), fade),
+            lerp(color.z, 0.5 * (color1.z + color2.z), fade),
+        )
+    } else {
+        color
+    }
+}
+
+pub struct Hit<'a> {
     pub p: Vec3,
+    /// Shading normal, possibly interpolated or perturbed.
     pub n: Vec3,
+    /// Geometric normal of the actual surface.
+    pub ng: Vec3,
     pub t: f32,
     pub uv: (f32, f32),
-    pub m: Material,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    /// Material of the surface, borrowed from the hit object.
+    pub m: &'a Material,
+    /// Index of the hit object within the scene, assigned by `Scene::hit`.
+    pub object_id: usize,
+    /// Whether the ray hit the surface from the outside, the side its outward normal points to.
+    /// The normals of hits returned by objects always face the ray, so this is the only way to tell
+    /// whether the ray enters or leaves the object. Spheres with negative radius are inside out.
+    pub front_face: bool,
+    /// Distance of the hit from the nearest edge of the face of a mesh (see `Material::Wireframe`),
+    /// infinite for other objects.
+    pub edge_distance: f32,
 }
 
-impl Hit {
-    pub fn new(p: Vec3, n: Vec3, t: f32, uv: (f32, f32), m: Material ) -> Hit {
-        Hit { p, n, t, uv, m }
+impl<'a> Hit<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(p: Vec3, n: Vec3, ng: Vec3, t: f32, uv: (f32, f32), dpdu: Vec3, dpdv: Vec3, m: &'a Material) -> Hit<'a> {
+        Hit { p, n, ng, t, uv, dpdu, dpdv, m, object_id: 0, front_face: true, edge_distance: f32::INFINITY }
+    }
+
+    /// Flip the normals to face the incoming ray, if the geometric normal does not, and set `front_face`
+    /// to whether it did. Expects outward normals.
+    ///
+    /// # Arguments
+    ///
+    /// * `d` - Direction of the incoming ray.
+    pub fn face_ray(&mut self, d: &Vec3) {
+        self.front_face = dot(d, &self.ng) < 0.0;
+        if !self.front_face {
+            self.n = -&self.n;
+            self.ng = -&self.ng;
+        }
+    }
+
+    /// Make sure the shading normal faces the incoming ray the same way as the geometric normal does.
+    ///
+    /// When the shading normal faces away from the ray while the geometric normal faces towards it
+    /// (or vice versa), it is bent just enough to get into the same hemisphere as the geometric normal.
+    ///
+    /// # Arguments
+    ///
+    /// * `d` - Direction of the incoming ray.
+    pub fn clamp_shading_normal(&mut self, d: &Vec3) {
+        let mut wo = -d;
+        wo.normalize();
+        let cos_g = dot(&wo, &self.ng);
+        let cos_s = dot(&wo, &self.n);
+        if cos_g * cos_s < 0.0 {
+            let target = 1e-3_f32.copysign(cos_g);
+            self.n += &((target - cos_s) * &wo);
+            self.n.normalize();
+        }
+    }
+
+    /// Check whether a direction scattered at the hit leaves the surface on the side the ray came from,
+    /// according to the geometric normal.
+    ///
+    /// Directions computed from a shading normal (for example, reflections off smoothed surfaces near
+    /// their silhouette) can point into the actual surface, and following them would leak light through it.
+    ///
+    /// # Arguments
+    ///
+    /// * `d_in` - Direction of the incoming ray.
+    /// * `d_out` - Scattered direction.
+    #[inline(always)]
+    pub fn leaves_surface(&self, d_in: &Vec3, d_out: &Vec3) -> bool {
+        dot(d_in, &self.ng) * dot(d_out, &self.ng) < 0.0
     }
 }
 
 pub trait Hitable {
-    fn hit(&self, ray: &Ray) -> Option<Hit>;
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>>;
+
+    /// Check whether anything blocks the ray before given ray parameter.
+    ///
+    /// Only needs to agree with `hit` on whether there is an intersection,
+    /// so implementations can skip computing the details of the hit.
+    ///
+    /// # Arguments
+    ///
+    /// * `ray` - Ray to test.
+    /// * `max_t` - Ray parameter beyond which intersections are ignored (for example, the distance to a light).
+    fn occluded(&self, ray: &Ray, max_t: f32) -> bool {
+        self.hit(ray).is_some_and(|hit| hit.t < max_t)
+    }
 }
 
+// Offset of rays spawned at surfaces, relative to the magnitude of the coordinates their starting points
+// are computed from. The precision of floating point coordinates is relative to their magnitude, and so is
+// the error of the computed hit points. This gives the offset of 0.001 for objects a couple of hundred units across.
+const RAY_EPSILON_PER_UNIT: f32 = 5e-6;
+
+/// Get the distance by which rays spawned at a point on a surface (bounces, shadow rays) are offset from it,
+/// so that they do not hit the surface they start from due to rounding errors.
+///
+/// Proportional to the largest absolute coordinate of the point and of the bounding box of the object it lies on,
+/// which bound the error of the point, so that a large object far away does not change the offset of rays
+/// spawned at the other objects.
+///
+/// # Arguments
+///
+/// * `p` - Point on a surface where the ray starts.
+/// * `bbox` - Bounding box of the object with the surface, ignored if empty or unbounded.
+pub fn ray_epsilon(p: &Vec3, bbox: &BBox) -> f32 {
+    let mut scale = p.x.abs().max(p.y.abs()).max(p.z.abs());
+    if bbox.is_finite() {
+        scale = [bbox.min.x, bbox.min.y, bbox.min.z, bbox.max.x, bbox.max.y, bbox.max.z].iter().fold(scale, |scale, c| scale.max(c.abs()));
+    }
+    RAY_EPSILON_PER_UNIT * scale
+}
+
+// Suggested max depths of paths in scenes with only diffuse surfaces, with metal, and with glass.
+const DIFFUSE_MAX_DEPTH: u32 = 4;
+const METAL_MAX_DEPTH: u32 = 8;
+const GLASS_MAX_DEPTH: u32 = 16;
+
+#[derive(Clone)]
 pub struct Scene {
+    /// Spheres in the scene, identified by their index.
     pub spheres: Vec<Sphere>,
+    /// Capsules in the scene, identified by their index offset by the number of spheres.
+    pub capsules: Vec<Capsule>,
+    /// Transformed spheres (for example, ellipsoids), identified by their index offset
+    /// by the number of spheres and capsules.
+    pub instances: Vec<Instance<Sphere>>,
+    /// Meshes of triangles and quads, identified by their index offset by the number of spheres,
+    /// capsules, and instances.
+    pub meshes: Vec<Mesh>,
+    pub background: Background,
+    /// Multiplier of the background color, to control how much light the background contributes.
+    pub background_intensity: f32,
+    // Acceleration structure over all the objects, if built.
+    grid: Option<Grid>,
+    // Lights that can be sampled, in increasing order of the ids of their objects, and the distribution of their power.
+    lights: Vec<Light>,
+    light_power: Distribution,
 }
 
 impl Scene {
     pub fn new(spheres: Vec<Sphere>) -> Scene {
-        Scene { spheres }
+        let background = Background::Gradient(Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.5, 0.7, 0.9));
+        let mut scene = Scene { spheres, capsules: Vec::new(), instances: Vec::new(), meshes: Vec::new(), background, background_intensity: 1.0, grid: None, lights: Vec::new(), light_power: Distribution::default() };
+        scene.update_lights();
+        scene
+    }
+
+    /// Find the lights that can be sampled directly (see `pick_light`): spheres, and the faces of meshes
+    /// (as triangles), with a light material, emitting any light, and visible to reflected rays.
+    ///
+    /// Called by `Scene::new` and `isolate`; call it again after adding or changing objects otherwise.
+    pub fn update_lights(&mut self) {
+        let power = |l: &Light| { let e = l.emission(); l.area() * (e.x + e.y + e.z) };
+        let spheres = self.spheres.iter().enumerate()
+            .filter(|(_, s)| s.visibility().includes(RayType::Reflection))
+            .map(|(id, s)| Light::Sphere(id, s.clone()));
+        let first_mesh = self.spheres.len() + self.capsules.len() + self.instances.len();
+        let faces = self.meshes.iter().enumerate()
+            .filter(|(_, mesh)| mesh.visibility().includes(RayType::Reflection))
+            .flat_map(|(i, mesh)| {
+                let triangles = mesh.triangles().iter().map(|t| ([t.a, t.b, t.c], &t.m));
+                // Quads are convex, so split along either diagonal.
+                let quads = mesh.quads().iter().flat_map(|q| {
+                    let [a, b, c, d] = *q.vertices();
+                    [([a, b, c], q.material()), ([a, c, d], q.material())]
+                });
+                triangles.chain(quads).filter_map(move |(vertices, m)| match *m {
+                    Material::Light(color) => Some(Light::Triangle(first_mesh + i, vertices, color)),
+                    _ => None,
+                })
+            });
+        self.lights = spheres.chain(faces).filter(|l| power(l) > 0.0).collect();
+        let powers: Vec<f32> = self.lights.iter().map(power).collect();
+        self.light_power = Distribution::new(&powers);
+    }
+
+    /// Choose one of the lights of the scene to sample directly, as of the last call to `update_lights`.
+    ///
+    /// # Arguments
+    ///
+    /// * `u` - Random number, in the range of 0.0 to 1.0 (exclusive).
+    /// * `selection` - How likely each light is to be chosen.
+    ///
+    /// # Returns
+    ///
+    /// The chosen light and the probability of choosing it, or `None` if there are no lights.
+    pub fn pick_light(&self, u: f32, selection: LightSelection) -> Option<(&Light, f32)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let (i, probability) = match selection {
+            LightSelection::Uniform => (((u * self.lights.len() as f32) as usize).min(self.lights.len() - 1), 1.0 / self.lights.len() as f32),
+            LightSelection::Power => self.light_power.sample(u)?,
+        };
+        Some((&self.lights[i], probability))
+    }
+
+    /// Check whether the object with given id (see `Hit::object_id`) has any of the lights chosen by `pick_light`.
+    pub fn is_sampled_light(&self, id: usize) -> bool {
+        self.lights.binary_search_by_key(&id, Light::object_id).is_ok()
+    }
+
+    /// Get the color of the background in given direction, including the background intensity.
+    ///
+    /// # Arguments
+    ///
+    /// * `d` - Direction to look at.
+    pub fn background_color(&self, d: &Vec3) -> Vec3 {
+        let mut color = match &self.background {
+            Background::Gradient(bottom, top) => {
+                let t = 0.5 * (normalize(d).y + 1.0);
+                Vec3::new(
+                    (1.0 - t) * bottom.x + t * top.x,
+                    (1.0 - t) * bottom.y + t * top.y,
+                    (1.0 - t) * bottom.z + t * top.z,
+                )
+            },
+            Background::Cubemap(faces) => {
+                let (face, u, v) = get_cubemap_uv(d);
+                faces[face].sample_bilinear(u, v)
+            },
+        };
+        color *= self.background_intensity;
+        color
+    }
+
+    /// Compute a fingerprint of the scene, to tell whether a saved render (see `Checkpoint`) is of the same scene.
+    ///
+    /// Covers the shapes, transforms, materials, and visibility of all the objects, and the background,
+    /// so that changing any of them changes the fingerprint.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for sphere in &self.spheres {
+            hash_floats(&mut hasher, &[sphere.c.x, sphere.c.y, sphere.c.z, sphere.r]);
+            (format!("{:?}", sphere.m), format!("{:?}", sphere.visibility)).hash(&mut hasher);
+        }
+        for capsule in &self.capsules {
+            hash_floats(&mut hasher, &[capsule.a.x, capsule.a.y, capsule.a.z, capsule.b.x, capsule.b.y, capsule.b.z, capsule.r]);
+            (format!("{:?}", capsule.m), format!("{:?}", capsule.visibility)).hash(&mut hasher);
+        }
+        for instance in &self.instances {
+            let sphere = instance.object();
+            hash_floats(&mut hasher, &[sphere.c.x, sphere.c.y, sphere.c.z, sphere.r]);
+            (format!("{:?}", sphere.m), format!("{:?}", instance.visibility())).hash(&mut hasher);
+            format!("{:?}", instance.transform()).hash(&mut hasher);
+        }
+        for mesh in &self.meshes {
+            for triangle in mesh.triangles() {
+                hash_floats(&mut hasher, &[triangle.a.x, triangle.a.y, triangle.a.z, triangle.b.x, triangle.b.y, triangle.b.z, triangle.c.x, triangle.c.y, triangle.c.z]);
+            }
+            for v in mesh.quads().iter().flat_map(|quad| quad.vertices()) {
+                hash_floats(&mut hasher, &[v.x, v.y, v.z]);
+            }
+            (format!("{:?}", mesh.materials().collect::<Vec<_>>()), format!("{:?}", mesh.visibility())).hash(&mut hasher);
+        }
+        match &self.background {
+            Background::Gradient(bottom, top) => hash_floats(&mut hasher, &[bottom.x, bottom.y, bottom.z, top.x, top.y, top.z]),
+            Background::Cubemap(faces) => for face in faces {
+                (face.width, face.height, &face.pixels).hash(&mut hasher);
+            },
+        }
+        hash_floats(&mut hasher, &[self.background_intensity]);
+        hasher.finish()
+    }
+
+    /// Add spheres modeled in another coordinate system, as instances transformed into the Y-up scene.
+    ///
+    /// The spheres keep their orientation relative to the rest of the model, including their texture
+    /// coordinates, so that, for example, a Z-up model stands upright in the scene.
+    ///
+    /// # Arguments
+    ///
+    /// * `spheres` - Spheres in the coordinate system of the model.
+    /// * `up_axis` - Axis pointing up in the model.
+    pub fn import_spheres(&mut self, spheres: Vec<Sphere>, up_axis: UpAxis) {
+        let root = up_axis.to_y_up();
+        self.instances.extend(spheres.into_iter().map(|sphere| Instance::new(sphere, root)));
+    }
+
+    /// Add a mesh modeled in another coordinate system, with its vertices transformed into the Y-up scene
+    /// (see `import_spheres`).
+    ///
+    /// # Arguments
+    ///
+    /// * `mesh` - Mesh in the coordinate system of the model.
+    /// * `up_axis` - Axis pointing up in the model.
+    pub fn import_mesh(&mut self, mesh: Mesh, up_axis: UpAxis) {
+        self.meshes.push(mesh.transformed(&up_axis.to_y_up()));
+    }
+
+    /// Create a copy of the scene where only given objects are seen by any rays, for example, to debug a single object.
+    ///
+    /// The other objects are still there, and keep their ids, but they do not show up, reflect, nor cast shadows.
+    ///
+    /// # Arguments
+    ///
+    /// * `ids` - Ids of the objects to keep, the same as in `Hit::object_id`.
+    pub fn isolate(&self, ids: &[usize]) -> Scene {
+        let hidden = Visibility { visible_to_camera: false, casts_shadows: false, visible_in_reflections: false };
+        let mut scene = self.clone();
+        let (sphere_count, capsule_count, instance_count) = (scene.spheres.len(), scene.capsules.len(), scene.instances.len());
+        for (_, sphere) in scene.spheres.iter_mut().enumerate().filter(|(id, _)| !ids.contains(id)) {
+            sphere.visibility = hidden;
+        }
+        for (_, capsule) in scene.capsules.iter_mut().enumerate().filter(|(i, _)| !ids.contains(&(sphere_count + i))) {
+            capsule.visibility = hidden;
+        }
+        scene.instances = scene.instances.into_iter().enumerate()
+            .map(|(i, instance)| if ids.contains(&(sphere_count + capsule_count + i)) { instance } else { instance.with_visibility(hidden) })
+            .collect();
+        scene.meshes = scene.meshes.into_iter().enumerate()
+            .map(|(i, mesh)| if ids.contains(&(sphere_count + capsule_count + instance_count + i)) { mesh } else { mesh.with_visibility(hidden) })
+            .collect();
+        scene.update_lights();
+        scene
+    }
+
+    /// Tessellate all the objects into a triangle mesh, with one named object per object of the scene
+    /// (for example, `sphere_0`, `capsule_0`, `instance_0`, and `mesh_0`), numbered within each kind of object.
+    ///
+    /// # Arguments
+    ///
+    /// * `segments` - Number of divisions of spheres from pole to pole; twice as many go around them.
+    pub fn to_mesh(&self, segments: u32) -> TriangleMesh {
+        let mut mesh = TriangleMesh::new();
+        for (i, sphere) in self.spheres.iter().enumerate() {
+            mesh.begin_object(&format!("sphere_{}", i));
+            mesh.add_sphere(sphere, segments);
+        }
+        for (i, capsule) in self.capsules.iter().enumerate() {
+            mesh.begin_object(&format!("capsule_{}", i));
+            mesh.add_capsule(capsule, segments);
+        }
+        for (i, instance) in self.instances.iter().enumerate() {
+            let mut object = TriangleMesh::new();
+            object.add_sphere(instance.object(), segments);
+            mesh.begin_object(&format!("instance_{}", i));
+            mesh.add_transformed(&object, instance.transform());
+        }
+        for (i, object) in self.meshes.iter().enumerate() {
+            mesh.begin_object(&format!("mesh_{}", i));
+            mesh.add_mesh(object);
+        }
+        mesh
+    }
+
+    /// Write the geometry of the scene to a Wavefront OBJ file, to check the placement and scale
+    /// of the objects in another application. See `to_mesh` for how the objects are tessellated.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the OBJ file.
+    /// * `segments` - Number of divisions of spheres from pole to pole; twice as many go around them.
+    pub fn export_obj(&self, path: &Path, segments: u32) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.to_mesh(segments).write_obj(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Check the materials of all the objects (see `Material::validate`).
+    ///
+    /// # Returns
+    ///
+    /// Id of the object and description of each problem found, empty if there are none.
+    pub fn validate_materials(&self) -> Vec<(usize, String)> {
+        self.object_materials()
+            .flat_map(|(id, m)| m.validate().into_iter().map(move |problem| (id, problem)))
+            .collect()
+    }
+
+    /// Suggest the max depth of paths (see `RenderSettings::max_depth`) based on the materials in the scene:
+    ///
+    /// * 4 bounces when all surfaces are diffuse, as every bounce off them loses light, and the light
+    ///   of the bounces beyond that is rarely visible,
+    /// * 8 bounces with metal, so that reflections show other reflections and the diffuse surfaces in them,
+    /// * 16 bounces with glass, where every object takes at least two bounces to get through, with more
+    ///   reflected inside.
+    pub fn suggested_max_depth(&self) -> u32 {
+        self.object_materials().map(|(_, m)| m.suggested_max_depth()).max().unwrap_or(DIFFUSE_MAX_DEPTH)
+    }
+
+    // Materials of all the objects, in the order of their ids, with the ids; meshes can have any number of them.
+    fn object_materials(&self) -> impl Iterator<Item = (usize, &Material)> {
+        let first_mesh = self.spheres.len() + self.capsules.len() + self.instances.len();
+        self.spheres.iter().map(|s| &s.m)
+            .chain(self.capsules.iter().map(|c| &c.m))
+            .chain(self.instances.iter().map(|i| &i.object().m))
+            .enumerate()
+            .chain(self.meshes.iter().enumerate().flat_map(move |(i, mesh)| mesh.materials().map(move |m| (first_mesh + i, m))))
+    }
+
+    /// Build a uniform grid over all the objects, so that rays only test the objects along their way.
+    ///
+    /// Without the grid, every ray is tested against every object. The grid has to be built again
+    /// whenever the objects change.
+    ///
+    /// # Arguments
+    ///
+    /// * `num_threads` - Number of threads building the grid, where 0 means one thread per available core.
+    pub fn build_grid(&mut self, num_threads: u32) {
+        self.grid = Some(Grid::new(&self.object_bboxes(), num_threads));
+    }
+
+    /// Remove the grid built by `build_grid`, and go back to testing every object.
+    pub fn clear_grid(&mut self) {
+        self.grid = None;
+    }
+
+    // Bounding boxes of all the objects, in the order of their ids.
+    fn object_bboxes(&self) -> Vec<BBox> {
+        self.spheres.iter().map(|s| s.bbox())
+            .chain(self.capsules.iter().map(|c| c.bbox()))
+            .chain(self.instances.iter().map(|i| i.bbox()))
+            .chain(self.meshes.iter().map(|m| m.bbox()))
+            .collect()
+    }
+
+    /// Get the distance by which rays spawned at a hit are offset from it (see `ray_epsilon`).
+    ///
+    /// # Arguments
+    ///
+    /// * `hit` - Intersection with one of the objects of the scene.
+    pub fn hit_epsilon(&self, hit: &Hit) -> f32 {
+        ray_epsilon(&hit.p, &self.object_bbox(hit.object_id))
+    }
+
+    /// Get the distance by which rays spawned at a point, which may lie on the surface of any of the objects,
+    /// are offset from it: the largest offset (see `ray_epsilon`) for the objects whose bounding boxes contain it.
+    ///
+    /// # Arguments
+    ///
+    /// * `p` - Point where the rays start.
+    pub fn point_epsilon(&self, p: &Vec3) -> f32 {
+        self.object_bboxes().iter()
+            .filter(|bbox| bbox.contains(*p))
+            .fold(ray_epsilon(p, &BBox::new()), |epsilon, bbox| epsilon.max(ray_epsilon(p, bbox)))
+    }
+
+    // Bounding box of a single object, identified the same way as in `Hit::object_id`.
+    fn object_bbox(&self, id: usize) -> BBox {
+        if id < self.spheres.len() {
+            self.spheres[id].bbox()
+        } else if id < self.spheres.len() + self.capsules.len() {
+            self.capsules[id - self.spheres.len()].bbox()
+        } else if id < self.spheres.len() + self.capsules.len() + self.instances.len() {
+            self.instances[id - self.spheres.len() - self.capsules.len()].bbox()
+        } else {
+            self.meshes[id - self.spheres.len() - self.capsules.len() - self.instances.len()].bbox()
+        }
+    }
+
+    /// Find the objects whose bounding boxes are (at least partially) inside a frustum,
+    /// for example, to only test those for the primary rays of a camera.
+    ///
+    /// # Arguments
+    ///
+    /// * `frustum` - Frustum to test the objects against.
+    pub fn objects_in_frustum(&self, frustum: &Frustum) -> Vec<usize> {
+        self.object_bboxes().iter().enumerate()
+            .filter(|(_, bbox)| frustum.intersects_bbox(bbox))
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Get the visibility of an object.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the object, the same as in `Hit::object_id`.
+    pub fn object_visibility(&self, id: usize) -> Visibility {
+        if id < self.spheres.len() {
+            self.spheres[id].visibility
+        } else if id < self.spheres.len() + self.capsules.len() {
+            self.capsules[id - self.spheres.len()].visibility
+        } else if id < self.spheres.len() + self.capsules.len() + self.instances.len() {
+            self.instances[id - self.spheres.len() - self.capsules.len()].visibility()
+        } else {
+            self.meshes[id - self.spheres.len() - self.capsules.len() - self.instances.len()].visibility()
+        }
+    }
+
+    /// Find the closest intersection of a ray with the objects visible to given kind of rays.
+    ///
+    /// Unlike `hit`, which tests all the objects regardless of their visibility.
+    ///
+    /// # Arguments
+    ///
+    /// * `ray` - Ray to intersect.
+    /// * `ray_type` - Kind of the ray.
+    pub fn hit_visible(&self, ray: &Ray, ray_type: RayType) -> Option<Hit<'_>> {
+        self.closest_hit(ray, Some(ray_type), &mut 0)
+    }
+
+    /// Find the closest intersection of a ray with the objects visible to given kind of rays, the same as
+    /// `hit_visible`, and count the intersection tests it took: one per object tested, and one per grid cell visited.
+    ///
+    /// # Arguments
+    ///
+    /// * `ray` - Ray to intersect.
+    /// * `ray_type` - Kind of the ray.
+    pub fn hit_counting_tests(&self, ray: &Ray, ray_type: RayType) -> (Option<Hit<'_>>, u32) {
+        let mut tests = 0;
+        let hit = self.closest_hit(ray, Some(ray_type), &mut tests);
+        (hit, tests)
+    }
+
+    /// Find the closest intersection of a ray with a subset of the objects,
+    /// skipping the ones not visible to given kind of rays.
+    ///
+    /// # Arguments
+    ///
+    /// * `ray` - Ray to intersect.
+    /// * `ids` - Ids of the objects to test, the same as in `Hit::object_id`.
+    /// * `ray_type` - Kind of the ray.
+    pub fn hit_objects(&self, ray: &Ray, ids: &[usize], ray_type: RayType) -> Option<Hit<'_>> {
+        let mut closest_hit: Option<Hit> = None;
+        for id in ids.iter().filter(|id| self.object_visibility(**id).includes(ray_type)) {
+            if let Some(hit) = self.hit_object(*id, ray) {
+                if closest_hit.as_ref().is_none_or(|closest| hit.t < closest.t) {
+                    closest_hit = Some(hit);
+                }
+            }
+        }
+        closest_hit
+    }
+
+    /// Compute the fraction of light passing along a ray up to given ray parameter, for each color channel.
+    ///
+    /// Unlike `occluded`, which treats every object casting shadows as opaque, this lets light through glass,
+    /// tinted by its color at every surface the ray crosses, the same as paths refracting through it are.
+    /// The ray is not bent, so glass casts colored, but not focused shadows. The light that the glass reflects
+    /// is lost, the same as for the paths reflecting off it instead of refracting (see `Material::shadow_transmittance`).
+    ///
+    /// # Arguments
+    ///
+    /// * `ray` - Ray towards the light.
+    /// * `max_t` - Ray parameter of the light.
+    pub fn transmittance(&self, ray: &Ray, max_t: f32) -> Vec3 {
+        let mut transmittance = Vec3::new(1.0, 1.0, 1.0);
+        let (mut ray, mut max_t) = (*ray, max_t);
+        while let Some(hit) = self.hit_visible(&ray, RayType::Shadow).filter(|hit| hit.t < max_t) {
+            let filter = hit.m.shadow_transmittance(dot(&normalize(&ray.d), &hit.n));
+            transmittance = Vec3::new(transmittance.x * filter.x, transmittance.y * filter.y, transmittance.z * filter.z);
+            if transmittance.x <= 0.0 && transmittance.y <= 0.0 && transmittance.z <= 0.0 {
+                return Vec3::new(0.0, 0.0, 0.0);
+            }
+            // Continue just past the surface.
+            let epsilon = self.hit_epsilon(&hit);
+            ray.o = ray.point_at(hit.t + epsilon);
+            max_t -= hit.t + epsilon;
+        }
+        transmittance
+    }
+
+    /// Compute the light arriving along a shadow ray that samples the background as a light: the background color
+    /// in the direction of the ray if nothing blocks it, no matter how far, or else the emission of the first light
+    /// in the way. Glass tints the light the same way as in `transmittance`, and other objects casting shadows block it.
+    ///
+    /// Lights do not block the background like other objects do, but take its place, so paths sampling
+    /// the background this way must not count the lights casting shadows again when their next bounce hits them.
+    ///
+    /// # Arguments
+    ///
+    /// * `ray` - Ray towards the background.
+    /// * `skip_sampled_lights` - Whether the lights chosen by `pick_light` block the background instead,
+    ///   when their light is sampled separately.
+    pub fn background_light(&self, ray: &Ray, skip_sampled_lights: bool) -> Vec3 {
+        let mut transmittance = Vec3::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        let light = loop {
+            let hit = match self.hit_visible(&ray, RayType::Shadow) {
+                Some(hit) => hit,
+                None => break self.background_color(&ray.d),
+            };
+            if let Material::Light(emission) = *hit.m {
+                if skip_sampled_lights && self.is_sampled_light(hit.object_id) {
+                    return Vec3::new(0.0, 0.0, 0.0);
+                }
+                break emission;
+            }
+            let filter = hit.m.shadow_transmittance(dot(&normalize(&ray.d), &hit.n));
+            transmittance = Vec3::new(transmittance.x * filter.x, transmittance.y * filter.y, transmittance.z * filter.z);
+            if transmittance.x <= 0.0 && transmittance.y <= 0.0 && transmittance.z <= 0.0 {
+                return Vec3::new(0.0, 0.0, 0.0);
+            }
+            // Continue just past the surface.
+            ray.o = ray.point_at(hit.t + self.hit_epsilon(&hit));
+        };
+        Vec3::new(transmittance.x * light.x, transmittance.y * light.y, transmittance.z * light.z)
+    }
+
+    // Intersect a single object, identified the same way as in `Hit::object_id`.
+    fn hit_object(&self, id: usize, ray: &Ray) -> Option<Hit<'_>> {
+        let hit = if id < self.spheres.len() {
+            self.spheres[id].hit(ray)
+        } else if id < self.spheres.len() + self.capsules.len() {
+            self.capsules[id - self.spheres.len()].hit(ray)
+        } else if id < self.spheres.len() + self.capsules.len() + self.instances.len() {
+            self.instances[id - self.spheres.len() - self.capsules.len()].hit(ray)
+        } else {
+            self.meshes[id - self.spheres.len() - self.capsules.len() - self.instances.len()].hit(ray)
+        };
+        hit.map(|mut hit| {
+            hit.object_id = id;
+            hit
+        })
+    }
+
+    fn object_occluded(&self, id: usize, ray: &Ray, max_t: f32) -> bool {
+        if id < self.spheres.len() {
+            self.spheres[id].occluded(ray, max_t)
+        } else if id < self.spheres.len() + self.capsules.len() {
+            self.capsules[id - self.spheres.len()].occluded(ray, max_t)
+        } else if id < self.spheres.len() + self.capsules.len() + self.instances.len() {
+            self.instances[id - self.spheres.len() - self.capsules.len()].occluded(ray, max_t)
+        } else {
+            self.meshes[id - self.spheres.len() - self.capsules.len() - self.instances.len()].occluded(ray, max_t)
+        }
     }
 }
 
-impl Hitable for Scene {
-    fn hit(&self, ray: &Ray) -> Option<Hit> {
-        let mut smallest_t = std::f32::MAX;
+impl Scene {
+    // Find the closest intersection with the objects visible to given kind of rays, or with all of them,
+    // adding the number of intersection tests (objects tested and grid cells visited) to `tests`.
+    fn closest_hit(&self, ray: &Ray, ray_type: Option<RayType>, tests: &mut u32) -> Option<Hit<'_>> {
+        let visible = |v: &Visibility| ray_type.is_none_or(|t| v.includes(t));
+        let mut smallest_t = f32::MAX;
         let mut closest_hit: Option<Hit> = None;
-        for sphere in &self.spheres {
-            if let Some(hit) = sphere.hit(ray) {
+        if let Some(grid) = &self.grid {
+            for id in grid.large_objects().iter().filter(|id| visible(&self.object_visibility(**id))) {
+                *tests += 1;
+                if let Some(hit) = self.hit_object(*id, ray) {
+                    if hit.t < smallest_t {
+                        smallest_t = hit.t;
+                        closest_hit = Some(hit);
+                    }
+                }
+            }
+            grid.traverse(ray, smallest_t, |objects, t_exit| {
+                *tests += 1;
+                for id in objects.iter().filter(|id| visible(&self.object_visibility(**id))) {
+                    *tests += 1;
+                    if let Some(hit) = self.hit_object(*id, ray) {
+                        if hit.t < smallest_t {
+                            smallest_t = hit.t;
+                            closest_hit = Some(hit);
+                        }
+                    }
+                }
+                // Objects in the cells further along the ray cannot be any closer.
+                smallest_t <= t_exit
+            });
+            return closest_hit;
+        }
+        for (id, sphere) in self.spheres.iter().enumerate().filter(|(_, s)| visible(&s.visibility)) {
+            *tests += 1;
+            if let Some(mut hit) = sphere.hit(ray) {
+                if hit.t < smallest_t {
+                    smallest_t = hit.t;
+                    hit.object_id = id;
+                    closest_hit = Some(hit);
+                }
+            }
+        }
+        for (id, capsule) in self.capsules.iter().enumerate().filter(|(_, c)| visible(&c.visibility)) {
+            *tests += 1;
+            if let Some(mut hit) = capsule.hit(ray) {
+                if hit.t < smallest_t {
+                    smallest_t = hit.t;
+                    hit.object_id = self.spheres.len() + id;
+                    closest_hit = Some(hit);
+                }
+            }
+        }
+        for (id, instance) in self.instances.iter().enumerate().filter(|(_, i)| visible(&i.visibility())) {
+            *tests += 1;
+            if let Some(mut hit) = instance.hit(ray) {
+                if hit.t < smallest_t {
+                    smallest_t = hit.t;
+                    hit.object_id = self.spheres.len() + self.capsules.len() + id;
+                    closest_hit = Some(hit);
+                }
+            }
+        }
+        for (id, mesh) in self.meshes.iter().enumerate().filter(|(_, m)| visible(&m.visibility())) {
+            *tests += 1;
+            if let Some(mut hit) = mesh.hit(ray) {
                 if hit.t < smallest_t {
                     smallest_t = hit.t;
+                    hit.object_id = self.spheres.len() + self.capsules.len() + self.instances.len() + id;
                     closest_hit = Some(hit);
                 }
             }
@@ -62,15 +928,216 @@ impl Hitable for Scene {
     }
 }
 
+impl Hitable for Scene {
+    /// Find the closest intersection with any of the objects, regardless of their visibility;
+    /// see `hit_visible` for rendering.
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+        self.closest_hit(ray, None, &mut 0)
+    }
+
+    /// Check whether any object casting shadows blocks the ray.
+    fn occluded(&self, ray: &Ray, max_t: f32) -> bool {
+        let blocks = |id: &usize| self.object_visibility(*id).casts_shadows && self.object_occluded(*id, ray, max_t);
+        if let Some(grid) = &self.grid {
+            if grid.large_objects().iter().any(blocks) {
+                return true;
+            }
+            let mut occluded = false;
+            grid.traverse(ray, max_t, |objects, _| {
+                occluded = objects.iter().any(blocks);
+                occluded
+            });
+            return occluded;
+        }
+        self.spheres.iter().any(|sphere| sphere.visibility.casts_shadows && sphere.occluded(ray, max_t))
+            || self.capsules.iter().any(|capsule| capsule.visibility.casts_shadows && capsule.occluded(ray, max_t))
+            || self.instances.iter().any(|instance| instance.visibility().casts_shadows && instance.occluded(ray, max_t))
+            || self.meshes.iter().any(|mesh| mesh.visibility().casts_shadows && mesh.occluded(ray, max_t))
+    }
+}
+
+#[derive(Clone)]
 pub struct Sphere {
     c: Vec3,
     r: f32,
     m: Material,
+    visibility: Visibility,
 }
 
 impl Sphere {
+    /// Create new sphere.
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - Center of the sphere.
+    /// * `r` - Radius of the sphere. A negative radius results in the same sphere with normals
+    ///   pointing inwards, for example, to model the inside of a hollow glass sphere.
+    /// * `m` - Material of the sphere.
     pub fn new(c: Vec3, r: f32, m: Material) -> Sphere {
-        Sphere { c, r, m }
+        Sphere { c, r, m, visibility: Visibility::default() }
+    }
+
+    /// Set which kinds of rays see the sphere, and return the sphere.
+    ///
+    /// # Arguments
+    ///
+    /// * `visibility` - Visibility of the sphere.
+    pub fn with_visibility(mut self, visibility: Visibility) -> Sphere {
+        self.visibility = visibility;
+        self
+    }
+
+    /// Get which kinds of rays see the sphere.
+    pub fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    /// Get the center of the sphere.
+    pub fn center(&self) -> Vec3 {
+        self.c
+    }
+
+    /// Get the radius of the sphere, negative if its normals point inwards.
+    pub fn radius(&self) -> f32 {
+        self.r
+    }
+
+    /// Get the material of the sphere.
+    pub fn material(&self) -> &Material {
+        &self.m
+    }
+
+    /// Get the color of the light emitted by the sphere, if its material is a light.
+    pub fn emission(&self) -> Option<Vec3> {
+        match self.m {
+            Material::Light(color) => Some(color),
+            _ => None,
+        }
+    }
+
+    /// Compute the surface area.
+    pub fn area(&self) -> f32 {
+        4.0 * PI * self.r * self.r
+    }
+
+    /// Get the point on the surface of the sphere in given direction from its center, and the normal there.
+    ///
+    /// # Arguments
+    ///
+    /// * `d` - Normalized direction from the center.
+    pub fn surface_point(&self, d: &Vec3) -> (Vec3, Vec3) {
+        let n = if self.r < 0.0 { -d } else { *d };
+        (&self.c + &(self.r.abs() * d), n)
+    }
+
+    /// Compute the solid angle of the cone that the sphere subtends when viewed from given point.
+    ///
+    /// Returns 0.0 if the point is inside the sphere.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - Point to view the sphere from.
+    pub fn solid_angle(&self, from: &Vec3) -> f32 {
+        let dist_squared = length_squared(&(&self.c - from));
+        if dist_squared <= self.r * self.r {
+            return 0.0;
+        }
+        let sin_theta_squared = self.r * self.r / dist_squared;
+        let cos_theta = (1.0 - sin_theta_squared).sqrt();
+        // Same as 2π(1 - cos θ), without the cancellation for small angles.
+        2.0 * PI * sin_theta_squared / (1.0 + cos_theta)
+    }
+
+    /// Sample a direction from given point towards the sphere, uniformly distributed over the solid angle
+    /// that the sphere subtends (see `solid_angle`), so that the probability density of each direction
+    /// is one over the solid angle.
+    ///
+    /// Returns `None` if the point is inside the sphere.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - Point to view the sphere from.
+    /// * `u` - Random number, in the range of 0.0 to 1.0, choosing the angle from the center of the sphere.
+    /// * `v` - Random number, in the range of 0.0 to 1.0, choosing the angle around the center.
+    pub fn sample_direction(&self, from: &Vec3, u: f32, v: f32) -> Option<Vec3> {
+        let to_center = &self.c - from;
+        let dist_squared = length_squared(&to_center);
+        if dist_squared <= self.r * self.r {
+            return None;
+        }
+        let sin_theta_squared = self.r * self.r / dist_squared;
+        let cos_theta = (1.0 - sin_theta_squared).sqrt();
+        // 1 - cos of the angle from the center, uniformly distributed up to 1 - cos θ, without the cancellation for small angles.
+        let one_minus_cos = u * sin_theta_squared / (1.0 + cos_theta);
+        let sin = (one_minus_cos * (2.0 - one_minus_cos)).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+        let frame = Onb::new_from_w(&normalize(&to_center));
+        Some(frame.local_to_world(&Vec3::new(sin * phi.cos(), sin * phi.sin(), 1.0 - one_minus_cos)))
+    }
+
+    /// Check whether the sphere intersects (or touches) another sphere.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The other sphere.
+    pub fn intersects(&self, other: &Sphere) -> bool {
+        let r = self.r.abs() + other.r.abs();
+        length_squared(&(&self.c - &other.c)) <= r * r
+    }
+
+    /// Check whether the sphere intersects (or touches) a bounding box.
+    ///
+    /// # Arguments
+    ///
+    /// * `bbox` - The bounding box.
+    pub fn intersects_bbox(&self, bbox: &BBox) -> bool {
+        // Distance to the closest point of the box.
+        let closest = Vec3::new(
+            self.c.x.clamp(bbox.min.x, bbox.max.x),
+            self.c.y.clamp(bbox.min.y, bbox.max.y),
+            self.c.z.clamp(bbox.min.z, bbox.max.z),
+        );
+        length_squared(&(&self.c - &closest)) <= self.r * self.r
+    }
+
+    /// Find the ray parameters where a ray enters and exits the sphere, for example,
+    /// to compute the distance the ray travels inside it.
+    ///
+    /// The entry is negative when the ray starts inside the sphere. Returns `None` when the ray
+    /// misses the sphere (or only touches it), or when the whole sphere is behind the ray.
+    ///
+    /// # Arguments
+    ///
+    /// * `ray` - Ray to intersect.
+    pub fn hit_interval(&self, ray: &Ray) -> Option<(f32, f32)> {
+        let oc = &ray.o - &self.c;
+        let a = length_squared(&ray.d);
+        let b = 2.0 * dot(&oc, &ray.d);
+        let c = length_squared(&oc) - self.r * self.r;
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant <= 0.0 {
+            return None;
+        }
+        let dsqrt = discriminant.sqrt();
+        let t1 = (-b - dsqrt) / (2.0 * a);
+        let t2 = (-b + dsqrt) / (2.0 * a);
+        if t2 > 0.0 { Some((t1, t2)) } else { None }
+    }
+
+    /// Compute the bounding box of the sphere.
+    pub fn bbox(&self) -> BBox {
+        let r = self.r.abs();
+        BBox::new_from_points(
+            &Vec3::new(self.c.x - r, self.c.y - r, self.c.z - r),
+            &Vec3::new(self.c.x + r, self.c.y + r, self.c.z + r),
+        )
+    }
+}
+
+// Add the exact bits of floating point values to a hash, as floats do not implement `Hash`.
+fn hash_floats(hasher: &mut impl Hasher, values: &[f32]) {
+    for v in values {
+        v.to_bits().hash(hasher);
     }
 }
 
@@ -83,32 +1150,1130 @@ fn get_sphere_uv(p: &Vec3) -> (f32, f32) {
     )
 }
 
+/// Find the cubemap face for given direction, and the UV coordinates within that face.
+///
+/// Faces are ordered +X, -X, +Y, -Y, +Z, -Z, and their UVs follow the OpenGL cubemap convention
+/// with (0, 0) in the top-left corner of each face image.
+pub fn get_cubemap_uv(d: &Vec3) -> (usize, f32, f32) {
+    let (ax, ay, az) = (d.x.abs(), d.y.abs(), d.z.abs());
+    let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+        if d.x > 0.0 { (0, -d.z, -d.y, ax) } else { (1, d.z, -d.y, ax) }
+    } else if ay >= az {
+        if d.y > 0.0 { (2, d.x, d.z, ay) } else { (3, d.x, -d.z, ay) }
+    } else if d.z > 0.0 {
+        (4, d.x, -d.y, az)
+    } else {
+        (5, -d.x, -d.y, az)
+    };
+    (
+        face,
+        0.5 * (sc / ma + 1.0),
+        0.5 * (tc / ma + 1.0),
+    )
+}
+
+// Partial derivatives of a point on the sphere with respect to the UV coordinates computed by `get_sphere_uv`.
+fn get_sphere_derivatives(n: &Vec3, r: f32) -> (Vec3, Vec3) {
+    let sin_theta = (n.x * n.x + n.z * n.z).sqrt();
+    let dpdu = (2.0 * PI * r) * &Vec3::new(n.z, 0.0, -n.x);
+    let dndv = if sin_theta > 1e-6 {
+        let cos_theta = -n.y;
+        Vec3::new(cos_theta * n.x / sin_theta, sin_theta, cos_theta * n.z / sin_theta)
+    } else {
+        Vec3::new(-n.y, 0.0, 0.0)
+    };
+    (dpdu, (PI * r) * &dndv)
+}
+
+impl Sphere {
+    // Create a hit at given ray parameter. UVs and derivatives do not depend
+    // on the sign of the radius, only the normal does.
+    fn hit_at(&self, ray: &Ray, t: f32) -> Hit<'_> {
+        let p = ray.point_at(t);
+        let mut outward = &p - &self.c;
+        outward.normalize();
+        let n = if self.r < 0.0 { -&outward } else { outward };
+        let (dpdu, dpdv) = get_sphere_derivatives(&outward, self.r.abs());
+        let mut hit = Hit::new(p, n, n, t, get_sphere_uv(&outward), dpdu, dpdv, &self.m);
+        hit.face_ray(&ray.d);
+        hit
+    }
+}
+
 impl Hitable for Sphere {
-    fn hit(&self, ray: &Ray) -> Option<Hit> {
-        let oc = &ray.o - &self.c;
-        let a = length_squared(&ray.d);
-        let b = 2.0 * dot(&oc, &ray.d);
-        let c = length_squared(&oc) - self.r * self.r;
-        let discriminant = b * b - 4.0 * a * c;
-        if discriminant > 0.0 {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let (t1, t2) = self.hit_interval(ray)?;
+        Some(self.hit_at(ray, if t1 > 0.0 { t1 } else { t2 }))
+    }
+
+    fn occluded(&self, ray: &Ray, max_t: f32) -> bool {
+        // Same as `hit`, without computing the hit point, normal, and UVs.
+        self.hit_interval(ray).is_some_and(|(t1, t2)| (if t1 > 0.0 { t1 } else { t2 }) < max_t)
+    }
+}
+
+/// Light that can be sampled directly (see `Scene::pick_light`): a sphere with a light material, or a triangle
+/// of a face of a mesh with a light material, emitting from both of its sides.
+#[derive(Clone)]
+pub enum Light {
+    /// Sphere, with its id.
+    Sphere(usize, Sphere),
+    /// Triangle, with the id of its mesh, its vertices, and the color of its light.
+    Triangle(usize, [Vec3; 3], Vec3),
+}
+
+/// Direction from a point towards a light, sampled by `Light::sample`.
+#[derive(Debug, Copy, Clone)]
+pub struct LightSample {
+    /// Normalized direction.
+    pub d: Vec3,
+    /// Distance to the light along the direction.
+    pub t: f32,
+    /// Inverse of the probability density of the direction, over solid angle.
+    pub inverse_density: f32,
+}
+
+impl Light {
+    /// Get the id of the object (see `Hit::object_id`) the light belongs to.
+    pub fn object_id(&self) -> usize {
+        match *self {
+            Light::Sphere(id, _) | Light::Triangle(id, ..) => id,
+        }
+    }
+
+    /// Get the color of the emitted light.
+    pub fn emission(&self) -> Vec3 {
+        match self {
+            Light::Sphere(_, sphere) => sphere.emission().unwrap_or(Vec3::new(0.0, 0.0, 0.0)),
+            Light::Triangle(_, _, emission) => *emission,
+        }
+    }
+
+    /// Compute the emitting area, counting both sides of triangles.
+    pub fn area(&self) -> f32 {
+        match self {
+            Light::Sphere(_, sphere) => sphere.area(),
+            Light::Triangle(_, [a, b, c], _) => length(&cross(&(b - a), &(c - a))),
+        }
+    }
+
+    /// Compute the bounding box of the light.
+    pub fn bbox(&self) -> BBox {
+        match self {
+            Light::Sphere(_, sphere) => sphere.bbox(),
+            Light::Triangle(_, [a, b, c], _) => {
+                let mut bbox = BBox::new_from_points(a, b);
+                bbox += c;
+                bbox
+            },
+        }
+    }
+
+    /// Get a point on the light, uniformly distributed over its emitting area (see `area`), and the normal there.
+    ///
+    /// # Arguments
+    ///
+    /// * `u`, `v` - Random numbers, in the range of 0.0 to 1.0 (exclusive). For triangles, the first one
+    ///   also chooses the side.
+    pub fn surface_point(&self, u: f32, v: f32) -> (Vec3, Vec3) {
+        match self {
+            Light::Sphere(_, sphere) => {
+                let z = 1.0 - 2.0 * u;
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let phi = 2.0 * PI * v;
+                sphere.surface_point(&Vec3::new(r * phi.cos(), r * phi.sin(), z))
+            },
+            Light::Triangle(_, vertices, _) => {
+                let (side, u) = if u < 0.5 { (1.0, 2.0 * u) } else { (-1.0, 2.0 * u - 1.0) };
+                (triangle_point(vertices, u, v), side * &triangle_normal(vertices))
+            },
+        }
+    }
+
+    /// Sample a direction from given point towards the light. Spheres are sampled uniformly over the solid angle
+    /// they subtend, and triangles uniformly over their area.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - Point outside of the light.
+    /// * `u`, `v` - Random numbers, in the range of 0.0 to 1.0 (exclusive).
+    ///
+    /// # Returns
+    ///
+    /// The sampled direction, or `None` if the point is inside the sphere, or in the plane of the triangle.
+    pub fn sample(&self, from: &Vec3, u: f32, v: f32) -> Option<LightSample> {
+        match self {
+            Light::Sphere(_, sphere) => {
+                let d = sphere.sample_direction(from, u, v)?;
+                let t = sphere.hit(&Ray::new(*from, d))?.t;
+                Some(LightSample { d, t, inverse_density: sphere.solid_angle(from) })
+            },
+            Light::Triangle(_, vertices, _) => {
+                let to_light = &triangle_point(vertices, u, v) - from;
+                let t = length(&to_light);
+                let d = (1.0 / t) * &to_light;
+                let cos = dot(&triangle_normal(vertices), &d).abs();
+                if cos == 0.0 {
+                    return None;
+                }
+                // Density over area converted to solid angle; only the side facing the point counts.
+                let area = 0.5 * length(&cross(&(&vertices[1] - &vertices[0]), &(&vertices[2] - &vertices[0])));
+                Some(LightSample { d, t, inverse_density: area * cos / (t * t) })
+            },
+        }
+    }
+}
+
+// Unit normal of a triangle, following the order of the vertices.
+fn triangle_normal([a, b, c]: &[Vec3; 3]) -> Vec3 {
+    normalize(&cross(&(b - a), &(c - a)))
+}
+
+// Point on a triangle, uniformly distributed over its area for uniformly distributed random numbers.
+fn triangle_point([a, b, c]: &[Vec3; 3], u: f32, v: f32) -> Vec3 {
+    let su = u.sqrt();
+    &(&((1.0 - su) * a) + &((su * (1.0 - v)) * b)) + &((su * v) * c)
+}
+
+/// Cylinder with hemispherical caps, or in other words, all points within given distance from a line segment.
+#[derive(Clone)]
+pub struct Capsule {
+    a: Vec3,
+    b: Vec3,
+    r: f32,
+    m: Material,
+    visibility: Visibility,
+}
+
+impl Capsule {
+    /// Create new capsule.
+    ///
+    /// # Arguments
+    ///
+    /// * `a` - Center of the first cap.
+    /// * `b` - Center of the second cap.
+    /// * `r` - Radius of the cylinder and the caps.
+    /// * `m` - Material of the capsule.
+    pub fn new(a: Vec3, b: Vec3, r: f32, m: Material) -> Capsule {
+        Capsule { a, b, r, m, visibility: Visibility::default() }
+    }
+
+    /// Set which kinds of rays see the capsule, and return the capsule.
+    ///
+    /// # Arguments
+    ///
+    /// * `visibility` - Visibility of the capsule.
+    pub fn with_visibility(mut self, visibility: Visibility) -> Capsule {
+        self.visibility = visibility;
+        self
+    }
+
+    /// Get which kinds of rays see the capsule.
+    pub fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    /// Get the centers of the two caps.
+    pub fn endpoints(&self) -> (Vec3, Vec3) {
+        (self.a, self.b)
+    }
+
+    /// Get the radius of the cylinder and the caps.
+    pub fn radius(&self) -> f32 {
+        self.r
+    }
+
+    /// Compute the bounding box of the capsule.
+    pub fn bbox(&self) -> BBox {
+        let mut bbox = BBox::new_from_points(&self.a, &self.b);
+        bbox.expand(self.r);
+        bbox
+    }
+
+    // Create a hit on the cylindrical body, with UVs wrapping around the axis and going from `a` to `b`.
+    fn body_hit(&self, ray: &Ray, t: f32, ba: &Vec3, y: f32) -> Hit<'_> {
+        let p = ray.point_at(t);
+        let axis_point = &self.a + &(y * ba);
+        let mut n = &p - &axis_point;
+        n.normalize();
+        let mut axis = *ba;
+        axis.normalize();
+        let frame = Onb::new_from_w(&axis);
+        let phi = dot(&n, &frame.v).atan2(dot(&n, &frame.u)) + PI;
+        let dpdu = (2.0 * PI * self.r) * &cross(&axis, &n);
+        let mut hit = Hit::new(p, n, n, t, (phi / (2.0 * PI), y), dpdu, *ba, &self.m);
+        hit.face_ray(&ray.d);
+        hit
+    }
+
+    // Create a hit on one of the caps, with UVs and derivatives of a sphere.
+    fn cap_hit(&self, ray: &Ray, t: f32, c: &Vec3) -> Hit<'_> {
+        let p = ray.point_at(t);
+        let mut n = &p - c;
+        n.normalize();
+        let (dpdu, dpdv) = get_sphere_derivatives(&n, self.r);
+        let mut hit = Hit::new(p, n, n, t, get_sphere_uv(&n), dpdu, dpdv, &self.m);
+        hit.face_ray(&ray.d);
+        hit
+    }
+}
+
+impl Hitable for Capsule {
+    fn hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let ba = &self.b - &self.a;
+        let oa = &ray.o - &self.a;
+        let baba = dot(&ba, &ba);
+        let bard = dot(&ba, &ray.d);
+        let baoa = dot(&ba, &oa);
+        let mut closest: Option<Hit> = None;
+        let mut smallest_t = f32::MAX;
+
+        // Infinite cylinder around the axis, limited to the part between the caps.
+        let a = baba * length_squared(&ray.d) - bard * bard;
+        let b = baba * dot(&oa, &ray.d) - baoa * bard;
+        let c = baba * length_squared(&oa) - baoa * baoa - self.r * self.r * baba;
+        let discriminant = b * b - a * c;
+        if a > 0.0 && discriminant > 0.0 {
             let dsqrt = discriminant.sqrt();
-            let t1 = (-b - dsqrt) / (2.0 * a);
-            let t2 = (-b + dsqrt) / (2.0 * a);
-            if t1 > 0.0 {
-                let p = ray.point_at(t1);
-                let mut n = &p - &self.c;
-                n.normalize();
-                Some(Hit::new(p, n, t1, get_sphere_uv(&n), self.m))
-            } else if t2 > 0.0 {
-                let p = ray.point_at(t2);
-                let mut n = &p - &self.c;
-                n.normalize();
-                Some(Hit::new(p, n, t2, get_sphere_uv(&n), self.m))
-            } else {
-                None
+            for &t in &[(-b - dsqrt) / a, (-b + dsqrt) / a] {
+                let y = (baoa + t * bard) / baba;
+                if t > 0.0 && t < smallest_t && y > 0.0 && y < 1.0 {
+                    smallest_t = t;
+                    closest = Some(self.body_hit(ray, t, &ba, y));
+                }
+            }
+        }
+
+        // Spheres at both ends, limited to the hemispheres outside of the cylinder,
+        // or a single whole sphere if the capsule has no length.
+        let degenerate = almost_zero(baba);
+        for (center, is_end) in &[(self.a, false), (self.b, true)] {
+            let oc = &ray.o - center;
+            let a = length_squared(&ray.d);
+            let b = dot(&oc, &ray.d);
+            let c = length_squared(&oc) - self.r * self.r;
+            let discriminant = b * b - a * c;
+            if discriminant <= 0.0 {
+                continue;
+            }
+            let dsqrt = discriminant.sqrt();
+            for &t in &[(-b - dsqrt) / a, (-b + dsqrt) / a] {
+                // With both caps at the same center, there is no axis to split the sphere by.
+                let on_cap = if degenerate {
+                    !*is_end
+                } else {
+                    let y = (baoa + t * bard) / baba;
+                    if *is_end { y >= 1.0 } else { y <= 0.0 }
+                };
+                if t > 0.0 && t < smallest_t && on_cap {
+                    smallest_t = t;
+                    closest = Some(self.cap_hit(ray, t, center));
+                }
+            }
+        }
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::mesh::Triangle;
+
+    #[test]
+    fn sphere_derivatives_follow_uv() {
+        let n = Vec3::new(0.48, 0.6, 0.64);
+        let (dpdu, dpdv) = get_sphere_derivatives(&n, 2.0);
+        let (u0, v0) = get_sphere_uv(&n);
+        // Step along each derivative on a sphere of radius 2, and compare the change in UV.
+        let eps = 0.001;
+        let mut n_u = &(2.0 * &n) + &(eps * &dpdu);
+        n_u.normalize();
+        let (u1, v1) = get_sphere_uv(&n_u);
+        assert!((u1 - u0 - eps).abs() < 1e-4); assert!((v1 - v0).abs() < 1e-4);
+        let mut n_v = &(2.0 * &n) + &(eps * &dpdv);
+        n_v.normalize();
+        let (u2, v2) = get_sphere_uv(&n_v);
+        assert!((u2 - u0).abs() < 1e-4); assert!((v2 - v0 - eps).abs() < 1e-4);
+    }
+
+    #[test]
+    fn sphere_derivatives_at_equator() {
+        let (dpdu, dpdv) = get_sphere_derivatives(&Vec3::new(1.0, 0.0, 0.0), 2.0);
+        assert!((length_squared(&dpdu).sqrt() - 4.0 * PI).abs() < 1e-5);
+        assert!((length_squared(&dpdv).sqrt() - 2.0 * PI).abs() < 1e-5);
+        assert_eq!(dot(&dpdu, &dpdv), 0.0);
+    }
+
+    fn hit_with_normals(n: Vec3, ng: Vec3) -> Hit<'static> {
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        Hit::new(zero, n, ng, 1.0, (0.0, 0.0), zero, zero, &Material::Normal)
+    }
+
+    #[test]
+    fn keep_consistent_shading_normal() {
+        let mut n = Vec3::new(0.3, 1.0, 0.0);
+        n.normalize();
+        let mut hit = hit_with_normals(n, Vec3::new(0.0, 1.0, 0.0));
+        hit.clamp_shading_normal(&Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(hit.n.x, n.x); assert_eq!(hit.n.y, n.y); assert_eq!(hit.n.z, n.z);
+    }
+
+    #[test]
+    fn reflection_below_geometric_surface() {
+        use super::super::vec3::reflect;
+        // Shading normal of a smoothed surface near its silhouette, tilted far from the flat geometric normal.
+        let ng = Vec3::new(0.0, 1.0, 0.0);
+        let hit = hit_with_normals(normalize(&Vec3::new(0.9, 0.3, 0.0)), ng);
+        let d = normalize(&Vec3::new(1.0, -0.2, 0.0));
+        let reflected = reflect(&d, &hit.n);
+        assert!(reflected.y < 0.0);
+        assert!(!hit.leaves_surface(&d, &reflected));
+        assert!(hit.leaves_surface(&d, &reflect(&d, &ng)));
+        // Same from below the surface.
+        let d = Vec3::new(0.0, 1.0, 0.0);
+        assert!(hit.leaves_surface(&d, &Vec3::new(0.5, -0.5, 0.0)));
+        assert!(!hit.leaves_surface(&d, &Vec3::new(0.5, 0.5, 0.0)));
+    }
+
+    #[test]
+    fn clamp_inconsistent_shading_normal() {
+        let mut n = Vec3::new(1.0, 0.2, 0.0);
+        n.normalize();
+        let mut hit = hit_with_normals(n, Vec3::new(0.0, 1.0, 0.0));
+        // Grazing ray, for which the shading normal faces away.
+        let d = Vec3::new(1.0, -0.1, 0.0);
+        hit.clamp_shading_normal(&d);
+        let wo = -&normalize(&d);
+        assert!(dot(&wo, &hit.n) > 0.0);
+        assert!((length_squared(&hit.n) - 1.0).abs() < 1e-5);
+        // The same applies when the ray hits the back side.
+        let mut hit = hit_with_normals(-&n, Vec3::new(0.0, -1.0, 0.0));
+        hit.clamp_shading_normal(&d);
+        assert!(dot(&wo, &hit.n) < 0.0);
+    }
+
+    #[test]
+    fn checkered_without_filtering() {
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let c = get_checkered_color(&white, &black, (1.0, 10.0), (0.5 * PI, 0.05 * PI), (0.0, 0.0));
+        assert_eq!(c.x, 1.0);
+        let c = get_checkered_color(&white, &black, (1.0, 10.0), (1.5 * PI, 0.05 * PI), (0.0, 0.0));
+        assert_eq!(c.x, 0.0);
+    }
+
+    #[test]
+    fn checkered_with_small_footprint() {
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let c = get_checkered_color(&white, &black, (1.0, 10.0), (0.5 * PI, 0.05 * PI), (0.1, 0.01));
+        assert_eq!(c.x, 1.0);
+    }
+
+    #[test]
+    fn checkered_with_large_footprint() {
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        let c = get_checkered_color(&white, &black, (1.0, 10.0), (0.5 * PI, 0.05 * PI), (PI, 0.0));
+        assert_eq!(c.x, 0.5);
+        let c = get_checkered_color(&white, &black, (1.0, 10.0), (0.5 * PI, 0.05 * PI), (0.0, f32::INFINITY));
+        assert_eq!(c.x, 0.5);
+        let c = get_checkered_color(&white, &black, (1.0, 10.0), (0.5 * PI, 0.05 * PI), (0.75 * PI, 0.0));
+        assert_eq!(c.x, 0.75);
+    }
+
+    #[test]
+    fn checkered_with_independent_scales() {
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let black = Vec3::new(0.0, 0.0, 0.0);
+        // With equal scales, the checks have the same size along U and V.
+        let c = get_checkered_color(&white, &black, (2.0, 2.0), (0.25 * PI, 0.25 * PI), (0.0, 0.0));
+        assert_eq!(c.x, 1.0);
+        let c = get_checkered_color(&white, &black, (2.0, 2.0), (0.25 * PI, 0.75 * PI), (0.0, 0.0));
+        assert_eq!(c.x, 0.0);
+        let c = get_checkered_color(&white, &black, (2.0, 2.0), (0.75 * PI, 0.25 * PI), (0.0, 0.0));
+        assert_eq!(c.x, 0.0);
+    }
+
+    #[test]
+    fn checkered_constructor_keeps_aspect() {
+        match Texture::checkered(Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, 0.0), 3.0) {
+            Texture::Checkered(_, _, scale_u, scale_v) => { assert_eq!(scale_u, 3.0); assert_eq!(scale_v, 30.0); }
+            Texture::None => panic!("expected checkered texture"),
+        }
+    }
+
+    #[test]
+    fn cubemap_face_centers() {
+        let dirs = [
+            Vec3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0),
+        ];
+        for (i, d) in dirs.iter().enumerate() {
+            let (face, u, v) = get_cubemap_uv(d);
+            assert_eq!(face, i); assert_eq!(u, 0.5); assert_eq!(v, 0.5);
+        }
+    }
+
+    #[test]
+    fn cubemap_face_orientation() {
+        // Looking down -Z, up maps to the top edge of the face.
+        let (face, u, v) = get_cubemap_uv(&Vec3::new(0.5, 0.5, -1.0));
+        assert_eq!(face, 5); assert_eq!(u, 0.25); assert_eq!(v, 0.25);
+        // Looking along +X, the top edge of the face is still up.
+        let (face, u, v) = get_cubemap_uv(&Vec3::new(2.0, 1.0, 0.0));
+        assert_eq!(face, 0); assert_eq!(u, 0.5); assert_eq!(v, 0.25);
+    }
+
+    #[test]
+    fn cubemap_face_mirroring() {
+        // Every face is white on the left and black on the right, so the side faces only join up
+        // if none of them is mirrored: the right edge of each meets the left edge of the next one.
+        let face = Image::new(2, 1, vec![255, 255, 255, 255, 0, 0, 0, 255]);
+        let mut scene = Scene::new(vec![]);
+        scene.background = Background::Cubemap([face.clone(), face.clone(), face.clone(), face.clone(), face.clone(), face]);
+        let seams = [
+            (Vec3::new(1.0, 0.0, -0.99), Vec3::new(0.99, 0.0, -1.0)),
+            (Vec3::new(-0.99, 0.0, -1.0), Vec3::new(-1.0, 0.0, -0.99)),
+            (Vec3::new(-1.0, 0.0, 0.99), Vec3::new(-0.99, 0.0, 1.0)),
+            (Vec3::new(0.99, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.99)),
+        ];
+        for (right, left) in seams.iter() {
+            assert_eq!(scene.background_color(right).x, 0.0);
+            assert_eq!(scene.background_color(left).x, 1.0);
+        }
+        // As in OpenGL, directions towards +X map to the left half of the -Z face.
+        assert_eq!(scene.background_color(&Vec3::new(0.5, 0.0, -1.0)).x, 1.0);
+        assert_eq!(scene.background_color(&Vec3::new(-0.5, 0.0, -1.0)).x, 0.0);
+    }
+
+    #[test]
+    fn cubemap_dominant_axis() {
+        let (face, _, _) = get_cubemap_uv(&Vec3::new(0.2, -0.9, 0.3));
+        assert_eq!(face, 3);
+        let (face, _, _) = get_cubemap_uv(&Vec3::new(-0.2, 0.1, 0.3));
+        assert_eq!(face, 4);
+    }
+
+    #[test]
+    fn scene_hit_reports_object_id() {
+        let scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0, Material::Normal),
+            Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Normal),
+            Sphere::new(Vec3::new(5.0, 0.0, -5.0), 1.0, Material::Normal),
+        ]);
+        let hit = scene.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+        assert_eq!(hit.object_id, 1);
+        let hit = scene.hit(&Ray::new(Vec3::new(5.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+        assert_eq!(hit.object_id, 2);
+    }
+
+    #[test]
+    fn shadow_through_glass() {
+        let red = Vec3::new(0.9, 0.2, 0.1);
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Glass(red, 1.5)),
+            Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0, Material::Diffuse(red, Texture::None, 0.0)),
+            Sphere::new(Vec3::new(3.0, 0.0, -5.0), 1.0, Material::Mix(Box::new(Material::Glass(red, 1.5)), Box::new(Material::Metal(red, 0.0, 0.0, 0.0)), 0.25)),
+        ]);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        // Tinted twice, entering and leaving the glass, each time reflecting 4% of the light away.
+        let t = scene.transmittance(&ray, 7.0);
+        let fresnel = 0.96 * 0.96;
+        assert!((t.x - 0.81 * fresnel).abs() < 1e-6); assert!((t.y - 0.04 * fresnel).abs() < 1e-6); assert!((t.z - 0.01 * fresnel).abs() < 1e-6);
+        // Opaque objects still block the light completely.
+        let t = scene.transmittance(&ray, 100.0);
+        assert_eq!(t.x, 0.0); assert_eq!(t.y, 0.0); assert_eq!(t.z, 0.0);
+        // Nothing in the way, or only beyond the light.
+        let t = scene.transmittance(&ray, 3.0);
+        assert_eq!(t.x, 1.0); assert_eq!(t.y, 1.0); assert_eq!(t.z, 1.0);
+        // Three quarters of a mixed surface are glass.
+        let ray = Ray::new(Vec3::new(3.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let t = scene.transmittance(&ray, 5.0);
+        assert!((t.x - 0.75 * 0.9 * 0.96).abs() < 1e-6);
+        // Glass that does not cast shadows is skipped entirely.
+        scene.spheres[0] = Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Glass(red, 1.5))
+            .with_visibility(Visibility { casts_shadows: false, ..Visibility::default() });
+        let t = scene.transmittance(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)), 7.0);
+        assert_eq!(t.x, 1.0); assert_eq!(t.y, 1.0); assert_eq!(t.z, 1.0);
+    }
+
+    #[test]
+    fn background_light_through_glass_and_lights() {
+        let red = Vec3::new(0.9, 0.2, 0.1);
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Glass(red, 1.5)),
+            Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0, Material::Light(Vec3::new(2.0, 2.0, 2.0))),
+            Sphere::new(Vec3::new(300.0, 0.0, -1e4), 100.0, Material::Diffuse(red, Texture::None, 0.0)),
+        ]);
+        scene.background = Background::Gradient(Vec3::new(0.5, 0.5, 0.5), Vec3::new(0.5, 0.5, 0.5));
+        // Nothing in the way.
+        let t = scene.background_light(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)), false);
+        assert_eq!(t.x, 0.5); assert_eq!(t.y, 0.5); assert_eq!(t.z, 0.5);
+        // The light behind the glass, tinted twice (less the reflections), instead of the background.
+        let t = scene.background_light(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)), false);
+        let fresnel = 0.96 * 0.96;
+        assert!((t.x - 1.62 * fresnel).abs() < 1e-5); assert!((t.y - 0.08 * fresnel).abs() < 1e-6); assert!((t.z - 0.02 * fresnel).abs() < 1e-6);
+        // Opaque objects block the background at any distance.
+        let t = scene.background_light(&Ray::new(Vec3::new(300.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)), false);
+        assert_eq!(t.x, 0.0); assert_eq!(t.y, 0.0); assert_eq!(t.z, 0.0);
+        // Lights that do not cast shadows let the background through, like other such objects.
+        scene.spheres[1] = Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0, Material::Light(Vec3::new(2.0, 2.0, 2.0)))
+            .with_visibility(Visibility { casts_shadows: false, ..Visibility::default() });
+        let t = scene.background_light(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)), false);
+        assert!((t.x - 0.405 * fresnel).abs() < 1e-6); assert!((t.y - 0.02 * fresnel).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sphere_light_surface() {
+        let light = Sphere::new(Vec3::new(1.0, 2.0, 3.0), -2.0, Material::Light(Vec3::new(4.0, 4.0, 4.0)));
+        assert_eq!(light.emission().unwrap().x, 4.0);
+        assert!(Sphere::new(Vec3::new(1.0, 2.0, 3.0), 2.0, Material::Normal).emission().is_none());
+        assert!((light.area() - 16.0 * PI).abs() < 1e-5);
+        let (p, n) = light.surface_point(&Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(p.x, 1.0); assert_eq!(p.y, 4.0); assert_eq!(p.z, 3.0);
+        assert_eq!(n.x, 0.0); assert_eq!(n.y, -1.0); assert_eq!(n.z, 0.0);
+    }
+
+    #[test]
+    fn sphere_solid_angle() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Normal);
+        let cos_theta = (1.0f32 - 0.25).sqrt();
+        assert!((sphere.solid_angle(&Vec3::new(0.0, 2.0, 0.0)) - 2.0 * PI * (1.0 - cos_theta)).abs() < 1e-6);
+        assert_eq!(sphere.solid_angle(&Vec3::new(0.5, 0.0, 0.0)), 0.0);
+        // Far away, the solid angle approaches the projected area divided by distance squared.
+        assert!((sphere.solid_angle(&Vec3::new(1000.0, 0.0, 0.0)) - PI / 1e6).abs() < 1e-10);
+    }
+
+    #[test]
+    fn sphere_direction_samples() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Normal);
+        let from = Vec3::new(0.0, 2.0, 0.0);
+        assert!(sphere.sample_direction(&Vec3::new(0.5, 0.0, 0.0), 0.5, 0.5).is_none());
+        let d = sphere.sample_direction(&from, 0.0, 0.3).unwrap();
+        assert!(d.x.abs() < 1e-6); assert!((d.y + 1.0).abs() < 1e-6); assert!(d.z.abs() < 1e-6);
+        // All the directions hit the sphere, and the edge of the cone touches it.
+        for i in 0..10 {
+            for j in 0..10 {
+                let d = sphere.sample_direction(&from, i as f32 / 9.0 * 0.9999, j as f32 / 10.0).unwrap();
+                assert!((length_squared(&d) - 1.0).abs() < 1e-5);
+                assert!(sphere.hit(&Ray::new(from, d)).is_some());
+            }
+        }
+        let d = sphere.sample_direction(&from, 1.0, 0.0).unwrap();
+        assert!((dot(&d, &Vec3::new(0.0, -1.0, 0.0)) - (0.75f32).sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pick_lights() {
+        let light = |x: f32, r: f32, e: f32| Sphere::new(Vec3::new(x, 0.0, 0.0), r, Material::Light(Vec3::new(e, e, e)));
+        let scene = Scene::new(vec![
+            light(0.0, 1.0, 1.0),
+            Sphere::new(Vec3::new(3.0, 0.0, 0.0), 1.0, Material::Normal),
+            light(6.0, 2.0, 0.5),
+            light(9.0, 1.0, 0.0),
+            light(12.0, 1.0, 1.0).with_visibility(Visibility { visible_to_camera: true, casts_shadows: true, visible_in_reflections: false }),
+        ]);
+        assert!(scene.is_sampled_light(0) && scene.is_sampled_light(2));
+        assert!(!scene.is_sampled_light(1) && !scene.is_sampled_light(3) && !scene.is_sampled_light(4));
+        // The second light is twice as powerful: four times the area, half the emission.
+        let pick = |scene: &Scene, u: f32, selection: LightSelection| scene.pick_light(u, selection).map(|(l, p)| (l.object_id(), p));
+        let (id, probability) = pick(&scene, 0.3, LightSelection::Power).unwrap();
+        assert_eq!(id, 0); assert!((probability - 1.0 / 3.0).abs() < 1e-6);
+        let (id, probability) = pick(&scene, 0.4, LightSelection::Power).unwrap();
+        assert_eq!(id, 2); assert!((probability - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(pick(&scene, 0.4, LightSelection::Uniform), Some((0, 0.5)));
+        assert_eq!(pick(&scene, 0.6, LightSelection::Uniform), Some((2, 0.5)));
+        // Hidden objects are not sampled.
+        let isolated = scene.isolate(&[2]);
+        assert!(!isolated.is_sampled_light(0) && isolated.is_sampled_light(2));
+        assert!(Scene::new(vec![]).pick_light(0.5, LightSelection::Power).is_none());
+    }
+
+    #[test]
+    fn pick_mesh_lights() {
+        // A light cube, as quads, and a lit triangle of a mesh otherwise not emitting any light.
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let mut scene = Scene::new(vec![Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Light(white))]);
+        scene.meshes.push(Mesh::cuboid(Vec3::new(2.0, 0.0, 0.0), Vec3::new(3.0, 1.0, 1.0), Material::Light(white)).with_quads());
+        scene.meshes.push(Mesh::new(vec![
+            Triangle::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(2.0, 5.0, 0.0), Vec3::new(0.0, 5.0, 2.0), Material::Light(white)),
+            Triangle::new(Vec3::new(0.0, 6.0, 0.0), Vec3::new(2.0, 6.0, 0.0), Vec3::new(0.0, 6.0, 2.0), Material::Normal),
+        ]));
+        scene.update_lights();
+        assert!(scene.is_sampled_light(0) && scene.is_sampled_light(1) && scene.is_sampled_light(2));
+        // Twelve triangles of the cube, and the light triangle, each emitting from both sides.
+        assert_eq!(scene.lights.len(), 14);
+        let total: f32 = scene.lights.iter().map(|l| l.area()).sum();
+        assert!((total - (4.0 * PI + 2.0 * 6.0 + 2.0 * 2.0)).abs() < 1e-4);
+        let (light, probability) = scene.pick_light(0.999, LightSelection::Power).unwrap();
+        assert_eq!(light.object_id(), 2);
+        assert!((probability - 4.0 / total).abs() < 1e-5);
+        // The light triangle seen from above, at a distance of 1 from its plane.
+        let from = Vec3::new(0.5, 6.0, 0.5);
+        let sample = light.sample(&from, 0.25, 0.5).unwrap();
+        assert!(length(&(&(&from + &(sample.t * &sample.d)) - &Vec3::new(0.5, 5.0, 0.5))) < 1e-5);
+        assert!((sample.inverse_density - 2.0).abs() < 1e-5);
+        // Points on both sides of the triangle.
+        assert!((light.surface_point(0.25, 0.5).1.y + 1.0).abs() < 1e-6);
+        assert!((light.surface_point(0.75, 0.5).1.y - 1.0).abs() < 1e-6);
+        // Hidden meshes are not sampled.
+        let isolated = scene.isolate(&[0, 1]);
+        assert!(isolated.is_sampled_light(1) && !isolated.is_sampled_light(2));
+    }
+
+    #[test]
+    fn capsule_body_and_caps() {
+        let capsule = Capsule::new(Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 0.5, Material::Normal);
+        // Body
+        let hit = capsule.hit(&Ray::new(Vec3::new(2.0, 0.5, 0.0), Vec3::new(-1.0, 0.0, 0.0))).unwrap();
+        assert!((hit.t - 1.5).abs() < 1e-5);
+        assert!((hit.n.x - 1.0).abs() < 1e-5); assert!(hit.n.y.abs() < 1e-5); assert!(hit.n.z.abs() < 1e-5);
+        assert!((hit.uv.1 - 0.75).abs() < 1e-5);
+        // Top of the cap
+        let hit = capsule.hit(&Ray::new(Vec3::new(0.0, 3.0, 0.0), Vec3::new(0.0, -1.0, 0.0))).unwrap();
+        assert!((hit.t - 1.5).abs() < 1e-5);
+        assert!((hit.n.y - 1.0).abs() < 1e-5);
+        // Side of the other cap
+        let hit = capsule.hit(&Ray::new(Vec3::new(0.4, -3.0, 0.0), Vec3::new(0.0, 1.0, 0.0))).unwrap();
+        assert!((hit.p.y + 1.3).abs() < 1e-5);
+        assert!((hit.n.x - 0.8).abs() < 1e-5); assert!((hit.n.y + 0.6).abs() < 1e-5);
+        assert!(hit.front_face);
+        // From the inside, with the normal facing the ray
+        let hit = capsule.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0))).unwrap();
+        assert!((hit.t - 0.5).abs() < 1e-5);
+        assert!((hit.n.z + 1.0).abs() < 1e-5);
+        assert!(!hit.front_face);
+        // Past the caps
+        assert!(capsule.hit(&Ray::new(Vec3::new(2.0, 1.6, 0.0), Vec3::new(-1.0, 0.0, 0.0))).is_none());
+        assert!(capsule.hit(&Ray::new(Vec3::new(2.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn capsule_without_length() {
+        let center = Vec3::new(0.0, 0.0, -5.0);
+        let capsule = Capsule::new(center, center, 0.5, Material::Normal);
+        let hit = capsule.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-5);
+        assert!((hit.n.z - 1.0).abs() < 1e-5);
+        // From the inside, the far side of the sphere
+        let hit = capsule.hit(&Ray::new(center, Vec3::new(0.0, 1.0, 0.0))).unwrap();
+        assert!((hit.t - 0.5).abs() < 1e-5);
+        assert!(capsule.hit(&Ray::new(Vec3::new(0.0, 0.6, 0.0), Vec3::new(0.0, 0.0, -1.0))).is_none());
+    }
+
+    #[test]
+    fn capsule_object_ids_follow_spheres() {
+        let mut scene = Scene::new(vec![Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0, Material::Normal)]);
+        scene.capsules.push(Capsule::new(Vec3::new(-1.0, 0.0, -5.0), Vec3::new(1.0, 0.0, -5.0), 0.5, Material::Normal));
+        let hit = scene.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+        assert_eq!(hit.object_id, 1);
+        assert!((hit.t - 4.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn occluded_agrees_with_hit() {
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Normal),
+            Sphere::new(Vec3::new(2.0, 1.0, -3.0), 0.5, Material::Normal),
+        ]);
+        scene.capsules.push(Capsule::new(Vec3::new(-2.0, -1.0, -4.0), Vec3::new(-2.0, 1.0, -4.0), 0.5, Material::Normal));
+        for i in 0..20 {
+            for j in 0..20 {
+                let d = Vec3::new(i as f32 / 5.0 - 2.0, j as f32 / 5.0 - 2.0, -3.0);
+                for &o in &[Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -5.0)] {
+                    let ray = Ray::new(o, d);
+                    for &max_t in &[0.5, 1.0, f32::MAX] {
+                        let blocked = scene.hit(&ray).is_some_and(|hit| hit.t < max_t);
+                        assert_eq!(scene.occluded(&ray, max_t), blocked);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn negative_radius_turns_inside_out() {
+        let outer = Sphere::new(Vec3::new(0.0, 0.0, -5.0), 2.0, Material::Normal);
+        let inner = Sphere::new(Vec3::new(0.0, 0.0, -5.0), -2.0, Material::Normal);
+        let ray = Ray::new(Vec3::new(0.5, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let (h1, h2) = (outer.hit(&ray).unwrap(), inner.hit(&ray).unwrap());
+        assert_eq!(h1.t, h2.t);
+        assert_eq!(h1.uv, h2.uv);
+        // Both normals face the ray, but the ray hits the inverted sphere from the inside.
+        assert_eq!(h1.n.x, h2.n.x); assert_eq!(h1.n.y, h2.n.y); assert_eq!(h1.n.z, h2.n.z);
+        assert_eq!(h2.ng.z, h2.n.z);
+        assert!(h2.n.z > 0.0);
+        assert!(h1.front_face && !h2.front_face);
+        assert_eq!(length_squared(&h1.dpdu), length_squared(&h2.dpdu));
+        assert!(outer.occluded(&ray, f32::MAX) && inner.occluded(&ray, f32::MAX));
+    }
+
+    #[test]
+    fn ray_epsilon_follows_hit() {
+        assert!((ray_epsilon(&Vec3::new(0.0, -200.0, 0.0), &BBox::new()) - 0.001).abs() < 1e-5);
+        assert!((ray_epsilon(&Vec3::new(10.0, 2000.0, -30.0), &BBox::infinite()) - 0.01).abs() < 1e-4);
+        // Points computed from the coordinates of a large object are just as imprecise.
+        let floor = Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Material::Normal);
+        assert!((ray_epsilon(&Vec3::new(0.0, 0.0, 0.0), &floor.bbox()) - 0.01).abs() < 1e-4);
+        // A huge object far away does not change the offset of the rays spawned at the others, even when added later.
+        let mut scene = Scene::new(vec![Sphere::new(Vec3::new(0.0, 0.0, -1.0), 0.5, Material::Normal)]);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let epsilon = scene.hit_epsilon(&scene.hit(&ray).unwrap());
+        assert!((epsilon - 5e-6 * 1.5).abs() < 1e-9);
+        scene.spheres.push(Sphere::new(Vec3::new(0.0, 0.0, -1e6), 1e5, Material::Normal));
+        assert_eq!(scene.hit_epsilon(&scene.hit(&ray).unwrap()), epsilon);
+        let far = Ray::new(Vec3::new(0.0, 0.0, -2.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!((scene.hit_epsilon(&scene.hit(&far).unwrap()) - 5.5).abs() < 1e-3);
+        // Points on no object only count their own coordinates.
+        assert_eq!(scene.point_epsilon(&Vec3::new(0.0, 0.0, 0.0)), 0.0);
+        assert!((scene.point_epsilon(&Vec3::new(0.0, 0.0, -1.4)) - 5e-6 * 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn normals_face_the_ray() {
+        use super::super::xform::Transform;
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, -5.0), 2.0, Material::Normal);
+        // From the outside, the normal points out of the sphere, towards the ray.
+        let d = normalize(&Vec3::new(0.1, 0.2, -1.0));
+        let hit = sphere.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.0), d)).unwrap();
+        let outward = normalize(&(&hit.p - &Vec3::new(0.0, 0.0, -5.0)));
+        assert!(hit.front_face);
+        assert!(dot(&hit.n, &outward) > 1.0 - 1e-5); assert!(dot(&hit.ng, &outward) > 1.0 - 1e-5);
+        assert!(dot(&hit.n, &d) < 0.0);
+        // From the inside, as a camera inside a dome, it points into the sphere, still towards the ray.
+        let hit = sphere.hit(&Ray::new(Vec3::new(0.0, 0.0, -5.5), d)).unwrap();
+        let outward = normalize(&(&hit.p - &Vec3::new(0.0, 0.0, -5.0)));
+        assert!(!hit.front_face);
+        assert!(dot(&hit.n, &outward) < -1.0 + 1e-5); assert!(dot(&hit.ng, &outward) < -1.0 + 1e-5);
+        assert!(dot(&hit.n, &d) < 0.0);
+        // The same holds for instances, whose normals are transformed.
+        let instance = Instance::new(sphere, Transform::scale(1.0, 3.0, 1.0));
+        let hit = instance.hit(&Ray::new(Vec3::new(0.0, 0.0, -5.0), d)).unwrap();
+        assert!(!hit.front_face);
+        assert!(dot(&hit.n, &d) < 0.0 && dot(&hit.ng, &d) < 0.0);
+    }
+
+    #[test]
+    fn hit_with_unnormalized_direction() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Normal);
+        let hit = sphere.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -2.0))).unwrap();
+        assert_eq!(hit.t, 2.0);
+        assert_eq!(hit.p.z, -4.0);
+        let capsule = Capsule::new(Vec3::new(0.0, -1.0, -5.0), Vec3::new(0.0, 1.0, -5.0), 1.0, Material::Normal);
+        let hit = capsule.hit(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -2.0))).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn fingerprint_changes_with_scene() {
+        let scene = |c: Vec3, m: Material| Scene::new(vec![Sphere::new(c, 0.5, m)]);
+        let fingerprint = scene(Vec3::new(0.0, 0.0, -1.0), Material::Normal).fingerprint();
+        assert_eq!(scene(Vec3::new(0.0, 0.0, -1.0), Material::Normal).fingerprint(), fingerprint);
+        let moved = scene(Vec3::new(0.0, 0.0, -2.0), Material::Normal);
+        let recolored = scene(Vec3::new(0.0, 0.0, -1.0), Material::Light(Vec3::new(1.0, 1.0, 1.0)));
+        let mut hidden = scene(Vec3::new(0.0, 0.0, -1.0), Material::Normal);
+        hidden.spheres[0].visibility.visible_to_camera = false;
+        let mut dimmed = scene(Vec3::new(0.0, 0.0, -1.0), Material::Normal);
+        dimmed.background_intensity = 0.5;
+        let mut extended = scene(Vec3::new(0.0, 0.0, -1.0), Material::Normal);
+        extended.capsules.push(Capsule::new(Vec3::new(0.0, -1.0, -3.0), Vec3::new(0.0, 1.0, -3.0), 0.5, Material::Normal));
+        let mut meshed = scene(Vec3::new(0.0, 0.0, -1.0), Material::Normal);
+        meshed.meshes.push(Mesh::cuboid(Vec3::new(-1.0, -1.0, -4.0), Vec3::new(1.0, 1.0, -3.0), Material::Normal));
+        for other in &[moved, recolored, hidden, dimmed, extended, meshed] {
+            assert_ne!(other.fingerprint(), fingerprint);
+        }
+        let stretched = |sx: f32| {
+            let mut scene = scene(Vec3::new(0.0, 0.0, -1.0), Material::Normal);
+            scene.instances.push(Instance::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Normal), super::super::xform::Transform::scale(sx, 1.0, 1.0)));
+            scene
+        };
+        assert_ne!(stretched(2.0).fingerprint(), stretched(3.0).fingerprint());
+    }
+
+    #[test]
+    fn grid_matches_brute_force() {
+        use super::super::xform::Transform;
+        use rand::{ Rng, SeedableRng };
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let mut spheres = Vec::new();
+        for x in -4..4 {
+            for z in -4..4 {
+                spheres.push(Sphere::new(Vec3::new(x as f32, rng.gen::<f32>(), z as f32), 0.3, Material::Normal));
+            }
+        }
+        spheres.push(Sphere::new(Vec3::new(0.0, -100.0, 0.0), 99.5, Material::Normal));
+        let mut scene = Scene::new(spheres);
+        scene.capsules.push(Capsule::new(Vec3::new(-3.0, 2.0, 0.0), Vec3::new(3.0, 2.0, 0.5), 0.25, Material::Normal));
+        scene.instances.push(Instance::new(
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Normal),
+            &Transform::translate(0.5, 3.0, -2.0) * &Transform::scale(2.0, 0.2, 0.5),
+        ));
+        scene.meshes.push(Mesh::cuboid(Vec3::new(-2.0, 0.5, 2.5), Vec3::new(-0.5, 1.5, 3.5), Material::Normal).with_quads());
+        let rays: Vec<Ray> = (0..500).map(|_| {
+            let o = Vec3::new(rng.gen_range(-6.0..6.0), rng.gen_range(-1.0..4.0), rng.gen_range(-6.0..6.0));
+            let d = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            Ray::new(o, d)
+        }).collect();
+        let expected: Vec<(Option<(usize, f32)>, bool)> = rays.iter()
+            .map(|ray| (scene.hit(ray).map(|h| (h.object_id, h.t)), scene.occluded(ray, 2.0)))
+            .collect();
+        scene.build_grid(1);
+        let mut hits = 0;
+        for (ray, (hit, occluded)) in rays.iter().zip(expected.iter()) {
+            assert_eq!(scene.hit(ray).map(|h| (h.object_id, h.t)), *hit);
+            assert_eq!(scene.occluded(ray, 2.0), *occluded);
+            hits += hit.is_some() as u32;
+        }
+        assert!(hits > 50);
+    }
+
+    #[test]
+    fn isolate_objects() {
+        use super::super::xform::Transform;
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Normal),
+            Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0, Material::Normal),
+        ]);
+        scene.capsules.push(Capsule::new(Vec3::new(-1.0, 0.0, -20.0), Vec3::new(1.0, 0.0, -20.0), 1.0, Material::Normal));
+        scene.instances.push(Instance::new(Sphere::new(Vec3::new(0.0, 0.0, -30.0), 1.0, Material::Normal), Transform::scale(1.0, 1.0, 1.0)));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        for ids in &[vec![1], vec![2], vec![3], vec![1, 3]] {
+            let isolated = scene.isolate(ids);
+            // The first object along the ray that is kept, with the same id as in the original scene.
+            for &ray_type in &[RayType::Camera, RayType::Reflection, RayType::Shadow] {
+                assert_eq!(isolated.hit_visible(&ray, ray_type).unwrap().object_id, ids[0]);
+            }
+            assert!(!isolated.occluded(&ray, 8.0));
+            for id in 0..4 {
+                assert_eq!(isolated.object_visibility(id).includes(RayType::Shadow), ids.contains(&id));
             }
-        } else {
-            None
         }
+        assert!(scene.isolate(&[]).hit_visible(&ray, RayType::Camera).is_none());
+        // The original scene does not change.
+        assert_eq!(scene.hit_visible(&ray, RayType::Camera).unwrap().object_id, 0);
+    }
+
+    #[test]
+    fn count_intersection_tests() {
+        // A row of small spheres along X, with one hidden from the camera.
+        let mut spheres: Vec<Sphere> = (0..20).map(|i| Sphere::new(Vec3::new(i as f32, 0.0, 0.0), 0.25, Material::Normal)).collect();
+        spheres[3] = Sphere::new(Vec3::new(3.0, 0.0, 0.0), 0.25, Material::Normal)
+            .with_visibility(Visibility { visible_to_camera: false, ..Visibility::default() });
+        let mut scene = Scene::new(spheres);
+        let along = Ray::new(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let across = Ray::new(Vec3::new(10.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        // Without the grid, every visible object is tested.
+        let (hit, tests) = scene.hit_counting_tests(&along, RayType::Camera);
+        assert_eq!(hit.unwrap().object_id, 0);
+        assert_eq!(tests, 19);
+        assert_eq!(scene.hit_counting_tests(&across, RayType::Camera).1, 19);
+        // With the grid, the ray stops at the first cell with a hit, and the crossing ray only visits the cells it pierces.
+        scene.build_grid(1);
+        let (hit, tests) = scene.hit_counting_tests(&along, RayType::Camera);
+        assert_eq!(hit.unwrap().object_id, 0);
+        assert!(tests < 19);
+        let (hit, tests) = scene.hit_counting_tests(&across, RayType::Camera);
+        assert_eq!(hit.unwrap().object_id, 10);
+        assert!(tests < 19);
+    }
+
+    #[test]
+    fn sphere_sphere_intersection() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Normal);
+        let overlapping = Sphere::new(Vec3::new(1.5, 0.0, 0.0), 1.0, Material::Normal);
+        let touching = Sphere::new(Vec3::new(0.0, 3.0, 0.0), 2.0, Material::Normal);
+        let disjoint = Sphere::new(Vec3::new(0.0, 0.0, 2.5), 1.0, Material::Normal);
+        let inside = Sphere::new(Vec3::new(0.1, 0.0, 0.0), 0.5, Material::Normal);
+        assert!(sphere.intersects(&overlapping) && overlapping.intersects(&sphere));
+        assert!(sphere.intersects(&touching));
+        assert!(!sphere.intersects(&disjoint) && !disjoint.intersects(&sphere));
+        assert!(sphere.intersects(&inside));
+        // Inverted spheres are the same spheres.
+        assert!(Sphere::new(Vec3::new(0.0, 0.0, 0.0), -1.0, Material::Normal).intersects(&overlapping));
+    }
+
+    #[test]
+    fn sphere_bbox_intersection() {
+        let bbox = BBox::new_from_points(&Vec3::new(-1.0, -1.0, -1.0), &Vec3::new(1.0, 1.0, 1.0));
+        let sphere = |x: f32, y: f32, z: f32, r: f32| Sphere::new(Vec3::new(x, y, z), r, Material::Normal);
+        assert!(sphere(0.0, 0.0, 0.0, 0.5).intersects_bbox(&bbox));
+        assert!(sphere(0.0, 0.0, 0.0, 5.0).intersects_bbox(&bbox));
+        assert!(sphere(1.5, 0.0, 0.0, 1.0).intersects_bbox(&bbox));
+        assert!(sphere(2.0, 0.0, 0.0, 1.0).intersects_bbox(&bbox));
+        assert!(!sphere(2.5, 0.0, 0.0, 1.0).intersects_bbox(&bbox));
+        // Near a corner, the sphere has to reach the corner itself.
+        assert!(!sphere(1.6, 1.6, 1.6, 1.0).intersects_bbox(&bbox));
+        assert!(sphere(1.5, 1.5, 1.5, 1.0).intersects_bbox(&bbox));
+    }
+
+    #[test]
+    fn visibility_flags() {
+        let shadow_only = Visibility { visible_to_camera: false, visible_in_reflections: false, ..Visibility::default() };
+        let reflection_only = Visibility { visible_to_camera: false, casts_shadows: false, ..Visibility::default() };
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, Material::Normal).with_visibility(shadow_only),
+            Sphere::new(Vec3::new(0.0, 0.0, -6.0), 1.0, Material::Normal).with_visibility(reflection_only),
+            Sphere::new(Vec3::new(0.0, 0.0, -9.0), 1.0, Material::Normal),
+        ]);
+        scene.capsules.push(Capsule::new(Vec3::new(-1.0, 0.0, -12.0), Vec3::new(1.0, 0.0, -12.0), 1.0, Material::Normal).with_visibility(shadow_only));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        for grid in [false, true] {
+            if grid {
+                scene.build_grid(1);
+            }
+            assert_eq!(scene.hit(&ray).unwrap().object_id, 0);
+            assert_eq!(scene.hit_visible(&ray, RayType::Camera).unwrap().object_id, 2);
+            assert_eq!(scene.hit_visible(&ray, RayType::Reflection).unwrap().object_id, 1);
+            assert_eq!(scene.hit_visible(&ray, RayType::Shadow).unwrap().object_id, 0);
+            assert_eq!(scene.hit_objects(&ray, &[0, 1], RayType::Camera).map(|h| h.object_id), None);
+            assert_eq!(scene.hit_objects(&ray, &[0, 1], RayType::Reflection).map(|h| h.object_id), Some(1));
+            // Only the shadow casters block shadow rays.
+            assert!(scene.occluded(&ray, 2.5));
+            let behind_first = Ray::new(Vec3::new(0.0, 0.0, -4.5), Vec3::new(0.0, 0.0, -1.0));
+            assert!(!scene.occluded(&behind_first, 3.0));
+            assert!(scene.occluded(&behind_first, 4.0));
+            let past_spheres = Ray::new(Vec3::new(0.0, 0.0, -10.5), Vec3::new(0.0, 0.0, -1.0));
+            assert!(scene.occluded(&past_spheres, f32::MAX));
+            assert_eq!(scene.object_visibility(3), shadow_only);
+        }
+    }
+
+    #[test]
+    fn sphere_hit_interval() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, -5.0), 1.0, Material::Normal);
+        let (entry, exit) = sphere.hit_interval(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+        assert_eq!(entry, 4.0); assert_eq!(exit, 6.0);
+        // Symmetric around the center when starting there.
+        let (entry, exit) = sphere.hit_interval(&Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.6, 0.8, 0.0))).unwrap();
+        assert!((entry + 1.0).abs() < 1e-6); assert!((exit - 1.0).abs() < 1e-6);
+        // Off-center chord, with both ends the same distance from its middle.
+        let (entry, exit) = sphere.hit_interval(&Ray::new(Vec3::new(0.6, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0))).unwrap();
+        assert!((entry - 4.2).abs() < 1e-5); assert!((exit - 5.8).abs() < 1e-5);
+        // Negative radius describes the same sphere.
+        let inverted = Sphere::new(Vec3::new(0.0, 0.0, -5.0), -1.0, Material::Normal);
+        assert_eq!(inverted.hit_interval(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0))), Some((4.0, 6.0)));
+        // Misses, and spheres behind the ray.
+        assert!(sphere.hit_interval(&Ray::new(Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0))).is_none());
+        assert!(sphere.hit_interval(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0))).is_none());
+    }
+
+    #[test]
+    fn import_z_up_spheres() {
+        // Snowman modeled with Z up, with a smaller sphere on top of a larger one.
+        let snowman = || vec![
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Normal),
+            Sphere::new(Vec3::new(0.0, 0.0, 1.5), 0.5, Material::Light(Vec3::new(1.0, 1.0, 1.0))),
+        ];
+        let mut scene = Scene::new(vec![]);
+        scene.import_spheres(snowman(), UpAxis::Z);
+        // Looking down, the top is the small sphere.
+        let hit = scene.hit(&Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0))).unwrap();
+        assert!(matches!(hit.m, Material::Light(_)));
+        assert!((hit.p.y - 2.0).abs() < 1e-5);
+        assert!((hit.n.y - 1.0).abs() < 1e-5);
+        let bbox = scene.instances[1].bbox();
+        assert_eq!(bbox.min.y, 1.0); assert_eq!(bbox.max.y, 2.0); assert_eq!(bbox.max.z, 0.5);
+        // Y-up models are not changed.
+        let mut scene = Scene::new(vec![]);
+        scene.import_spheres(snowman(), UpAxis::Y);
+        let hit = scene.hit(&Ray::new(Vec3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0))).unwrap();
+        assert!(matches!(hit.m, Material::Normal));
+    }
+
+    #[test]
+    fn import_z_up_mesh() {
+        // Box modeled with Z up, with a lit top face, standing on the ground.
+        let mut faces = Mesh::cuboid(Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 2.0), Material::Normal).triangles().to_vec();
+        for face in faces.iter_mut().filter(|f| f.a.z == 2.0 && f.b.z == 2.0 && f.c.z == 2.0) {
+            face.m = Material::Light(Vec3::new(1.0, 1.0, 1.0));
+        }
+        let mut scene = Scene::new(vec![]);
+        scene.import_mesh(Mesh::new(faces).with_quads(), UpAxis::Z);
+        // Looking down, the top is the lit face, facing up.
+        let hit = scene.hit(&Ray::new(Vec3::new(0.25, 10.0, 0.25), Vec3::new(0.0, -1.0, 0.0))).unwrap();
+        assert!(matches!(hit.m, Material::Light(_)));
+        assert!((hit.p.y - 2.0).abs() < 1e-5);
+        assert!((hit.n.y - 1.0).abs() < 1e-5);
+        let bbox = scene.meshes[0].bbox();
+        assert_eq!(bbox.min.y, 0.0); assert_eq!(bbox.max.y, 2.0);
+        // The side away from the viewer in the model is away from the viewer in the scene, too.
+        let hit = scene.hit(&Ray::new(Vec3::new(0.25, 1.0, -10.0), Vec3::new(0.0, 0.0, 1.0))).unwrap();
+        assert!((hit.p.z + 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn export_obj_round_trip() {
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Material::Normal),
+            Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, Material::Normal),
+        ]);
+        scene.capsules.push(Capsule::new(Vec3::new(-2.0, 0.5, 0.0), Vec3::new(2.0, 0.5, 0.0), 0.5, Material::Normal));
+        scene.import_spheres(vec![Sphere::new(Vec3::new(0.0, 0.0, 3.0), 0.5, Material::Normal)], UpAxis::Z);
+        scene.meshes.push(Mesh::cuboid(Vec3::new(-1.0, 0.0, -1.0), Vec3::new(1.0, 1.0, 1.0), Material::Normal).with_quads());
+        let path = std::env::temp_dir().join(format!("rpt-export-test-{}.obj", std::process::id()));
+        scene.export_obj(&path, 6).unwrap();
+        let obj = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines = |prefix: &str| obj.lines().filter(|l| l.starts_with(prefix)).collect::<Vec<_>>();
+        // 3 spheres with 2 poles and 5 rings of 12 vertices, a capsule with 2 poles and 6 rings,
+        // and a box of 6 quads split into triangles.
+        let vertices = lines("v ");
+        assert_eq!(vertices.len(), 3 * (2 + 5 * 12) + 2 + 6 * 12 + 6 * 4);
+        let faces = lines("f ");
+        assert_eq!(faces.len(), 3 * (2 * 12 * 5) + 2 * 12 * 6 + 6 * 2);
+        assert!(faces.iter().flat_map(|f| f[2..].split(' ')).all(|i| (1..=vertices.len()).contains(&i.parse().unwrap())));
+        assert_eq!(lines("o "), vec!["o sphere_0", "o sphere_1", "o capsule_0", "o instance_0", "o mesh_0"]);
+        // The imported sphere ends up above the others.
+        let top = vertices.iter().map(|v| v.split(' ').nth(2).unwrap().parse::<f32>().unwrap()).fold(0.0, f32::max);
+        assert!((top - 3.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn validate_materials() {
+        let gray = Vec3::new(0.5, 0.5, 0.5);
+        assert!(Material::Diffuse(gray, Texture::checkered(gray, Vec3::new(1.0, 1.0, 1.0), 1.0), 0.5).validate().is_empty());
+        assert!(Material::Light(Vec3::new(10.0, 10.0, 10.0)).validate().is_empty());
+        assert_eq!(Material::Metal(Vec3::new(1.5, 0.5, 0.5), 0.0, 0.0, 0.0).validate(), vec!["metal albedo (1.5, 0.5, 0.5) is outside [0, 1]"]);
+        assert_eq!(Material::Glass(Vec3::new(1.0, 1.0, -0.1), 0.0).validate().len(), 2);
+        let mix = Material::Mix(Box::new(Material::Diffuse(gray, Texture::checkered(gray, Vec3::new(2.0, 0.0, 0.0), 1.0), 0.0)), Box::new(Material::Normal), 1.5);
+        assert_eq!(mix.validate(), vec!["mixing factor 1.5 is outside [0, 1]", "texture color (2, 0, 0) is outside [0, 1]"]);
+        let wireframe = Material::Wireframe(Box::new(Material::Metal(Vec3::new(0.5, 1.5, 0.5), 0.0, 0.0, 0.0)), Vec3::new(2.0, 2.0, 2.0), 0.1);
+        assert_eq!(wireframe.validate(), vec!["metal albedo (0.5, 1.5, 0.5) is outside [0, 1]"]);
+
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Diffuse(gray, Texture::None, 0.0)),
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Glass(gray, -1.5)),
+        ]);
+        scene.capsules.push(Capsule::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.5, Material::Normal));
+        scene.import_spheres(vec![Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Diffuse(Vec3::new(1.0, 1.2, 1.0), Texture::None, 0.0))], UpAxis::Y);
+        let problems = scene.validate_materials();
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].0, 1); assert_eq!(problems[1].0, 3);
+    }
+
+    #[test]
+    fn suggested_max_depth() {
+        let gray = Vec3::new(0.5, 0.5, 0.5);
+        let diffuse = Material::Diffuse(gray, Texture::None, 0.0);
+        assert_eq!(Scene::new(vec![]).suggested_max_depth(), 4);
+        let mut scene = Scene::new(vec![
+            Sphere::new(Vec3::new(0.0, -1001.0, 0.0), 1000.0, diffuse.clone()),
+            Sphere::new(Vec3::new(0.0, 3.0, 0.0), 0.5, Material::Light(Vec3::new(4.0, 4.0, 4.0))),
+        ]);
+        assert_eq!(scene.suggested_max_depth(), 4);
+        scene.capsules.push(Capsule::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.5, Material::Metal(gray, 0.1, 0.0, 0.0)));
+        assert_eq!(scene.suggested_max_depth(), 8);
+        // Glass counts even when only mixed in, and in meshes and instances.
+        let mut glassy = scene.clone();
+        glassy.spheres.push(Sphere::new(Vec3::new(2.0, 0.0, 0.0), 0.5, Material::Mix(Box::new(diffuse), Box::new(Material::Glass(gray, 1.5)), 0.1)));
+        assert_eq!(glassy.suggested_max_depth(), 16);
+        let mut meshed = scene.clone();
+        meshed.meshes.push(Mesh::cuboid(Vec3::new(2.0, 0.0, 0.0), Vec3::new(3.0, 1.0, 1.0), Material::Glass(gray, 1.5)));
+        assert_eq!(meshed.suggested_max_depth(), 16);
+        scene.import_spheres(vec![Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Material::Glass(gray, 1.5))], UpAxis::Y);
+        assert_eq!(scene.suggested_max_depth(), 16);
     }
 }
@@ -0,0 +1,102 @@
+use std::io::{ self, BufRead };
+use super::vec3::Vec3;
+use super::scene::{ Material, Sphere, Texture };
+use super::material_library::MaterialLibrary;
+
+/// Read spheres from a plain text description, one definition per line, for example, to render
+/// scenes generated by other programs:
+///
+/// ```text
+/// # Named material, usable by any of the following spheres.
+/// material floor diffuse 0.5 0.5 0.5
+/// sphere 0 -100 0 99 floor
+/// sphere 0 0 0 1 glass 1 1 1 1.5
+/// ```
+///
+/// Materials are either the name of a material defined earlier, or one of `diffuse <r> <g> <b> [roughness]`,
+/// `metal <r> <g> <b> [roughness]`, `glass <r> <g> <b> <ior>`, `light <r> <g> <b>`, and `normal`.
+/// Blank lines and lines starting with `#` are skipped.
+///
+/// Fails with `InvalidData`, naming the line, for lines that cannot be parsed, and for input without any spheres.
+///
+/// # Arguments
+///
+/// * `reader` - Source of the description, for example, the standard input.
+pub fn read_spheres(reader: impl BufRead) -> io::Result<Vec<Sphere>> {
+    let mut materials = MaterialLibrary::new();
+    let mut spheres = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", i + 1, message));
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {},
+            [first, ..] if first.starts_with('#') => {},
+            ["material", name, material @ ..] => {
+                let material = parse_material(material, &materials).map_err(invalid)?;
+                materials.insert(name, material);
+            },
+            ["sphere", x, y, z, r, material @ ..] => {
+                let (c, r) = (Vec3::new(parse(x).map_err(invalid)?, parse(y).map_err(invalid)?, parse(z).map_err(invalid)?), parse(r).map_err(invalid)?);
+                spheres.push(Sphere::new(c, r, parse_material(material, &materials).map_err(invalid)?));
+            },
+            _ => return Err(invalid(format!("expected a material or a sphere, found \"{}\"", line.trim()))),
+        }
+    }
+    if spheres.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no spheres"));
+    }
+    Ok(spheres)
+}
+
+fn parse(word: &str) -> Result<f32, String> {
+    word.parse().map_err(|_| format!("invalid number \"{}\"", word))
+}
+
+fn parse_color(r: &str, g: &str, b: &str) -> Result<Vec3, String> {
+    Ok(Vec3::new(parse(r)?, parse(g)?, parse(b)?))
+}
+
+fn parse_material(words: &[&str], materials: &MaterialLibrary) -> Result<Material, String> {
+    match *words {
+        ["diffuse", r, g, b] => Ok(Material::Diffuse(parse_color(r, g, b)?, Texture::None, 0.0)),
+        ["diffuse", r, g, b, roughness] => Ok(Material::Diffuse(parse_color(r, g, b)?, Texture::None, parse(roughness)?)),
+        ["metal", r, g, b] => Ok(Material::Metal(parse_color(r, g, b)?, 0.0, 0.0, 0.0)),
+        ["metal", r, g, b, roughness] => Ok(Material::Metal(parse_color(r, g, b)?, parse(roughness)?, 0.0, 0.0)),
+        ["glass", r, g, b, ior] => Ok(Material::Glass(parse_color(r, g, b)?, parse(ior)?)),
+        ["light", r, g, b] => Ok(Material::Light(parse_color(r, g, b)?)),
+        ["normal"] => Ok(Material::Normal),
+        [name] => materials.get(name).ok_or_else(|| format!("unknown material \"{}\"", name)),
+        _ => Err(format!("invalid material \"{}\"", words.join(" "))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_spheres_and_materials() {
+        let text = "# Floor and a glass ball\nmaterial floor diffuse 0.5 0.5 0.5\n\nsphere 0 -100 0 99 floor\nsphere 0 0 0 1 glass 1 1 1 1.5\n";
+        let spheres = read_spheres(text.as_bytes()).unwrap();
+        assert_eq!(spheres.len(), 2);
+        assert_eq!(spheres[0].radius(), 99.0);
+        assert_eq!(spheres[0].material(), &Material::Diffuse(Vec3::new(0.5, 0.5, 0.5), Texture::None, 0.0));
+        assert_eq!(spheres[1].material(), &Material::Glass(Vec3::new(1.0, 1.0, 1.0), 1.5));
+    }
+
+    #[test]
+    fn reject_invalid_input() {
+        let error = |text: &str| {
+            let error = read_spheres(text.as_bytes()).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            error.to_string()
+        };
+        assert_eq!(error(""), "no spheres");
+        assert_eq!(error("# nothing\n"), "no spheres");
+        assert_eq!(error("sphere 0 0 0 1 normal\nsphere 0 0 x 1 normal"), "line 2: invalid number \"x\"");
+        assert_eq!(error("sphere 0 0 0 1 gold"), "line 1: unknown material \"gold\"");
+        assert_eq!(error("sphere 0 0 0 1 glass 1 1 1"), "line 1: invalid material \"glass 1 1 1\"");
+        assert_eq!(error("cube 0 0 0 1"), "line 1: expected a material or a sphere, found \"cube 0 0 0 1\"");
+    }
+}
@@ -0,0 +1,192 @@
+use super::sampling::{ LightSelection, SamplingMode };
+use super::vec3::Vec3;
+use super::color::{ ToneMapping, Transfer };
+use super::image::BitDepth;
+use super::tile::TileOrder;
+
+/// Part of the lighting to render.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightingComponent {
+    /// All the light.
+    All,
+    /// Light reaching the camera directly from the lights or background, or after a single bounce.
+    Direct,
+    /// Light reaching the camera after two or more bounces.
+    Indirect,
+}
+
+impl LightingComponent {
+    /// Check whether light arriving after given number of bounces belongs to this component.
+    #[inline(always)]
+    pub fn includes(&self, bounces: u32) -> bool {
+        match self {
+            LightingComponent::All => true,
+            LightingComponent::Direct => bounces <= 1,
+            LightingComponent::Indirect => bounces >= 2,
+        }
+    }
+}
+
+/// Settings controlling the rendering process.
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    /// Output image width.
+    pub width: u32,
+    /// Output image height.
+    pub height: u32,
+    /// Number of samples per pixel.
+    pub pixel_samples: u32,
+    /// Max recursion depth of traced paths. See `Scene::suggested_max_depth` for one that fits the scene.
+    pub max_depth: u32,
+    /// Separate max recursion depth for scattering off glass. When set, glass bounces
+    /// count against this budget instead of `max_depth`, so that paths can traverse
+    /// several layers of glass without cutting diffuse paths short.
+    pub max_refraction_depth: Option<u32>,
+    /// Number of threads to render with, or 0 to use one thread per available core.
+    pub num_threads: u32,
+    /// Strategy for choosing the positions of samples within a pixel.
+    pub sampling: SamplingMode,
+    /// Whether procedural textures should fade to their average color
+    /// where their pattern is too fine for the pixel footprint. Off by default.
+    pub filter_textures: bool,
+    /// Whether to mirror the image horizontally.
+    pub flip_u: bool,
+    /// Whether to mirror the image vertically, so that the first row of the output is the bottom of the image.
+    pub flip_v: bool,
+    /// Color used in place of NaN or infinite color channels in the output.
+    /// Defaults to magenta in debug builds, so that numerical problems stand out, and to black otherwise.
+    pub invalid_color: Vec3,
+    /// Part of the lighting to render, for debugging where the light (or noise) comes from.
+    pub lighting: LightingComponent,
+    /// Whether diffuse surfaces should sample the background directly as a light,
+    /// which reduces noise in scenes lit mostly by the background.
+    pub sample_background: bool,
+    /// Whether diffuse surfaces should sample one of the lights directly with a shadow ray (see `Scene::pick_light`),
+    /// instead of only finding them by chance, which reduces noise from small lights.
+    pub sample_lights: bool,
+    /// How the light sampled by `sample_lights` is chosen.
+    pub light_selection: LightSelection,
+    /// Whether the alpha channel of the output should be the fraction of primary rays hitting geometry,
+    /// instead of being always opaque.
+    pub alpha_coverage: bool,
+    /// Operator mapping the rendered linear colors into the displayable range.
+    pub tone_mapping: ToneMapping,
+    /// Transfer function encoding the tone mapped colors into PNG files (and the terminal preview).
+    /// PFM files always store the linear colors.
+    pub png_transfer: Transfer,
+    /// Base seed of the random number generators. Rendering the same scene
+    /// with the same settings and seed produces the same image.
+    pub seed: u64,
+    /// Number of progressive passes, each taking `pixel_samples` samples per pixel.
+    /// The final image is the average of all the passes.
+    pub passes: u32,
+    /// Number of passes after which the accumulated result is saved to the checkpoint file
+    /// (when one is given), or 0 to never save it.
+    pub checkpoint_interval: u32,
+    /// Whether primary rays should only be tested against the objects inside the view of the camera.
+    pub frustum_culling: bool,
+    /// Constant light added to every diffuse surface, for quick previews without any lights.
+    /// It is not physically based, and black (no light) by default.
+    pub ambient: Vec3,
+    /// Whether a progressive render should start with a quick, noisy preview of the whole image,
+    /// taking a single sample per pixel. The preview is only for display, and does not change the final image.
+    pub preview: bool,
+    /// Whether to sum the samples of each pixel, and the passes of progressive renders, in double precision.
+    /// Slower and takes more memory, but keeps the average from drifting at very high sample counts.
+    pub double_precision: bool,
+    /// Number of rays testing the ambient occlusion at each diffuse hit, or 0 to render with global illumination.
+    /// When set, diffuse surfaces are shaded by their albedo times the fraction of the rays not blocked
+    /// within `ao_radius`, without tracing any further bounces. Much faster, but not physically based.
+    pub ao_samples: u32,
+    /// Distance within which geometry blocks the ambient occlusion rays.
+    pub ao_radius: f32,
+    /// Number of bits per channel of the saved PNG images. 16 bits avoid banding in smooth gradients.
+    pub bit_depth: BitDepth,
+    /// Number of paths traced from the lights per pixel in each pass, or 0 to only trace paths from the camera.
+    /// Light paths reaching a diffuse surface visible from the camera through glass or mirrors are added
+    /// to the image where the camera sees the surface, which resolves caustics much faster. The paths
+    /// from the camera then leave these caustics out, so that they are not counted twice.
+    pub light_samples: u32,
+    /// Order in which the threads take the tiles of the image to render. It does not change the final image.
+    pub tile_order: TileOrder,
+    /// Ids of the only objects to render, as in `Hit::object_id`, or `None` to render all of them.
+    /// The other objects are hidden from all rays, so they do not cast shadows or show up in reflections either.
+    pub solo: Option<Vec<usize>>,
+    /// Whether renders taking a single sample per pixel should sample the exact center of each pixel,
+    /// instead of a random position within it, so that the image does not depend on the seed.
+    /// Renders with more samples per pixel spread them over the pixel either way. Note that progressive
+    /// passes of a single sample then all sample the centers, without any antialiasing.
+    pub center_single_sample: bool,
+    /// Mean relative variance of the pixels (see `Convergence::relative_variance`) below which a progressive
+    /// render stops, even if not all the passes are done yet, or `None` to always render all the passes.
+    pub convergence_threshold: Option<f32>,
+    /// Whether to print the relative variance of the image (see `convergence_threshold`) after every progressive pass,
+    /// the number of passes it took to converge, and the checkpoints resumed or ignored as of a different render,
+    /// all to the standard error. Checkpoints that cannot be read or saved are always reported.
+    pub verbose: bool,
+    /// Number of standard deviations above the running mean brightness of a pixel's samples beyond which
+    /// a sample is left out of the pixel's average as a firefly, or `None` to keep all the samples.
+    /// Unlike clamping, this keeps bright pixels that are bright in most of their samples, but it still
+    /// darkens rare, legitimate highlights, such as small caustics.
+    pub outlier_sigma: Option<f32>,
+    /// Trace a single, random wavelength of light along each path instead of RGB colors, converting the colors
+    /// of materials and lights to spectra, which makes saturated colors under colored light more accurate
+    /// and splits light refracted by glass into colors. Noisier than RGB rendering with the same number of samples.
+    /// Caustics rendered by light tracing (see `light_samples`) stay in RGB.
+    pub spectral: bool,
+    /// Number of bounces after which paths are ended at random (Russian roulette), continuing with the probability
+    /// of the largest channel of the albedo of the surface they hit, or `None` to only end them at `max_depth`.
+    /// Paths off dark surfaces, which carry little light, end sooner; the surviving paths are weighted up to make up
+    /// for the ended ones, so the image stays the same on average, only with more noise.
+    pub russian_roulette: Option<u32>,
+    /// Max brightness (of the brightest channel) of the light that surfaces reached after one or more bounces
+    /// scatter back along the path, or `None` to not limit it. This tames fireflies of indirect lighting,
+    /// while lights and the light they shine directly onto surfaces stay as bright as they are. Darkens
+    /// the image where indirect light is legitimately bright, such as in caustics.
+    pub indirect_clamp: Option<f32>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 512,
+            height: 512,
+            pixel_samples: 128,
+            max_depth: 8,
+            max_refraction_depth: None,
+            num_threads: 16,
+            sampling: SamplingMode::Random,
+            filter_textures: false,
+            flip_u: false,
+            flip_v: false,
+            invalid_color: if cfg!(debug_assertions) { Vec3::new(1.0, 0.0, 1.0) } else { Vec3::new(0.0, 0.0, 0.0) },
+            lighting: LightingComponent::All,
+            sample_background: false,
+            sample_lights: false,
+            light_selection: LightSelection::Power,
+            alpha_coverage: false,
+            tone_mapping: ToneMapping::Clamp,
+            png_transfer: Transfer::Gamma2,
+            seed: 0,
+            passes: 1,
+            checkpoint_interval: 0,
+            frustum_culling: false,
+            ambient: Vec3::new(0.0, 0.0, 0.0),
+            preview: false,
+            double_precision: false,
+            ao_samples: 0,
+            ao_radius: 1.0,
+            bit_depth: BitDepth::Eight,
+            light_samples: 0,
+            tile_order: TileOrder::Scanline,
+            solo: None,
+            center_single_sample: false,
+            convergence_threshold: None,
+            verbose: false,
+            outlier_sigma: None,
+            spectral: false,
+            russian_roulette: None,
+            indirect_clamp: None,
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use super::vec3::Vec3;
+
+/// Shortest wavelength of light traced by spectral rendering, in nanometers.
+pub const WAVELENGTH_MIN: f32 = 380.0;
+/// Longest wavelength of light traced by spectral rendering, in nanometers.
+pub const WAVELENGTH_MAX: f32 = 720.0;
+
+// Wavelength of the sodium D line, at which indices of refraction are usually given, in nanometers.
+const IOR_WAVELENGTH: f32 = 589.3;
+
+// Coefficient B of Cauchy's equation, n = A + B / wavelength^2, for a typical crown glass, in square nanometers.
+const CAUCHY_B: f32 = 4200.0;
+
+// Scale of each color channel making the average of `wavelength_to_rgb` over the spectrum white,
+// (1.0, 1.0, 1.0), instead of the slightly reddish color of a spectrum constant at 1.0.
+const WHITE_BALANCE: [f32; 3] = [2.648793, 3.348495, 3.503349];
+
+// Spectra of Smits' conversion from RGB, in 10 equally wide bins from `WAVELENGTH_MIN` to `WAVELENGTH_MAX`.
+const SMITS_WHITE: [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// Gaussian with different widths on either side of its peak.
+#[inline(always)]
+fn gaussian(x: f32, mu: f32, sigma1: f32, sigma2: f32) -> f32 {
+    let t = (x - mu) / if x < mu { sigma1 } else { sigma2 };
+    (-0.5 * t * t).exp()
+}
+
+/// Map a random number to a wavelength, uniformly distributed over the traced spectrum.
+///
+/// # Arguments
+///
+/// * `u` - Random number, in the range of 0.0 to 1.0.
+#[inline(always)]
+pub fn sample_wavelength(u: f32) -> f32 {
+    WAVELENGTH_MIN + u * (WAVELENGTH_MAX - WAVELENGTH_MIN)
+}
+
+/// Evaluate the CIE 1931 color matching functions, using the analytic, multi-lobe fit
+/// of Wyman, Sloan, and Shirley.
+///
+/// # Arguments
+///
+/// * `lambda` - Wavelength, in nanometers.
+///
+/// # Returns
+///
+/// The X, Y, and Z responses to light of given wavelength.
+pub fn xyz_matching(lambda: f32) -> Vec3 {
+    Vec3::new(
+        1.056 * gaussian(lambda, 599.8, 37.9, 31.0) + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7) - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2),
+        0.821 * gaussian(lambda, 568.8, 46.9, 40.5) + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1),
+        1.217 * gaussian(lambda, 437.0, 11.8, 36.0) + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8),
+    )
+}
+
+/// Compute the linear sRGB color that a single sample of a path carrying light of given wavelength adds to a pixel.
+///
+/// The color is scaled so that the average over uniformly distributed wavelengths (see `sample_wavelength`) is
+/// white, (1.0, 1.0, 1.0), for a spectrum constant at 1.0. Saturated wavelengths are outside of the sRGB gamut,
+/// so some channels may be negative; they average out with the other samples of the pixel.
+///
+/// # Arguments
+///
+/// * `lambda` - Wavelength, in nanometers.
+pub fn wavelength_to_rgb(lambda: f32) -> Vec3 {
+    let xyz = xyz_matching(lambda);
+    Vec3::new(
+        WHITE_BALANCE[0] * (3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z),
+        WHITE_BALANCE[1] * (-0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z),
+        WHITE_BALANCE[2] * (0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z),
+    )
+}
+
+/// Evaluate a smooth spectrum with given RGB color at given wavelength, using Smits' conversion: the color is split
+/// into white, plus a secondary color (cyan, magenta, or yellow), plus a primary color (red, green, or blue), each
+/// with a precomputed spectrum. Reflectances between 0.0 and 1.0 give spectra (nearly) between 0.0 and 1.0.
+///
+/// # Arguments
+///
+/// * `c` - Linear RGB color, such as an albedo or the color of a light.
+/// * `lambda` - Wavelength, in nanometers.
+pub fn rgb_to_spectrum(c: &Vec3, lambda: f32) -> f32 {
+    let bin = (((lambda - WAVELENGTH_MIN) / (WAVELENGTH_MAX - WAVELENGTH_MIN) * 10.0) as usize).min(9);
+    let (r, g, b) = (c.x, c.y, c.z);
+    if r <= g && r <= b {
+        let secondary = if g <= b { (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin] } else { (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin] };
+        r * SMITS_WHITE[bin] + secondary
+    } else if g <= r && g <= b {
+        let secondary = if r <= b { (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin] } else { (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin] };
+        g * SMITS_WHITE[bin] + secondary
+    } else {
+        let secondary = if r <= g { (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin] } else { (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin] };
+        b * SMITS_WHITE[bin] + secondary
+    }
+}
+
+/// Compute the index of refraction of glass at given wavelength, using Cauchy's equation, so that shorter
+/// wavelengths bend more (dispersion).
+///
+/// # Arguments
+///
+/// * `ior` - Index of refraction at the wavelength of the sodium D line (589.3 nm), as usually given.
+/// * `lambda` - Wavelength, in nanometers.
+#[inline(always)]
+pub fn dispersed_ior(ior: f32, lambda: f32) -> f32 {
+    ior + CAUCHY_B * (1.0 / (lambda * lambda) - 1.0 / (IOR_WAVELENGTH * IOR_WAVELENGTH))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Average the color of a spectrum over regularly spaced wavelengths.
+    fn spectrum_to_rgb(spectrum: impl Fn(f32) -> f32) -> Vec3 {
+        let n = 3400;
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let lambda = sample_wavelength((i as f32 + 0.5) / n as f32);
+            sum += &(spectrum(lambda) * &wavelength_to_rgb(lambda));
+        }
+        (1.0 / n as f32) * &sum
+    }
+
+    #[test]
+    fn constant_spectrum_is_white() {
+        let white = spectrum_to_rgb(|_| 1.0);
+        assert!((white.x - 1.0).abs() < 1e-3); assert!((white.y - 1.0).abs() < 1e-3); assert!((white.z - 1.0).abs() < 1e-3);
+        // The green channel peaks in the middle of the spectrum, and the blue one at its short end.
+        let green = wavelength_to_rgb(540.0);
+        assert!(green.y > green.x && green.y > green.z);
+        let blue = wavelength_to_rgb(450.0);
+        assert!(blue.z > blue.x && blue.z > blue.y);
+    }
+
+    #[test]
+    fn rgb_round_trip() {
+        for c in &[Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.8, 0.5, 0.2), Vec3::new(0.2, 0.3, 0.9), Vec3::new(4.0, 4.0, 4.0)] {
+            let rgb = spectrum_to_rgb(|lambda| rgb_to_spectrum(c, lambda));
+            assert!((rgb.x - c.x).abs() < 0.02 * c.x.max(1.0));
+            assert!((rgb.y - c.y).abs() < 0.02 * c.y.max(1.0));
+            assert!((rgb.z - c.z).abs() < 0.02 * c.z.max(1.0));
+        }
+        // Reflectances stay (nearly) in range, and gray is flat.
+        let c = Vec3::new(0.9, 0.1, 0.5);
+        assert!((0..34).map(|i| rgb_to_spectrum(&c, 385.0 + 10.0 * i as f32)).all(|r| (0.0..=1.0).contains(&r)));
+        assert!((0..34).map(|i| rgb_to_spectrum(&Vec3::new(0.5, 0.5, 0.5), 385.0 + 10.0 * i as f32)).all(|r| (r - 0.5).abs() < 1e-3));
+    }
+
+    #[test]
+    fn dispersion() {
+        assert_eq!(dispersed_ior(1.5, IOR_WAVELENGTH), 1.5);
+        assert!(dispersed_ior(1.5, 400.0) > 1.51);
+        assert!(dispersed_ior(1.5, 700.0) < 1.5);
+    }
+}
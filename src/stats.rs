@@ -0,0 +1,113 @@
+use std::fmt;
+
+/// Statistics collected while rendering.
+#[derive(Debug, Clone)]
+pub struct RenderStats {
+    /// Number of paths that terminated at each depth, from 0 up to (and including) the max depth.
+    pub path_depths: Vec<u64>,
+    /// Number of pixels whose color had NaN or infinite values.
+    pub invalid_pixels: u64,
+    /// Number of times a ray hitting glass was reflected because it could not refract.
+    pub total_internal_reflections: u64,
+    /// Number of samples left out of the pixel averages as outliers (see `RenderSettings::outlier_sigma`).
+    pub rejected_samples: u64,
+    /// Mean relative variance of the pixels of a progressive render after the last pass, as computed by
+    /// `Convergence::relative_variance`, or `None` if it could not be estimated.
+    pub relative_variance: Option<f32>,
+}
+
+impl RenderStats {
+    /// Create new, empty statistics.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_depth` - Max recursion depth of the rendered paths.
+    pub fn new(max_depth: u32) -> RenderStats {
+        RenderStats {
+            path_depths: vec![0; max_depth as usize + 1],
+            invalid_pixels: 0,
+            total_internal_reflections: 0,
+            rejected_samples: 0,
+            relative_variance: None,
+        }
+    }
+
+    /// Record a path that terminated at given depth.
+    #[inline(always)]
+    pub fn record_path_depth(&mut self, depth: u32) {
+        self.path_depths[depth as usize] += 1;
+    }
+
+    /// Get total number of recorded paths.
+    pub fn total_paths(&self) -> u64 {
+        self.path_depths.iter().sum()
+    }
+
+    /// Add statistics collected elsewhere (for example, in another thread) to this one.
+    pub fn merge(&mut self, stats: &RenderStats) {
+        for (count, other) in self.path_depths.iter_mut().zip(stats.path_depths.iter()) {
+            *count += other;
+        }
+        self.invalid_pixels += stats.invalid_pixels;
+        self.total_internal_reflections += stats.total_internal_reflections;
+        self.rejected_samples += stats.rejected_samples;
+        // The latest estimate, from a later pass, replaces the earlier one.
+        if stats.relative_variance.is_some() {
+            self.relative_variance = stats.relative_variance;
+        }
+    }
+}
+
+impl fmt::Display for RenderStats {
+    /// Print the statistics as a summary table.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total_paths().max(1) as f64;
+        writeln!(f, "{:>6} {:>12} {:>8}", "depth", "paths", "%")?;
+        for (depth, count) in self.path_depths.iter().enumerate() {
+            writeln!(f, "{:>6} {:>12} {:>7.2}%", depth, count, 100.0 * *count as f64 / total)?;
+        }
+        writeln!(f, "invalid pixels: {}", self.invalid_pixels)?;
+        writeln!(f, "total internal reflections: {}", self.total_internal_reflections)?;
+        if self.rejected_samples > 0 {
+            writeln!(f, "rejected samples: {}", self.rejected_samples)?;
+        }
+        if let Some(relative_variance) = self.relative_variance {
+            writeln!(f, "relative variance: {:.3e}", relative_variance)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_depths() {
+        let mut stats = RenderStats::new(2);
+        stats.record_path_depth(0);
+        stats.record_path_depth(2);
+        stats.record_path_depth(2);
+        assert_eq!(stats.path_depths, vec![1, 0, 2]);
+        assert_eq!(stats.total_paths(), 3);
+    }
+
+    #[test]
+    fn merge_stats() {
+        let mut stats1 = RenderStats::new(2);
+        stats1.record_path_depth(1);
+        let mut stats2 = RenderStats::new(2);
+        stats2.record_path_depth(1);
+        stats2.record_path_depth(2);
+        stats2.invalid_pixels = 3;
+        stats2.total_internal_reflections = 5;
+        stats2.rejected_samples = 7;
+        stats2.relative_variance = Some(0.5);
+        stats1.merge(&stats2);
+        assert_eq!(stats1.rejected_samples, 7);
+        assert_eq!(stats1.relative_variance, Some(0.5));
+        assert_eq!(stats1.path_depths, vec![0, 2, 1]);
+        assert_eq!(stats1.invalid_pixels, 3);
+        assert_eq!(stats1.total_internal_reflections, 5);
+    }
+}
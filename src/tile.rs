@@ -0,0 +1,187 @@
+/// Rectangular region of the image, from the min (inclusive) to the max (exclusive) pixel coordinates.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile {
+    pub min_x: u32,
+    pub min_y: u32,
+    pub max_x: u32,
+    pub max_y: u32,
+}
+
+impl Tile {
+    pub fn new(min_x: u32, min_y: u32, max_x: u32, max_y: u32) -> Tile {
+        Tile { min_x, min_y, max_x, max_y }
+    }
+
+    /// Get number of pixels in the tile.
+    pub fn pixel_count(&self) -> u32 {
+        (self.max_x - self.min_x) * (self.max_y - self.min_y)
+    }
+}
+
+/// Order in which the tiles of an image are rendered.
+///
+/// The order only changes how the image appears while it is being rendered, not the final image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TileOrder {
+    /// Row by row, from the top left corner.
+    Scanline,
+    /// Around the center of the image, spiraling outwards, so that the usually most interesting part is rendered first.
+    Spiral,
+    /// Along the Hilbert curve, which keeps consecutive tiles next to each other.
+    Hilbert,
+}
+
+/// Iterator over tiles covering an image, row by row, or in another order.
+///
+/// Tiles on the right and bottom edges are cropped to the image.
+pub struct TileIterator {
+    tiles: std::vec::IntoIter<Tile>,
+}
+
+impl TileIterator {
+    /// Create new iterator, going row by row.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - Image width.
+    /// * `height` - Image height.
+    /// * `tile_width` - Width of the tiles (at least 1).
+    /// * `tile_height` - Height of the tiles (at least 1).
+    pub fn new(width: u32, height: u32, tile_width: u32, tile_height: u32) -> TileIterator {
+        TileIterator::with_order(width, height, tile_width, tile_height, TileOrder::Scanline)
+    }
+
+    /// Create new iterator, going in given order.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - Image width.
+    /// * `height` - Image height.
+    /// * `tile_width` - Width of the tiles (at least 1).
+    /// * `tile_height` - Height of the tiles (at least 1).
+    /// * `order` - Order of the tiles.
+    pub fn with_order(width: u32, height: u32, tile_width: u32, tile_height: u32, order: TileOrder) -> TileIterator {
+        let (tile_width, tile_height) = (tile_width.max(1), tile_height.max(1));
+        let (columns, rows) = (width.div_ceil(tile_width), height.div_ceil(tile_height));
+        let mut cells: Vec<(u32, u32)> = (0..rows).flat_map(|row| (0..columns).map(move |column| (column, row))).collect();
+        match order {
+            TileOrder::Scanline => {},
+            TileOrder::Spiral => {
+                // Rings of tiles around the center, each one going around by the angle.
+                let (center_x, center_y) = (0.5 * (columns as f32 - 1.0), 0.5 * (rows as f32 - 1.0));
+                let key = |&(column, row): &(u32, u32)| {
+                    let (dx, dy) = (column as f32 - center_x, row as f32 - center_y);
+                    (dx.abs().max(dy.abs()), dy.atan2(dx))
+                };
+                cells.sort_by(|a, b| {
+                    let (ka, kb) = (key(a), key(b));
+                    ka.0.total_cmp(&kb.0).then(ka.1.total_cmp(&kb.1))
+                });
+            },
+            TileOrder::Hilbert => {
+                let size = columns.max(rows).next_power_of_two();
+                cells.sort_by_key(|&(column, row)| hilbert_index(size, column, row));
+            },
+        }
+        let tiles: Vec<Tile> = cells.into_iter()
+            .map(|(column, row)| {
+                let (x, y) = (column * tile_width, row * tile_height);
+                Tile::new(x, y, (x + tile_width).min(width), (y + tile_height).min(height))
+            })
+            .collect();
+        TileIterator { tiles: tiles.into_iter() }
+    }
+}
+
+impl Iterator for TileIterator {
+    type Item = Tile;
+
+    fn next(&mut self) -> Option<Tile> {
+        self.tiles.next()
+    }
+}
+
+// Distance along the Hilbert curve filling a square grid of given size (a power of two) to given cell.
+fn hilbert_index(size: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0;
+    let mut s = size / 2;
+    while s > 0 {
+        let (rx, ry) = ((x & s) > 0, (y & s) > 0);
+        index += (s as u64) * (s as u64) * ((3 * rx as u64) ^ ry as u64);
+        // Rotate the quadrant, so that the curve in it starts and ends next to the neighboring quadrants.
+        if !ry {
+            if rx {
+                x = size - 1 - x;
+                y = size - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_partition_image() {
+        for &(width, height, tile_width, tile_height) in &[(16, 16, 4, 4), (17, 9, 4, 5), (5, 3, 8, 8), (7, 1, 1, 1)] {
+            let mut covered = vec![0; (width * height) as usize];
+            for tile in TileIterator::new(width, height, tile_width, tile_height) {
+                assert!(tile.min_x < tile.max_x && tile.max_x <= width);
+                assert!(tile.min_y < tile.max_y && tile.max_y <= height);
+                for y in tile.min_y..tile.max_y {
+                    for x in tile.min_x..tile.max_x {
+                        covered[(y * width + x) as usize] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|c| *c == 1));
+        }
+    }
+
+    #[test]
+    fn edge_tiles() {
+        let tiles: Vec<Tile> = TileIterator::new(10, 6, 4, 4).collect();
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[2], Tile::new(8, 0, 10, 4));
+        assert_eq!(tiles[5], Tile::new(8, 4, 10, 6));
+        assert_eq!(TileIterator::new(0, 6, 4, 4).count(), 0);
+    }
+
+    #[test]
+    fn orders_cover_image_once() {
+        for &(width, height, tile_width, tile_height) in &[(16, 16, 4, 4), (17, 9, 4, 5), (5, 3, 8, 8), (7, 1, 1, 1), (30, 20, 3, 3)] {
+            let key = |t: &Tile| (t.min_y, t.min_x);
+            let mut scanline: Vec<Tile> = TileIterator::new(width, height, tile_width, tile_height).collect();
+            scanline.sort_by_key(key);
+            for &order in &[TileOrder::Spiral, TileOrder::Hilbert] {
+                let mut tiles: Vec<Tile> = TileIterator::with_order(width, height, tile_width, tile_height, order).collect();
+                tiles.sort_by_key(key);
+                assert_eq!(tiles, scanline);
+            }
+        }
+        assert_eq!(TileIterator::with_order(0, 6, 4, 4, TileOrder::Spiral).count(), 0);
+    }
+
+    #[test]
+    fn spiral_starts_in_center() {
+        let tiles: Vec<Tile> = TileIterator::with_order(20, 12, 4, 4, TileOrder::Spiral).collect();
+        assert_eq!(tiles[0], Tile::new(8, 4, 12, 8));
+        // The tiles around the center come before the outer ones.
+        let ring = |t: &Tile| (t.min_x as i32 / 4 - 2).abs().max((t.min_y as i32 / 4 - 1).abs());
+        assert!(tiles.windows(2).all(|w| ring(&w[0]) <= ring(&w[1])));
+    }
+
+    #[test]
+    fn hilbert_tiles_are_adjacent() {
+        let tiles: Vec<Tile> = TileIterator::with_order(32, 32, 4, 4, TileOrder::Hilbert).collect();
+        assert_eq!(tiles[0], Tile::new(0, 0, 4, 4));
+        for w in tiles.windows(2) {
+            let (dx, dy) = ((w[0].min_x as i32 - w[1].min_x as i32).abs(), (w[0].min_y as i32 - w[1].min_y as i32).abs());
+            assert_eq!(dx + dy, 4);
+        }
+    }
+}
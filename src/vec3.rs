@@ -1,6 +1,11 @@
 use std::ops;
+use super::math::{ Degrees, deg_to_rad, f0_from_ior };
 
-#[derive(Debug, Copy, Clone)]
+// Vectors shorter than this have no reliable direction, as their squared length loses precision
+// or underflows to zero, so `normalize` leaves them as they are instead of producing NaNs.
+const MIN_NORMALIZE_LENGTH: f32 = 1e-18;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -16,15 +21,61 @@ impl Vec3 {
         Vec3 { x, y, z }
     }
 
+    /// Compute new vector with absolute values of all components.
+    #[inline(always)]
+    pub fn abs(&self) -> Vec3 {
+        Vec3::new(self.x.abs(), self.y.abs(), self.z.abs())
+    }
+
+    /// Scale the vector to unit length. Zero (or nearly zero) vectors, which have no direction, are left unchanged.
     #[inline(always)]
     pub fn normalize(&mut self) {
         let len = length(self);
-        //debug_assert_ne!(len, 0.0);
+        if len < MIN_NORMALIZE_LENGTH {
+            return;
+        }
         let inv_len = 1.0 / len;
         self.x *= inv_len;
         self.y *= inv_len;
         self.z *= inv_len;
     }
+
+    /// Compute new vector rotated around an axis, using the Rodrigues' rotation formula.
+    ///
+    /// # Arguments
+    ///
+    /// * `axis` - Rotation axis (does not need to be normalized).
+    /// * `angle` - Rotation angle in degrees, counter-clockwise when looking against the axis.
+    #[inline(always)]
+    pub fn rotate_around(&self, axis: &Vec3, angle: Degrees) -> Vec3 {
+        let k = normalize(axis);
+        let (sin, cos) = deg_to_rad(angle).sin_cos();
+        let mut v = cos * self;
+        v += &(sin * &cross(&k, self));
+        v += &(dot(&k, self) * (1.0 - cos) * &k);
+        v
+    }
+
+    /// Encode the vector as its X, Y, and Z components, each in the little-endian byte order.
+    #[inline(always)]
+    pub fn to_le_bytes(self) -> [u8; 12] {
+        let mut bytes = [0u8; 12];
+        bytes[0..4].copy_from_slice(&self.x.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.y.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.z.to_le_bytes());
+        bytes
+    }
+
+    /// Decode a vector encoded by `to_le_bytes`.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - X, Y, and Z components, each in the little-endian byte order.
+    #[inline(always)]
+    pub fn from_le_bytes(bytes: [u8; 12]) -> Vec3 {
+        let component = |i: usize| f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Vec3::new(component(0), component(4), component(8))
+    }
 }
 
 #[inline(always)]
@@ -59,9 +110,13 @@ pub fn distance(v1: &Vec3, v2: &Vec3) -> f32 {
     (dx * dx + dy * dy + dz * dz).sqrt()
 }
 
+/// Compute the vector scaled to unit length. Zero (or nearly zero) vectors, which have no direction, are returned unchanged.
 #[inline(always)]
 pub fn normalize(v: &Vec3) -> Vec3 {
     let len = length(v);
+    if len < MIN_NORMALIZE_LENGTH {
+        return *v;
+    }
     (1.0 / len) * v
 }
 
@@ -94,6 +149,30 @@ pub fn refract(v: &Vec3, n: &Vec3, ni_over_nt: f32) -> Option<Vec3> {
     }
 }
 
+/// Approximate the Fresnel reflectance of a dielectric surface using Schlick's formula.
+///
+/// The formula is evaluated with the angle on the side of the optically less dense medium, so when the ray
+/// comes from the denser one (leaving glass, or entering an air bubble in water), the reflectance rises
+/// smoothly to 1 at the critical angle, beyond which all of the light is reflected.
+///
+/// # Arguments
+///
+/// * `cos_theta` - Cosine of the angle between the incoming direction and the surface normal.
+/// * `refraction_ratio` - Ratio of the index of refraction on the incoming side to the one on the other side.
+#[inline(always)]
+pub fn reflectance(cos_theta: f32, refraction_ratio: f32) -> f32 {
+    let mut cos = cos_theta;
+    if refraction_ratio > 1.0 {
+        let sin2_t = refraction_ratio * refraction_ratio * (1.0 - cos_theta * cos_theta);
+        if sin2_t >= 1.0 {
+            return 1.0;
+        }
+        cos = (1.0 - sin2_t).sqrt();
+    }
+    let r0 = f0_from_ior(refraction_ratio);
+    r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+}
+
 impl ops::Neg for &Vec3 {
     type Output = Vec3;
 
@@ -156,3 +235,106 @@ impl ops::MulAssign<f32> for Vec3 {
         self.z *= s;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_product() {
+        let v1 = Vec3::new(1.0, 2.0, 3.0);
+        let v2 = Vec3::new(-2.0, 0.5, 4.0);
+        assert_eq!(dot(&v1, &v2), 11.0);
+        assert_eq!(dot(&v1, &v2), dot(&v2, &v1));
+        assert_eq!(dot(&v1, &v1), length_squared(&v1));
+    }
+
+    #[test]
+    fn cross_product() {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let z = cross(&x, &y);
+        assert_eq!(z.x, 0.0); assert_eq!(z.y, 0.0); assert_eq!(z.z, 1.0);
+        let v1 = Vec3::new(1.0, 2.0, 3.0);
+        let v2 = Vec3::new(-2.0, 0.5, 4.0);
+        let c = cross(&v1, &v2);
+        assert_eq!(dot(&c, &v1), 0.0); assert_eq!(dot(&c, &v2), 0.0);
+        let c2 = cross(&v2, &v1);
+        assert_eq!(c.x, -c2.x); assert_eq!(c.y, -c2.y); assert_eq!(c.z, -c2.z);
+    }
+
+    #[test]
+    fn normalize_zero() {
+        let v = normalize(&Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(v.x, 0.0); assert_eq!(v.y, 0.0); assert_eq!(v.z, 0.0);
+        // Too short to square without underflow.
+        let v = normalize(&Vec3::new(1e-30, -1e-30, 0.0));
+        assert_eq!(v.x, 1e-30); assert_eq!(v.y, -1e-30); assert_eq!(v.z, 0.0);
+        let mut v = Vec3::new(0.0, 0.0, 0.0);
+        v.normalize();
+        assert_eq!(v.x, 0.0); assert_eq!(v.y, 0.0); assert_eq!(v.z, 0.0);
+        // Short, but still with a reliable direction.
+        let v = normalize(&Vec3::new(0.0, 3e-10, -4e-10));
+        assert!((v.y - 0.6).abs() < 1e-6); assert!((v.z + 0.8).abs() < 1e-6);
+        let mut v = Vec3::new(1e-12, 0.0, 0.0);
+        v.normalize();
+        assert_eq!(v.x, 1.0); assert_eq!(v.y, 0.0); assert_eq!(v.z, 0.0);
+    }
+
+    #[test]
+    fn absolute_value() {
+        let v = Vec3::new(-1.5, 0.0, 2.0).abs();
+        assert_eq!(v.x, 1.5); assert_eq!(v.y, 0.0); assert_eq!(v.z, 2.0);
+        let v = Vec3::new(-0.0, -3.0, f32::NEG_INFINITY).abs();
+        assert_eq!(v.x, 0.0); assert_eq!(v.y, 3.0); assert_eq!(v.z, f32::INFINITY);
+    }
+
+    #[test]
+    fn schlick_reflectance() {
+        assert!((reflectance(1.0, 1.5) - 0.04).abs() < 1e-6);
+        assert!((reflectance(1.0, 1.0 / 1.5) - 0.04).abs() < 1e-6);
+        assert_eq!(reflectance(0.0, 1.5), 1.0);
+    }
+
+    #[test]
+    fn reflectance_from_denser_medium() {
+        // Air bubble in water, entered at the critical angle and beyond.
+        let ratio = 1.33;
+        let cos_critical = (1.0f32 - 1.0 / (ratio * ratio)).sqrt();
+        assert_eq!(reflectance(cos_critical - 1e-3, ratio), 1.0);
+        assert!(reflectance(cos_critical + 1e-4, ratio) > 0.9);
+        assert!(reflectance(cos_critical + 0.1, ratio) < reflectance(cos_critical + 0.01, ratio));
+        // Light takes the same path in both directions, and is reflected the same way.
+        for &cos_i in &[1.0f32, 0.9, 0.5, 0.1] {
+            let cos_t = (1.0 - (1.0 - cos_i * cos_i) / (ratio * ratio)).sqrt();
+            assert!((reflectance(cos_i, 1.0 / ratio) - reflectance(cos_t, ratio)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn rotate_around_axis() {
+        let v = Vec3::new(1.0, 0.0, 0.0).rotate_around(&Vec3::new(0.0, 0.0, 1.0), 90.0);
+        assert!(v.x.abs() < 1e-6); assert!((v.y - 1.0).abs() < 1e-6); assert!(v.z.abs() < 1e-6);
+        // Unnormalized axis, with the component along the axis kept as is.
+        let v = Vec3::new(1.0, 2.0, 3.0).rotate_around(&Vec3::new(0.0, 2.0, 0.0), 180.0);
+        assert!((v.x + 1.0).abs() < 1e-6); assert!((v.y - 2.0).abs() < 1e-6); assert!((v.z + 3.0).abs() < 1e-6);
+        let v = Vec3::new(0.0, 1.0, 0.0).rotate_around(&Vec3::new(1.0, 0.0, 0.0), -90.0);
+        assert!(v.x.abs() < 1e-6); assert!(v.y.abs() < 1e-6); assert!((v.z + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn le_bytes_round_trip() {
+        let v = Vec3::new(1.0, -2.5, 0.0);
+        let bytes = v.to_le_bytes();
+        assert_eq!(&bytes[0..4], &[0x00, 0x00, 0x80, 0x3f]);
+        assert_eq!(&bytes[4..8], &[0x00, 0x00, 0x20, 0xc0]);
+        assert_eq!(&bytes[8..12], &[0x00, 0x00, 0x00, 0x00]);
+        let v = Vec3::from_le_bytes(bytes);
+        assert_eq!(v.x, 1.0); assert_eq!(v.y, -2.5); assert_eq!(v.z, 0.0);
+        // Special values keep their exact bits.
+        for &(x, y, z) in &[(f32::INFINITY, f32::NEG_INFINITY, f32::NAN), (-0.0, f32::MIN_POSITIVE / 2.0, f32::MAX), (f32::MIN, f32::EPSILON, -1e-30)] {
+            let v = Vec3::from_le_bytes(Vec3::new(x, y, z).to_le_bytes());
+            assert_eq!(v.x.to_bits(), x.to_bits()); assert_eq!(v.y.to_bits(), y.to_bits()); assert_eq!(v.z.to_bits(), z.to_bits());
+        }
+    }
+}
@@ -4,6 +4,7 @@ use super::vec3::{ Vec3, normalize, cross };
 use super::mat4::{ Mat4, inverse, transpose };
 use super::ray::Ray;
 use super::bbox::BBox;
+use super::quat::Quat;
 
 /// Affine transform represented by a 4x4 matrix,
 /// also storing the inverse of the matrix.
@@ -33,6 +34,38 @@ impl Transform {
         Transform { matrix, inverse, swaps_handedness }
     }
 
+    /// Create new identity transform.
+    #[inline(always)]
+    pub fn identity() -> Transform {
+        Transform::new(Mat4::identity(), Mat4::identity())
+    }
+
+    /// Create new transform by combining a list of transforms,
+    /// where the rightmost transform is applied first.
+    ///
+    /// # Arguments
+    ///
+    /// * `transforms` - Transforms to combine. An empty list results in an identity transform.
+    pub fn compose(transforms: &[Transform]) -> Transform {
+        transforms.iter().fold(Transform::identity(), |acc, xform| &acc * xform)
+    }
+
+    /// Create new transform that scales, then rotates, and then translates.
+    ///
+    /// # Arguments
+    ///
+    /// * `translate` - Translation.
+    /// * `rotation` - Rotation, as a normalized quaternion.
+    /// * `scale` - Scale along each axis.
+    pub fn from_trs(translate: &Vec3, rotation: &Quat, scale: &Vec3) -> Transform {
+        let r = rotation.to_mat4();
+        Transform::compose(&[
+            Transform::translate(translate.x, translate.y, translate.z),
+            Transform::new(r, transpose(&r)),
+            Transform::scale(scale.x, scale.y, scale.z),
+        ])
+    }
+
     /// Create new transform translating by given delta.
     ///
     /// # Arguments
@@ -289,6 +322,36 @@ impl Transform {
         new_bbox
     }
 
+    /// Transform the bounding box of a sphere, which gives the bounding box of the resulting ellipsoid.
+    ///
+    /// Unlike transforming the corners of the sphere's bounding box (see `apply_to_bbox`), which gets loose
+    /// when the transform rotates the sphere, the result is exact for any affine transform.
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - Center of the sphere.
+    /// * `r` - Radius of the sphere.
+    #[inline(always)]
+    pub fn apply_to_sphere_bbox(&self, c: &Vec3, r: f32) -> BBox {
+        // Along each axis, the ellipsoid reaches as far from its center as the radius times the length
+        // of the corresponding row of the matrix.
+        let m = &self.matrix;
+        let r = r.abs();
+        let extent = Vec3::new(
+            r * (m.m00 * m.m00 + m.m01 * m.m01 + m.m02 * m.m02).sqrt(),
+            r * (m.m10 * m.m10 + m.m11 * m.m11 + m.m12 * m.m12).sqrt(),
+            r * (m.m20 * m.m20 + m.m21 * m.m21 + m.m22 * m.m22).sqrt(),
+        );
+        let center = self.apply_to_point(c);
+        BBox::new_from_points(&(&center - &extent), &(&center + &extent))
+    }
+
+    /// Check whether the transform changes the handedness of the coordinate system.
+    #[inline(always)]
+    pub fn swaps_handedness(&self) -> bool {
+        self.swaps_handedness
+    }
+
     /// Invert transformation.
     pub fn invert(&self) -> Transform {
         Transform::new(self.inverse, self.matrix)
@@ -312,8 +375,38 @@ impl ops::MulAssign<&Transform> for Transform {
     /// Combine this transform with another transform.
     #[inline(always)]
     fn mul_assign(&mut self, xform: &Transform) {
-        self.matrix = &self.matrix * &xform.matrix;
-        self.inverse = &xform.inverse * &self.inverse;
+        *self = Transform::new(
+            &self.matrix * &xform.matrix,
+            &xform.inverse * &self.inverse,
+        );
+    }
+}
+
+/// Axis pointing up in the coordinate system of a model.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UpAxis {
+    /// Y axis up, the same as in the scene.
+    Y,
+    /// Z axis up (with Y pointing away from the viewer), as used by many modeling tools.
+    Z,
+}
+
+impl UpAxis {
+    /// Get the transform from the model's coordinate system to the Y-up coordinate system of the scene.
+    pub fn to_y_up(self) -> Transform {
+        match self {
+            UpAxis::Y => Transform::identity(),
+            UpAxis::Z => {
+                // Rotation by -90 degrees around the X axis, with exact zeros and ones.
+                let m = Mat4::new(
+                    1.0, 0.0, 0.0, 0.0,
+                    0.0, 0.0, 1.0, 0.0,
+                    0.0, -1.0, 0.0, 0.0,
+                    0.0, 0.0, 0.0, 1.0
+                );
+                Transform::new(m, transpose(&m))
+            },
+        }
     }
 }
 
@@ -403,6 +496,63 @@ mod tests {
         assert_eq!(tp.x, -0.5); assert_eq!(tp.y, 0.49999994); assert_eq!(tp.z, 1.0);
     }
 
+    #[test]
+    fn identity_xform() {
+        let t = Transform::identity();
+        let v = Vec3::new(2.0, 3.0, 4.0);
+        let tp = t.apply_to_point(&v);
+        assert_eq!(tp.x, 2.0); assert_eq!(tp.y, 3.0); assert_eq!(tp.z, 4.0);
+        let tv = t.apply_to_vector(&v);
+        assert_eq!(tv.x, 2.0); assert_eq!(tv.y, 3.0); assert_eq!(tv.z, 4.0);
+        let tn = t.apply_to_normal(&v);
+        assert_eq!(tn.x, 2.0); assert_eq!(tn.y, 3.0); assert_eq!(tn.z, 4.0);
+        assert!(!t.swaps_handedness());
+        assert!(!t.invert().swaps_handedness());
+    }
+
+    #[test]
+    fn xform_handedness() {
+        assert!(!Transform::rotate_y(45.0).swaps_handedness());
+        assert!(!Transform::scale(2.0, 3.0, 4.0).swaps_handedness());
+        assert!(Transform::scale(-1.0, 1.0, 1.0).swaps_handedness());
+        assert!(!Transform::scale(-1.0, -1.0, 1.0).swaps_handedness());
+        let t = &Transform::identity() * &Transform::scale(1.0, 1.0, -2.0);
+        assert!(t.swaps_handedness());
+        let mut t = Transform::identity();
+        t *= &Transform::scale(1.0, 1.0, -2.0);
+        assert!(t.swaps_handedness());
+    }
+
+    #[test]
+    fn compose_empty_list() {
+        let t = Transform::compose(&[]);
+        let p = Vec3::new(1.0, 2.0, 3.0);
+        let tp = t.apply_to_point(&p);
+        assert_eq!(tp.x, 1.0); assert_eq!(tp.y, 2.0); assert_eq!(tp.z, 3.0);
+    }
+
+    #[test]
+    fn compose_single_xform() {
+        let t = Transform::compose(&[Transform::translate(0.5, -0.5, 1.0)]);
+        let p = Vec3::new(2.0, 3.0, 4.0);
+        let tp = t.apply_to_point(&p);
+        assert_eq!(tp.x, 2.5); assert_eq!(tp.y, 2.5); assert_eq!(tp.z, 5.0);
+    }
+
+    #[test]
+    fn compose_multiple_xforms() {
+        let t = Transform::compose(&[
+            Transform::translate(0.5, -0.5, 1.0),
+            Transform::rotate_z(90.0),
+            Transform::scale(2.0, 2.0, 2.0),
+        ]);
+        let p = Vec3::new(0.5, 0.5, 0.0);
+        let tp = t.apply_to_point(&p);
+        assert_eq!(tp.x, -0.5); assert_eq!(tp.y, 0.49999994); assert_eq!(tp.z, 1.0);
+        let ip = t.invert().apply_to_point(&tp);
+        assert!((ip.x - 0.5).abs() < 1e-6); assert!((ip.y - 0.5).abs() < 1e-6); assert!(ip.z.abs() < 1e-6);
+    }
+
     #[test]
     fn add_xform_to_self() {
         let mut t = Transform::translate(0.5, -0.5, 1.0);
@@ -412,4 +562,65 @@ mod tests {
         let tp = t.apply_to_point(&p);
         assert_eq!(tp.x, -0.5); assert_eq!(tp.y, 0.49999994); assert_eq!(tp.z, 1.0);
     }
+
+    #[test]
+    fn xform_from_trs() {
+        let t = Transform::from_trs(
+            &Vec3::new(0.5, -0.5, 1.0),
+            &Quat::from_axis_angle(90.0, &Vec3::new(0.0, 0.0, 1.0)),
+            &Vec3::new(2.0, 3.0, 4.0),
+        );
+        let chain = &(&Transform::translate(0.5, -0.5, 1.0) * &Transform::rotate_z(90.0)) * &Transform::scale(2.0, 3.0, 4.0);
+        let p = Vec3::new(1.0, 2.0, 3.0);
+        let (tp, cp) = (t.apply_to_point(&p), chain.apply_to_point(&p));
+        assert!((tp.x - cp.x).abs() < 1e-5); assert!((tp.y - cp.y).abs() < 1e-5); assert!((tp.z - cp.z).abs() < 1e-5);
+        let ip = t.invert().apply_to_point(&tp);
+        assert!((ip.x - p.x).abs() < 1e-5); assert!((ip.y - p.y).abs() < 1e-5); assert!((ip.z - p.z).abs() < 1e-5);
+    }
+
+    #[test]
+    fn z_up_to_y_up() {
+        let t = UpAxis::Z.to_y_up();
+        let up = t.apply_to_vector(&Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(up.x, 0.0); assert_eq!(up.y, 1.0); assert_eq!(up.z, 0.0);
+        // Forward in Z-up tools (+Y) points away from the viewer (-Z).
+        let forward = t.apply_to_vector(&Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(forward.x, 0.0); assert_eq!(forward.y, 0.0); assert_eq!(forward.z, -1.0);
+        let p = t.apply_to_point(&Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(p.x, 1.0); assert_eq!(p.y, 3.0); assert_eq!(p.z, -2.0);
+        assert!(!t.swaps_handedness());
+        let p = UpAxis::Y.to_y_up().apply_to_point(&Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(p.x, 1.0); assert_eq!(p.y, 2.0); assert_eq!(p.z, 3.0);
+    }
+
+    #[test]
+    fn tight_sphere_bbox() {
+        // Sphere stretched along X, then rotated by 45 degrees around Z and moved.
+        let t = Transform::compose(&[Transform::translate(1.0, 2.0, 3.0), Transform::rotate_z(45.0), Transform::scale(2.0, 1.0, 1.0)]);
+        let c = Vec3::new(0.5, 0.0, 0.0);
+        let tight = t.apply_to_sphere_bbox(&c, 1.0);
+        let loose = t.apply_to_bbox(&BBox::new_from_points(&Vec3::new(-0.5, -1.0, -1.0), &Vec3::new(1.5, 1.0, 1.0)));
+        // Points all over the ellipsoid reach the tight box, but not the corners of the loose one.
+        let mut points = BBox::new();
+        for i in 0..=100 {
+            for j in 0..200 {
+                let (theta, phi) = (std::f32::consts::PI * i as f32 / 100.0, 2.0 * std::f32::consts::PI * j as f32 / 200.0);
+                let d = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                points += &t.apply_to_point(&(&c + &d));
+            }
+        }
+        for (a, b) in [(tight.min, points.min), (tight.max, points.max)].iter() {
+            assert!((a.x - b.x).abs() < 1e-3); assert!((a.y - b.y).abs() < 1e-3); assert!((a.z - b.z).abs() < 1e-3);
+        }
+        // Half widths of sqrt(2^2 / 2 + 1 / 2) instead of (2 + 1) / sqrt(2) along X and Y.
+        assert!((tight.max.x - tight.min.x - 2.0 * 2.5f32.sqrt()).abs() < 1e-5);
+        assert!((loose.max.x - loose.min.x - 3.0 * 2.0f32.sqrt()).abs() < 1e-5);
+        assert!(tight.volume() < 0.6 * loose.volume());
+        // Without rotation, both are the same.
+        let t = Transform::compose(&[Transform::translate(1.0, 2.0, 3.0), Transform::scale(2.0, 1.0, 3.0)]);
+        let tight = t.apply_to_sphere_bbox(&c, -1.0);
+        let loose = t.apply_to_bbox(&BBox::new_from_points(&Vec3::new(-0.5, -1.0, -1.0), &Vec3::new(1.5, 1.0, 1.0)));
+        assert_eq!(tight.min.x, loose.min.x); assert_eq!(tight.min.y, loose.min.y); assert_eq!(tight.min.z, loose.min.z);
+        assert_eq!(tight.max.x, loose.max.x); assert_eq!(tight.max.y, loose.max.y); assert_eq!(tight.max.z, loose.max.z);
+    }
 }